@@ -0,0 +1,47 @@
+/* The C API of wasm-isolate. Link against the cdylib built by `cargo build --release`. */
+
+#ifndef WASM_ISOLATE_H
+#define WASM_ISOLATE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Bytes allocated by wasm-isolate. Free them with wasm_isolate_buf_free. */
+typedef struct {
+  uint8_t *ptr;
+  size_t len;
+} WasmIsolateBuf;
+
+/*
+ * Isolates items from a module. `options_json` is a NUL-terminated JSON object like
+ * {"roots": {"funcs": [12]}}, or NULL for no options. Its fields are the same as in a config
+ * file: "roots", "cut", "custom-sections", "standalone", plus "indirect-closure",
+ * "chase-exports", and "strip-object-metadata".
+ *
+ * Returns 0 on success, with the isolated module in `out_buf`. Otherwise returns the status the
+ * command line would exit with, and `out_buf` holds the error message as UTF-8 (not
+ * NUL-terminated). Either way, `out_buf` must be freed.
+ */
+int32_t wasm_isolate_run(const uint8_t *input_ptr, size_t input_len, const char *options_json,
+                         WasmIsolateBuf *out_buf);
+
+void wasm_isolate_buf_free(WasmIsolateBuf buf);
+
+/*
+ * Allocates zeroed bytes for the input and options, for hosts that cannot pass pointers to their
+ * own memory, like JS calling wasm-isolate compiled to WebAssembly. Free them with
+ * wasm_isolate_free.
+ */
+uint8_t *wasm_isolate_alloc(size_t len);
+
+void wasm_isolate_free(uint8_t *ptr, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,556 @@
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
+
+use anyhow::Result;
+use rayon::prelude::*;
+
+use crate::features::*;
+use crate::liveness::*;
+use crate::module::{export_item, ParsedModule};
+use crate::schema::{self, SCHEMA_VERSION};
+use crate::uses::*;
+use crate::wat;
+
+/// Something in the module that refers to an item.
+#[derive(Clone, Copy)]
+pub enum User<'a> {
+    Item(WorkItem),
+    Export(&'a str),
+    Start,
+}
+
+/// Finds everything in the module that directly refers to the given item, whether from a
+/// function body, an init expression, a segment, an export, or the start section.
+pub fn find_users<'a>(module: &ParsedModule<'a>, target: WorkItem) -> Result<Vec<User<'a>>> {
+    let options = LivenessOptions::default();
+
+    let mut res: Vec<User> = vec![];
+    for item in module.all_items() {
+        if item == target {
+            continue;
+        }
+        if get_item_uses(module, item, &options)?.contains(target) {
+            res.push(User::Item(item));
+        }
+    }
+    for export in &module.exports {
+        if export_item(export) == target {
+            res.push(User::Export(export.name));
+        }
+    }
+    if let (Some(idx), WorkItem::Func(target_idx)) = (module.start_idx, target) {
+        if idx == target_idx {
+            res.push(User::Start);
+        }
+    }
+    Ok(res)
+}
+
+/// Why an item is kept: a chain of uses from something that keeps items alive, like an export, to
+/// the item.
+pub struct Explanation<'a> {
+    pub origin: User<'a>,
+    /// Each item uses the next one. The first is the one the origin refers to, and the last is
+    /// the item being explained.
+    pub path: Vec<WorkItem>,
+}
+
+/// Finds one of the shortest chains of uses from the given origins to the target, or `None` if
+/// none of them keep it. Without any origins, every export and the start function are used,
+/// which explains why the target is in the module at all.
+pub fn explain<'a>(
+    module: &ParsedModule<'a>,
+    origins: &[User<'a>],
+    target: WorkItem,
+    options: &LivenessOptions,
+) -> Result<Option<Explanation<'a>>> {
+    Ok(explain_each(module, origins, &[target], options)?
+        .pop()
+        .flatten())
+}
+
+/// Explains each of the targets like [`explain`], with a single search of the module.
+pub fn explain_each<'a>(
+    module: &ParsedModule<'a>,
+    origins: &[User<'a>],
+    targets: &[WorkItem],
+    options: &LivenessOptions,
+) -> Result<Vec<Option<Explanation<'a>>>> {
+    let mut default_origins: Vec<User> = vec![];
+    if origins.is_empty() {
+        default_origins.extend(module.exports.iter().map(|e| User::Export(e.name)));
+        if module.start_idx.is_some() {
+            default_origins.push(User::Start);
+        }
+    }
+    let origins = if origins.is_empty() {
+        &default_origins
+    } else {
+        origins
+    };
+
+    // Every item that has been reached, with the item it was reached from. Items reached straight
+    // from an origin have the index of that origin instead.
+    let mut reached: HashMap<WorkItem, Result<WorkItem, usize>> = HashMap::new();
+    let mut queue: VecDeque<WorkItem> = VecDeque::new();
+    for (i, origin) in origins.iter().enumerate() {
+        let item = match origin {
+            User::Item(item) => *item,
+            User::Export(name) => match module.find_export(name) {
+                Some(item) => item,
+                None => anyhow::bail!("export \"{}\" was not found in the module", name),
+            },
+            User::Start => match module.start_idx {
+                Some(idx) => WorkItem::Func(idx),
+                None => anyhow::bail!("the module has no start function"),
+            },
+        };
+        if let Entry::Vacant(entry) = reached.entry(item) {
+            entry.insert(Err(i));
+            queue.push_back(item);
+        }
+    }
+
+    let mut remaining: HashSet<WorkItem> = targets
+        .iter()
+        .copied()
+        .filter(|target| !reached.contains_key(target))
+        .collect();
+    while let Some(item) = queue.pop_front() {
+        if remaining.is_empty() {
+            break;
+        }
+        for used in live_items(&get_item_uses(module, item, options)?) {
+            if let (true, Entry::Vacant(entry)) = (used != item, reached.entry(used)) {
+                entry.insert(Ok(item));
+                queue.push_back(used);
+                remaining.remove(&used);
+            }
+        }
+    }
+
+    Ok(targets
+        .iter()
+        .map(|target| {
+            let mut path = vec![*target];
+            let mut current = *target;
+            loop {
+                match reached.get(&current)? {
+                    Ok(parent) => {
+                        path.push(*parent);
+                        current = *parent;
+                    }
+                    Err(i) => {
+                        path.reverse();
+                        return Some(Explanation {
+                            origin: origins[*i],
+                            path,
+                        });
+                    }
+                }
+            }
+        })
+        .collect())
+}
+
+/// Describes an item along with where it comes from and its type, e.g.
+/// `func 3 (log) (import "env" "log") (export "log"): (func (param i32))`.
+pub fn describe_in_detail(module: &ParsedModule, item: WorkItem) -> String {
+    let mut res = module.describe(item);
+    if let Some(import) = module.import_of(item) {
+        res.push_str(&format!(
+            " (import \"{}\" \"{}\")",
+            import.module, import.name
+        ));
+    }
+    for name in module.exports_of(item) {
+        res.push_str(&format!(" (export \"{}\")", name));
+    }
+    if let Some(ty) = wat::item_type(module, item) {
+        res.push_str(&format!(": {}", ty));
+    }
+    res
+}
+
+/// Describes why an item is kept in the JSON format.
+pub fn build_explanation(
+    module: &ParsedModule,
+    target: WorkItem,
+    explanation: Option<&Explanation>,
+) -> schema::Explanation {
+    schema::Explanation {
+        schema: SCHEMA_VERSION,
+        item: schema::Item::new(module, target),
+        origin: explanation.map(|explanation| user_entry(module, &explanation.origin)),
+        path: explanation
+            .into_iter()
+            .flat_map(|explanation| &explanation.path)
+            .map(|item| schema::ItemDetails {
+                item: schema::Item::new(module, *item),
+                ty: wat::item_type(module, *item),
+                import: module.import_of(*item).map(|import| schema::ImportName {
+                    module: import.module.to_string(),
+                    name: import.name.to_string(),
+                }),
+                exports: module
+                    .exports_of(*item)
+                    .into_iter()
+                    .map(|name| name.to_string())
+                    .collect(),
+            })
+            .collect(),
+    }
+}
+
+/// Lists every item in the module that is not in a set of items, one index space at a time.
+pub fn unreachable_items(module: &ParsedModule, items: &Uses) -> Vec<WorkItem> {
+    module
+        .all_items()
+        .into_iter()
+        .filter(|item| !items.contains(*item))
+        .collect()
+}
+
+/// Describes the items that would be dropped in the JSON format.
+pub fn build_dropped(module: &ParsedModule, items: &[WorkItem]) -> schema::DroppedItems {
+    schema::DroppedItems {
+        schema: SCHEMA_VERSION,
+        items: items
+            .iter()
+            .map(|item| schema::DroppedItem {
+                item: schema::Item::new(module, *item),
+                size: module.item_size(*item),
+            })
+            .collect(),
+    }
+}
+
+/// Lists the largest defined functions and data segments of a set of items, by encoded size,
+/// largest first.
+pub fn largest_items(module: &ParsedModule, items: &Uses, n: usize) -> Vec<(WorkItem, usize)> {
+    let mut res: Vec<(WorkItem, usize)> = live_items(items)
+        .into_iter()
+        .filter(|item| matches!(item, WorkItem::Func(_) | WorkItem::Data(_)))
+        .filter(|item| module.import_of(*item).is_none())
+        .filter_map(|item| Some((item, module.item_size(item)?)))
+        .collect();
+    res.sort_by(|(a, a_size), (b, b_size)| {
+        b_size
+            .cmp(a_size)
+            .then(a.kind_name().cmp(b.kind_name()))
+            .then(a.index().cmp(&b.index()))
+    });
+    res.truncate(n);
+    res
+}
+
+/// Describes the largest items and what keeps them in the JSON format.
+pub fn build_top(
+    module: &ParsedModule,
+    items: &[(WorkItem, usize)],
+    explanations: &[Option<Explanation>],
+) -> schema::TopItems {
+    schema::TopItems {
+        schema: SCHEMA_VERSION,
+        items: items
+            .iter()
+            .zip(explanations)
+            .map(|((item, size), explanation)| schema::TopItem {
+                item: schema::Item::new(module, *item),
+                size: *size,
+                origin: explanation
+                    .as_ref()
+                    .map(|explanation| user_entry(module, &explanation.origin)),
+                path: explanation
+                    .iter()
+                    .flat_map(|explanation| &explanation.path)
+                    .map(|item| schema::Item::new(module, *item))
+                    .collect(),
+            })
+            .collect(),
+    }
+}
+
+fn user_entry(module: &ParsedModule, user: &User) -> schema::UserEntry {
+    match user {
+        User::Item(item) => schema::UserEntry::Item(schema::Item::new(module, *item)),
+        User::Export(name) => schema::UserEntry::Export {
+            name: name.to_string(),
+        },
+        User::Start => schema::UserEntry::Start,
+    }
+}
+
+/// A summary of what a defined function depends on, for picking isolation targets at a glance.
+pub struct FuncSummary {
+    pub idx: u32,
+    pub size: usize,
+    pub num_callees: usize,
+    pub num_globals: usize,
+    pub num_memories: usize,
+    pub uses_simd: bool,
+    pub uses_atomics: bool,
+    pub uses_gc: bool,
+}
+
+pub fn summarize_funcs(module: &ParsedModule) -> Result<Vec<FuncSummary>> {
+    let mut res: Vec<FuncSummary> = vec![];
+    for (i, func) in module.defined_funcs.iter().enumerate() {
+        let idx = module.num_imported_functions + i as u32;
+        let mut uses = Uses::default();
+        let mut summary = FuncSummary {
+            idx,
+            size: func.body_range.len(),
+            num_callees: 0,
+            num_globals: 0,
+            num_memories: 0,
+            uses_simd: false,
+            uses_atomics: false,
+            uses_gc: false,
+        };
+        for instr in func.operators()? {
+            let instr = instr?;
+            uses.merge(get_instr_uses(&instr));
+            summary.uses_simd |= is_simd(&instr);
+            summary.uses_atomics |= is_atomic(&instr);
+            summary.uses_gc |= is_gc(&instr);
+        }
+        summary.num_callees = uses.live_funcs.len();
+        summary.num_globals = uses.live_globals.len();
+        summary.num_memories = uses.live_memories.len();
+        res.push(summary);
+    }
+    Ok(res)
+}
+
+/// Describes the users of an item in the JSON format.
+pub fn build_users(module: &ParsedModule, target: WorkItem, users: &[User]) -> schema::Users {
+    schema::Users {
+        schema: SCHEMA_VERSION,
+        item: schema::Item::new(module, target),
+        users: users.iter().map(|user| user_entry(module, user)).collect(),
+    }
+}
+
+/// Describes the proposals that some items need in the JSON format.
+pub fn build_features(module: &ParsedModule, features: &[RequiredFeature]) -> schema::Features {
+    schema::Features {
+        schema: SCHEMA_VERSION,
+        features: features
+            .iter()
+            .map(|feature| schema::FeatureEntry {
+                proposal: feature.proposal.to_string(),
+                item: schema::Item::new(module, feature.item),
+                reason: feature.reason.clone(),
+            })
+            .collect(),
+    }
+}
+
+/// Describes the contents of a module in the JSON format.
+pub fn build_inventory(module: &ParsedModule) -> Result<schema::Inventory> {
+    let spaces = [
+        ("types", module.types.len(), 0),
+        (
+            "funcs",
+            module.func_types.len(),
+            module.num_imported_functions,
+        ),
+        (
+            "tables",
+            module.table_types.len(),
+            module.num_imported_tables,
+        ),
+        (
+            "memories",
+            module.memory_types.len(),
+            module.num_imported_memories,
+        ),
+        (
+            "globals",
+            module.global_types.len(),
+            module.num_imported_globals,
+        ),
+        ("tags", module.tag_types.len(), module.num_imported_tags),
+        ("datas", module.datas.len(), 0),
+        ("elems", module.elems.len(), 0),
+    ];
+
+    let mut funcs: Vec<schema::FuncEntry> = vec![];
+    for summary in summarize_funcs(module)? {
+        let mut features: Vec<String> = vec![];
+        if summary.uses_simd {
+            features.push("simd".to_string());
+        }
+        if summary.uses_atomics {
+            features.push("atomics".to_string());
+        }
+        if summary.uses_gc {
+            features.push("gc".to_string());
+        }
+        funcs.push(schema::FuncEntry {
+            index: summary.idx,
+            name: module
+                .item_name(WorkItem::Func(summary.idx))
+                .map(|name| name.to_string()),
+            size: summary.size,
+            num_callees: summary.num_callees,
+            num_globals: summary.num_globals,
+            num_memories: summary.num_memories,
+            features,
+        });
+    }
+
+    Ok(schema::Inventory {
+        schema: SCHEMA_VERSION,
+        index_spaces: spaces
+            .into_iter()
+            .map(|(name, count, imported)| schema::IndexSpaceCount {
+                name: name.to_string(),
+                count,
+                imported: imported as usize,
+            })
+            .collect(),
+        funcs,
+    })
+}
+
+/// An item of a module, with what can be known about it on its own.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde-impls", derive(serde::Serialize, serde::Deserialize))]
+pub struct ItemInfo {
+    pub item: WorkItem,
+    /// The encoded size in bytes, for functions and segments.
+    pub size: Option<usize>,
+    /// The item's name from the name section, if it has one.
+    pub name: Option<String>,
+}
+
+impl ItemInfo {
+    pub fn kind(&self) -> &'static str {
+        self.item.kind_name()
+    }
+}
+
+/// Every item of a module and how the items use each other, in both directions. This is the same
+/// information isolation works from, for tools that want to look at a module's structure without
+/// isolating anything.
+#[cfg_attr(feature = "serde-impls", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde-impls", serde(from = "ModuleGraphParts"))]
+pub struct ModuleGraph {
+    items: Vec<ItemInfo>,
+    /// Where each item is in `items`, which is rebuilt rather than written out.
+    #[cfg_attr(feature = "serde-impls", serde(skip))]
+    node_of: HashMap<WorkItem, usize>,
+    uses: Vec<Vec<(WorkItem, UseKind)>>,
+    users: Vec<Vec<(WorkItem, UseKind)>>,
+    exports: Vec<(String, WorkItem)>,
+    start: Option<u32>,
+}
+
+/// A [`ModuleGraph`] as it is written out.
+#[cfg(feature = "serde-impls")]
+#[derive(serde::Deserialize)]
+struct ModuleGraphParts {
+    items: Vec<ItemInfo>,
+    uses: Vec<Vec<(WorkItem, UseKind)>>,
+    users: Vec<Vec<(WorkItem, UseKind)>>,
+    exports: Vec<(String, WorkItem)>,
+    start: Option<u32>,
+}
+
+#[cfg(feature = "serde-impls")]
+impl From<ModuleGraphParts> for ModuleGraph {
+    fn from(parts: ModuleGraphParts) -> Self {
+        ModuleGraph {
+            node_of: node_of(&parts.items),
+            items: parts.items,
+            uses: parts.uses,
+            users: parts.users,
+            exports: parts.exports,
+            start: parts.start,
+        }
+    }
+}
+
+fn node_of(items: &[ItemInfo]) -> HashMap<WorkItem, usize> {
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, info)| (info.item, i))
+        .collect()
+}
+
+/// Parses a module and builds its [`ModuleGraph`].
+pub fn analyze(wasm: &[u8]) -> Result<ModuleGraph> {
+    ModuleGraph::new(&ParsedModule::parse(wasm)?)
+}
+
+impl ModuleGraph {
+    pub fn new(module: &ParsedModule) -> Result<ModuleGraph> {
+        let options = LivenessOptions::default();
+        let items: Vec<ItemInfo> = module
+            .all_items()
+            .into_iter()
+            .map(|item| ItemInfo {
+                item,
+                size: module.item_size(item),
+                name: module.item_name(item).map(|name| name.to_string()),
+            })
+            .collect();
+        let node_of = node_of(&items);
+        let uses: Vec<Vec<(WorkItem, UseKind)>> = items
+            .par_iter()
+            .map(|info| get_labeled_uses(module, info.item, &options))
+            .collect::<Result<_>>()?;
+        let mut users: Vec<Vec<(WorkItem, UseKind)>> = vec![vec![]; items.len()];
+        for (info, item_uses) in items.iter().zip(&uses) {
+            for (used, kind) in item_uses {
+                if let Some(&i) = node_of.get(used) {
+                    users[i].push((info.item, *kind));
+                }
+            }
+        }
+        Ok(ModuleGraph {
+            items,
+            node_of,
+            uses,
+            users,
+            exports: module
+                .exports
+                .iter()
+                .map(|export| (export.name.to_string(), export_item(export)))
+                .collect(),
+            start: module.start_idx,
+        })
+    }
+
+    /// Every item of the module, one index space at a time.
+    pub fn items(&self) -> &[ItemInfo] {
+        &self.items
+    }
+
+    pub fn info(&self, item: WorkItem) -> Option<&ItemInfo> {
+        self.node_of.get(&item).map(|i| &self.items[*i])
+    }
+
+    /// The items that an item directly refers to, and how.
+    pub fn uses(&self, item: WorkItem) -> &[(WorkItem, UseKind)] {
+        self.node_of.get(&item).map_or(&[], |i| &self.uses[*i])
+    }
+
+    /// The items that directly refer to an item, and how. Exports and the start function are not
+    /// items, so they are listed by [`ModuleGraph::exports`] and [`ModuleGraph::start`] instead.
+    pub fn users(&self, item: WorkItem) -> &[(WorkItem, UseKind)] {
+        self.node_of.get(&item).map_or(&[], |i| &self.users[*i])
+    }
+
+    /// Every export, by name, with the item it exports.
+    pub fn exports(&self) -> &[(String, WorkItem)] {
+        &self.exports
+    }
+
+    /// The start function, if the module has one.
+    pub fn start(&self) -> Option<u32> {
+        self.start
+    }
+}
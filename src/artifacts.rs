@@ -0,0 +1,495 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use wasm_encoder::{CustomSection, Encode, SectionId};
+
+use crate::demangle;
+use crate::emit::{synthetic_exports, EmitOptions};
+use crate::error::{Error, ErrorKind};
+use crate::liveness::*;
+use crate::module::ParsedModule;
+use crate::relocation::*;
+use crate::schema::{self, SCHEMA_VERSION};
+use crate::stats::index_space_counts;
+use crate::uses::Uses;
+
+/// Lists the new index of every live item.
+pub fn build_map(all_uses: &Uses, relocations: &Relocations) -> Result<schema::Map> {
+    let mut items: Vec<schema::MapEntry> = vec![];
+    for item in live_items(all_uses) {
+        let Some(new_idx) = relocations.get(&Relocation::from(item)) else {
+            return Err(Error::new(
+                ErrorKind::Internal,
+                format!(
+                    "live {} {} was not relocated",
+                    item.kind_name(),
+                    item.index()
+                ),
+            )
+            .into());
+        };
+        items.push(schema::MapEntry {
+            kind: item.kind_name().to_string(),
+            old_index: item.index(),
+            new_index: *new_idx,
+        });
+    }
+    Ok(schema::Map {
+        schema: SCHEMA_VERSION,
+        items,
+    })
+}
+
+/// The name of the custom section that `--annotate` adds.
+pub const ANNOTATION_SECTION: &str = "wasm-isolate";
+
+/// Adds a custom section to the end of an isolated module recording how it was made: the command
+/// line, a hash of the original module, and the roots that were kept.
+pub fn append_annotation(
+    isolated: &mut Vec<u8>,
+    module: &ParsedModule,
+    roots: &[WorkItem],
+    args: Vec<String>,
+) -> Result<()> {
+    let annotation = schema::Annotation {
+        schema: SCHEMA_VERSION,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        args,
+        input_sha256: Sha256::digest(module.buf)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+        roots: roots
+            .iter()
+            .map(|item| schema::Item::new(module, *item))
+            .collect(),
+    };
+    isolated.push(SectionId::Custom as u8);
+    CustomSection {
+        name: Cow::Borrowed(ANNOTATION_SECTION),
+        data: Cow::Owned(serde_json::to_vec(&annotation)?),
+    }
+    .encode(isolated);
+    Ok(())
+}
+
+/// Pairs up the bodies of the live functions in the original module with their bodies in the
+/// isolated one.
+pub fn build_offset_map(
+    module: &ParsedModule,
+    isolated: &[u8],
+    all_uses: &Uses,
+) -> Result<schema::OffsetMap> {
+    let isolated_module = ParsedModule::parse(isolated)?;
+
+    let mut funcs: Vec<schema::FuncOffsets> = vec![];
+    for (new_idx, old_idx) in all_uses.live_funcs.iter().enumerate() {
+        let new_idx = new_idx as u32;
+        // Imports have no body, though in standalone mode they get a stub that doesn't
+        // correspond to anything in the original.
+        if old_idx < module.num_imported_functions
+            || new_idx < isolated_module.num_imported_functions
+        {
+            continue;
+        }
+        let old_range =
+            &module.defined_funcs[(old_idx - module.num_imported_functions) as usize].body_range;
+        let new_range = &isolated_module.defined_funcs
+            [(new_idx - isolated_module.num_imported_functions) as usize]
+            .body_range;
+        funcs.push(schema::FuncOffsets {
+            old_index: old_idx,
+            new_index: new_idx,
+            old_start: old_range.start - module.code_start,
+            old_end: old_range.end - module.code_start,
+            new_start: new_range.start - isolated_module.code_start,
+            new_end: new_range.end - isolated_module.code_start,
+            verbatim: module.buf[old_range.clone()] == isolated[new_range.clone()],
+        });
+    }
+    Ok(schema::OffsetMap {
+        schema: SCHEMA_VERSION,
+        funcs,
+    })
+}
+
+/// Writes the offset map of `--emit-offset-map`, a line-based text format that is easy to read
+/// from scripts. Offsets are in hex, from the start of each module:
+///
+/// ```text
+/// # wasm-isolate offset map
+/// code 0x2f 0x1c
+/// func 5 2
+/// 0x4a 0x31
+/// 0x4c 0x33
+/// ```
+///
+/// The `code` line gives where the code section's contents start in the original and isolated
+/// modules, to convert to and from the code offsets that DWARF uses. Each `func` line gives a kept
+/// function's original and new index, followed by the original and new offset of the start of its
+/// body, of each of its instructions, and of the end of its body. Imports have no offsets, and
+/// neither do functions with a new body that doesn't line up with the old one, like cut ones.
+pub fn write_offset_map(
+    w: &mut dyn Write,
+    module: &ParsedModule,
+    isolated: &[u8],
+    all_uses: &Uses,
+    relocations: &Relocations,
+    options: &EmitOptions,
+) -> Result<()> {
+    let isolated_module = ParsedModule::parse(isolated)?;
+    let offsets = |func: &crate::module::Func| -> Result<Vec<usize>> {
+        let mut res = vec![func.body_range.start];
+        for op in func.body.get_operators_reader()?.into_iter_with_offsets() {
+            res.push(op?.1);
+        }
+        res.push(func.body_range.end);
+        Ok(res)
+    };
+
+    writeln!(w, "# wasm-isolate offset map")?;
+    writeln!(
+        w,
+        "code {:#x} {:#x}",
+        module.code_start, isolated_module.code_start
+    )?;
+    for old_idx in all_uses.live_funcs.iter() {
+        let Some(&new_idx) = relocations.get(&Relocation::Func(old_idx)) else {
+            continue;
+        };
+        writeln!(w, "func {} {}", old_idx, new_idx)?;
+        let (Some(old_i), Some(new_i)) = (
+            old_idx.checked_sub(module.num_imported_functions),
+            new_idx.checked_sub(isolated_module.num_imported_functions),
+        ) else {
+            continue;
+        };
+        if options.is_stub(old_idx) {
+            continue;
+        }
+        let old = offsets(&module.defined_funcs[old_i as usize])?;
+        let new = offsets(&isolated_module.defined_funcs[new_i as usize])?;
+        // Bodies only differ in the number of instructions when some were rewritten, like uses
+        // of data segments with --no-data.
+        if old.len() != new.len() {
+            continue;
+        }
+        for (old, new) in old.iter().zip(&new) {
+            writeln!(w, "{:#x} {:#x}", old, new)?;
+        }
+    }
+    Ok(())
+}
+
+/// Like [`build_offset_map`], but with offsets from the start of each module instead of the start
+/// of its code section.
+pub fn build_source_map_remap(
+    module: &ParsedModule,
+    isolated: &[u8],
+    all_uses: &Uses,
+) -> Result<schema::SourceMapRemap> {
+    let isolated_code_start = ParsedModule::parse(isolated)?.code_start;
+    let mut funcs = build_offset_map(module, isolated, all_uses)?.funcs;
+    for func in &mut funcs {
+        func.old_start += module.code_start;
+        func.old_end += module.code_start;
+        func.new_start += isolated_code_start;
+        func.new_end += isolated_code_start;
+    }
+    Ok(schema::SourceMapRemap {
+        schema: SCHEMA_VERSION,
+        source_mapping_url: module.source_mapping_url.map(|url| url.to_string()),
+        funcs,
+    })
+}
+
+/// Builds the dependency graph between live items, with the size each item retains. An item
+/// retains everything that every path from the roots to it goes through, so the roots are needed
+/// too. Live items the roots don't reach, like types kept for their rec groups, count as roots.
+pub fn build_graph(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    roots: &[WorkItem],
+    options: &LivenessOptions,
+) -> Result<schema::Graph> {
+    let items = live_items(all_uses);
+    let node_of: HashMap<WorkItem, usize> = items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| (*item, i))
+        .collect();
+    let mut succs: Vec<Vec<usize>> = vec![vec![]; items.len()];
+    let mut edges: Vec<schema::GraphEdge> = vec![];
+    for (i, item) in items.iter().enumerate() {
+        for (used, kind) in get_labeled_uses(module, *item, options)? {
+            // Dropped items are used but not live.
+            let Some(&to) = node_of.get(&used) else {
+                continue;
+            };
+            succs[i].push(to);
+            edges.push(schema::GraphEdge {
+                from: schema::Item::new(module, *item),
+                to: schema::Item::new(module, used),
+                kind: kind.name().to_string(),
+            });
+        }
+    }
+
+    let sizes: Vec<usize> = items
+        .iter()
+        .map(|item| module.item_size(*item).unwrap_or(0))
+        .collect();
+    let roots: Vec<usize> = roots
+        .iter()
+        .filter_map(|item| node_of.get(item).copied())
+        .collect();
+    let retained = retained_sizes(&succs, &roots, &sizes);
+    let nodes = items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| schema::GraphNode {
+            item: schema::Item::new(module, *item),
+            size: sizes[i],
+            retained_size: retained[i],
+        })
+        .collect();
+    Ok(schema::Graph {
+        schema: SCHEMA_VERSION,
+        nodes,
+        edges,
+    })
+}
+
+/// Finds the retained size of every node of a graph by building its dominator tree, using the
+/// iterative algorithm from Cooper, Harvey, and Kennedy's "A Simple, Fast Dominance Algorithm".
+fn retained_sizes(succs: &[Vec<usize>], roots: &[usize], sizes: &[usize]) -> Vec<usize> {
+    // A virtual node at the end leads to every root, so the graph has a single entry. Whatever the
+    // roots don't reach is added to the roots as the search finds it.
+    let entry = succs.len();
+    let mut succs = succs.to_vec();
+    succs.push(vec![]);
+    let mut postorder: Vec<usize> = vec![];
+    let mut visited = vec![false; entry + 1];
+    for start in roots.iter().copied().chain(0..entry) {
+        if visited[start] {
+            continue;
+        }
+        succs[entry].push(start);
+        visited[start] = true;
+        let mut stack: Vec<(usize, usize)> = vec![(start, 0)];
+        while let Some((node, next)) = stack.pop() {
+            if let Some(&succ) = succs[node].get(next) {
+                stack.push((node, next + 1));
+                if !visited[succ] {
+                    visited[succ] = true;
+                    stack.push((succ, 0));
+                }
+            } else {
+                postorder.push(node);
+            }
+        }
+    }
+    postorder.push(entry);
+
+    let mut order = vec![0; entry + 1];
+    for (i, node) in postorder.iter().enumerate() {
+        order[*node] = i;
+    }
+    let mut preds: Vec<Vec<usize>> = vec![vec![]; entry + 1];
+    for (node, node_succs) in succs.iter().enumerate() {
+        for succ in node_succs {
+            preds[*succ].push(node);
+        }
+    }
+
+    let mut idom: Vec<Option<usize>> = vec![None; entry + 1];
+    idom[entry] = Some(entry);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &node in postorder.iter().rev().skip(1) {
+            let mut new_idom: Option<usize> = None;
+            for &pred in &preds[node] {
+                if idom[pred].is_none() {
+                    continue;
+                }
+                new_idom = Some(match new_idom {
+                    None => pred,
+                    Some(mut a) => {
+                        let mut b = pred;
+                        while a != b {
+                            while order[a] < order[b] {
+                                a = idom[a].expect("processed nodes have dominators");
+                            }
+                            while order[b] < order[a] {
+                                b = idom[b].expect("processed nodes have dominators");
+                            }
+                        }
+                        a
+                    }
+                });
+            }
+            if new_idom != idom[node] {
+                idom[node] = new_idom;
+                changed = true;
+            }
+        }
+    }
+
+    // Nodes come before their dominators in postorder, so their sizes are complete by the time
+    // they are added to them.
+    let mut retained: Vec<usize> = sizes.to_vec();
+    retained.push(0);
+    for &node in &postorder[..entry] {
+        let parent = idom[node].expect("every node is reachable");
+        retained[parent] += retained[node];
+    }
+    retained.truncate(entry);
+    retained
+}
+
+/// Writes a dependency graph in Graphviz format. Nodes are labeled with their original indices,
+/// or their names where the module has them, and with their sizes.
+pub fn write_dot(w: &mut dyn Write, graph: &schema::Graph) -> Result<()> {
+    writeln!(w, "digraph {{")?;
+    for node in &graph.nodes {
+        let item = &node.item;
+        let mut label = match &item.name {
+            Some(name) => format!("{} {}", item.kind, demangle::for_display(name)),
+            None => format!("{} {}", item.kind, item.index),
+        };
+        if node.retained_size > 0 {
+            label += &format!("\n{} B, retains {} B", node.size, node.retained_size);
+        }
+        writeln!(w, "  \"{}:{}\" [label={:?}];", item.kind, item.index, label)?;
+    }
+    for edge in &graph.edges {
+        writeln!(
+            w,
+            "  \"{}:{}\" -> \"{}:{}\" [label={:?}];",
+            edge.from.kind, edge.from.index, edge.to.kind, edge.to.index, edge.kind
+        )?;
+    }
+    writeln!(w, "}}")?;
+    Ok(())
+}
+
+/// Writes a dependency graph in GraphML, which graph tools like Gephi and yEd can lay out and
+/// filter at sizes where Graphviz gives up. Nodes have the same attributes as in the JSON graph,
+/// and edges have the kind of use.
+pub fn write_graphml(w: &mut dyn Write, graph: &schema::Graph) -> Result<()> {
+    writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        w,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    for (id, domain, name, ty) in [
+        ("kind", "node", "kind", "string"),
+        ("index", "node", "index", "long"),
+        ("name", "node", "name", "string"),
+        ("size", "node", "size", "long"),
+        ("retained_size", "node", "retained_size", "long"),
+        ("use", "edge", "kind", "string"),
+    ] {
+        writeln!(
+            w,
+            r#"  <key id="{}" for="{}" attr.name="{}" attr.type="{}"/>"#,
+            id, domain, name, ty
+        )?;
+    }
+    writeln!(w, r#"  <graph id="G" edgedefault="directed">"#)?;
+    for node in &graph.nodes {
+        let item = &node.item;
+        write!(w, r#"    <node id="{}:{}">"#, item.kind, item.index)?;
+        write!(w, r#"<data key="kind">{}</data>"#, item.kind)?;
+        write!(w, r#"<data key="index">{}</data>"#, item.index)?;
+        if let Some(name) = &item.name {
+            write!(
+                w,
+                r#"<data key="name">{}</data>"#,
+                xml_escape(&demangle::for_display(name))
+            )?;
+        }
+        write!(w, r#"<data key="size">{}</data>"#, node.size)?;
+        write!(
+            w,
+            r#"<data key="retained_size">{}</data>"#,
+            node.retained_size
+        )?;
+        writeln!(w, "</node>")?;
+    }
+    for edge in &graph.edges {
+        writeln!(
+            w,
+            r#"    <edge source="{}:{}" target="{}:{}"><data key="use">{}</data></edge>"#,
+            edge.from.kind, edge.from.index, edge.to.kind, edge.to.index, edge.kind
+        )?;
+    }
+    writeln!(w, "  </graph>")?;
+    writeln!(w, "</graphml>")?;
+    Ok(())
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            // Control characters can't appear in XML 1.0 at all, even escaped.
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => res.push('\u{fffd}'),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// Describes the result of an isolation run. `requested` lists the items given on the command
+/// line, including any that were out of range and therefore ignored.
+pub fn build_summary(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    relocations: &Relocations,
+    options: &EmitOptions,
+    requested: &[WorkItem],
+    warnings: Vec<String>,
+    output_size: usize,
+) -> Result<schema::Summary> {
+    Ok(schema::Summary {
+        schema: SCHEMA_VERSION,
+        requested: requested
+            .iter()
+            .map(|item| schema::RequestedItem {
+                kind: item.kind_name().to_string(),
+                old_index: item.index(),
+                new_index: relocations.get(&Relocation::from(*item)).copied(),
+                cut: matches!(item, WorkItem::Func(idx) if options.cut_funcs.contains(*idx)),
+            })
+            .collect(),
+        index_spaces: index_space_counts(module, all_uses),
+        items: build_map(all_uses, relocations)?.items,
+        synthetic_exports: synthetic_exports(module, all_uses, relocations, options)
+            .into_iter()
+            .map(|(name, kind, index)| schema::SyntheticExport {
+                name,
+                kind: match kind {
+                    wasm_encoder::ExportKind::Func => "func",
+                    wasm_encoder::ExportKind::Table => "table",
+                    wasm_encoder::ExportKind::Memory => "memory",
+                    wasm_encoder::ExportKind::Global => "global",
+                    wasm_encoder::ExportKind::Tag => "tag",
+                }
+                .to_string(),
+                index,
+            })
+            .collect(),
+        warnings,
+        output_size,
+        groups: vec![],
+    })
+}
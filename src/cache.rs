@@ -0,0 +1,145 @@
+//! Keeping the scans of a module's function bodies on disk, so that running again on the same
+//! module, like when bisecting with different roots, skips the slowest part of finding what is
+//! live. Each module's scans are kept in their own file, named after the hash of the module.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::data::{ConstantAccesses, MemoryAccesses};
+use crate::liveness::{live_items, BodyScan, WorkItem};
+use crate::module::ParsedModule;
+use crate::uses::Uses;
+
+/// The version of what is stored for each function body. It must be bumped whenever scanning a
+/// body changes, like when it learns about new instructions, since the package version does not
+/// change between builds.
+const SCAN_VERSION: u32 = 3;
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    /// The version of wasm-isolate that wrote the file. Files from other versions are ignored,
+    /// since what a body refers to may have changed.
+    version: String,
+    /// The [`SCAN_VERSION`] of the build that wrote the file. Files from before there was one
+    /// have zero, and are ignored like any other mismatch.
+    #[serde(default)]
+    scan_version: u32,
+    funcs: Vec<CachedFunc>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedFunc {
+    /// The items the body refers to, like "func:12".
+    uses: Vec<String>,
+    indirect_calls: Vec<(u32, u32)>,
+    func_refs: Vec<u32>,
+    constant_accesses: ConstantAccesses,
+    /// The memories the body accesses at computed addresses.
+    computed_accesses: Vec<u32>,
+    num_operators: u64,
+}
+
+/// Loads the module's body scans from the cache, or scans the module and saves them if the cache
+/// has none. Returns whether the scans were found in the cache.
+pub fn load_or_save(module: &ParsedModule, dir: &Path) -> Result<bool> {
+    let path = cache_path(module, dir);
+    match load(module, &path) {
+        Ok(Some(scans)) => {
+            log::debug!("loaded the function body scans from {}", path.display());
+            module.set_body_scans(scans)?;
+            return Ok(true);
+        }
+        Ok(None) => {}
+        Err(err) => log::warn!("ignoring the cache file {}: {:#}", path.display(), err),
+    }
+
+    let file = CacheFile {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        scan_version: SCAN_VERSION,
+        funcs: module
+            .body_scans()?
+            .iter()
+            .map(|scan| CachedFunc {
+                uses: live_items(&scan.uses)
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect(),
+                indirect_calls: scan.indirect_calls.clone(),
+                func_refs: scan.func_refs.clone(),
+                constant_accesses: scan.memory_accesses.constant.clone(),
+                computed_accesses: scan.memory_accesses.computed.iter().copied().collect(),
+                num_operators: scan.num_operators,
+            })
+            .collect(),
+    };
+    fs::create_dir_all(dir).with_context(|| format!("could not create {}", dir.display()))?;
+    // Another run may be reading the file, so it is replaced all at once. Runs on the same module
+    // each write their own temporary file, so that none of them renames one that another is still
+    // writing.
+    let tmp_path = path.with_extension(format!(
+        "json.{}-{:016x}.tmp",
+        std::process::id(),
+        fastrand::u64(..)
+    ));
+    let written = fs::write(&tmp_path, serde_json::to_vec(&file)?)
+        .with_context(|| format!("could not write {}", tmp_path.display()))
+        .and_then(|()| {
+            fs::rename(&tmp_path, &path)
+                .with_context(|| format!("could not write {}", path.display()))
+        });
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    written?;
+    log::debug!("saved the function body scans to {}", path.display());
+    Ok(false)
+}
+
+fn cache_path(module: &ParsedModule, dir: &Path) -> PathBuf {
+    let hash: String = Sha256::digest(module.buf)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    dir.join(format!("{}.json", hash))
+}
+
+fn load(module: &ParsedModule, path: &Path) -> Result<Option<Vec<BodyScan>>> {
+    let Ok(text) = fs::read(path) else {
+        return Ok(None);
+    };
+    let file: CacheFile = serde_json::from_slice(&text)?;
+    if file.version != env!("CARGO_PKG_VERSION") || file.scan_version != SCAN_VERSION {
+        return Ok(None);
+    }
+    if file.funcs.len() != module.defined_funcs.len() {
+        anyhow::bail!(
+            "it has {} functions, but the module has {}",
+            file.funcs.len(),
+            module.defined_funcs.len()
+        );
+    }
+    file.funcs
+        .into_iter()
+        .map(|func| {
+            let mut uses = Uses::default();
+            for item in &func.uses {
+                uses.insert(item.parse::<WorkItem>()?);
+            }
+            Ok(BodyScan {
+                uses,
+                indirect_calls: func.indirect_calls,
+                func_refs: func.func_refs,
+                memory_accesses: MemoryAccesses {
+                    constant: func.constant_accesses,
+                    computed: func.computed_accesses.into_iter().collect(),
+                },
+                num_operators: func.num_operators,
+            })
+        })
+        .collect::<Result<_>>()
+        .map(Some)
+}
@@ -0,0 +1,74 @@
+//! Reading and writing modules that web asset pipelines have compressed.
+
+use std::io::{Read, Write};
+
+use anyhow::{Context, Result};
+
+/// The first bytes of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CompressionFormat {
+    Gzip,
+    Brotli,
+}
+
+/// Works out how an input is compressed, if it is. Gzip is recognized by its header, but brotli
+/// has none, so it is only assumed for files ending in `.br`.
+pub fn compression_of(bytes: &[u8], filename: &str) -> Option<CompressionFormat> {
+    if bytes.starts_with(&GZIP_MAGIC) {
+        Some(CompressionFormat::Gzip)
+    } else if filename.ends_with(".br") && !bytes.starts_with(b"\0asm") {
+        Some(CompressionFormat::Brotli)
+    } else {
+        None
+    }
+}
+
+/// Undoes any compression on an input, as recognized by [`compression_of`].
+pub fn decompress(bytes: Vec<u8>, filename: &str) -> Result<Vec<u8>> {
+    match compression_of(&bytes, filename) {
+        Some(CompressionFormat::Gzip) => {
+            let mut res = vec![];
+            flate2::read::MultiGzDecoder::new(bytes.as_slice())
+                .read_to_end(&mut res)
+                .with_context(|| format!("could not decompress {} as gzip", filename))?;
+            Ok(res)
+        }
+        Some(CompressionFormat::Brotli) => decompress_brotli(&bytes, filename),
+        None => Ok(bytes),
+    }
+}
+
+#[cfg(feature = "brotli")]
+fn decompress_brotli(bytes: &[u8], filename: &str) -> Result<Vec<u8>> {
+    let mut res = vec![];
+    brotli::Decompressor::new(bytes, 4096)
+        .read_to_end(&mut res)
+        .with_context(|| format!("could not decompress {} as brotli", filename))?;
+    Ok(res)
+}
+
+#[cfg(not(feature = "brotli"))]
+fn decompress_brotli(_bytes: &[u8], filename: &str) -> Result<Vec<u8>> {
+    anyhow::bail!(
+        "{} looks like it is compressed with brotli, which needs the brotli feature",
+        filename
+    )
+}
+
+pub fn gzip(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::best());
+    encoder.write_all(bytes)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(feature = "brotli")]
+pub fn brotli(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut res = vec![];
+    {
+        let mut encoder = brotli::CompressorWriter::new(&mut res, 4096, 11, 22);
+        encoder.write_all(bytes)?;
+    }
+    Ok(res)
+}
@@ -0,0 +1,147 @@
+//! Isolation jobs described in a TOML file, so that a long list of roots can be checked in and
+//! reviewed instead of living in a shell history. Everything in the file adds to what is given on
+//! the command line.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::demangle;
+use crate::liveness::WorkItem;
+use crate::module::ParsedModule;
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// The module to isolate, if not given on the command line.
+    pub input: Option<String>,
+    pub roots: Roots,
+    /// Functions to keep as unreachable stubs, by index or name.
+    pub cut: Vec<ItemRef>,
+    pub custom_sections: CustomSections,
+    pub output: Output,
+    pub standalone: bool,
+}
+
+/// The items to isolate. Every item can be given by index or by name.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Roots {
+    pub types: Vec<ItemRef>,
+    pub funcs: Vec<ItemRef>,
+    pub tables: Vec<ItemRef>,
+    pub globals: Vec<ItemRef>,
+    pub memories: Vec<ItemRef>,
+    pub datas: Vec<ItemRef>,
+    pub elems: Vec<ItemRef>,
+    pub tags: Vec<ItemRef>,
+    /// Exported items of any kind, by export name.
+    pub exports: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum ItemRef {
+    Index(u32),
+    Name(String),
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CustomSections {
+    pub keep: Vec<String>,
+    pub strip: Vec<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Output {
+    /// Where to write the isolated module.
+    pub out: Option<String>,
+    /// The path prefix for the artifacts in `emit`.
+    pub prefix: Option<String>,
+    /// The artifacts to produce, named as for `--emit`.
+    pub emit: Vec<String>,
+}
+
+impl Config {
+    /// Parses a config from TOML text, leaving its paths as they are.
+    pub fn parse(text: &str) -> Result<Config> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Reads a config file. Paths in the file are relative to the directory it is in.
+    pub fn load(path: &str) -> Result<Config> {
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("could not read {}", path))?;
+        let mut config =
+            Config::parse(&text).with_context(|| format!("could not parse {}", path))?;
+
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        let resolve = |file: &mut Option<String>| {
+            if let Some(file) = file {
+                if file != "-" {
+                    *file = dir.join(&*file).to_string_lossy().into_owned();
+                }
+            }
+        };
+        resolve(&mut config.input);
+        resolve(&mut config.output.out);
+        resolve(&mut config.output.prefix);
+        Ok(config)
+    }
+}
+
+impl Roots {
+    /// Finds every item listed, including the exports.
+    pub fn resolve(&self, module: &ParsedModule) -> Result<Vec<WorkItem>> {
+        let kinds = [
+            (&self.types, WorkItem::Type as fn(u32) -> WorkItem),
+            (&self.funcs, WorkItem::Func),
+            (&self.tables, WorkItem::Table),
+            (&self.globals, WorkItem::Global),
+            (&self.memories, WorkItem::Memory),
+            (&self.datas, WorkItem::Data),
+            (&self.elems, WorkItem::Elem),
+            (&self.tags, WorkItem::Tag),
+        ];
+        let mut res = vec![];
+        for (refs, make_item) in kinds {
+            for item in refs {
+                res.push(make_item(item.resolve(module, make_item)?));
+            }
+        }
+        for name in &self.exports {
+            let Some(item) = module.find_export(name) else {
+                anyhow::bail!("export \"{}\" was not found in the module", name);
+            };
+            res.push(item);
+        }
+        Ok(res)
+    }
+}
+
+impl ItemRef {
+    /// Finds the item this refers to. Names are looked up in the name section first, then in the
+    /// exports, and must belong to an item of the given kind. Mangled names also match their
+    /// demangled forms.
+    pub fn resolve(&self, module: &ParsedModule, make_item: fn(u32) -> WorkItem) -> Result<u32> {
+        let name = match self {
+            ItemRef::Index(idx) => return Ok(*idx),
+            ItemRef::Name(name) => name,
+        };
+        let kind = make_item(0).kind_name();
+        module
+            .all_items()
+            .into_iter()
+            .find(|item| {
+                item.kind_name() == kind
+                    && module
+                        .item_name(*item)
+                        .is_some_and(|item_name| demangle::name_matches(item_name, name))
+            })
+            .map(|item| item.index())
+            .with_context(|| format!("no {} named \"{}\" was found", kind, name))
+    }
+}
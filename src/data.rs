@@ -0,0 +1,418 @@
+//! Working out what active data segments put into memory, and which parts of it are used.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use anyhow::Result;
+use wasmparser::{ConstExpr, DataKind, MemArg, Operator};
+
+use crate::error::{Error, ErrorKind};
+use crate::index_set::IndexSet;
+use crate::module::ParsedModule;
+use crate::uses::Uses;
+
+/// Evaluates a constant expression that produces an address, such as the offset of a data
+/// segment. Globals are followed to their initializers, but imported globals have no known value.
+pub fn eval_address(module: &ParsedModule, expr: &ConstExpr) -> Result<u64> {
+    eval_address_inner(module, expr, 0)
+}
+
+fn eval_address_inner(module: &ParsedModule, expr: &ConstExpr, depth: u32) -> Result<u64> {
+    // Globals can only refer to earlier globals, so this only guards against malformed modules.
+    if depth > module.global_types.len() as u32 {
+        return Err(Error::new(
+            ErrorKind::InvalidModule,
+            "constant expression refers to itself",
+        )
+        .into());
+    }
+
+    let mut stack: Vec<u64> = vec![];
+    for op in expr.get_operators_reader() {
+        match op? {
+            Operator::I32Const { value } => stack.push(value as u32 as u64),
+            Operator::I64Const { value } => stack.push(value as u64),
+            Operator::GlobalGet { global_index } => {
+                if global_index < module.num_imported_globals {
+                    return Err(Error::new(
+                        ErrorKind::Unsupported,
+                        format!(
+                            "the address depends on imported global {}, whose value is unknown",
+                            global_index
+                        ),
+                    )
+                    .into());
+                }
+                let global =
+                    &module.defined_globals[(global_index - module.num_imported_globals) as usize];
+                stack.push(eval_address_inner(module, &global.init_expr, depth + 1)?);
+            }
+            op @ (Operator::I32Add
+            | Operator::I32Sub
+            | Operator::I32Mul
+            | Operator::I64Add
+            | Operator::I64Sub
+            | Operator::I64Mul) => {
+                let (Some(b), Some(a)) = (stack.pop(), stack.pop()) else {
+                    return Err(malformed());
+                };
+                stack.push(match op {
+                    Operator::I32Add => (a as u32).wrapping_add(b as u32) as u64,
+                    Operator::I32Sub => (a as u32).wrapping_sub(b as u32) as u64,
+                    Operator::I32Mul => (a as u32).wrapping_mul(b as u32) as u64,
+                    Operator::I64Add => a.wrapping_add(b),
+                    Operator::I64Sub => a.wrapping_sub(b),
+                    _ => a.wrapping_mul(b),
+                });
+            }
+            Operator::End => {}
+            op => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("cannot evaluate {:?} in a constant address", op),
+                )
+                .into())
+            }
+        }
+    }
+    match stack.as_slice() {
+        [value] => Ok(*value),
+        _ => Err(malformed()),
+    }
+}
+
+fn malformed() -> anyhow::Error {
+    Error::new(ErrorKind::InvalidModule, "malformed constant expression").into()
+}
+
+/// Builds the contents of a memory right after instantiation, by applying its active data
+/// segments in order. The image covers the memory's initial size.
+pub fn build_memory_image(module: &ParsedModule, memory_idx: u32) -> Result<Vec<u8>> {
+    let Some(ty) = module.memory_types.get(memory_idx as usize) else {
+        anyhow::bail!("memory {} is out of range", memory_idx);
+    };
+    let page_size = 1u64 << ty.page_size_log2.unwrap_or(16);
+    let Some(size) = ty
+        .initial
+        .checked_mul(page_size)
+        .and_then(|size| usize::try_from(size).ok())
+    else {
+        anyhow::bail!("memory {} is too big to build an image of", memory_idx);
+    };
+
+    let mut image = vec![0u8; size];
+    for (i, data) in module.datas.iter().enumerate() {
+        let DataKind::Active {
+            memory_index,
+            offset_expr,
+        } = &data.kind
+        else {
+            continue;
+        };
+        if *memory_index != memory_idx {
+            continue;
+        }
+        let offset = eval_address(module, offset_expr)?;
+        let end = offset.checked_add(data.data.len() as u64);
+        match end {
+            Some(end) if end <= size as u64 => {
+                image[offset as usize..end as usize].copy_from_slice(data.data);
+            }
+            _ => anyhow::bail!(
+                "data segment {} does not fit in the initial size of memory {}, so instantiation would fail",
+                i,
+                memory_idx
+            ),
+        }
+    }
+    Ok(image)
+}
+
+/// The bytes of each memory that kept code accesses at constant addresses, by memory index.
+pub type ConstantAccesses = BTreeMap<u32, Vec<Range<u64>>>;
+
+/// Which parts of each data segment to keep, relative to the start of the segment's data, by data
+/// segment index. Segments that aren't listed are kept whole.
+pub type DataTrims = BTreeMap<u32, Vec<Range<usize>>>;
+
+/// Pieces of a trimmed segment that are closer together than this are merged, so that a segment
+/// read a few bytes at a time doesn't turn into lots of tiny segments.
+const TRIM_GAP: u64 = 16;
+
+/// What a recently seen instruction left on the stack, for spotting constant addresses.
+#[derive(Clone, Copy)]
+enum Pushed {
+    Const(u64),
+    /// Some single value that isn't a constant.
+    Value,
+    Other,
+}
+
+/// How kept code accesses each memory.
+#[derive(Clone, Default, Debug)]
+pub struct MemoryAccesses {
+    /// The bytes accessed at constant addresses, by memory index.
+    pub constant: ConstantAccesses,
+    /// The memories that are also accessed at addresses computed at runtime, which could be
+    /// anywhere in them.
+    pub computed: BTreeSet<u32>,
+}
+
+/// Finds how the kept functions access memory, from the scans of their bodies. See
+/// [`AccessScanner`] for which accesses are found.
+pub fn find_memory_accesses(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    cut_funcs: &IndexSet,
+) -> Result<MemoryAccesses> {
+    let scans = module.body_scans()?;
+    let mut res = MemoryAccesses::default();
+    for idx in all_uses.live_funcs.iter() {
+        if idx < module.num_imported_functions || cut_funcs.contains(idx) {
+            continue;
+        }
+        let accesses = &scans[(idx - module.num_imported_functions) as usize].memory_accesses;
+        for (memory, ranges) in &accesses.constant {
+            res.constant
+                .entry(*memory)
+                .or_default()
+                .extend(ranges.iter().cloned());
+        }
+        res.computed.extend(&accesses.computed);
+    }
+    Ok(res)
+}
+
+/// Finds how a function accesses memory, one instruction at a time. Constant addresses are only
+/// seen when they are written right next to the instruction that uses them, like
+/// `i32.const 1024` followed by `i32.load`, or constant arguments to `memory.init`,
+/// `memory.copy`, and `memory.fill`. Any other access to a memory counts as computed at runtime.
+///
+/// Addresses in 64-bit memories are `i64.const`, and static offsets can be bigger than 32 bits,
+/// so accesses are tracked as 64-bit ranges. Ranges that would run past the end of the address
+/// space stop at its end instead, since an access like that traps anyway.
+pub struct AccessScanner {
+    /// The last three things pushed, most recent last.
+    recent: [Pushed; 3],
+    accesses: MemoryAccesses,
+}
+
+impl Default for AccessScanner {
+    fn default() -> Self {
+        AccessScanner {
+            recent: [Pushed::Other; 3],
+            accesses: MemoryAccesses::default(),
+        }
+    }
+}
+
+impl AccessScanner {
+    /// Looks at the next instruction, given what it refers to.
+    pub fn visit(&mut self, op: &Operator, uses: &Uses) {
+        let res = &mut self.accesses.constant;
+        let constant = match (op, self.recent) {
+            (_, [_, _, Pushed::Const(addr)]) if load_width(op).is_some() => {
+                let (memarg, width) = load_width(op).unwrap();
+                let start = addr.wrapping_add(memarg.offset);
+                res.entry(memarg.memory)
+                    .or_default()
+                    .push(start..start.saturating_add(width));
+                true
+            }
+            (_, [_, Pushed::Const(addr), Pushed::Const(_) | Pushed::Value])
+                if store_width(op).is_some() =>
+            {
+                let (memarg, width) = store_width(op).unwrap();
+                let start = addr.wrapping_add(memarg.offset);
+                res.entry(memarg.memory)
+                    .or_default()
+                    .push(start..start.saturating_add(width));
+                true
+            }
+            (
+                Operator::MemoryInit { mem, .. } | Operator::MemoryFill { mem },
+                [Pushed::Const(dst), _, Pushed::Const(len)],
+            ) => {
+                res.entry(*mem)
+                    .or_default()
+                    .push(dst..dst.saturating_add(len));
+                true
+            }
+            (
+                Operator::MemoryCopy { dst_mem, src_mem },
+                [Pushed::Const(dst), Pushed::Const(src), Pushed::Const(len)],
+            ) => {
+                res.entry(*dst_mem)
+                    .or_default()
+                    .push(dst..dst.saturating_add(len));
+                res.entry(*src_mem)
+                    .or_default()
+                    .push(src..src.saturating_add(len));
+                true
+            }
+            // These use the memory without touching its contents.
+            (Operator::MemorySize { .. } | Operator::MemoryGrow { .. }, _) => true,
+            _ => false,
+        };
+        if !constant {
+            self.accesses.computed.extend(uses.live_memories.iter());
+        }
+
+        let pushed = match op {
+            Operator::I32Const { value } => Pushed::Const(*value as u32 as u64),
+            Operator::I64Const { value } => Pushed::Const(*value as u64),
+            Operator::LocalGet { .. } | Operator::GlobalGet { .. } => Pushed::Value,
+            _ => Pushed::Other,
+        };
+        self.recent = [self.recent[1], self.recent[2], pushed];
+    }
+
+    pub fn finish(self) -> MemoryAccesses {
+        self.accesses
+    }
+}
+
+/// Works out which parts of the kept active data segments are covered by the given accesses.
+/// Segments whose offset isn't a known constant are left alone, and so are the segments of
+/// memories that kept code also accesses at computed addresses, since those could read any byte.
+pub fn plan_data_trims(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    accesses: &MemoryAccesses,
+) -> DataTrims {
+    let mut res = DataTrims::new();
+    for idx in all_uses.live_datas.iter() {
+        let data = &module.datas[idx as usize];
+        let DataKind::Active {
+            memory_index,
+            offset_expr,
+        } = &data.kind
+        else {
+            continue;
+        };
+        // The memory may have been dropped, in which case the segment is made passive and kept
+        // whole.
+        if !all_uses.live_memories.contains(*memory_index) {
+            continue;
+        }
+        if accesses.computed.contains(memory_index) {
+            continue;
+        }
+        let Ok(offset) = eval_address(module, offset_expr) else {
+            continue;
+        };
+        let len = data.data.len() as u64;
+
+        let mut pieces: Vec<Range<u64>> = accesses
+            .constant
+            .get(memory_index)
+            .into_iter()
+            .flatten()
+            .filter_map(|access| {
+                let start = access.start.max(offset);
+                let end = access.end.min(offset.saturating_add(len));
+                (start < end).then(|| start - offset..end - offset)
+            })
+            .collect();
+        pieces.sort_by_key(|piece| piece.start);
+
+        let mut merged: Vec<Range<usize>> = vec![];
+        for piece in pieces {
+            match merged.last_mut() {
+                Some(last) if piece.start <= last.end as u64 + TRIM_GAP => {
+                    last.end = last.end.max(piece.end as usize);
+                }
+                _ => merged.push(piece.start as usize..piece.end as usize),
+            }
+        }
+        let whole = merged.len() == 1 && merged[0] == (0..data.data.len());
+        if !whole {
+            res.insert(idx, merged);
+        }
+    }
+    res
+}
+
+/// The size of a load and its memory argument.
+fn load_width(op: &Operator) -> Option<(MemArg, u64)> {
+    Some(match op {
+        Operator::I32Load8S { memarg }
+        | Operator::I32Load8U { memarg }
+        | Operator::I64Load8S { memarg }
+        | Operator::I64Load8U { memarg } => (*memarg, 1),
+        Operator::I32Load16S { memarg }
+        | Operator::I32Load16U { memarg }
+        | Operator::I64Load16S { memarg }
+        | Operator::I64Load16U { memarg } => (*memarg, 2),
+        Operator::I32Load { memarg }
+        | Operator::F32Load { memarg }
+        | Operator::I64Load32S { memarg }
+        | Operator::I64Load32U { memarg } => (*memarg, 4),
+        Operator::I64Load { memarg } | Operator::F64Load { memarg } => (*memarg, 8),
+        Operator::V128Load { memarg } => (*memarg, 16),
+        _ => return None,
+    })
+}
+
+/// The size of a store and its memory argument.
+fn store_width(op: &Operator) -> Option<(MemArg, u64)> {
+    Some(match op {
+        Operator::I32Store8 { memarg } | Operator::I64Store8 { memarg } => (*memarg, 1),
+        Operator::I32Store16 { memarg } | Operator::I64Store16 { memarg } => (*memarg, 2),
+        Operator::I32Store { memarg }
+        | Operator::F32Store { memarg }
+        | Operator::I64Store32 { memarg } => (*memarg, 4),
+        Operator::I64Store { memarg } | Operator::F64Store { memarg } => (*memarg, 8),
+        Operator::V128Store { memarg } => (*memarg, 16),
+        _ => return None,
+    })
+}
+
+/// Works out how many pages each kept memory needs to start with so that the kept active data
+/// segments still fit, taking any trimming into account. Memories are never made bigger than they
+/// were, and memories with segments at unknown offsets are left alone.
+pub fn clamp_memory_minimums(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    data_trims: Option<&DataTrims>,
+) -> BTreeMap<u32, u64> {
+    let mut ends: BTreeMap<u32, Option<u64>> = all_uses
+        .live_memories
+        .iter()
+        .map(|idx| (idx, Some(0)))
+        .collect();
+    for idx in all_uses.live_datas.iter() {
+        let data = &module.datas[idx as usize];
+        let DataKind::Active {
+            memory_index,
+            offset_expr,
+        } = &data.kind
+        else {
+            continue;
+        };
+        let Some(end) = ends.get_mut(memory_index) else {
+            continue;
+        };
+        let Ok(offset) = eval_address(module, offset_expr) else {
+            *end = None;
+            continue;
+        };
+        // A trimmed segment that keeps nothing still has to start within the memory.
+        let len = match data_trims.and_then(|trims| trims.get(&idx)) {
+            Some(pieces) => pieces.last().map_or(0, |piece| piece.end),
+            None => data.data.len(),
+        };
+        *end = end.map(|end| end.max(offset.saturating_add(len as u64)));
+    }
+
+    let mut res = BTreeMap::new();
+    for (idx, end) in ends {
+        let Some(end) = end else {
+            continue;
+        };
+        let ty = &module.memory_types[idx as usize];
+        let page_size = 1u64 << ty.page_size_log2.unwrap_or(16);
+        res.insert(idx, end.div_ceil(page_size).min(ty.initial));
+    }
+    res
+}
@@ -0,0 +1,62 @@
+//! Demangling Rust and Itanium C++ symbols, which is how most toolchains name functions in the name
+//! section, so that people can refer to functions the way they appear in source.
+
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use cpp_demangle::{DemangleOptions, Symbol};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turns demangling on or off for names in human-readable output. It is off by default, so output
+/// shows names as they are in the module. Selecting functions by their demangled names works
+/// either way.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Gets the name to show people for a symbol, which is demangled if demangling is turned on.
+pub fn for_display(name: &str) -> Cow<'_, str> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Cow::Borrowed(name);
+    }
+    match demangle(name) {
+        Some(demangled) => Cow::Owned(demangled),
+        None => Cow::Borrowed(name),
+    }
+}
+
+/// Demangles a Rust (legacy or v0) or Itanium C++ symbol, or returns `None` if the name isn't
+/// mangled. The hash at the end of legacy Rust symbols is left out.
+pub fn demangle(name: &str) -> Option<String> {
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        return Some(format!("{:#}", demangled));
+    }
+    if !name.starts_with("_Z") {
+        return None;
+    }
+    Symbol::new(name).ok()?.demangle().ok()
+}
+
+/// Checks whether a name from the module is the one someone asked for. Besides the name itself,
+/// this accepts the demangled name, a Rust name with its hash, and a C++ name without its
+/// parameter list, e.g. `ns::func` for `ns::func(int)`.
+pub fn name_matches(name: &str, spec: &str) -> bool {
+    if name == spec {
+        return true;
+    }
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        return format!("{:#}", demangled) == spec || demangled.to_string() == spec;
+    }
+    if !name.starts_with("_Z") {
+        return false;
+    }
+    let Ok(symbol) = Symbol::new(name) else {
+        return false;
+    };
+    let no_params = DemangleOptions::new().no_params().no_return_type();
+    symbol.demangle().is_ok_and(|demangled| demangled == spec)
+        || symbol
+            .demangle_with_options(&no_params)
+            .is_ok_and(|demangled| demangled == spec)
+}
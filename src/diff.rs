@@ -0,0 +1,144 @@
+//! Comparing the index spaces of two modules, such as an original module and what isolation made
+//! of it, or two builds of the same program.
+
+use std::collections::HashMap;
+
+use crate::liveness::WorkItem;
+use crate::module::ParsedModule;
+use crate::schema::{self, SCHEMA_VERSION};
+use crate::wat;
+
+pub struct IndexDiff {
+    pub kind: &'static str,
+    pub name: Option<String>,
+    pub change: IndexChange,
+}
+
+/// A change to an item, with the item as it is in the old module, the new one, or both.
+pub enum IndexChange {
+    Added { new: WorkItem },
+    Removed { old: WorkItem },
+    Renumbered { old: WorkItem, new: WorkItem },
+}
+
+/// What an item is matched on: its name if it has one, and otherwise its type. Items with the
+/// same key are matched in index order.
+#[derive(PartialEq, Eq, Hash)]
+enum Key<'a> {
+    Name(&'a str),
+    Type(Option<String>),
+}
+
+/// Finds the items in each module's types, functions, tables, memories, globals, and tags that
+/// were added, removed, or moved to a new index. Items are matched by name where they have one,
+/// from the name section or the exports, and otherwise by their type, in index order.
+pub fn diff_modules(old: &ParsedModule, new: &ParsedModule) -> Vec<IndexDiff> {
+    let mut res: Vec<IndexDiff> = vec![];
+    let spaces: [fn(u32) -> WorkItem; 6] = [
+        WorkItem::Type,
+        WorkItem::Func,
+        WorkItem::Table,
+        WorkItem::Memory,
+        WorkItem::Global,
+        WorkItem::Tag,
+    ];
+    for make in spaces {
+        let kind = make(0).kind_name();
+        let old_keyed = keyed_items(old, kind);
+        let mut new_keyed = keyed_items(new, kind);
+
+        for (key, old_items) in &old_keyed {
+            let new_items = new_keyed.remove(key).unwrap_or_default();
+            for (i, old_idx) in old_items.iter().enumerate() {
+                let change = match new_items.get(i) {
+                    Some(new_idx) if new_idx == old_idx => continue,
+                    Some(new_idx) => IndexChange::Renumbered {
+                        old: make(*old_idx),
+                        new: make(*new_idx),
+                    },
+                    None => IndexChange::Removed {
+                        old: make(*old_idx),
+                    },
+                };
+                res.push(IndexDiff {
+                    kind,
+                    name: old.item_name(make(*old_idx)).map(|name| name.to_string()),
+                    change,
+                });
+            }
+            for new_idx in new_items.iter().skip(old_items.len()) {
+                res.push(IndexDiff {
+                    kind,
+                    name: new.item_name(make(*new_idx)).map(|name| name.to_string()),
+                    change: IndexChange::Added {
+                        new: make(*new_idx),
+                    },
+                });
+            }
+        }
+        for new_idx in new_keyed.into_values().flatten() {
+            res.push(IndexDiff {
+                kind,
+                name: new.item_name(make(new_idx)).map(|name| name.to_string()),
+                change: IndexChange::Added { new: make(new_idx) },
+            });
+        }
+    }
+
+    // Keep each index space together, with the changes to existing items in the order of their
+    // old indices, followed by the added items in the order of their new indices.
+    let order = |diff: &IndexDiff| match diff.change {
+        IndexChange::Removed { old } | IndexChange::Renumbered { old, .. } => (0, old.index()),
+        IndexChange::Added { new } => (1, new.index()),
+    };
+    let space = |kind: &str| {
+        spaces
+            .iter()
+            .position(|make| make(0).kind_name() == kind)
+            .unwrap_or(0)
+    };
+    res.sort_by_key(|diff| (space(diff.kind), order(diff)));
+    res
+}
+
+/// Groups the items of one kind by what they are matched on, each group in index order.
+fn keyed_items<'a>(module: &'a ParsedModule, kind: &str) -> HashMap<Key<'a>, Vec<u32>> {
+    let mut res: HashMap<Key, Vec<u32>> = HashMap::new();
+    for item in module.all_items() {
+        if item.kind_name() != kind {
+            continue;
+        }
+        let key = match module.item_name(item) {
+            Some(name) => Key::Name(name),
+            None => Key::Type(wat::item_type(module, item)),
+        };
+        res.entry(key).or_default().push(item.index());
+    }
+    res
+}
+
+/// Describes the differences between two modules in the JSON format.
+pub fn build_index_diffs(diffs: &[IndexDiff]) -> schema::IndexDiffs {
+    schema::IndexDiffs {
+        schema: SCHEMA_VERSION,
+        diffs: diffs
+            .iter()
+            .map(|diff| {
+                let (change, old_index, new_index) = match diff.change {
+                    IndexChange::Added { new } => ("added", None, Some(new.index())),
+                    IndexChange::Removed { old } => ("removed", Some(old.index()), None),
+                    IndexChange::Renumbered { old, new } => {
+                        ("renumbered", Some(old.index()), Some(new.index()))
+                    }
+                };
+                schema::IndexDiffEntry {
+                    kind: diff.kind.to_string(),
+                    name: diff.name.clone(),
+                    change: change.to_string(),
+                    old_index,
+                    new_index,
+                }
+            })
+            .collect(),
+    }
+}
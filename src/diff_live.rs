@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+
+use crate::liveness::*;
+use crate::module::ParsedModule;
+use crate::schema::{self, SCHEMA_VERSION};
+
+pub struct LiveDiff {
+    pub kind: &'static str,
+    /// The item's name if it has one, or otherwise its index (e.g. `#12`).
+    pub key: String,
+    pub change: LiveChange,
+}
+
+pub enum LiveChange {
+    Added { size: Option<usize> },
+    Removed { size: Option<usize> },
+    Resized { old: usize, new: usize },
+}
+
+/// Computes the live closure of the given exports in two versions of a module, and reports which
+/// items appear, disappear, or change size between them. Items are matched by name where
+/// possible, and by index otherwise.
+pub fn diff_live(
+    old: &ParsedModule,
+    new: &ParsedModule,
+    exports: &[String],
+    options: &LivenessOptions,
+) -> Result<Vec<LiveDiff>> {
+    let old_items = live_closure_by_key(old, exports, options)?;
+    let new_items = live_closure_by_key(new, exports, options)?;
+
+    let mut res: Vec<LiveDiff> = vec![];
+    for ((kind, key), old_size) in &old_items {
+        match new_items.get(&(*kind, key.clone())) {
+            None => res.push(LiveDiff {
+                kind,
+                key: key.clone(),
+                change: LiveChange::Removed { size: *old_size },
+            }),
+            Some(new_size) => {
+                if let (Some(old_size), Some(new_size)) = (old_size, new_size) {
+                    if old_size != new_size {
+                        res.push(LiveDiff {
+                            kind,
+                            key: key.clone(),
+                            change: LiveChange::Resized {
+                                old: *old_size,
+                                new: *new_size,
+                            },
+                        });
+                    }
+                }
+            }
+        }
+    }
+    for ((kind, key), new_size) in &new_items {
+        if !old_items.contains_key(&(*kind, key.clone())) {
+            res.push(LiveDiff {
+                kind,
+                key: key.clone(),
+                change: LiveChange::Added { size: *new_size },
+            });
+        }
+    }
+    Ok(res)
+}
+
+fn live_closure_by_key(
+    module: &ParsedModule,
+    exports: &[String],
+    options: &LivenessOptions,
+) -> Result<BTreeMap<(&'static str, String), Option<usize>>> {
+    let mut roots: Vec<WorkItem> = vec![];
+    for name in exports {
+        let Some(item) = module.find_export(name) else {
+            anyhow::bail!("export \"{}\" was not found in the module", name);
+        };
+        roots.push(item);
+    }
+    let live = find_live(module, roots, options)?;
+
+    let mut res = BTreeMap::new();
+    for item in live_items(&live) {
+        let key = match module.item_name(item) {
+            Some(name) => name.to_string(),
+            None => format!("#{}", item.index()),
+        };
+        res.insert((item.kind_name(), key), module.item_size(item));
+    }
+    Ok(res)
+}
+
+/// Describes a set of differences in the JSON format.
+pub fn build_live_diffs(diffs: &[LiveDiff]) -> schema::LiveDiffs {
+    schema::LiveDiffs {
+        schema: SCHEMA_VERSION,
+        diffs: diffs
+            .iter()
+            .map(|diff| {
+                let (change, old_size, new_size) = match diff.change {
+                    LiveChange::Added { size } => ("added", None, size),
+                    LiveChange::Removed { size } => ("removed", size, None),
+                    LiveChange::Resized { old, new } => ("resized", Some(old), Some(new)),
+                };
+                schema::LiveDiffEntry {
+                    kind: diff.kind.to_string(),
+                    key: diff.key.clone(),
+                    change: change.to_string(),
+                    old_size,
+                    new_size,
+                }
+            })
+            .collect(),
+    }
+}
@@ -0,0 +1,2150 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+use anyhow::Result;
+use globset::{Glob, GlobMatcher, GlobSet, GlobSetBuilder};
+use rayon::prelude::*;
+use wasm_encoder::{
+    reencode::Reencode, BranchHint, BranchHints, CodeSection, ConstExpr, CustomSection,
+    DataSection, DataSegment, DataSegmentMode, ElementMode, ElementSection, ElementSegment,
+    Elements, Encode, EntityType, ExportSection, Function, FunctionSection, GlobalSection,
+    ImportSection, IndirectNameMap, Instruction, MemorySection, Module, NameMap, NameSection,
+    ProducersField, ProducersSection, TableSection, TagSection, TypeSection,
+};
+
+use crate::artifacts::build_summary;
+use crate::data::{eval_address, DataTrims};
+use crate::error::{Error, ErrorKind};
+use crate::index_set::IndexSet;
+use crate::liveness::{
+    find_live, get_elem_funcs, get_item_uses, live_items, BodyScan, LivenessOptions, WorkItem,
+};
+use crate::module::{export_item, CodeMetadata, CodeMetadataEntry, Func, ParsedModule, Section};
+use crate::relocation::*;
+use crate::stats::build_report;
+use crate::uses::{
+    get_constexpr_uses, get_globaltype_uses, get_tabletype_uses, get_tagtype_uses, get_type_uses,
+    Uses,
+};
+
+pub struct EmitOptions<'a> {
+    /// The items explicitly requested by the user, which get extra exports so they are easy to
+    /// test in isolation.
+    pub requested: &'a Uses,
+    /// Functions whose bodies are replaced with `unreachable`.
+    pub cut_funcs: &'a IndexSet,
+    /// Items left out even though kept code refers to them, which is patched to do without them.
+    pub dropped: &'a Uses,
+    /// The exact set of exports to emit, if any.
+    pub api: Option<&'a [String]>,
+    /// Whether to also emit the exports of the functions in kept tables' active element segments,
+    /// even if the API file doesn't list them.
+    pub chase_exports: bool,
+    /// Whether to replace imports with definitions, so the output can be instantiated without a
+    /// host. Imported functions become stubs that trap.
+    pub standalone: bool,
+    /// Whether to export the start function as `_initialize` instead of running it on
+    /// instantiation.
+    pub demote_start: bool,
+    /// Whether to drop the linking and relocation sections of object files instead of refusing to
+    /// isolate them.
+    pub strip_object_metadata: bool,
+    /// Which custom sections to carry through to the output.
+    pub custom_sections: &'a CustomSectionFilter,
+    /// Whether to add exports for the requested items.
+    pub synthetic_exports: bool,
+    /// Whether to add exports for every kept item rather than just the requested ones.
+    pub export_all_kept: bool,
+    /// What the names of the added exports start with, before the item's name or, if it has none,
+    /// its kind and index.
+    pub export_prefix: &'a str,
+    /// Parts of active data segments to keep, with the rest of their bytes left out.
+    pub data_trims: Option<&'a DataTrims>,
+    /// New initial page counts for memories, by their index in the original module.
+    pub memory_minimums: &'a BTreeMap<u32, u64>,
+    /// Whether to make active data and element segments passive, and apply them from an exported
+    /// init function instead of at instantiation.
+    pub passive_init: bool,
+    /// Whether every data segment has been dropped, in which case instructions that refer to them
+    /// are replaced with ones that do nothing.
+    pub no_data: bool,
+    /// New names for imports. The first rule that matches an import applies.
+    pub import_renames: &'a [ImportRename],
+    /// Which exports to rename or drop, including the ones that isolation adds.
+    pub export_filter: &'a ExportFilter,
+    /// Whether to keep the name section, with its names moved to their items' new indices.
+    pub keep_names: bool,
+    /// Whether to keep the producers section, with wasm-isolate added to its processed-by field.
+    pub producers: bool,
+    /// Whether to emit a data count section. By default, one is emitted only if kept code uses
+    /// data segments by index, which is the only time validation needs it.
+    pub data_count: Option<bool>,
+    /// Whether to reencode every section and function body, even the ones that isolation leaves
+    /// unchanged and that would otherwise be copied as they are. Custom sections are still copied.
+    pub reencode: bool,
+    /// Items that nothing needs but that are kept anyway, so that every other item keeps its
+    /// original index. Functions become stubs that trap and segments become empty passive ones.
+    /// Nothing exports or starts them.
+    pub placeholders: &'a Uses,
+}
+
+impl Default for EmitOptions<'_> {
+    /// The options of the command line when none of its flags are given, with nothing requested.
+    fn default() -> Self {
+        static NO_USES: LazyLock<Uses> = LazyLock::new(Uses::default);
+        static ALL_CUSTOM_SECTIONS: LazyLock<CustomSectionFilter> =
+            LazyLock::new(CustomSectionFilter::default);
+        static ALL_EXPORTS: LazyLock<ExportFilter> = LazyLock::new(ExportFilter::default);
+        static NO_MEMORY_MINIMUMS: BTreeMap<u32, u64> = BTreeMap::new();
+        static NO_CUT_FUNCS: LazyLock<IndexSet> = LazyLock::new(IndexSet::new);
+        EmitOptions {
+            requested: &NO_USES,
+            cut_funcs: &NO_CUT_FUNCS,
+            dropped: &NO_USES,
+            api: None,
+            chase_exports: false,
+            standalone: false,
+            demote_start: false,
+            strip_object_metadata: false,
+            custom_sections: &ALL_CUSTOM_SECTIONS,
+            synthetic_exports: true,
+            export_all_kept: false,
+            export_prefix: "isolated_",
+            data_trims: None,
+            memory_minimums: &NO_MEMORY_MINIMUMS,
+            passive_init: false,
+            no_data: false,
+            import_renames: &[],
+            export_filter: &ALL_EXPORTS,
+            keep_names: false,
+            producers: true,
+            data_count: None,
+            reencode: false,
+            placeholders: &NO_USES,
+        }
+    }
+}
+
+impl EmitOptions<'_> {
+    /// Whether a function's body is replaced with `unreachable`.
+    pub fn is_stub(&self, idx: u32) -> bool {
+        self.cut_funcs.contains(idx) || self.placeholders.live_funcs.contains(idx)
+    }
+}
+
+/// The name of the function that `--passive-init` adds.
+pub const INIT_FUNC_NAME: &str = "__wasm_isolate_init";
+
+/// Picks custom sections to keep by matching their names against globs.
+#[derive(Default)]
+pub struct CustomSectionFilter {
+    /// If set, only custom sections matching one of these are kept.
+    keep: Option<GlobSet>,
+    /// Custom sections matching any of these are dropped, even if they also match `keep`.
+    strip: GlobSet,
+}
+
+impl CustomSectionFilter {
+    pub fn new(keep: &[String], strip: &[String]) -> Result<Self> {
+        fn build(globs: &[String]) -> Result<GlobSet> {
+            let mut builder = GlobSetBuilder::new();
+            for glob in globs {
+                builder.add(Glob::new(glob)?);
+            }
+            Ok(builder.build()?)
+        }
+        Ok(Self {
+            keep: if keep.is_empty() {
+                None
+            } else {
+                Some(build(keep)?)
+            },
+            strip: build(strip)?,
+        })
+    }
+
+    pub fn keeps(&self, name: &str) -> bool {
+        self.keep.as_ref().is_none_or(|keep| keep.is_match(name)) && !self.strip.is_match(name)
+    }
+}
+
+/// Gives imports a new module or name. Written as `module::name=new_module::new_name`, where the
+/// module and name to match can be globs, and a `*` on the right keeps that part as it was. For
+/// example, `env::*=host::*` moves every import from `env` to `host`.
+#[derive(Clone, Debug)]
+pub struct ImportRename {
+    module: GlobMatcher,
+    name: GlobMatcher,
+    new_module: Option<String>,
+    new_name: Option<String>,
+}
+
+impl ImportRename {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let Some((from, to)) = spec.split_once('=') else {
+            anyhow::bail!(
+                "expected a rename like \"env::foo=host::bar\", but got \"{}\"",
+                spec
+            );
+        };
+        let (Some((module, name)), Some((new_module, new_name))) =
+            (from.split_once("::"), to.split_once("::"))
+        else {
+            anyhow::bail!(
+                "both sides of \"{}\" need a module and a name, separated by \"::\"",
+                spec
+            );
+        };
+        let keep = |part: &str| (part != "*").then(|| part.to_string());
+        Ok(Self {
+            module: Glob::new(module)?.compile_matcher(),
+            name: Glob::new(name)?.compile_matcher(),
+            new_module: keep(new_module),
+            new_name: keep(new_name),
+        })
+    }
+}
+
+/// The module and name an import ends up with.
+fn rename_import<'a>(
+    renames: &'a [ImportRename],
+    import: &'a wasmparser::Import,
+) -> (&'a str, &'a str) {
+    let rename = renames
+        .iter()
+        .find(|rename| rename.module.is_match(import.module) && rename.name.is_match(import.name));
+    match rename {
+        Some(rename) => (
+            rename.new_module.as_deref().unwrap_or(import.module),
+            rename.new_name.as_deref().unwrap_or(import.name),
+        ),
+        None => (import.module, import.name),
+    }
+}
+
+/// Renames and drops exports of the isolated module. Exports are dropped by the names they would
+/// otherwise have, before any renaming.
+#[derive(Default)]
+pub struct ExportFilter {
+    strip: GlobSet,
+    renames: Vec<(String, String)>,
+}
+
+impl ExportFilter {
+    pub fn new(strip: &[String], renames: &[(String, String)]) -> Result<Self> {
+        let mut builder = GlobSetBuilder::new();
+        for glob in strip {
+            builder.add(Glob::new(glob)?);
+        }
+        Ok(Self {
+            strip: builder.build()?,
+            renames: renames.to_vec(),
+        })
+    }
+
+    /// The name an export ends up with, or `None` if it is dropped.
+    pub fn apply<'a>(&'a self, name: &'a str) -> Option<&'a str> {
+        if self.strip.is_match(name) {
+            return None;
+        }
+        Some(
+            self.renames
+                .iter()
+                .find(|(old, _)| old == name)
+                .map_or(name, |(_, new)| new),
+        )
+    }
+}
+
+/// One of the index spaces of a module, named in the plural, like `funcs`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IndexSpace {
+    Types,
+    Funcs,
+    Tables,
+    Memories,
+    Globals,
+    Tags,
+    Elems,
+    Datas,
+}
+
+impl IndexSpace {
+    pub const ALL: [IndexSpace; 8] = [
+        IndexSpace::Types,
+        IndexSpace::Funcs,
+        IndexSpace::Tables,
+        IndexSpace::Memories,
+        IndexSpace::Globals,
+        IndexSpace::Tags,
+        IndexSpace::Elems,
+        IndexSpace::Datas,
+    ];
+
+    pub fn of(item: WorkItem) -> IndexSpace {
+        match item {
+            WorkItem::Type(_) => IndexSpace::Types,
+            WorkItem::Func(_) => IndexSpace::Funcs,
+            WorkItem::Table(_) => IndexSpace::Tables,
+            WorkItem::Memory(_) => IndexSpace::Memories,
+            WorkItem::Global(_) => IndexSpace::Globals,
+            WorkItem::Tag(_) => IndexSpace::Tags,
+            WorkItem::Elem(_) => IndexSpace::Elems,
+            WorkItem::Data(_) => IndexSpace::Datas,
+        }
+    }
+}
+
+impl FromStr for IndexSpace {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "types" => IndexSpace::Types,
+            "funcs" => IndexSpace::Funcs,
+            "tables" => IndexSpace::Tables,
+            "memories" => IndexSpace::Memories,
+            "globals" => IndexSpace::Globals,
+            "tags" => IndexSpace::Tags,
+            "elems" => IndexSpace::Elems,
+            "datas" => IndexSpace::Datas,
+            _ => anyhow::bail!(
+                "unknown index space \"{}\" (expected types, funcs, tables, memories, globals, tags, elems, or datas)",
+                s
+            ),
+        })
+    }
+}
+
+/// Lists the items that aren't live in the given index spaces, to keep as placeholders so that no
+/// index in those spaces changes. Placeholders keep their declarations, so whatever those refer
+/// to is kept as a placeholder too, even in other index spaces.
+pub fn placeholder_items(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    spaces: &[IndexSpace],
+) -> Result<Uses> {
+    let mut queue: Vec<WorkItem> = vec![];
+    let mut add = |make: fn(u32) -> WorkItem, len: usize| {
+        if spaces.contains(&IndexSpace::of(make(0))) {
+            queue.extend((0..len as u32).map(make));
+        }
+    };
+    add(WorkItem::Type, module.types.len());
+    add(WorkItem::Func, module.func_types.len());
+    add(WorkItem::Table, module.table_types.len());
+    add(WorkItem::Memory, module.memory_types.len());
+    add(WorkItem::Global, module.global_types.len());
+    add(WorkItem::Tag, module.tag_types.len());
+    add(WorkItem::Elem, module.elems.len());
+    add(WorkItem::Data, module.datas.len());
+
+    let mut res = Uses::default();
+    while let Some(item) = queue.pop() {
+        if all_uses.contains(item) || !res.insert(item) {
+            continue;
+        }
+        let uses = match item {
+            WorkItem::Type(idx) => get_type_uses(&module.types[idx as usize]),
+            WorkItem::Func(idx) => Uses::single_type(module.func_types[idx as usize]),
+            WorkItem::Table(idx) => {
+                let mut uses = get_tabletype_uses(&module.table_types[idx as usize]);
+                let defined = idx
+                    .checked_sub(module.num_imported_tables)
+                    .map(|i| &module.defined_tables[i as usize]);
+                if let Some(wasmparser::TableInit::Expr(expr)) = defined.map(|table| &table.init) {
+                    uses.merge(get_constexpr_uses(expr)?);
+                }
+                uses
+            }
+            WorkItem::Global(idx) => {
+                let mut uses = get_globaltype_uses(&module.global_types[idx as usize]);
+                if let Some(i) = idx.checked_sub(module.num_imported_globals) {
+                    uses.merge(get_constexpr_uses(
+                        &module.defined_globals[i as usize].init_expr,
+                    )?);
+                }
+                uses
+            }
+            WorkItem::Tag(idx) => get_tagtype_uses(&module.tag_types[idx as usize]),
+            // Placeholder memories and segments refer to nothing.
+            WorkItem::Memory(_) | WorkItem::Elem(_) | WorkItem::Data(_) => Uses::default(),
+        };
+        queue.extend(live_items(&uses));
+    }
+    Ok(res)
+}
+
+/// Lists the exports that isolation adds to the module, as their name, kind, and index in the
+/// isolated module.
+pub fn synthetic_exports(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    relocations: &Relocations,
+    options: &EmitOptions,
+) -> Vec<(String, wasm_encoder::ExportKind, u32)> {
+    added_exports(module, all_uses, relocations, options)
+        .into_iter()
+        .filter_map(|(name, kind, idx)| {
+            let name = options.export_filter.apply(&name)?.to_string();
+            Some((name, kind, idx))
+        })
+        .collect()
+}
+
+/// The exports that isolation adds, before they are renamed or dropped.
+fn added_exports(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    relocations: &Relocations,
+    options: &EmitOptions,
+) -> Vec<(String, wasm_encoder::ExportKind, u32)> {
+    let mut res: Vec<(String, wasm_encoder::ExportKind, u32)> = vec![];
+    if options.demote_start {
+        if let Some(new_idx) = module
+            .start_idx
+            .filter(|idx| !options.placeholders.live_funcs.contains(*idx))
+            .and_then(|idx| relocations.get(&Relocation::Func(idx)))
+        {
+            res.push((
+                "_initialize".to_string(),
+                wasm_encoder::ExportKind::Func,
+                *new_idx,
+            ));
+        }
+    }
+
+    if options.passive_init {
+        res.push((
+            INIT_FUNC_NAME.to_string(),
+            wasm_encoder::ExportKind::Func,
+            all_uses.live_funcs.len() as u32,
+        ));
+    }
+
+    if options.api.is_some() || !options.synthetic_exports {
+        // The API file describes the exact set of exports we want, so don't add any of our own.
+        // Some users also just don't want any surprise exports.
+        return res;
+    }
+
+    // Also export the explicitly-requested things so it's easy to test them in isolation. Items
+    // with a name are exported under it, unless that would clash with another export.
+    let mut taken: HashSet<String> = module.exports.iter().map(|e| e.name.to_string()).collect();
+    taken.extend(res.iter().map(|(name, _, _)| name.clone()));
+    let roots = if options.export_all_kept {
+        all_uses
+    } else {
+        options.requested
+    };
+    let requested = [
+        (
+            &roots.live_funcs,
+            wasm_encoder::ExportKind::Func,
+            WorkItem::Func as fn(u32) -> WorkItem,
+        ),
+        (
+            &roots.live_tables,
+            wasm_encoder::ExportKind::Table,
+            WorkItem::Table,
+        ),
+        (
+            &roots.live_globals,
+            wasm_encoder::ExportKind::Global,
+            WorkItem::Global,
+        ),
+        (
+            &roots.live_memories,
+            wasm_encoder::ExportKind::Memory,
+            WorkItem::Memory,
+        ),
+        (
+            &roots.live_tags,
+            wasm_encoder::ExportKind::Tag,
+            WorkItem::Tag,
+        ),
+    ];
+    for (indices, kind, make_item) in requested {
+        for idx in indices.iter() {
+            let item = make_item(idx);
+            if options.placeholders.contains(item) {
+                continue;
+            }
+            if let Some(new_idx) = relocations.get(&Relocation::from(item)) {
+                let name = module
+                    .item_name(item)
+                    .map(|name| format!("{}{}", options.export_prefix, name))
+                    .filter(|name| !taken.contains(name))
+                    .unwrap_or_else(|| {
+                        format!("{}{}_{}", options.export_prefix, item.kind_name(), idx)
+                    });
+                taken.insert(name.clone());
+                res.push((name, kind, *new_idx));
+            }
+        }
+    }
+    res
+}
+
+/// Isolates the given roots twice, each time from a fresh parse of the original, and fails if the
+/// modules or their summaries and reports differ in any byte. Each run finds the live items and
+/// their new indices anew, so this covers everything from the roots to the output. For tests of
+/// tools built on this crate that rely on stable output.
+pub fn check_deterministic(
+    wasm: &[u8],
+    roots: &[WorkItem],
+    liveness_options: &LivenessOptions,
+    options: &EmitOptions,
+) -> Result<Vec<u8>> {
+    let isolate = || -> Result<(Vec<u8>, String, String)> {
+        let module = ParsedModule::parse(wasm)?;
+        let all_uses = find_live(&module, roots.to_vec(), liveness_options)?;
+        let relocations = build_relocations(&all_uses);
+        let out = emit(&module, &all_uses, &relocations, options)?;
+        let summary = build_summary(
+            &module,
+            &all_uses,
+            &relocations,
+            options,
+            roots,
+            vec![],
+            out.len(),
+        )?;
+        let report = build_report(wasm, &out, &module, &all_uses)?;
+        Ok((
+            out,
+            serde_json::to_string(&summary)?,
+            serde_json::to_string(&report)?,
+        ))
+    };
+    let (first, first_summary, first_report) = isolate()?;
+    let (second, second_summary, second_report) = isolate()?;
+    check_same("module", &first, &second)?;
+    check_same(
+        "summary",
+        first_summary.as_bytes(),
+        second_summary.as_bytes(),
+    )?;
+    check_same("report", first_report.as_bytes(), second_report.as_bytes())?;
+    Ok(first)
+}
+
+/// Fails if two runs of [`check_deterministic`] gave different bytes for the same output.
+fn check_same(what: &str, first: &[u8], second: &[u8]) -> Result<()> {
+    if let Some(offset) = first.iter().zip(second).position(|(a, b)| a != b) {
+        return Err(Error::new(
+            ErrorKind::Internal,
+            format!(
+                "isolating the module twice gave a different {} at offset {:#x}",
+                what, offset
+            ),
+        )
+        .into());
+    }
+    if first.len() != second.len() {
+        return Err(Error::new(
+            ErrorKind::Internal,
+            format!(
+                "isolating the module twice gave a {} of {} and then {} bytes",
+                what,
+                first.len(),
+                second.len()
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Checks whether every item in a set of uses keeps its original index.
+fn is_identity(relocations: &Relocations, uses: &Uses) -> bool {
+    live_items(uses)
+        .into_iter()
+        .all(|item| relocations.get(&Relocation::from(item)) == Some(&item.index()))
+}
+
+/// Checks whether every index a function's body refers to keeps its original value, in which case
+/// it can be copied as-is instead of being reencoded.
+fn is_unrelocated(relocations: &Relocations, scan: &BodyScan) -> bool {
+    is_identity(relocations, &scan.uses)
+}
+
+/// Writes out a new module containing only the live items, renumbered according to the given
+/// relocations.
+///
+/// The output depends only on the input module and the options, never on hash map iteration
+/// order or anything else that varies between runs or machines, so isolating the same items twice
+/// gives byte-for-byte identical modules. [`check_deterministic`] checks this for a whole run.
+pub fn emit(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    relocations: &Relocations,
+    options: &EmitOptions,
+) -> Result<Vec<u8>> {
+    let mut out = Module::new();
+    let mut reencoder =
+        RelocatingReencoder::new(relocations, options.dropped, &module.global_types);
+    let data_count = match options.data_count {
+        Some(data_count) => data_count,
+        None => needs_data_count(module, all_uses, options)?,
+    };
+    let func_refs = func_refs(module, all_uses, relocations, options)?;
+    let declarations = undeclared_func_refs(module, all_uses, relocations, options, &func_refs)?;
+    for (section, range) in plan_sections(
+        module,
+        all_uses,
+        relocations,
+        options,
+        data_count,
+        !declarations.is_empty(),
+    ) {
+        if let (true, Some(range)) = (section.is_custom(), &range) {
+            if !options
+                .custom_sections
+                .keeps(module.custom_section_name(range)?)
+            {
+                continue;
+            }
+        }
+        if let Some(range) = &range {
+            // Standalone mode moves imports into other sections, so only custom sections can be
+            // left alone.
+            if (section.is_custom() || !(options.standalone || options.reencode))
+                && is_unchanged(
+                    module,
+                    all_uses,
+                    relocations,
+                    &section,
+                    options,
+                    &func_refs,
+                    &declarations,
+                )?
+            {
+                log::trace!("copying the {:?} section as-is", section);
+                out.section(&VerbatimSection::new(module.buf, range.clone()));
+                continue;
+            }
+        }
+
+        match section {
+            Section::Custom => {
+                return Err(Error::new(
+                    ErrorKind::Internal,
+                    "custom sections should always be copied unchanged",
+                )
+                .into())
+            }
+            Section::ObjectMetadata => {
+                // The symbol table and relocations would all point at the wrong things after
+                // isolation, so the only safe thing to do is leave them out.
+                if !options.strip_object_metadata {
+                    return Err(Error::new(
+                        ErrorKind::Unsupported,
+                        "the module is an object file with linking or relocation sections, which cannot be kept after isolation (use --strip-object-metadata to remove them)",
+                    )
+                    .into());
+                }
+            }
+            Section::Names => {
+                if options.keep_names {
+                    out.section(&build_name_section(module, relocations, options));
+                }
+            }
+            Section::Producers => {
+                if options.producers {
+                    out.section(&build_producers_section(module));
+                }
+            }
+            Section::BranchHints => {
+                let branch_hints = remap_branch_hints(module, relocations, options)?;
+                if !branch_hints.is_empty() {
+                    out.section(&branch_hints);
+                }
+            }
+            Section::CodeMetadata => {
+                let Some(metadata) = module
+                    .code_metadata
+                    .iter()
+                    .find(|metadata| Some(&metadata.range) == range.as_ref())
+                else {
+                    return Err(Error::new(
+                        ErrorKind::Internal,
+                        "a code metadata section was not parsed",
+                    )
+                    .into());
+                };
+                if let Some(section) = remap_code_metadata(module, metadata, relocations, options)?
+                {
+                    out.section(&section);
+                }
+            }
+
+            Section::Type => {
+                let mut type_section = TypeSection::new();
+                let mut idx: u32 = 0;
+                for rg in &module.rec_groups {
+                    let mut sub_types: Vec<wasm_encoder::SubType> = vec![];
+                    for ty in rg.types() {
+                        // Types merged by --dedupe-types have a new index but aren't live.
+                        if all_uses.live_types.contains(idx) {
+                            sub_types.push(reencoder.sub_type(ty.clone())?);
+                        }
+                        idx += 1;
+                    }
+                    if let ([ty], false) = (sub_types.as_slice(), rg.is_explicit_rec_group()) {
+                        type_section.ty().subtype(ty);
+                    } else if sub_types.len() > 1
+                        || (rg.is_explicit_rec_group() && !sub_types.is_empty())
+                    {
+                        type_section.ty().rec(sub_types)
+                    }
+                }
+                if options.passive_init && init_func_type(module, all_uses, relocations).1 {
+                    type_section.ty().function([], []);
+                }
+                out.section(&type_section);
+            }
+            Section::Import => {
+                if options.standalone {
+                    continue;
+                }
+
+                let mut import_section = ImportSection::new();
+
+                let mut num_imported_funcs = 0;
+                let mut num_imported_tables = 0;
+                let mut num_imported_memories = 0;
+                let mut num_imported_globals = 0;
+                let mut num_imported_tags = 0;
+                for import in &module.imports {
+                    let (module_name, name) = rename_import(options.import_renames, import);
+                    match import.ty {
+                        wasmparser::TypeRef::Func(type_idx) => {
+                            if all_uses.live_funcs.contains(num_imported_funcs) {
+                                import_section.import(
+                                    module_name,
+                                    name,
+                                    EntityType::Function(reencoder.type_index(type_idx)),
+                                );
+                            }
+                            num_imported_funcs += 1;
+                        }
+                        wasmparser::TypeRef::Table(ty) => {
+                            if all_uses.live_tables.contains(num_imported_tables) {
+                                import_section.import(module_name, name, reencoder.table_type(ty)?);
+                            }
+                            num_imported_tables += 1;
+                        }
+                        wasmparser::TypeRef::Memory(ty) => {
+                            if all_uses.live_memories.contains(num_imported_memories) {
+                                import_section.import(
+                                    module_name,
+                                    name,
+                                    memory_type(&mut reencoder, options, num_imported_memories, ty),
+                                );
+                            }
+                            num_imported_memories += 1;
+                        }
+                        wasmparser::TypeRef::Global(ty) => {
+                            if all_uses.live_globals.contains(num_imported_globals) {
+                                import_section.import(
+                                    module_name,
+                                    name,
+                                    reencoder.global_type(ty)?,
+                                );
+                            }
+                            num_imported_globals += 1;
+                        }
+                        wasmparser::TypeRef::Tag(ty) => {
+                            if all_uses.live_tags.contains(num_imported_tags) {
+                                import_section.import(module_name, name, reencoder.tag_type(ty));
+                            }
+                            num_imported_tags += 1;
+                        }
+                    }
+                }
+
+                out.section(&import_section);
+            }
+            Section::Function => {
+                let mut function_section = FunctionSection::new();
+                if options.standalone {
+                    for idx in live_imports(&all_uses.live_funcs, module.num_imported_functions) {
+                        function_section
+                            .function(reencoder.type_index(module.func_types[idx as usize]));
+                    }
+                }
+                for (i, _) in module.defined_funcs.iter().enumerate() {
+                    let idx = module.num_imported_functions + i as u32;
+                    if relocations.contains_key(&Relocation::Func(idx)) {
+                        function_section
+                            .function(reencoder.type_index(module.func_types[idx as usize]));
+                    }
+                }
+                if options.passive_init {
+                    function_section.function(init_func_type(module, all_uses, relocations).0);
+                }
+                out.section(&function_section);
+            }
+            Section::Table => {
+                let mut table_section = TableSection::new();
+                if options.standalone {
+                    for idx in live_imports(&all_uses.live_tables, module.num_imported_tables) {
+                        let ty = module.table_types[idx as usize];
+                        if !ty.element_type.is_nullable() {
+                            return Err(Error::new(
+                                ErrorKind::Unsupported,
+                                format!(
+                                    "imported table {} cannot be stubbed because its elements are not nullable",
+                                    idx
+                                ),
+                            )
+                            .into());
+                        }
+                        table_section.table(reencoder.table_type(ty)?);
+                    }
+                }
+                for (i, table) in module.defined_tables.iter().enumerate() {
+                    let idx = module.num_imported_tables + i as u32;
+                    if relocations.contains_key(&Relocation::Table(idx)) {
+                        match &table.init {
+                            wasmparser::TableInit::RefNull => {
+                                table_section.table(reencoder.table_type(table.ty)?);
+                            }
+                            wasmparser::TableInit::Expr(init_expr) => {
+                                table_section.table_with_init(
+                                    reencoder.table_type(table.ty)?,
+                                    &reencoder.const_expr(init_expr.clone())?,
+                                );
+                            }
+                        }
+                    }
+                }
+                out.section(&table_section);
+            }
+            Section::Memory => {
+                let mut memory_section = MemorySection::new();
+                let first = if options.standalone {
+                    0
+                } else {
+                    module.num_imported_memories
+                };
+                for idx in first..(module.memory_types.len() as u32) {
+                    if relocations.contains_key(&Relocation::Memory(idx)) {
+                        let mem_type = module.memory_types[idx as usize];
+                        memory_section.memory(memory_type(&mut reencoder, options, idx, mem_type));
+                    }
+                }
+                out.section(&memory_section);
+            }
+            Section::Global => {
+                let mut global_section = GlobalSection::new();
+                if options.standalone {
+                    for idx in live_imports(&all_uses.live_globals, module.num_imported_globals) {
+                        let ty = reencoder.global_type(module.global_types[idx as usize])?;
+                        global_section.global(ty, &default_const_expr(ty.val_type)?);
+                    }
+                }
+                for (i, global) in module.defined_globals.iter().enumerate() {
+                    let idx = module.num_imported_globals + i as u32;
+                    if relocations.contains_key(&Relocation::Global(idx)) {
+                        global_section.global(
+                            reencoder.global_type(global.ty)?,
+                            &reencoder.const_expr(global.init_expr.clone())?,
+                        );
+                    }
+                }
+                out.section(&global_section);
+            }
+            Section::Export => {
+                let mut export_section = ExportSection::new();
+                let mut names: HashSet<String> = HashSet::new();
+                let chased = chased_funcs(module, relocations, options)?;
+                for export in &module.exports {
+                    if !is_export_wanted(export, options, &chased) {
+                        continue;
+                    }
+
+                    // We don't use the reencoder here because we need to actually look up from the
+                    // relocation map anyway to figure out if we should export at all. So then we
+                    // might as well just write the value we find there.
+                    let item = match export.kind {
+                        wasmparser::ExternalKind::Func => WorkItem::Func(export.index),
+                        wasmparser::ExternalKind::Table => WorkItem::Table(export.index),
+                        wasmparser::ExternalKind::Memory => WorkItem::Memory(export.index),
+                        wasmparser::ExternalKind::Global => WorkItem::Global(export.index),
+                        wasmparser::ExternalKind::Tag => WorkItem::Tag(export.index),
+                    };
+                    let Some(new_idx) = relocations.get(&Relocation::from(item)) else {
+                        continue;
+                    };
+                    if options.placeholders.contains(item) {
+                        continue;
+                    }
+                    let Some(name) = options.export_filter.apply(export.name) else {
+                        continue;
+                    };
+                    if !names.insert(name.to_string()) {
+                        anyhow::bail!("more than one export would be named \"{}\"", name);
+                    }
+                    export_section.export(name, export.kind.into(), *new_idx);
+                }
+
+                for (name, kind, new_idx) in
+                    synthetic_exports(module, all_uses, relocations, options)
+                {
+                    if !names.insert(name.clone()) {
+                        anyhow::bail!("more than one export would be named \"{}\"", name);
+                    }
+                    export_section.export(&name, kind, new_idx);
+                }
+
+                out.section(&export_section);
+            }
+            Section::Start => {
+                if options.demote_start {
+                    continue;
+                }
+                if let Some(idx) = module
+                    .start_idx
+                    .filter(|idx| !options.placeholders.live_funcs.contains(*idx))
+                {
+                    if let Some(new_idx) = relocations.get(&Relocation::Func(idx)) {
+                        out.section(&wasm_encoder::StartSection {
+                            function_index: *new_idx,
+                        });
+                    }
+                }
+            }
+            Section::Element => {
+                let mut element_section = ElementSection::new();
+                for (i, elem) in module.elems.iter().enumerate() {
+                    let idx = i as u32;
+                    if options.placeholders.live_elems.contains(idx) {
+                        element_section.segment(ElementSegment {
+                            mode: ElementMode::Passive,
+                            elements: Elements::Functions(Cow::Borrowed(&[])),
+                        });
+                    } else if let (true, wasmparser::ElementKind::Declared) =
+                        (relocations.contains_key(&Relocation::Elem(idx)), &elem.kind)
+                    {
+                        // Declared segments are cut down to the functions kept code refers to,
+                        // since that is all they are for.
+                        let funcs: Vec<u32> = get_elem_funcs(elem)?
+                            .into_iter()
+                            .filter_map(|idx| relocations.get(&Relocation::Func(idx)).copied())
+                            .filter(|idx| func_refs.contains(idx))
+                            .collect();
+                        element_section.segment(ElementSegment {
+                            mode: ElementMode::Declared,
+                            elements: Elements::Functions(Cow::Owned(funcs)),
+                        });
+                    } else if relocations.contains_key(&Relocation::Elem(idx)) {
+                        let expr: ConstExpr;
+                        element_section.segment(ElementSegment {
+                            mode: match &elem.kind {
+                                wasmparser::ElementKind::Passive => ElementMode::Passive,
+                                wasmparser::ElementKind::Active { table_index, .. }
+                                    if options.passive_init
+                                        || options
+                                            .dropped
+                                            .live_tables
+                                            .contains(table_index.unwrap_or(0)) =>
+                                {
+                                    ElementMode::Passive
+                                }
+                                wasmparser::ElementKind::Active {
+                                    table_index,
+                                    offset_expr,
+                                } => {
+                                    expr = reencoder.const_expr(offset_expr.clone())?;
+                                    ElementMode::Active {
+                                        table: table_index.map(|idx| reencoder.table_index(idx)),
+                                        offset: &expr,
+                                    }
+                                }
+                                wasmparser::ElementKind::Declared => ElementMode::Declared,
+                            },
+                            elements: reencoder.element_items(elem.items.clone())?,
+                        });
+                    }
+                }
+                if !declarations.is_empty() {
+                    element_section.segment(ElementSegment {
+                        mode: ElementMode::Declared,
+                        elements: Elements::Functions(Cow::Borrowed(&declarations)),
+                    });
+                }
+                out.section(&element_section);
+            }
+            Section::Code => {
+                let mut code_section = CodeSection::new();
+                if options.standalone {
+                    for _ in live_imports(&all_uses.live_funcs, module.num_imported_functions) {
+                        let mut stub = Function::new([]);
+                        stub.instruction(&Instruction::Unreachable);
+                        stub.instruction(&Instruction::End);
+                        code_section.function(&stub);
+                    }
+                }
+                // Re-encoding bodies is most of the time spent emitting a big module, so they are
+                // encoded in parallel and then added in order.
+                let scans = module.body_scans()?;
+                let bodies: Vec<Option<Cow<[u8]>>> = module
+                    .defined_funcs
+                    .par_iter()
+                    .enumerate()
+                    .map(|(i, func)| {
+                        let idx = i as u32 + module.num_imported_functions;
+                        if !all_uses.live_funcs.contains(idx) {
+                            return Ok(None);
+                        }
+                        if options.is_stub(idx) {
+                            let mut new_func = Function::new([]);
+                            new_func.instruction(&Instruction::Unreachable);
+                            new_func.instruction(&Instruction::End);
+                            return Ok(Some(Cow::Owned(new_func.into_raw_body())));
+                        }
+                        if !options.reencode && is_unrelocated(relocations, &scans[i]) {
+                            log::trace!("copying the body of func:{} as-is", idx);
+                            return Ok(Some(Cow::Borrowed(&module.buf[func.body_range.clone()])));
+                        }
+                        let mut reencoder = RelocatingReencoder::new(
+                            relocations,
+                            options.dropped,
+                            &module.global_types,
+                        );
+                        let new_func = reencode_func(&mut reencoder, func, options.no_data, None)?;
+                        Ok(Some(Cow::Owned(new_func.into_raw_body())))
+                    })
+                    .collect::<Result<_>>()?;
+                // If every body was kept and copied as-is, so is the section.
+                if let (Some(range), false, false) =
+                    (&range, options.standalone, options.passive_init)
+                {
+                    if bodies
+                        .iter()
+                        .all(|body| matches!(body, Some(Cow::Borrowed(_))))
+                    {
+                        log::trace!("copying the {:?} section as-is", section);
+                        out.section(&VerbatimSection::new(module.buf, range.clone()));
+                        continue;
+                    }
+                }
+                for body in bodies.iter().flatten() {
+                    code_section.raw(body);
+                }
+                if options.passive_init {
+                    code_section.function(&build_init_func(
+                        module,
+                        all_uses,
+                        relocations,
+                        &mut reencoder,
+                        options,
+                    )?);
+                }
+                out.section(&code_section);
+            }
+            Section::Data | Section::DataCount if options.no_data => {}
+            Section::Data => {
+                let mut data_section = DataSection::new();
+                let mut extra_pieces: Vec<(u32, u64, &[u8])> = vec![];
+                for (i, data) in module.datas.iter().enumerate() {
+                    let idx = i as u32;
+                    if options.placeholders.live_datas.contains(idx) {
+                        data_section.passive([]);
+                    } else if relocations.contains_key(&Relocation::Data(idx)) {
+                        let detached = matches!(
+                            data.kind,
+                            wasmparser::DataKind::Active { memory_index, .. }
+                                if options.dropped.live_memories.contains(memory_index)
+                        );
+                        if let (
+                            false,
+                            Some(pieces),
+                            wasmparser::DataKind::Active {
+                                memory_index,
+                                offset_expr,
+                            },
+                        ) = (
+                            detached,
+                            options.data_trims.and_then(|t| t.get(&idx)),
+                            &data.kind,
+                        ) {
+                            // The segment keeps its index with its first piece, or no bytes at
+                            // all if none of it is used. The other pieces go at the end.
+                            let base = eval_address(module, offset_expr)?;
+                            let first = pieces.first().cloned().unwrap_or(0..0);
+                            add_trimmed_segment(
+                                &mut data_section,
+                                module,
+                                &mut reencoder,
+                                options,
+                                *memory_index,
+                                base.wrapping_add(first.start as u64),
+                                &data.data[first],
+                            );
+                            for piece in pieces.iter().skip(1) {
+                                extra_pieces.push((
+                                    *memory_index,
+                                    base.wrapping_add(piece.start as u64),
+                                    &data.data[piece.clone()],
+                                ));
+                            }
+                            continue;
+                        }
+
+                        let unchanged = match &data.kind {
+                            wasmparser::DataKind::Passive => true,
+                            wasmparser::DataKind::Active {
+                                memory_index,
+                                offset_expr,
+                            } => {
+                                !options.passive_init
+                                    && !detached
+                                    && is_identity(relocations, &Uses::single_memory(*memory_index))
+                                    && is_identity(relocations, &get_constexpr_uses(offset_expr)?)
+                            }
+                        };
+                        if unchanged {
+                            data_section.raw(&module.buf[data.range.clone()]);
+                            continue;
+                        }
+
+                        let expr: ConstExpr;
+                        data_section.segment(DataSegment {
+                            mode: match &data.kind {
+                                wasmparser::DataKind::Passive => DataSegmentMode::Passive,
+                                wasmparser::DataKind::Active { .. }
+                                    if options.passive_init || detached =>
+                                {
+                                    DataSegmentMode::Passive
+                                }
+                                wasmparser::DataKind::Active {
+                                    memory_index,
+                                    offset_expr,
+                                } => {
+                                    expr = reencoder.const_expr(offset_expr.clone())?;
+                                    DataSegmentMode::Active {
+                                        memory_index: reencoder.memory_index(*memory_index),
+                                        offset: &expr,
+                                    }
+                                }
+                            },
+                            data: data.data.iter().copied(),
+                        });
+                    }
+                }
+                for (memory_index, address, bytes) in extra_pieces {
+                    add_trimmed_segment(
+                        &mut data_section,
+                        module,
+                        &mut reencoder,
+                        options,
+                        memory_index,
+                        address,
+                        bytes,
+                    );
+                }
+                out.section(&data_section);
+            }
+            Section::DataCount => {
+                out.section(&wasm_encoder::DataCountSection {
+                    count: (all_uses.live_datas.len() + extra_data_segments(options)) as u32,
+                });
+            }
+            Section::Tag => {
+                let mut tag_section = TagSection::new();
+                let first = if options.standalone {
+                    0
+                } else {
+                    module.num_imported_tags
+                };
+                for idx in first..(module.tag_types.len() as u32) {
+                    if relocations.contains_key(&Relocation::Tag(idx)) {
+                        let tag_type = &module.tag_types[idx as usize];
+                        tag_section.tag(reencoder.tag_type(*tag_type));
+                    }
+                }
+                out.section(&tag_section);
+            }
+        }
+    }
+
+    Ok(out.finish())
+}
+
+/// Re-encodes a function body with its indices relocated. If `offsets` is given, it receives the
+/// new offset of every instruction, keyed by its original offset. Both are relative to the start
+/// of the body, which is how branch hints refer to instructions.
+fn reencode_func(
+    reencoder: &mut RelocatingReencoder,
+    func: &Func,
+    no_data: bool,
+    mut offsets: Option<&mut HashMap<u32, u32>>,
+) -> Result<Function> {
+    let mut new_locals: Vec<(u32, wasm_encoder::ValType)> = vec![];
+    for (n, ty) in &func.locals {
+        new_locals.push((*n, reencoder.val_type(*ty)?));
+    }
+    let mut new_func = Function::new(new_locals);
+    for instr in func.body.get_operators_reader()?.into_iter_with_offsets() {
+        let (instr, offset) = instr?;
+        if let Some(offsets) = offsets.as_deref_mut() {
+            offsets.insert(
+                (offset - func.body_range.start) as u32,
+                new_func.byte_len() as u32,
+            );
+        }
+        if no_data {
+            match instr {
+                // Without the segment there is nothing to copy, so just consume the operands.
+                wasmparser::Operator::MemoryInit { .. } => {
+                    for _ in 0..3 {
+                        new_func.instruction(&Instruction::Drop);
+                    }
+                    continue;
+                }
+                wasmparser::Operator::DataDrop { .. } => continue,
+                wasmparser::Operator::ArrayNewData { .. }
+                | wasmparser::Operator::ArrayInitData { .. } => {
+                    return Err(Error::new(
+                        ErrorKind::Unsupported,
+                        format!(
+                            "{:?} cannot do without its data segment, so data cannot be dropped",
+                            instr
+                        ),
+                    )
+                    .into());
+                }
+                _ => {}
+            }
+        }
+        new_func.instruction(&reencoder.instruction(instr)?);
+    }
+    Ok(new_func)
+}
+
+/// Rebuilds the producers section with wasm-isolate and its version in the processed-by field,
+/// replacing any older version that was already there.
+fn build_producers_section(module: &ParsedModule) -> ProducersSection {
+    const PROCESSED_BY: &str = "processed-by";
+    const TOOL: &str = env!("CARGO_PKG_NAME");
+    let mut section = ProducersSection::new();
+    let mut fields: Vec<(&str, ProducersField)> = vec![];
+    for (name, values) in &module.producers {
+        let mut field = ProducersField::new();
+        for value in values {
+            if *name != PROCESSED_BY || value.name != TOOL {
+                field.value(value.name, value.version);
+            }
+        }
+        fields.push((name, field));
+    }
+    if !fields.iter().any(|(name, _)| *name == PROCESSED_BY) {
+        fields.push((PROCESSED_BY, ProducersField::new()));
+    }
+    for (name, mut field) in fields {
+        if name == PROCESSED_BY {
+            field.value(TOOL, env!("CARGO_PKG_VERSION"));
+        }
+        section.field(name, &field);
+    }
+    section
+}
+
+/// Rebuilds the name section with every name moved to its item's new index. Names of removed items
+/// are left out, and so are the label names and non-parameter local names of cut functions, whose
+/// bodies no longer have those locals or blocks.
+fn build_name_section(
+    module: &ParsedModule,
+    relocations: &Relocations,
+    options: &EmitOptions,
+) -> NameSection {
+    let names = &module.names;
+    // Types merged by --dedupe-types share a new index with an earlier type, which keeps its name.
+    let is_new = |new_idx: u32, last: &mut Option<u32>| {
+        let res = last.is_none_or(|last| new_idx > last);
+        *last = Some(new_idx);
+        res
+    };
+    let remap = |map: &BTreeMap<u32, &str>, reloc: fn(u32) -> Relocation| {
+        let mut res = NameMap::new();
+        let mut last = None;
+        for (idx, name) in map {
+            if let Some(new_idx) = relocations.get(&reloc(*idx)) {
+                if is_new(*new_idx, &mut last) {
+                    res.append(*new_idx, name);
+                }
+            }
+        }
+        (!res.is_empty()).then_some(res)
+    };
+    let remap_indirect = |map: &BTreeMap<u32, BTreeMap<u32, &str>>,
+                          reloc: fn(u32) -> Relocation,
+                          keep: &dyn Fn(u32, u32) -> bool| {
+        let mut res = IndirectNameMap::new();
+        let mut any = false;
+        for (idx, inner) in map {
+            let Some(new_idx) = relocations.get(&reloc(*idx)) else {
+                continue;
+            };
+            let mut inner_names = NameMap::new();
+            for (inner_idx, name) in inner {
+                if keep(*idx, *inner_idx) {
+                    inner_names.append(*inner_idx, name);
+                }
+            }
+            if !inner_names.is_empty() {
+                res.append(*new_idx, &inner_names);
+                any = true;
+            }
+        }
+        any.then_some(res)
+    };
+    let is_cut = |idx: u32| options.is_stub(idx);
+    let is_param = |idx: u32, local_idx: u32| {
+        module
+            .signature(module.func_types[idx as usize])
+            .is_ok_and(|ty| (local_idx as usize) < ty.params().len())
+    };
+
+    let mut section = NameSection::new();
+    if let Some(name) = names.module {
+        section.module(name);
+    }
+    // Subsections have to be in this order, and empty ones are left out.
+    if let Some(map) = remap(&names.funcs, Relocation::Func) {
+        section.functions(&map);
+    }
+    if let Some(map) = remap_indirect(&names.locals, Relocation::Func, &|idx, local_idx| {
+        !is_cut(idx) || is_param(idx, local_idx)
+    }) {
+        section.locals(&map);
+    }
+    if let Some(map) = remap_indirect(&names.labels, Relocation::Func, &|idx, _| !is_cut(idx)) {
+        section.labels(&map);
+    }
+    if let Some(map) = remap(&names.types, Relocation::Type) {
+        section.types(&map);
+    }
+    if let Some(map) = remap(&names.tables, Relocation::Table) {
+        section.tables(&map);
+    }
+    if let Some(map) = remap(&names.memories, Relocation::Memory) {
+        section.memories(&map);
+    }
+    if let Some(map) = remap(&names.globals, Relocation::Global) {
+        section.globals(&map);
+    }
+    if let Some(map) = remap(&names.elems, Relocation::Elem) {
+        section.elements(&map);
+    }
+    if let Some(map) = remap(&names.datas, Relocation::Data) {
+        section.data(&map);
+    }
+    if let Some(map) = remap_indirect(&names.fields, Relocation::Type, &|_, _| true) {
+        section.fields(&map);
+    }
+    if let Some(map) = remap(&names.tags, Relocation::Tag) {
+        section.tags(&map);
+    }
+    section
+}
+
+/// Rewrites the branch hints of the kept functions. Hints for removed or cut functions are
+/// dropped, and hints in re-encoded bodies are moved along with the instructions they refer to.
+fn remap_branch_hints(
+    module: &ParsedModule,
+    relocations: &Relocations,
+    options: &EmitOptions,
+) -> Result<BranchHints> {
+    let mut reencoder =
+        RelocatingReencoder::new(relocations, options.dropped, &module.global_types);
+    let mut branch_hints = BranchHints::new();
+    for (idx, hints) in &module.branch_hints {
+        let Some((new_idx, offsets)) =
+            new_body_offsets(module, &mut reencoder, relocations, options, *idx)?
+        else {
+            continue;
+        };
+        let new_hints: Vec<BranchHint> = hints
+            .iter()
+            .filter_map(|hint| {
+                let offset = match &offsets {
+                    Some(offsets) => *offsets.get(&hint.func_offset)?,
+                    None => hint.func_offset,
+                };
+                Some(BranchHint {
+                    branch_func_offset: offset,
+                    branch_hint_value: hint.taken as u32,
+                })
+            })
+            .collect();
+        if !new_hints.is_empty() {
+            branch_hints.function_hints(new_idx, new_hints);
+        }
+    }
+    Ok(branch_hints)
+}
+
+/// Rewrites a `metadata.code.*` section for the isolated module, the same way as branch hints.
+/// The data attached to each instruction is copied as it is.
+fn remap_code_metadata<'a>(
+    module: &ParsedModule,
+    metadata: &CodeMetadata<'a>,
+    relocations: &Relocations,
+    options: &EmitOptions,
+) -> Result<Option<CustomSection<'a>>> {
+    let mut reencoder =
+        RelocatingReencoder::new(relocations, options.dropped, &module.global_types);
+    let mut funcs: Vec<(u32, Vec<CodeMetadataEntry>)> = vec![];
+    for (idx, entries) in &metadata.funcs {
+        let Some((new_idx, offsets)) =
+            new_body_offsets(module, &mut reencoder, relocations, options, *idx)?
+        else {
+            continue;
+        };
+        let new_entries: Vec<CodeMetadataEntry> = entries
+            .iter()
+            .filter_map(|(offset, data)| {
+                let offset = match &offsets {
+                    Some(offsets) => *offsets.get(offset)?,
+                    None => *offset,
+                };
+                Some((offset, *data))
+            })
+            .collect();
+        if !new_entries.is_empty() {
+            funcs.push((new_idx, new_entries));
+        }
+    }
+    if funcs.is_empty() {
+        return Ok(None);
+    }
+
+    let mut data: Vec<u8> = vec![];
+    funcs.len().encode(&mut data);
+    for (idx, entries) in funcs {
+        idx.encode(&mut data);
+        entries.len().encode(&mut data);
+        for (offset, bytes) in entries {
+            offset.encode(&mut data);
+            bytes.encode(&mut data);
+        }
+    }
+    Ok(Some(CustomSection {
+        name: Cow::Borrowed(metadata.name),
+        data: Cow::Owned(data),
+    }))
+}
+
+/// The new offsets of a function body's instructions by their original offsets, if they moved.
+type BodyOffsets = Option<HashMap<u32, u32>>;
+
+/// Finds a kept function's new index and, unless its body is copied as-is and keeps all its
+/// offsets, the new offset of each of its instructions by their original offset. Offsets are from
+/// the start of the body, as in code metadata. Functions that aren't kept or that are cut to a
+/// stub have neither.
+fn new_body_offsets(
+    module: &ParsedModule,
+    reencoder: &mut RelocatingReencoder,
+    relocations: &Relocations,
+    options: &EmitOptions,
+    idx: u32,
+) -> Result<Option<(u32, BodyOffsets)>> {
+    if options.is_stub(idx) {
+        return Ok(None);
+    }
+    let Some(new_idx) = relocations.get(&Relocation::Func(idx)) else {
+        return Ok(None);
+    };
+    let Some(i) = idx
+        .checked_sub(module.num_imported_functions)
+        .filter(|i| (*i as usize) < module.defined_funcs.len())
+    else {
+        return Ok(None);
+    };
+    let func = &module.defined_funcs[i as usize];
+    if !options.reencode && is_unrelocated(relocations, &module.body_scans()?[i as usize]) {
+        return Ok(Some((*new_idx, None)));
+    }
+    let mut offsets = HashMap::new();
+    reencode_func(reencoder, func, options.no_data, Some(&mut offsets))?;
+    Ok(Some((*new_idx, Some(offsets))))
+}
+
+/// Determines whether a section would come out exactly the same after isolation, in which case
+/// we can copy its original bytes instead of re-encoding it. This keeps the output as close to the
+/// input as possible, down to the LEB encodings.
+fn is_unchanged(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    relocations: &Relocations,
+    section: &Section,
+    options: &EmitOptions,
+    func_refs: &BTreeSet<u32>,
+    declarations: &[u32],
+) -> Result<bool> {
+    let num_funcs = module.func_types.len() as u32;
+    let num_tables = module.table_types.len() as u32;
+    let num_memories = module.memory_types.len() as u32;
+    let num_globals = module.global_types.len() as u32;
+    let num_tags = module.tag_types.len() as u32;
+
+    // Type indices are only left alone if we keep every type.
+    let all_types_kept = all_live(&all_uses.live_types, 0..module.types.len() as u32);
+
+    // Items are written the same if they keep their index and so does everything they refer to.
+    let liveness_options = LivenessOptions::default();
+    let all_identity = |make: fn(u32) -> WorkItem, range: Range<u32>| -> Result<bool> {
+        for idx in range {
+            let uses = get_item_uses(module, make(idx), &liveness_options)?;
+            if !is_identity(relocations, &uses) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    };
+
+    Ok(match section {
+        Section::Custom => true,
+        Section::Type => {
+            all_types_kept
+                && !(options.passive_init && init_func_type(module, all_uses, relocations).1)
+        }
+        Section::Function => {
+            !options.passive_init
+                && all_types_kept
+                && all_live(
+                    &all_uses.live_funcs,
+                    module.num_imported_functions..num_funcs,
+                )
+        }
+        Section::Memory => {
+            all_live(
+                &all_uses.live_memories,
+                module.num_imported_memories..num_memories,
+            ) && options.memory_minimums.is_empty()
+        }
+        Section::Tag => {
+            all_types_kept && all_live(&all_uses.live_tags, module.num_imported_tags..num_tags)
+        }
+        Section::DataCount => {
+            all_live(&all_uses.live_datas, 0..module.datas.len() as u32)
+                && extra_data_segments(options) == 0
+        }
+        Section::Import => {
+            options.import_renames.is_empty()
+                && options.memory_minimums.is_empty()
+                && all_identity(WorkItem::Func, 0..module.num_imported_functions)?
+                && all_identity(WorkItem::Table, 0..module.num_imported_tables)?
+                && all_identity(WorkItem::Memory, 0..module.num_imported_memories)?
+                && all_identity(WorkItem::Global, 0..module.num_imported_globals)?
+                && all_identity(WorkItem::Tag, 0..module.num_imported_tags)?
+        }
+        Section::Table => all_identity(WorkItem::Table, module.num_imported_tables..num_tables)?,
+        Section::Global => {
+            all_identity(WorkItem::Global, module.num_imported_globals..num_globals)?
+        }
+        Section::Export => {
+            options.api.is_none()
+                && module.exports.iter().all(|export| {
+                    let item = export_item(export);
+                    relocations.get(&Relocation::from(item)) == Some(&export.index)
+                        && !options.placeholders.contains(item)
+                        && options.export_filter.apply(export.name) == Some(export.name)
+                })
+                && synthetic_exports(module, all_uses, relocations, options).is_empty()
+        }
+        Section::Start => {
+            !options.demote_start
+                && module.start_idx.is_some_and(|idx| {
+                    relocations.get(&Relocation::Func(idx)) == Some(&idx)
+                        && !options.placeholders.live_funcs.contains(idx)
+                })
+        }
+        Section::Element => {
+            if options.passive_init
+                || !options.placeholders.live_elems.is_empty()
+                || !all_identity(WorkItem::Elem, 0..module.elems.len() as u32)?
+            {
+                return Ok(false);
+            }
+            // Declared segments are cut down to the functions kept code refers to, and more are
+            // added for references nothing declares.
+            for elem in &module.elems {
+                if let wasmparser::ElementKind::Declared = elem.kind {
+                    for idx in get_elem_funcs(elem)? {
+                        if relocations.get(&Relocation::Func(idx)) != Some(&idx)
+                            || !func_refs.contains(&idx)
+                        {
+                            return Ok(false);
+                        }
+                    }
+                }
+            }
+            declarations.is_empty()
+        }
+        Section::Data => {
+            !options.passive_init
+                && options.data_trims.is_none_or(|trims| trims.is_empty())
+                && options.placeholders.live_datas.is_empty()
+                && all_identity(WorkItem::Data, 0..module.datas.len() as u32)?
+        }
+        _ => false,
+    })
+}
+
+/// The type of a memory in the output, with its initial size changed if requested.
+fn memory_type(
+    reencoder: &mut RelocatingReencoder,
+    options: &EmitOptions,
+    idx: u32,
+    ty: wasmparser::MemoryType,
+) -> wasm_encoder::MemoryType {
+    let mut ty = reencoder.memory_type(ty);
+    if let Some(minimum) = options.memory_minimums.get(&idx) {
+        ty.minimum = *minimum;
+    }
+    ty
+}
+
+/// How many data segments trimming adds to the end of the data section, one for each piece of a
+/// trimmed segment after the first.
+fn extra_data_segments(options: &EmitOptions) -> usize {
+    options.data_trims.map_or(0, |trims| {
+        trims
+            .values()
+            .map(|pieces| pieces.len().saturating_sub(1))
+            .sum()
+    })
+}
+
+/// Adds a data segment holding part of a trimmed segment, placed at a constant address, or a
+/// passive one if the init function places it instead.
+fn add_trimmed_segment(
+    data_section: &mut DataSection,
+    module: &ParsedModule,
+    reencoder: &mut RelocatingReencoder,
+    options: &EmitOptions,
+    memory_index: u32,
+    address: u64,
+    bytes: &[u8],
+) {
+    let offset = ConstExpr::extended([address_const(module, memory_index, address)]);
+    data_section.segment(DataSegment {
+        mode: if options.passive_init {
+            DataSegmentMode::Passive
+        } else {
+            DataSegmentMode::Active {
+                memory_index: reencoder.memory_index(memory_index),
+                offset: &offset,
+            }
+        },
+        data: bytes.iter().copied(),
+    });
+}
+
+/// A constant address in a memory, typed according to whether it is a 64-bit memory.
+fn address_const(module: &ParsedModule, memory_index: u32, address: u64) -> Instruction<'static> {
+    if module.memory_types[memory_index as usize].memory64 {
+        Instruction::I64Const(address as i64)
+    } else {
+        Instruction::I32Const(address as u32 as i32)
+    }
+}
+
+/// Checks whether the output needs a data count section, which is when there are data segments
+/// and kept code refers to them by index, with `memory.init`, `data.drop`, `array.new_data`, or
+/// `array.init_data`. The function that `--passive-init` adds uses `memory.init`.
+fn needs_data_count(module: &ParsedModule, all_uses: &Uses, options: &EmitOptions) -> Result<bool> {
+    if options.no_data || all_uses.live_datas.is_empty() {
+        return Ok(false);
+    }
+    if options.passive_init {
+        return Ok(true);
+    }
+    let scans = module.body_scans()?;
+    for idx in all_uses.live_funcs.iter() {
+        if idx < module.num_imported_functions || options.is_stub(idx) {
+            continue;
+        }
+        let scan = &scans[(idx - module.num_imported_functions) as usize];
+        if !scan.uses.live_datas.is_empty() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Finds the kept functions that kept code takes references to with `ref.func`, by their new
+/// indices.
+fn func_refs(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    relocations: &Relocations,
+    options: &EmitOptions,
+) -> Result<BTreeSet<u32>> {
+    let scans = module.body_scans()?;
+    let mut res = BTreeSet::new();
+    for idx in all_uses.live_funcs.iter() {
+        if idx < module.num_imported_functions || options.is_stub(idx) {
+            continue;
+        }
+        let scan = &scans[(idx - module.num_imported_functions) as usize];
+        // References to dropped functions become unreachable, so they have no new index.
+        res.extend(
+            scan.func_refs
+                .iter()
+                .filter_map(|idx| relocations.get(&Relocation::Func(*idx)).copied()),
+        );
+    }
+    Ok(res)
+}
+
+/// Whether an export of the original module belongs in the output, as long as its item is kept.
+/// With an API file, only the exports it lists do, along with the exports of `chased` functions.
+fn is_export_wanted(
+    export: &wasmparser::Export,
+    options: &EmitOptions,
+    chased: &HashSet<u32>,
+) -> bool {
+    match options.api {
+        None => true,
+        Some(api) => {
+            api.iter().any(|name| name == export.name)
+                || (export.kind == wasmparser::ExternalKind::Func && chased.contains(&export.index))
+        }
+    }
+}
+
+/// Lists the names of the exports that --chase-exports adds to the ones in the API file.
+pub fn chased_exports(
+    module: &ParsedModule,
+    relocations: &Relocations,
+    options: &EmitOptions,
+) -> Result<Vec<String>> {
+    let chased = chased_funcs(module, relocations, options)?;
+    let mut res = vec![];
+    for export in &module.exports {
+        let listed = options
+            .api
+            .is_some_and(|api| api.iter().any(|name| name == export.name));
+        if listed || !is_export_wanted(export, options, &chased) {
+            continue;
+        }
+        let item = export_item(export);
+        if !relocations.contains_key(&Relocation::from(item)) || options.placeholders.contains(item)
+        {
+            continue;
+        }
+        if let Some(name) = options.export_filter.apply(export.name) {
+            res.push(name.to_string());
+        }
+    }
+    Ok(res)
+}
+
+/// Finds the functions whose exports --chase-exports keeps even when the API file doesn't list
+/// them: the ones in the kept active element segments of kept tables.
+fn chased_funcs(
+    module: &ParsedModule,
+    relocations: &Relocations,
+    options: &EmitOptions,
+) -> Result<HashSet<u32>> {
+    let mut res = HashSet::new();
+    if !options.chase_exports || options.api.is_none() {
+        return Ok(res);
+    }
+    for (i, elem) in module.elems.iter().enumerate() {
+        let idx = i as u32;
+        let wasmparser::ElementKind::Active { table_index, .. } = &elem.kind else {
+            continue;
+        };
+        if relocations.contains_key(&Relocation::Elem(idx))
+            && relocations.contains_key(&Relocation::Table(table_index.unwrap_or(0)))
+            && !options.placeholders.live_elems.contains(idx)
+        {
+            res.extend(get_elem_funcs(elem)?);
+        }
+    }
+    Ok(res)
+}
+
+/// Finds the functions in `func_refs` that nothing else in the output declares. A function has to
+/// be in an element segment, an export, or an init expression before `ref.func` can refer to it,
+/// which was true of the original module but may not be once segments and exports are left out.
+fn undeclared_func_refs(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    relocations: &Relocations,
+    options: &EmitOptions,
+    func_refs: &BTreeSet<u32>,
+) -> Result<Vec<u32>> {
+    let new_idx = |idx: u32| relocations.get(&Relocation::Func(idx)).copied();
+    let mut undeclared = func_refs.clone();
+    if undeclared.is_empty() {
+        return Ok(vec![]);
+    }
+
+    for (i, elem) in module.elems.iter().enumerate() {
+        let idx = i as u32;
+        if relocations.contains_key(&Relocation::Elem(idx))
+            && !options.placeholders.live_elems.contains(idx)
+        {
+            for idx in get_elem_funcs(elem)? {
+                undeclared.remove(&new_idx(idx).unwrap_or(u32::MAX));
+            }
+        }
+    }
+    let chased = chased_funcs(module, relocations, options)?;
+    for export in &module.exports {
+        let kept = is_export_wanted(export, options, &chased)
+            && options.export_filter.apply(export.name).is_some();
+        if let (wasmparser::ExternalKind::Func, true) = (export.kind, kept) {
+            if options.placeholders.live_funcs.contains(export.index) {
+                continue;
+            }
+            undeclared.remove(&new_idx(export.index).unwrap_or(u32::MAX));
+        }
+    }
+    for (_, kind, idx) in synthetic_exports(module, all_uses, relocations, options) {
+        if kind == wasm_encoder::ExportKind::Func {
+            undeclared.remove(&idx);
+        }
+    }
+    for (i, global) in module.defined_globals.iter().enumerate() {
+        let idx = module.num_imported_globals + i as u32;
+        if relocations.contains_key(&Relocation::Global(idx)) {
+            for func_idx in get_constexpr_uses(&global.init_expr)?.live_funcs.iter() {
+                undeclared.remove(&new_idx(func_idx).unwrap_or(u32::MAX));
+            }
+        }
+    }
+    for (i, table) in module.defined_tables.iter().enumerate() {
+        let idx = module.num_imported_tables + i as u32;
+        if let (true, wasmparser::TableInit::Expr(expr)) = (
+            relocations.contains_key(&Relocation::Table(idx)),
+            &table.init,
+        ) {
+            for func_idx in get_constexpr_uses(expr)?.live_funcs.iter() {
+                undeclared.remove(&new_idx(func_idx).unwrap_or(u32::MAX));
+            }
+        }
+    }
+    Ok(undeclared.into_iter().collect())
+}
+
+/// The type of the function that `--passive-init` adds, as its new index and whether the type
+/// has to be added to the end of the type section. An existing `[] -> []` type is used if one was
+/// kept.
+fn init_func_type(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    relocations: &Relocations,
+) -> (u32, bool) {
+    let existing = all_uses.live_types.iter().find(|idx| {
+        !module.types[*idx as usize].composite_type.shared
+            && module
+                .signature(*idx)
+                .is_ok_and(|ty| ty.params().is_empty() && ty.results().is_empty())
+    });
+    match existing.and_then(|idx| relocations.get(&Relocation::Type(idx))) {
+        Some(new_idx) => (*new_idx, false),
+        None => (all_uses.live_types.len() as u32, true),
+    }
+}
+
+/// Builds the function that `--passive-init` adds, which applies the kept active segments in the
+/// same order that instantiation would have: element segments first, then data segments.
+fn build_init_func(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    relocations: &Relocations,
+    reencoder: &mut RelocatingReencoder,
+    options: &EmitOptions,
+) -> Result<Function> {
+    fn offset(
+        f: &mut Function,
+        reencoder: &mut RelocatingReencoder,
+        expr: &wasmparser::ConstExpr,
+    ) -> Result<()> {
+        for op in expr.get_operators_reader() {
+            match op? {
+                wasmparser::Operator::End => {}
+                op => {
+                    f.instruction(&reencoder.instruction(op)?);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    let mut f = Function::new([]);
+    for (i, elem) in module.elems.iter().enumerate() {
+        let (
+            Some(new_idx),
+            wasmparser::ElementKind::Active {
+                table_index,
+                offset_expr,
+            },
+        ) = (relocations.get(&Relocation::Elem(i as u32)), &elem.kind)
+        else {
+            continue;
+        };
+        if options.placeholders.live_elems.contains(i as u32)
+            || options
+                .dropped
+                .live_tables
+                .contains(table_index.unwrap_or(0))
+        {
+            continue;
+        }
+        let len = match &elem.items {
+            wasmparser::ElementItems::Functions(items) => items.count(),
+            wasmparser::ElementItems::Expressions(_, items) => items.count(),
+        };
+        offset(&mut f, reencoder, offset_expr)?;
+        f.instruction(&Instruction::I32Const(0));
+        f.instruction(&Instruction::I32Const(len as i32));
+        f.instruction(&Instruction::TableInit {
+            elem_index: *new_idx,
+            table: reencoder.table_index(table_index.unwrap_or(0)),
+        });
+        f.instruction(&Instruction::ElemDrop(*new_idx));
+    }
+
+    // Pieces of trimmed segments get their own segments at the end of the data section.
+    let mut next_extra = all_uses.live_datas.len() as u32;
+    for (i, data) in module.datas.iter().enumerate() {
+        let idx = i as u32;
+        let (
+            Some(new_idx),
+            wasmparser::DataKind::Active {
+                memory_index,
+                offset_expr,
+            },
+        ) = (relocations.get(&Relocation::Data(idx)), &data.kind)
+        else {
+            continue;
+        };
+        if options.placeholders.live_datas.contains(idx)
+            || options.dropped.live_memories.contains(*memory_index)
+        {
+            continue;
+        }
+        let mem = reencoder.memory_index(*memory_index);
+        let init = |f: &mut Function, data_index: u32, len: usize| {
+            f.instruction(&Instruction::I32Const(0));
+            f.instruction(&Instruction::I32Const(len as i32));
+            f.instruction(&Instruction::MemoryInit { mem, data_index });
+            f.instruction(&Instruction::DataDrop(data_index));
+        };
+        match options.data_trims.and_then(|trims| trims.get(&idx)) {
+            Some(pieces) => {
+                let base = eval_address(module, offset_expr)?;
+                let first = pieces.first().cloned().unwrap_or(0..0);
+                f.instruction(&address_const(
+                    module,
+                    *memory_index,
+                    base.wrapping_add(first.start as u64),
+                ));
+                init(&mut f, *new_idx, first.len());
+                for piece in pieces.iter().skip(1) {
+                    f.instruction(&address_const(
+                        module,
+                        *memory_index,
+                        base.wrapping_add(piece.start as u64),
+                    ));
+                    init(&mut f, next_extra, piece.len());
+                    next_extra += 1;
+                }
+            }
+            None => {
+                offset(&mut f, reencoder, offset_expr)?;
+                init(&mut f, *new_idx, data.data.len());
+            }
+        }
+    }
+    f.instruction(&Instruction::End);
+    Ok(f)
+}
+
+/// Lists the sections to write, in order. These are the sections of the original module, plus any
+/// that the output needs but the original lacks, such as a code section for stubbed imports.
+/// Added sections have no range in the original module.
+fn plan_sections(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    relocations: &Relocations,
+    options: &EmitOptions,
+    data_count: bool,
+    declarations: bool,
+) -> Vec<(Section, Option<Range<usize>>)> {
+    let mut plan: Vec<(Section, Option<Range<usize>>)> = module
+        .sections
+        .iter()
+        .copied()
+        .zip(module.section_ranges.iter().cloned().map(Some))
+        .filter(|(section, _)| data_count || *section != Section::DataCount)
+        .collect();
+
+    let mut needed: Vec<Section> = vec![];
+    if data_count {
+        needed.push(Section::DataCount);
+    }
+    if options.standalone {
+        if live_imports(&all_uses.live_funcs, module.num_imported_functions)
+            .next()
+            .is_some()
+        {
+            needed.push(Section::Function);
+            needed.push(Section::Code);
+        }
+        if live_imports(&all_uses.live_tables, module.num_imported_tables)
+            .next()
+            .is_some()
+        {
+            needed.push(Section::Table);
+        }
+        if live_imports(&all_uses.live_memories, module.num_imported_memories)
+            .next()
+            .is_some()
+        {
+            needed.push(Section::Memory);
+        }
+        if live_imports(&all_uses.live_tags, module.num_imported_tags)
+            .next()
+            .is_some()
+        {
+            needed.push(Section::Tag);
+        }
+        if live_imports(&all_uses.live_globals, module.num_imported_globals)
+            .next()
+            .is_some()
+        {
+            needed.push(Section::Global);
+        }
+    }
+    if !synthetic_exports(module, all_uses, relocations, options).is_empty() {
+        needed.push(Section::Export);
+    }
+    if declarations {
+        needed.push(Section::Element);
+    }
+    if options.passive_init {
+        if init_func_type(module, all_uses, relocations).1 {
+            needed.push(Section::Type);
+        }
+        needed.push(Section::Function);
+        needed.push(Section::Code);
+    }
+
+    for section in needed {
+        if plan.iter().any(|(s, _)| *s == section) {
+            continue;
+        }
+        // Custom sections can go anywhere, so place the new section relative to the known ones.
+        let order = section_order(section);
+        let pos = match plan
+            .iter()
+            .position(|(s, _)| !s.is_custom() && section_order(*s) > order)
+        {
+            Some(pos) => pos,
+            None => plan
+                .iter()
+                .rposition(|(s, _)| !s.is_custom())
+                .map_or(0, |pos| pos + 1),
+        };
+        plan.insert(pos, (section, None));
+    }
+    plan
+}
+
+/// The position of a known section in a module, as required by the spec.
+fn section_order(section: Section) -> u32 {
+    match section {
+        Section::Custom
+        | Section::Producers
+        | Section::BranchHints
+        | Section::CodeMetadata
+        | Section::Names
+        | Section::ObjectMetadata => 0,
+        Section::Type => 1,
+        Section::Import => 2,
+        Section::Function => 3,
+        Section::Table => 4,
+        Section::Memory => 5,
+        Section::Tag => 6,
+        Section::Global => 7,
+        Section::Export => 8,
+        Section::Start => 9,
+        Section::Element => 10,
+        Section::DataCount => 11,
+        Section::Code => 12,
+        Section::Data => 13,
+    }
+}
+
+/// Iterates over the live items in an index space that are imports.
+fn live_imports(live: &IndexSet, num_imported: u32) -> impl Iterator<Item = u32> + '_ {
+    live.iter().take_while(move |idx| *idx < num_imported)
+}
+
+/// The value a stubbed imported global starts with.
+fn default_const_expr(ty: wasm_encoder::ValType) -> Result<ConstExpr> {
+    Ok(match ty {
+        wasm_encoder::ValType::I32 => ConstExpr::i32_const(0),
+        wasm_encoder::ValType::I64 => ConstExpr::i64_const(0),
+        wasm_encoder::ValType::F32 => ConstExpr::f32_const(0.0),
+        wasm_encoder::ValType::F64 => ConstExpr::f64_const(0.0),
+        wasm_encoder::ValType::V128 => ConstExpr::v128_const(0),
+        wasm_encoder::ValType::Ref(ty) => {
+            if !ty.nullable {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "non-nullable imported globals cannot be stubbed",
+                )
+                .into());
+            }
+            ConstExpr::ref_null(ty.heap_type)
+        }
+    })
+}
+
+/// Whether every index in the range is live.
+fn all_live(live: &IndexSet, range: Range<u32>) -> bool {
+    range.into_iter().all(|idx| live.contains(idx))
+}
+
+/// A section copied from the input exactly as it was originally encoded.
+pub(crate) struct VerbatimSection<'a> {
+    id: u8,
+    /// The section's size and contents, not including the id.
+    bytes: &'a [u8],
+}
+
+impl<'a> VerbatimSection<'a> {
+    pub(crate) fn new(buf: &'a [u8], range: Range<usize>) -> Self {
+        Self {
+            id: buf[range.start],
+            bytes: &buf[range.start + 1..range.end],
+        }
+    }
+}
+
+impl Encode for VerbatimSection<'_> {
+    fn encode(&self, sink: &mut Vec<u8>) {
+        sink.extend_from_slice(self.bytes);
+    }
+}
+
+impl wasm_encoder::Section for VerbatimSection<'_> {
+    fn id(&self) -> u8 {
+        self.id
+    }
+}
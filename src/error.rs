@@ -0,0 +1,75 @@
+//! The kinds of failure that callers may want to tell apart. Errors are still passed around as
+//! `anyhow::Error`, with an [`Error`] somewhere in the chain when the kind matters, and
+//! [`error_kind`] finds it again.
+
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorKind {
+    /// The options asked for something that doesn't make sense for this module, like an item
+    /// that doesn't exist. This is the kind of any error that doesn't say otherwise.
+    InvalidRequest,
+    /// A file could not be read or written.
+    Io,
+    /// The input is not a valid WebAssembly module, or is too big to process within the limits.
+    InvalidModule,
+    /// The module uses something that wasm-isolate cannot handle, or cannot keep working after
+    /// isolation.
+    Unsupported,
+    /// wasm-isolate broke one of its own assumptions. This is always a bug.
+    Internal,
+}
+
+impl ErrorKind {
+    /// The exit status for this kind of failure, so that scripts can tell them apart. Clap
+    /// already exits with 2 for bad arguments, and 101 matches a panic.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorKind::InvalidRequest => 1,
+            ErrorKind::Io => 3,
+            ErrorKind::InvalidModule => 4,
+            ErrorKind::Unsupported => 5,
+            ErrorKind::Internal => 101,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+    message: String,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Error {
+        Error {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Works out what kind of failure an error describes, from the first error in its chain that
+/// says.
+pub fn error_kind(err: &anyhow::Error) -> ErrorKind {
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<Error>() {
+            return err.kind;
+        }
+        if cause.is::<std::io::Error>() {
+            return ErrorKind::Io;
+        }
+        if cause.is::<wasmparser::BinaryReaderError>() {
+            return ErrorKind::InvalidModule;
+        }
+    }
+    ErrorKind::InvalidRequest
+}
@@ -0,0 +1,420 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use wasmparser::{
+    AbstractHeapType, BlockType, CompositeInnerType, ConstExpr, DataKind, ElementItems,
+    ElementKind, HeapType, Operator, Parser, Payload, RefType, TableInit, ValType, Validator,
+    WasmFeatures,
+};
+
+use crate::error::{Error, ErrorKind};
+use crate::index_set::IndexSet;
+use crate::liveness::WorkItem;
+use crate::module::ParsedModule;
+use crate::uses::Uses;
+use crate::wat;
+
+macro_rules! define_operator_proposal {
+    ($( @$proposal:ident $op:ident $({ $($arg:ident: $argty:ty),* })? => $visit:ident ($($ann:tt)*) )*) => {
+        /// Gets the proposal that introduced an operator, as named by wasmparser (e.g. `mvp`,
+        /// `simd`, or `gc`).
+        pub fn operator_proposal(op: &Operator) -> &'static str {
+            match op {
+                $( Operator::$op { .. } => stringify!($proposal), )*
+                _ => "unknown",
+            }
+        }
+
+        /// Gets the name of an operator as wasmparser spells it (e.g. `I32Add`).
+        pub fn operator_name(op: &Operator) -> &'static str {
+            match op {
+                $( Operator::$op { .. } => stringify!($op), )*
+                _ => "unknown",
+            }
+        }
+    };
+}
+wasmparser::for_each_operator!(define_operator_proposal);
+
+pub fn is_simd(op: &Operator) -> bool {
+    matches!(operator_proposal(op), "simd" | "relaxed_simd")
+}
+
+pub fn is_atomic(op: &Operator) -> bool {
+    matches!(
+        operator_proposal(op),
+        "threads" | "shared_everything_threads"
+    )
+}
+
+pub fn is_gc(op: &Operator) -> bool {
+    operator_proposal(op) == "gc"
+}
+
+/// Validates a module with only one encoding of exception handling enabled: the legacy `try`,
+/// `catch`, `delegate`, and `rethrow` if `legacy` is set, and otherwise `try_table`, `throw_ref`,
+/// and `exnref`. Tags and `throw` belong to both.
+pub fn validate_exception_handling(wasm: &[u8], legacy: bool) -> Result<()> {
+    let encoding = if legacy { "legacy" } else { "exnref" };
+    let mut features = WasmFeatures::all();
+    features.set(WasmFeatures::LEGACY_EXCEPTIONS, legacy);
+    if let Err(err) = Validator::new_with_features(features).validate_all(wasm) {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "the output is not valid with {} exception handling: {}",
+                encoding, err
+            ),
+        )
+        .into());
+    }
+    if !legacy {
+        return Ok(());
+    }
+
+    // Tags need the exceptions feature, which also turns on the new instructions, so the
+    // validator can't rule those out by itself.
+    for payload in Parser::new(0).parse_all(wasm) {
+        let Payload::CodeSectionEntry(body) = payload? else {
+            continue;
+        };
+        for op in body.get_operators_reader()?.into_iter_with_offsets() {
+            let (op, offset) = op?;
+            if matches!(op, Operator::TryTable { .. } | Operator::ThrowRef) {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "the output uses {} at {:#x}, which is not part of legacy exception handling",
+                        operator_name(&op),
+                        offset
+                    ),
+                )
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A proposal that some items need, along with the first item found that needs it.
+pub struct RequiredFeature {
+    /// The proposal, as named by wasmparser (e.g. `simd` or `memory64`).
+    pub proposal: &'static str,
+    pub item: WorkItem,
+    /// What the item uses from the proposal, e.g. `I8x16Add` or `v128`.
+    pub reason: String,
+}
+
+/// The proposal a value type comes from, if it isn't in the MVP.
+fn val_type_proposal(ty: &ValType) -> Option<&'static str> {
+    match ty {
+        ValType::V128 => Some("simd"),
+        ValType::Ref(ty) => Some(ref_type_proposal(ty)),
+        _ => None,
+    }
+}
+
+fn ref_type_proposal(ty: &RefType) -> &'static str {
+    match ty.heap_type() {
+        HeapType::Abstract { shared: true, .. } => "shared_everything_threads",
+        HeapType::Abstract {
+            ty: AbstractHeapType::Func | AbstractHeapType::Extern,
+            ..
+        } if ty.is_nullable() => "reference_types",
+        HeapType::Abstract {
+            ty: AbstractHeapType::Func | AbstractHeapType::Extern,
+            ..
+        } => "function_references",
+        HeapType::Abstract {
+            ty: AbstractHeapType::Exn | AbstractHeapType::NoExn,
+            ..
+        } => "exceptions",
+        HeapType::Abstract {
+            ty: AbstractHeapType::Cont | AbstractHeapType::NoCont,
+            ..
+        } => "stack_switching",
+        HeapType::Abstract { .. } => "gc",
+        HeapType::Concrete(_) => "function_references",
+    }
+}
+
+/// The proposals found so far, with the first item that needs each one.
+#[derive(Default)]
+struct Found(BTreeMap<&'static str, (WorkItem, String)>);
+
+impl Found {
+    fn add(&mut self, proposal: &'static str, item: WorkItem, reason: impl ToString) {
+        self.0
+            .entry(proposal)
+            .or_insert_with(|| (item, reason.to_string()));
+    }
+
+    fn val_type(&mut self, item: WorkItem, ty: &ValType) {
+        if let Some(proposal) = val_type_proposal(ty) {
+            self.add(proposal, item, wat::val_type(ty));
+        }
+    }
+
+    fn ref_type(&mut self, item: WorkItem, ty: &RefType) {
+        self.val_type(item, &ValType::Ref(*ty));
+    }
+
+    fn operator(&mut self, item: WorkItem, op: &Operator) {
+        match operator_proposal(op) {
+            "mvp" => {}
+            proposal => self.add(proposal, item, operator_name(op)),
+        }
+    }
+
+    /// Constant expressions can only do arithmetic with the extended-const proposal.
+    fn const_expr(&mut self, item: WorkItem, expr: &ConstExpr) -> Result<()> {
+        for op in expr.get_operators_reader() {
+            let op = op?;
+            match op {
+                Operator::I32Add
+                | Operator::I32Sub
+                | Operator::I32Mul
+                | Operator::I64Add
+                | Operator::I64Sub
+                | Operator::I64Mul => self.add("extended_const", item, operator_name(&op)),
+                _ => self.operator(item, &op),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Works out which proposals beyond the MVP the given items need, by looking at their types, the
+/// instructions in the bodies of functions that aren't cut, and their constant expressions. Each
+/// proposal is listed once, in alphabetical order, with the first item that needs it.
+///
+/// This only looks at the items themselves. Options that add things to the output, like
+/// `--passive-init`, can need more.
+pub fn find_required_features(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    cut_funcs: &IndexSet,
+) -> Result<Vec<RequiredFeature>> {
+    let mut found = Found::default();
+
+    let mut idx: u32 = 0;
+    for rg in &module.rec_groups {
+        for ty in rg.types() {
+            let item = WorkItem::Type(idx);
+            idx += 1;
+            if !all_uses.contains(item) {
+                continue;
+            }
+            if rg.is_explicit_rec_group() {
+                found.add("gc", item, "rec");
+            }
+            if !ty.is_final || ty.supertype_idx.is_some() {
+                found.add("gc", item, "sub");
+            }
+            if ty.composite_type.shared {
+                found.add("shared_everything_threads", item, "shared");
+            }
+            match &ty.composite_type.inner {
+                CompositeInnerType::Func(func_type) => {
+                    if func_type.results().len() > 1 {
+                        found.add("multi_value", item, "multiple results");
+                    }
+                    for ty in func_type.params().iter().chain(func_type.results()) {
+                        found.val_type(item, ty);
+                    }
+                }
+                CompositeInnerType::Array(_) => found.add("gc", item, "array"),
+                CompositeInnerType::Struct(_) => found.add("gc", item, "struct"),
+                CompositeInnerType::Cont(_) => found.add("stack_switching", item, "cont"),
+            }
+        }
+    }
+
+    for idx in all_uses.live_funcs.iter() {
+        if idx < module.num_imported_functions || cut_funcs.contains(idx) {
+            continue;
+        }
+        let item = WorkItem::Func(idx);
+        let func = &module.defined_funcs[(idx - module.num_imported_functions) as usize];
+        for (_, ty) in &func.locals {
+            found.val_type(item, ty);
+        }
+        for op in func.operators()? {
+            let op = op?;
+            let blockty = match &op {
+                Operator::Block { blockty }
+                | Operator::Loop { blockty }
+                | Operator::If { blockty }
+                | Operator::Try { blockty } => Some(blockty),
+                Operator::TryTable { try_table } => Some(&try_table.ty),
+                _ => None,
+            };
+            match blockty {
+                Some(BlockType::FuncType(_)) => found.add("multi_value", item, "block type"),
+                Some(BlockType::Type(ty)) => found.val_type(item, ty),
+                _ => {}
+            }
+            found.operator(item, &op);
+        }
+    }
+
+    if all_uses.live_tables.len() > 1 {
+        let item = WorkItem::Table(all_uses.live_tables.iter().nth(1).unwrap_or(0));
+        found.add("reference_types", item, "multiple tables");
+    }
+    for idx in all_uses.live_tables.iter() {
+        let item = WorkItem::Table(idx);
+        let ty = &module.table_types[idx as usize];
+        if ty.table64 {
+            found.add("memory64", item, "i64 table");
+        }
+        if ty.shared {
+            found.add("shared_everything_threads", item, "shared table");
+        }
+        if ty.element_type != RefType::FUNCREF {
+            found.ref_type(item, &ty.element_type);
+        }
+        if idx >= module.num_imported_tables {
+            let table = &module.defined_tables[(idx - module.num_imported_tables) as usize];
+            if let TableInit::Expr(expr) = &table.init {
+                found.add("function_references", item, "table initializer");
+                found.const_expr(item, expr)?;
+            }
+        }
+    }
+
+    if all_uses.live_memories.len() > 1 {
+        let item = WorkItem::Memory(all_uses.live_memories.iter().nth(1).unwrap_or(0));
+        found.add("multi_memory", item, "multiple memories");
+    }
+    for idx in all_uses.live_memories.iter() {
+        let item = WorkItem::Memory(idx);
+        let ty = &module.memory_types[idx as usize];
+        if ty.memory64 {
+            found.add("memory64", item, "i64 memory");
+        }
+        if ty.shared {
+            found.add("threads", item, "shared memory");
+        }
+        if ty.page_size_log2.is_some() {
+            found.add("custom_page_sizes", item, "custom page size");
+        }
+    }
+
+    for idx in all_uses.live_globals.iter() {
+        let item = WorkItem::Global(idx);
+        let ty = &module.global_types[idx as usize];
+        found.val_type(item, &ty.content_type);
+        if ty.shared {
+            found.add("shared_everything_threads", item, "shared global");
+        }
+        if idx < module.num_imported_globals {
+            if ty.mutable {
+                found.add("mutable_global", item, "imported mutable global");
+            }
+        } else {
+            let global = &module.defined_globals[(idx - module.num_imported_globals) as usize];
+            found.const_expr(item, &global.init_expr)?;
+        }
+    }
+
+    for idx in all_uses.live_tags.iter() {
+        found.add("exceptions", WorkItem::Tag(idx), "tag");
+    }
+
+    for idx in all_uses.live_elems.iter() {
+        let item = WorkItem::Elem(idx);
+        let elem = &module.elems[idx as usize];
+        match &elem.kind {
+            ElementKind::Passive => found.add("bulk_memory", item, "passive element segment"),
+            ElementKind::Declared => found.add("reference_types", item, "declared element segment"),
+            ElementKind::Active { offset_expr, .. } => found.const_expr(item, offset_expr)?,
+        }
+        if let ElementItems::Expressions(ty, exprs) = &elem.items {
+            if *ty != RefType::FUNCREF {
+                found.ref_type(item, ty);
+            }
+            for expr in exprs.clone() {
+                found.const_expr(item, &expr?)?;
+            }
+        }
+    }
+
+    for idx in all_uses.live_datas.iter() {
+        let item = WorkItem::Data(idx);
+        match &module.datas[idx as usize].kind {
+            DataKind::Passive => found.add("bulk_memory", item, "passive data segment"),
+            DataKind::Active { offset_expr, .. } => found.const_expr(item, offset_expr)?,
+        }
+    }
+
+    Ok(found
+        .0
+        .into_iter()
+        .map(|(proposal, (item, reason))| RequiredFeature {
+            proposal,
+            item,
+            reason,
+        })
+        .collect())
+}
+
+/// Gets the features for a proposal, named the way wasmparser does (e.g. `simd` or `gc`), with
+/// either underscores or dashes. `mvp` is nothing beyond the MVP, `default` is what wasmparser
+/// enables by default, and `all` is every proposal.
+pub fn feature_from_name(name: &str) -> Option<WasmFeatures> {
+    match name {
+        "mvp" => Some(WasmFeatures::empty()),
+        "default" => Some(WasmFeatures::default()),
+        "all" => Some(WasmFeatures::all()),
+        _ => WasmFeatures::from_name(&name.to_uppercase().replace('-', "_")),
+    }
+}
+
+/// Checks that the given items only need the allowed proposals, and then that the output module
+/// validates with only those proposals enabled, in case an option added something that needs
+/// more. Floats and reference types as such are always allowed, since they aren't proposals.
+pub fn check_features(
+    module: &ParsedModule,
+    required: &[RequiredFeature],
+    allowed: WasmFeatures,
+    output: &[u8],
+) -> Result<()> {
+    let disallowed: Vec<String> = required
+        .iter()
+        .filter(|feature| {
+            feature_from_name(feature.proposal).is_none_or(|needed| !allowed.contains(needed))
+        })
+        .map(|feature| {
+            format!(
+                "{} ({} uses {})",
+                feature.proposal,
+                module.describe(feature.item),
+                feature.reason
+            )
+        })
+        .collect();
+    if !disallowed.is_empty() {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "the kept items need proposals that are not allowed by --features: {}",
+                disallowed.join(", ")
+            ),
+        )
+        .into());
+    }
+
+    let features = allowed | WasmFeatures::FLOATS | WasmFeatures::GC_TYPES;
+    if let Err(err) = Validator::new_with_features(features).validate_all(output) {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "the output is not valid with only the proposals allowed by --features: {}",
+                err
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,183 @@
+//! A C interface to the isolation engine, so that tools written in other languages can link
+//! against wasm-isolate instead of running it as a subprocess. The declarations are in
+//! `include/wasm_isolate.h`.
+//!
+//! Everything here works on buffers in memory, so the same interface is exported when the library
+//! is compiled to WebAssembly. The host then uses [`wasm_isolate_alloc`] to make room in linear
+//! memory for the input and options.
+
+use std::ffi::{c_char, CStr};
+use std::panic::{self, AssertUnwindSafe};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::compression::decompress;
+use crate::config::{CustomSections, ItemRef, Roots};
+use crate::emit::{emit, CustomSectionFilter, EmitOptions};
+use crate::error::{error_kind, ErrorKind};
+use crate::index_set::IndexSet;
+use crate::liveness::{drop_unmatched_indirect_targets, find_live, LivenessOptions, WorkItem};
+use crate::module::ParsedModule;
+use crate::relocation::build_relocations;
+use crate::uses::Uses;
+
+/// What to isolate, as JSON. The fields mean the same as in a config file.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Options {
+    pub roots: Roots,
+    pub cut: Vec<ItemRef>,
+    pub custom_sections: CustomSections,
+    pub standalone: bool,
+    pub indirect_closure: bool,
+    pub chase_exports: bool,
+    pub strip_object_metadata: bool,
+}
+
+/// Isolates the roots described by some JSON options from a module, with the same defaults as
+/// the command line. The module may be compressed with gzip.
+pub fn isolate_with_options(wasm: &[u8], options_json: &str) -> Result<Vec<u8>> {
+    let options: Options =
+        serde_json::from_str(options_json).context("could not parse the options")?;
+    let wasm = decompress(wasm.to_vec(), "the input")?;
+    let module = ParsedModule::parse(&wasm)?;
+
+    let roots = options.roots.resolve(&module)?;
+    for root in &roots {
+        if !module.contains(*root) {
+            anyhow::bail!("{} {} is out of range", root.kind_name(), root.index());
+        }
+    }
+    let cut_funcs = options
+        .cut
+        .iter()
+        .map(|item| item.resolve(&module, WorkItem::Func))
+        .collect::<Result<IndexSet>>()?;
+
+    let mut requested = Uses::default();
+    for root in &roots {
+        requested.insert(*root);
+    }
+    let mut liveness_options = LivenessOptions {
+        cut_funcs: cut_funcs.clone(),
+        dropped: Uses::default(),
+        indirect_closure: options.indirect_closure,
+        chase_exports: options.chase_exports,
+        ..Default::default()
+    };
+    drop_unmatched_indirect_targets(&module, &roots, &mut liveness_options)?;
+    let all_uses = find_live(&module, roots, &liveness_options)?;
+    let relocations = build_relocations(&all_uses);
+    let custom_sections = CustomSectionFilter::new(
+        &options.custom_sections.keep,
+        &options.custom_sections.strip,
+    )?;
+    emit(
+        &module,
+        &all_uses,
+        &relocations,
+        &EmitOptions {
+            requested: &requested,
+            cut_funcs: &cut_funcs,
+            chase_exports: options.chase_exports,
+            standalone: options.standalone,
+            strip_object_metadata: options.strip_object_metadata,
+            custom_sections: &custom_sections,
+            ..Default::default()
+        },
+    )
+}
+
+/// Bytes allocated by wasm-isolate, which must be given back to [`wasm_isolate_buf_free`].
+#[repr(C)]
+pub struct WasmIsolateBuf {
+    pub ptr: *mut u8,
+    pub len: usize,
+}
+
+impl WasmIsolateBuf {
+    fn new(bytes: Vec<u8>) -> WasmIsolateBuf {
+        let bytes = Box::into_raw(bytes.into_boxed_slice());
+        WasmIsolateBuf {
+            ptr: bytes as *mut u8,
+            len: bytes.len(),
+        }
+    }
+}
+
+/// Isolates items from a module. `options_json` is a NUL-terminated JSON object like
+/// `{"roots": {"funcs": [12]}}`, or null for no options. On success, this returns 0 and `out_buf`
+/// holds the isolated module. Otherwise it returns the same status the command line would exit
+/// with, and `out_buf` holds the error message as UTF-8.
+///
+/// # Safety
+///
+/// `input_ptr` must point to `input_len` readable bytes, `options_json` must be null or a valid
+/// NUL-terminated string, and `out_buf` must point to writable memory for a `WasmIsolateBuf`.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_isolate_run(
+    input_ptr: *const u8,
+    input_len: usize,
+    options_json: *const c_char,
+    out_buf: *mut WasmIsolateBuf,
+) -> i32 {
+    let input = if input_len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(input_ptr, input_len)
+    };
+    let options = if options_json.is_null() {
+        Ok("{}")
+    } else {
+        CStr::from_ptr(options_json).to_str()
+    };
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| match options {
+        Ok(options) => isolate_with_options(input, options),
+        Err(err) => Err(anyhow::Error::new(err).context("the options are not valid UTF-8")),
+    }));
+    let (status, bytes) = match result {
+        Ok(Ok(bytes)) => (0, bytes),
+        Ok(Err(err)) => (
+            error_kind(&err).exit_code(),
+            format!("{:#}", err).into_bytes(),
+        ),
+        Err(_) => (
+            ErrorKind::Internal.exit_code(),
+            b"wasm-isolate panicked".to_vec(),
+        ),
+    };
+    out_buf.write(WasmIsolateBuf::new(bytes));
+    status as i32
+}
+
+/// Frees a buffer returned by [`wasm_isolate_run`].
+///
+/// # Safety
+///
+/// `buf` must have come from `wasm_isolate_run` and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_isolate_buf_free(buf: WasmIsolateBuf) {
+    wasm_isolate_free(buf.ptr, buf.len);
+}
+
+/// Allocates `len` zeroed bytes, for hosts that need to put the input somewhere wasm-isolate can
+/// read it, such as the linear memory of wasm-isolate compiled to WebAssembly. Free them with
+/// [`wasm_isolate_free`].
+#[no_mangle]
+pub extern "C" fn wasm_isolate_alloc(len: usize) -> *mut u8 {
+    WasmIsolateBuf::new(vec![0; len]).ptr
+}
+
+/// Frees bytes from [`wasm_isolate_alloc`], or the contents of a [`WasmIsolateBuf`].
+///
+/// # Safety
+///
+/// `ptr` and `len` must have come from one of those and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_isolate_free(ptr: *mut u8, len: usize) {
+    if !ptr.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+    }
+}
@@ -0,0 +1,289 @@
+//! Scripts that instantiate an isolated module and call its entry points, so that a repro can be
+//! run without writing any glue code by hand.
+
+use std::io::Write;
+
+use anyhow::Result;
+use wasmparser::{HeapType, RefType, TypeRef, ValType};
+
+use crate::module::ParsedModule;
+
+/// Writes a Node.js script that instantiates the module with dummy imports and calls each of the
+/// given function exports with default arguments, printing what they return or how they trap.
+pub fn write_js_harness(
+    w: &mut dyn Write,
+    module: &ParsedModule,
+    wasm_file: &str,
+    entry_points: &[String],
+) -> Result<()> {
+    writeln!(
+        w,
+        "// Generated by wasm-isolate. Run with `node <this file>`."
+    )?;
+    writeln!(w, "const fs = require(\"fs\");")?;
+    writeln!(w, "const path = require(\"path\");")?;
+    writeln!(w)?;
+    writeln!(
+        w,
+        "const bytes = fs.readFileSync(path.join(__dirname, {}));",
+        js_string(wasm_file)
+    )?;
+
+    // Group the imports by module, in the order they first appear.
+    let mut modules: Vec<(&str, Vec<(&str, String)>)> = vec![];
+    for import in &module.imports {
+        let value = js_import(module, import.module, import.name, import.ty)?;
+        match modules.iter_mut().find(|(name, _)| *name == import.module) {
+            Some((_, fields)) => fields.push((import.name, value)),
+            None => modules.push((import.module, vec![(import.name, value)])),
+        }
+    }
+    writeln!(w, "const imports = {{")?;
+    for (module_name, fields) in &modules {
+        writeln!(w, "  {}: {{", js_string(module_name))?;
+        for (name, value) in fields {
+            writeln!(w, "    {}: {},", js_string(name), value)?;
+        }
+        writeln!(w, "  }},")?;
+    }
+    writeln!(w, "}};")?;
+    writeln!(
+        w,
+        "const instance = new WebAssembly.Instance(new WebAssembly.Module(bytes), imports);"
+    )?;
+    writeln!(w)?;
+    writeln!(w, "function call(name, args) {{")?;
+    writeln!(w, "  const desc = `${{name}}(${{args.join(\", \")}})`;")?;
+    writeln!(w, "  try {{")?;
+    writeln!(
+        w,
+        "    console.log(`${{desc}} returned`, instance.exports[name](...args));"
+    )?;
+    writeln!(w, "  }} catch (e) {{")?;
+    writeln!(w, "    console.log(`${{desc}} trapped: ${{e.message}}`);")?;
+    writeln!(w, "  }}")?;
+    writeln!(w, "}}")?;
+    writeln!(w)?;
+
+    for name in entry_points {
+        let Some(ty) = entry_point_type(module, name)? else {
+            continue;
+        };
+        let args: Option<Vec<String>> = ty.params().iter().map(|ty| js_default(*ty)).collect();
+        match args {
+            Some(args) => writeln!(w, "call({}, [{}]);", js_string(name), args.join(", "))?,
+            None => writeln!(
+                w,
+                "// {} takes parameters that cannot be created from JS.",
+                name
+            )?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes a shell script that calls each of the given function exports with wasmtime, using
+/// default arguments. Wasmtime can only stub out imported functions, so modules that import
+/// anything else should be isolated with `--standalone` first.
+pub fn write_wasmtime_harness(
+    w: &mut dyn Write,
+    module: &ParsedModule,
+    wasm_file: &str,
+    entry_points: &[String],
+) -> Result<()> {
+    writeln!(w, "#!/bin/sh")?;
+    writeln!(
+        w,
+        "# Generated by wasm-isolate. Calls each entry point of the isolated module with wasmtime."
+    )?;
+    for import in &module.imports {
+        if !matches!(import.ty, TypeRef::Func(_)) {
+            writeln!(
+                w,
+                "# Warning: wasmtime cannot stub the import {}.{}, so instantiation will fail. Isolate with --standalone to avoid this.",
+                import.module, import.name
+            )?;
+        }
+    }
+    writeln!(w, "cd \"$(dirname \"$0\")\"")?;
+    for name in entry_points {
+        let Some(ty) = entry_point_type(module, name)? else {
+            continue;
+        };
+        let args: Option<Vec<&str>> = ty
+            .params()
+            .iter()
+            .map(|ty| match ty {
+                ValType::I32 | ValType::I64 | ValType::F32 | ValType::F64 => Some("0"),
+                _ => None,
+            })
+            .collect();
+        match args {
+            Some(args) => {
+                let mut line = format!(
+                    "wasmtime run -W unknown-imports-default=y --invoke {} {}",
+                    shell_string(name),
+                    shell_string(wasm_file)
+                );
+                for arg in args {
+                    line.push(' ');
+                    line.push_str(arg);
+                }
+                writeln!(w, "{}", line)?;
+            }
+            None => writeln!(
+                w,
+                "# {} takes parameters that cannot be passed on the command line.",
+                name
+            )?,
+        }
+    }
+    Ok(())
+}
+
+/// Finds the signature of an exported function, or nothing if the export is not a function.
+fn entry_point_type<'a>(
+    module: &'a ParsedModule,
+    name: &str,
+) -> Result<Option<&'a wasmparser::FuncType>> {
+    let Some(export) = module
+        .exports
+        .iter()
+        .find(|e| e.name == name && e.kind == wasmparser::ExternalKind::Func)
+    else {
+        return Ok(None);
+    };
+    Ok(Some(
+        module.signature(module.func_types[export.index as usize])?,
+    ))
+}
+
+/// A JS expression for a dummy import of the given type.
+fn js_import(module: &ParsedModule, module_name: &str, name: &str, ty: TypeRef) -> Result<String> {
+    Ok(match ty {
+        TypeRef::Func(type_idx) => {
+            let results = module.signature(type_idx)?.results();
+            let values: Option<Vec<String>> = results.iter().map(|ty| js_default(*ty)).collect();
+            let body = match values {
+                Some(values) if values.is_empty() => String::new(),
+                Some(values) if values.len() == 1 => format!(" return {};", values[0]),
+                Some(values) => format!(" return [{}];", values.join(", ")),
+                None => " throw new Error(\"cannot return these types from JS\");".to_string(),
+            };
+            format!(
+                "(...args) => {{ console.log(\"called {}.{} with\", args);{} }}",
+                module_name.escape_default(),
+                name.escape_default(),
+                body
+            )
+        }
+        TypeRef::Memory(ty) => {
+            let mut desc = vec![format!("initial: {}", js_limit(ty.initial, ty.memory64))];
+            if let Some(max) = ty.maximum {
+                desc.push(format!("maximum: {}", js_limit(max, ty.memory64)));
+            }
+            if ty.shared {
+                desc.push("shared: true".to_string());
+            }
+            if ty.memory64 {
+                desc.push("address: \"i64\"".to_string());
+            }
+            format!("new WebAssembly.Memory({{ {} }})", desc.join(", "))
+        }
+        TypeRef::Table(ty) => {
+            let Some(element) = js_ref_type(ty.element_type) else {
+                return Ok("undefined /* tables of this type cannot be created from JS */".into());
+            };
+            let mut desc = vec![
+                format!("element: \"{}\"", element),
+                format!("initial: {}", js_limit(ty.initial, ty.table64)),
+            ];
+            if let Some(max) = ty.maximum {
+                desc.push(format!("maximum: {}", js_limit(max, ty.table64)));
+            }
+            if ty.table64 {
+                desc.push("address: \"i64\"".to_string());
+            }
+            format!("new WebAssembly.Table({{ {} }})", desc.join(", "))
+        }
+        TypeRef::Global(ty) => {
+            let (Some(value), Some(init)) =
+                (js_val_type(ty.content_type), js_default(ty.content_type))
+            else {
+                return Ok("undefined /* globals of this type cannot be created from JS */".into());
+            };
+            format!(
+                "new WebAssembly.Global({{ value: \"{}\", mutable: {} }}, {})",
+                value, ty.mutable, init
+            )
+        }
+        TypeRef::Tag(ty) => {
+            let params: Option<Vec<String>> = module
+                .signature(ty.func_type_idx)?
+                .params()
+                .iter()
+                .map(|ty| js_val_type(*ty).map(|ty| format!("\"{}\"", ty)))
+                .collect();
+            match params {
+                Some(params) => format!(
+                    "new WebAssembly.Tag({{ parameters: [{}] }})",
+                    params.join(", ")
+                ),
+                None => "undefined /* tags of this type cannot be created from JS */".to_string(),
+            }
+        }
+    })
+}
+
+/// The default value of a type as a JS expression, if JS can represent it.
+fn js_default(ty: ValType) -> Option<String> {
+    match ty {
+        ValType::I32 | ValType::F32 | ValType::F64 => Some("0".to_string()),
+        ValType::I64 => Some("0n".to_string()),
+        ValType::V128 => None,
+        ValType::Ref(ty) => ty.is_nullable().then(|| "null".to_string()),
+    }
+}
+
+/// The name of a type in the JS API, if it has one.
+fn js_val_type(ty: ValType) -> Option<&'static str> {
+    match ty {
+        ValType::I32 => Some("i32"),
+        ValType::I64 => Some("i64"),
+        ValType::F32 => Some("f32"),
+        ValType::F64 => Some("f64"),
+        ValType::V128 => None,
+        ValType::Ref(ty) => js_ref_type(ty),
+    }
+}
+
+fn js_ref_type(ty: RefType) -> Option<&'static str> {
+    match ty.heap_type() {
+        HeapType::Abstract {
+            shared: false,
+            ty: wasmparser::AbstractHeapType::Func,
+        } => Some("anyfunc"),
+        HeapType::Abstract {
+            shared: false,
+            ty: wasmparser::AbstractHeapType::Extern,
+        } => Some("externref"),
+        _ => None,
+    }
+}
+
+/// Memory and table limits are BigInts when the index type is i64.
+fn js_limit(value: u64, is_64: bool) -> String {
+    if is_64 {
+        format!("{}n", value)
+    } else {
+        value.to_string()
+    }
+}
+
+fn js_string(s: &str) -> String {
+    serde_json::to_string(s).expect("strings can always be serialized")
+}
+
+fn shell_string(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
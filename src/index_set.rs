@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+/// A set of indices into one of a module's index spaces, stored as a sparse bitset. Membership
+/// checks and merges are cheap even for modules with hundreds of thousands of items, and
+/// iteration is always in index order so that anything built from a set is deterministic.
+#[derive(Default, Clone, PartialEq, Eq)]
+pub struct IndexSet {
+    /// 64-bit words of the bitset, keyed by the index of their first bit divided by 64. Words
+    /// with no bits set are never stored.
+    words: BTreeMap<u32, u64>,
+    len: usize,
+}
+
+impl IndexSet {
+    pub fn new() -> IndexSet {
+        Self::default()
+    }
+
+    /// Adds an index to the set, returning whether it was newly added.
+    pub fn insert(&mut self, idx: u32) -> bool {
+        let word = self.words.entry(idx / 64).or_default();
+        let bit = 1 << (idx % 64);
+        if *word & bit != 0 {
+            return false;
+        }
+        *word |= bit;
+        self.len += 1;
+        true
+    }
+
+    /// Removes an index from the set, returning whether it was there.
+    pub fn remove(&mut self, idx: u32) -> bool {
+        let Some(word) = self.words.get_mut(&(idx / 64)) else {
+            return false;
+        };
+        let bit = 1 << (idx % 64);
+        if *word & bit == 0 {
+            return false;
+        }
+        *word &= !bit;
+        if *word == 0 {
+            self.words.remove(&(idx / 64));
+        }
+        self.len -= 1;
+        true
+    }
+
+    pub fn contains(&self, idx: u32) -> bool {
+        match self.words.get(&(idx / 64)) {
+            Some(word) => word & (1 << (idx % 64)) != 0,
+            None => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds every index from another set to this one.
+    pub fn union_with(&mut self, other: &IndexSet) {
+        for (key, other_word) in &other.words {
+            let word = self.words.entry(*key).or_default();
+            self.len += (other_word & !*word).count_ones() as usize;
+            *word |= other_word;
+        }
+    }
+
+    /// Iterates over the indices in the set, in increasing order.
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.words.iter().flat_map(|(key, word)| {
+            let word = *word;
+            (0..64)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| key * 64 + bit)
+        })
+    }
+}
+
+impl Debug for IndexSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl FromIterator<u32> for IndexSet {
+    fn from_iter<I: IntoIterator<Item = u32>>(iter: I) -> Self {
+        let mut res = IndexSet::new();
+        res.extend(iter);
+        res
+    }
+}
+
+impl Extend<u32> for IndexSet {
+    fn extend<I: IntoIterator<Item = u32>>(&mut self, iter: I) {
+        for idx in iter {
+            self.insert(idx);
+        }
+    }
+}
+
+/// Sets are written as a list of their indices, in increasing order.
+#[cfg(feature = "serde-impls")]
+impl serde::Serialize for IndexSet {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+#[cfg(feature = "serde-impls")]
+impl<'de> serde::Deserialize<'de> for IndexSet {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Vec::<u32>::deserialize(deserializer)?.into_iter().collect())
+    }
+}
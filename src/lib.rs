@@ -0,0 +1,32 @@
+pub mod analyze;
+pub mod artifacts;
+pub mod cache;
+pub mod compression;
+pub mod config;
+pub mod data;
+pub mod demangle;
+pub mod diff;
+pub mod diff_live;
+pub mod emit;
+pub mod error;
+pub mod features;
+pub mod ffi;
+pub mod harness;
+pub mod index_set;
+pub mod link;
+pub mod liveness;
+pub mod module;
+pub mod patch;
+pub mod print;
+pub mod profile;
+pub mod reduce;
+pub mod relocation;
+#[cfg(feature = "run")]
+pub mod run;
+pub mod schema;
+pub mod stack_trace;
+pub mod stats;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod uses;
+pub mod wat;
@@ -0,0 +1,499 @@
+//! Linking stubs from a "fake host" module into an isolated module, so that the output defines
+//! what it would otherwise import. Each remaining import is matched by name to an export of the
+//! same kind in the stub module, and that export is copied in along with everything it needs.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use wasm_encoder::{
+    reencode::Reencode, CodeSection, DataCountSection, DataSection, ElementSection, ExportSection,
+    FunctionSection, GlobalSection, ImportSection, MemorySection, Module, NameMap, NameSection,
+    StartSection, TableSection, TagSection, TypeSection,
+};
+
+use crate::emit::VerbatimSection;
+use crate::error::{Error, ErrorKind};
+use crate::index_set::IndexSet;
+use crate::liveness::{find_live, LivenessOptions, WorkItem};
+use crate::module::{ParsedModule, Section};
+use crate::relocation::{RelocatingReencoder, Relocation, Relocations};
+use crate::uses::{get_type_uses, Uses};
+use crate::wat;
+
+/// An isolated module with stubs linked into it.
+pub struct Linked {
+    pub wasm: Vec<u8>,
+    /// The new index of every item of the isolated module. Imports that were linked have the index
+    /// of the stub that replaced them.
+    pub relocations: Relocations,
+    /// The imports that were replaced by stubs, as `module.name`.
+    pub linked: Vec<String>,
+}
+
+/// The index spaces that imports can provide items for.
+const IMPORTABLE: [fn(u32) -> WorkItem; 5] = [
+    WorkItem::Func,
+    WorkItem::Table,
+    WorkItem::Memory,
+    WorkItem::Global,
+    WorkItem::Tag,
+];
+
+/// Replaces the imports of `module` that `stubs` exports a definition for. The module's own items
+/// come first in each index space, followed by the stubs and whatever they need. Stubs that import
+/// something the module also imports, with the same type, share the module's import.
+///
+/// Only function names are kept in the name section, since stubs and the items they replace may
+/// disagree about locals and labels.
+pub fn link_stubs(module: &ParsedModule, stubs: &ParsedModule) -> Result<Linked> {
+    let mut links: Vec<(WorkItem, WorkItem)> = vec![];
+    let mut linked: Vec<String> = vec![];
+    for item in imported_items(module) {
+        let import = module.import_of(item).expect("imported items have imports");
+        let Some(stub) = stubs
+            .find_export(import.name)
+            .filter(|stub| stub.kind_name() == item.kind_name())
+        else {
+            continue;
+        };
+        let (expected, found) = (wat::item_type(module, item), wat::item_type(stubs, stub));
+        if expected != found {
+            return Err(Error::new(
+                ErrorKind::InvalidRequest,
+                format!(
+                    "the stub for {}.{} is {}, but the import is {}",
+                    import.module,
+                    import.name,
+                    found.unwrap_or_default(),
+                    expected.unwrap_or_default()
+                ),
+            )
+            .into());
+        }
+        log::debug!(
+            "linking {}.{} to the stub {}",
+            import.module,
+            import.name,
+            stub
+        );
+        links.push((item, stub));
+        linked.push(format!("{}.{}", import.module, import.name));
+    }
+
+    let roots: Vec<WorkItem> = links.iter().map(|(_, stub)| *stub).collect();
+    let mut stub_uses = find_live(stubs, roots, &LivenessOptions::default())?;
+    keep_whole_rec_groups(stubs, &mut stub_uses);
+
+    // Lay out each index space: the module's remaining imports, then any new imports for the
+    // stubs, then the module's definitions, and finally the stubs' definitions.
+    let mut relocations = Relocations::default();
+    let mut stub_relocations = Relocations::default();
+    let mut stub_imports: Vec<WorkItem> = vec![];
+    let num_types = module.types.len() as u32;
+    for idx in 0..num_types {
+        relocations.insert(WorkItem::Type(idx), idx);
+    }
+    for (i, idx) in stub_uses.live_types.iter().enumerate() {
+        stub_relocations.insert(WorkItem::Type(idx), num_types + i as u32);
+    }
+    for make in IMPORTABLE {
+        let (num_imported, len) = space(module, make);
+        let (stub_num_imported, _) = space(stubs, make);
+        let is_linked = |item: WorkItem| links.iter().any(|(import, _)| *import == item);
+        let mut next: u32 = 0;
+        for idx in 0..num_imported {
+            if !is_linked(make(idx)) {
+                relocations.insert(make(idx), next);
+                next += 1;
+            }
+        }
+        for item in live_in(&stub_uses, make).filter(|item| item.index() < stub_num_imported) {
+            let shared = (0..num_imported)
+                .map(make)
+                .filter(|item| !is_linked(*item))
+                .find(|own| same_import(module, *own, stubs, item));
+            match shared {
+                Some(own) => stub_relocations.insert(item, own.index()),
+                None => {
+                    stub_relocations.insert(item, next);
+                    stub_imports.push(item);
+                    next += 1;
+                }
+            }
+        }
+        for idx in num_imported..len {
+            relocations.insert(make(idx), next);
+            next += 1;
+        }
+        for item in live_in(&stub_uses, make).filter(|item| item.index() >= stub_num_imported) {
+            stub_relocations.insert(item, next);
+            next += 1;
+        }
+    }
+    for (import, stub) in &links {
+        let new_idx = stub_relocations
+            .get(&Relocation::from(*stub))
+            .copied()
+            .expect("stubs are live");
+        relocations.insert(*import, new_idx);
+    }
+    for idx in 0..module.elems.len() as u32 {
+        relocations.insert(WorkItem::Elem(idx), idx);
+    }
+    for (i, idx) in stub_uses.live_elems.iter().enumerate() {
+        stub_relocations.insert(WorkItem::Elem(idx), module.elems.len() as u32 + i as u32);
+    }
+    for idx in 0..module.datas.len() as u32 {
+        relocations.insert(WorkItem::Data(idx), idx);
+    }
+    for (i, idx) in stub_uses.live_datas.iter().enumerate() {
+        stub_relocations.insert(WorkItem::Data(idx), module.datas.len() as u32 + i as u32);
+    }
+
+    let wasm = encode(
+        module,
+        stubs,
+        &stub_uses,
+        &relocations,
+        &stub_relocations,
+        &links,
+        &stub_imports,
+    )?;
+    Ok(Linked {
+        wasm,
+        relocations,
+        linked,
+    })
+}
+
+/// Writes out the linked module, with the items of each section in the order they were laid out.
+fn encode(
+    module: &ParsedModule,
+    stubs: &ParsedModule,
+    stub_uses: &Uses,
+    relocations: &Relocations,
+    stub_relocations: &Relocations,
+    links: &[(WorkItem, WorkItem)],
+    stub_imports: &[WorkItem],
+) -> Result<Vec<u8>> {
+    let no_dropped = Uses::default();
+    let mut own = RelocatingReencoder::new(relocations, &no_dropped, &module.global_types);
+    let mut other = RelocatingReencoder::new(stub_relocations, &no_dropped, &stubs.global_types);
+    let mut out = Module::new();
+
+    // Custom sections before the first known section, like `dylink.0`, have to stay there.
+    let leading = module
+        .sections
+        .iter()
+        .position(|section| !section.is_custom())
+        .unwrap_or(module.sections.len());
+    for (section, range) in module.sections[..leading]
+        .iter()
+        .zip(&module.section_ranges)
+    {
+        if *section == Section::Custom {
+            out.section(&VerbatimSection::new(module.buf, range.clone()));
+        }
+    }
+
+    let mut types = TypeSection::new();
+    for rg in &module.rec_groups {
+        own.parse_recursive_type_group(types.ty(), rg.clone())?;
+    }
+    let mut idx: u32 = 0;
+    for rg in &stubs.rec_groups {
+        if stub_uses.live_types.contains(idx) {
+            other.parse_recursive_type_group(types.ty(), rg.clone())?;
+        }
+        idx += rg.types().len() as u32;
+    }
+    if !types.is_empty() {
+        out.section(&types);
+    }
+
+    let mut imports = ImportSection::new();
+    for import in &module.imports {
+        let is_linked = links.iter().any(|(item, _)| {
+            module
+                .import_of(*item)
+                .is_some_and(|linked| std::ptr::eq(linked, import))
+        });
+        if !is_linked {
+            own.parse_import(&mut imports, *import)?;
+        }
+    }
+    for item in stub_imports {
+        let import = stubs.import_of(*item).expect("imported items have imports");
+        other.parse_import(&mut imports, *import)?;
+    }
+    if !imports.is_empty() {
+        out.section(&imports);
+    }
+
+    let stub_defined = |make: fn(u32) -> WorkItem, num_imported: u32| {
+        live_in(stub_uses, make).filter(move |item| item.index() >= num_imported)
+    };
+
+    let mut funcs = FunctionSection::new();
+    for idx in module.num_imported_functions..module.func_types.len() as u32 {
+        funcs.function(own.type_index(module.func_types[idx as usize]));
+    }
+    for item in stub_defined(WorkItem::Func, stubs.num_imported_functions) {
+        funcs.function(other.type_index(stubs.func_types[item.index() as usize]));
+    }
+    if !funcs.is_empty() {
+        out.section(&funcs);
+    }
+
+    let mut tables = TableSection::new();
+    for table in &module.defined_tables {
+        own.parse_table(&mut tables, table.clone())?;
+    }
+    for item in stub_defined(WorkItem::Table, stubs.num_imported_tables) {
+        let table = &stubs.defined_tables[(item.index() - stubs.num_imported_tables) as usize];
+        other.parse_table(&mut tables, table.clone())?;
+    }
+    if !tables.is_empty() {
+        out.section(&tables);
+    }
+
+    let mut memories = MemorySection::new();
+    for ty in &module.memory_types[module.num_imported_memories as usize..] {
+        memories.memory(own.memory_type(*ty));
+    }
+    for item in stub_defined(WorkItem::Memory, stubs.num_imported_memories) {
+        memories.memory(other.memory_type(stubs.memory_types[item.index() as usize]));
+    }
+    if !memories.is_empty() {
+        out.section(&memories);
+    }
+
+    let mut tags = TagSection::new();
+    for ty in &module.tag_types[module.num_imported_tags as usize..] {
+        tags.tag(own.tag_type(*ty));
+    }
+    for item in stub_defined(WorkItem::Tag, stubs.num_imported_tags) {
+        tags.tag(other.tag_type(stubs.tag_types[item.index() as usize]));
+    }
+    if !tags.is_empty() {
+        out.section(&tags);
+    }
+
+    let mut globals = GlobalSection::new();
+    for global in &module.defined_globals {
+        own.parse_global(&mut globals, global.clone())?;
+    }
+    for item in stub_defined(WorkItem::Global, stubs.num_imported_globals) {
+        let global = &stubs.defined_globals[(item.index() - stubs.num_imported_globals) as usize];
+        other.parse_global(&mut globals, global.clone())?;
+    }
+    if !globals.is_empty() {
+        out.section(&globals);
+    }
+
+    let mut exports = ExportSection::new();
+    for export in &module.exports {
+        own.parse_export(&mut exports, *export);
+    }
+    if !exports.is_empty() {
+        out.section(&exports);
+    }
+
+    if let Some(start_idx) = module.start_idx {
+        out.section(&StartSection {
+            function_index: own.function_index(start_idx),
+        });
+    }
+
+    let mut elems = ElementSection::new();
+    for elem in &module.elems {
+        own.parse_element(&mut elems, elem.clone())?;
+    }
+    for idx in stub_uses.live_elems.iter() {
+        other.parse_element(&mut elems, stubs.elems[idx as usize].clone())?;
+    }
+    if !elems.is_empty() {
+        out.section(&elems);
+    }
+
+    let num_datas = module.datas.len() + stub_uses.live_datas.len();
+    let had_data_count = |module: &ParsedModule| module.sections.contains(&Section::DataCount);
+    if num_datas > 0 && (had_data_count(module) || had_data_count(stubs)) {
+        out.section(&DataCountSection {
+            count: num_datas as u32,
+        });
+    }
+
+    let mut code = CodeSection::new();
+    for func in &module.defined_funcs {
+        own.parse_function_body(&mut code, func.body.clone())?;
+    }
+    for item in stub_defined(WorkItem::Func, stubs.num_imported_functions) {
+        let func = &stubs.defined_funcs[(item.index() - stubs.num_imported_functions) as usize];
+        other.parse_function_body(&mut code, func.body.clone())?;
+    }
+    if !code.is_empty() {
+        out.section(&code);
+    }
+
+    let mut datas = DataSection::new();
+    for data in &module.datas {
+        own.parse_data(&mut datas, data.clone())?;
+    }
+    for idx in stub_uses.live_datas.iter() {
+        other.parse_data(&mut datas, stubs.datas[idx as usize].clone())?;
+    }
+    if !datas.is_empty() {
+        out.section(&datas);
+    }
+
+    for (section, range) in module.sections[leading..]
+        .iter()
+        .zip(&module.section_ranges[leading..])
+    {
+        match section {
+            // The module was isolated already, so its producers section is up to date.
+            Section::Custom | Section::Producers => {
+                out.section(&VerbatimSection::new(module.buf, range.clone()));
+            }
+            Section::Names => {
+                out.section(&build_name_section(
+                    module,
+                    stubs,
+                    stub_uses,
+                    relocations,
+                    stub_relocations,
+                ));
+            }
+            // Code offsets change when bodies are reencoded, so the hints would point at the
+            // wrong instructions.
+            Section::BranchHints => {
+                log::warn!("leaving out branch hints, which linking stubs does not keep");
+            }
+            Section::CodeMetadata => {
+                log::warn!(
+                    "leaving out the {} section, which linking stubs does not keep",
+                    module.custom_section_name(range)?
+                );
+            }
+            _ => {}
+        }
+    }
+
+    Ok(out.finish())
+}
+
+/// Names every function in the linked module, from the name sections of both modules.
+fn build_name_section(
+    module: &ParsedModule,
+    stubs: &ParsedModule,
+    stub_uses: &Uses,
+    relocations: &Relocations,
+    stub_relocations: &Relocations,
+) -> NameSection {
+    let mut func_names: BTreeMap<u32, &str> = BTreeMap::new();
+    for (idx, name) in &module.names.funcs {
+        if let Some(new_idx) = relocations.get(&Relocation::Func(*idx)) {
+            func_names.entry(*new_idx).or_insert(name);
+        }
+    }
+    for (idx, name) in &stubs.names.funcs {
+        if !stub_uses.live_funcs.contains(*idx) {
+            continue;
+        }
+        if let Some(new_idx) = stub_relocations.get(&Relocation::Func(*idx)) {
+            func_names.entry(*new_idx).or_insert(name);
+        }
+    }
+
+    let mut names = NameSection::new();
+    if let Some(name) = module.names.module {
+        names.module(name);
+    }
+    let mut map = NameMap::new();
+    for (idx, name) in func_names {
+        map.append(idx, name);
+    }
+    names.functions(&map);
+    names
+}
+
+/// Lists every imported item, one index space at a time.
+fn imported_items(module: &ParsedModule) -> Vec<WorkItem> {
+    IMPORTABLE
+        .iter()
+        .flat_map(|make| (0..space(module, *make).0).map(*make))
+        .collect()
+}
+
+/// The number of imported items in an index space, and the size of the whole space.
+fn space(module: &ParsedModule, make: fn(u32) -> WorkItem) -> (u32, u32) {
+    match make(0) {
+        WorkItem::Func(_) => (
+            module.num_imported_functions,
+            module.func_types.len() as u32,
+        ),
+        WorkItem::Table(_) => (module.num_imported_tables, module.table_types.len() as u32),
+        WorkItem::Memory(_) => (
+            module.num_imported_memories,
+            module.memory_types.len() as u32,
+        ),
+        WorkItem::Global(_) => (
+            module.num_imported_globals,
+            module.global_types.len() as u32,
+        ),
+        WorkItem::Tag(_) => (module.num_imported_tags, module.tag_types.len() as u32),
+        _ => (0, 0),
+    }
+}
+
+/// The live items in one index space, in order.
+fn live_in(uses: &Uses, make: fn(u32) -> WorkItem) -> impl Iterator<Item = WorkItem> + '_ {
+    let live = match make(0) {
+        WorkItem::Func(_) => &uses.live_funcs,
+        WorkItem::Table(_) => &uses.live_tables,
+        WorkItem::Memory(_) => &uses.live_memories,
+        WorkItem::Global(_) => &uses.live_globals,
+        WorkItem::Tag(_) => &uses.live_tags,
+        WorkItem::Type(_) => &uses.live_types,
+        WorkItem::Data(_) => &uses.live_datas,
+        WorkItem::Elem(_) => &uses.live_elems,
+    };
+    live.iter().map(make)
+}
+
+/// Whether two imports have the same name and type, so that one can stand in for the other.
+fn same_import(module: &ParsedModule, own: WorkItem, stubs: &ParsedModule, item: WorkItem) -> bool {
+    match (module.import_of(own), stubs.import_of(item)) {
+        (Some(a), Some(b)) => {
+            a.module == b.module
+                && a.name == b.name
+                && wat::item_type(module, own) == wat::item_type(stubs, item)
+        }
+        _ => false,
+    }
+}
+
+/// Types are copied a whole rec group at a time, so keeps every type in a rec group that has a
+/// live type, along with the types those refer to.
+fn keep_whole_rec_groups(module: &ParsedModule, uses: &mut Uses) {
+    let mut group_of: Vec<std::ops::Range<u32>> = vec![];
+    for rg in &module.rec_groups {
+        let start = group_of.last().map_or(0, |group| group.end);
+        let len = rg.types().len() as u32;
+        group_of.extend(std::iter::repeat_n(start..start + len, len as usize));
+    }
+
+    let mut seen = IndexSet::new();
+    let mut queue: Vec<u32> = uses.live_types.iter().collect();
+    while let Some(idx) = queue.pop() {
+        if !seen.insert(idx) {
+            continue;
+        }
+        for member in group_of[idx as usize].clone() {
+            uses.live_types.insert(member);
+            queue.push(member);
+        }
+        queue.extend(get_type_uses(&module.types[idx as usize]).live_types.iter());
+    }
+}
@@ -0,0 +1,820 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    str::FromStr,
+};
+
+use anyhow::Result;
+use rayon::prelude::*;
+use wasmparser::{DataKind, Element, ElementKind, Operator, SubType, TableInit};
+
+use crate::data::{AccessScanner, MemoryAccesses};
+use crate::features::operator_name;
+use crate::index_set::IndexSet;
+use crate::module::{Func, ParsedModule};
+use crate::uses::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde-impls", derive(serde::Serialize, serde::Deserialize))]
+pub enum WorkItem {
+    Type(u32),
+    Func(u32),
+    Table(u32),
+    Global(u32),
+    Memory(u32),
+    Data(u32),
+    Elem(u32),
+    Tag(u32),
+}
+
+impl FromStr for WorkItem {
+    type Err = anyhow::Error;
+
+    /// Parses an item in the form `<kind>:<index>`, e.g. `func:5`.
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((kind, idx)) = s.split_once(':') else {
+            anyhow::bail!("expected an item like \"func:5\", but got \"{}\"", s);
+        };
+        let idx: u32 = idx.parse()?;
+        Ok(match kind {
+            "type" => WorkItem::Type(idx),
+            "func" => WorkItem::Func(idx),
+            "table" => WorkItem::Table(idx),
+            "global" => WorkItem::Global(idx),
+            "memory" => WorkItem::Memory(idx),
+            "data" => WorkItem::Data(idx),
+            "elem" => WorkItem::Elem(idx),
+            "tag" => WorkItem::Tag(idx),
+            _ => anyhow::bail!("unknown item kind \"{}\"", kind),
+        })
+    }
+}
+
+impl fmt::Display for WorkItem {
+    /// Formats the item the same way it is parsed, e.g. `func:5`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.kind_name(), self.index())
+    }
+}
+
+impl WorkItem {
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            WorkItem::Type(_) => "type",
+            WorkItem::Func(_) => "func",
+            WorkItem::Table(_) => "table",
+            WorkItem::Global(_) => "global",
+            WorkItem::Memory(_) => "memory",
+            WorkItem::Data(_) => "data",
+            WorkItem::Elem(_) => "elem",
+            WorkItem::Tag(_) => "tag",
+        }
+    }
+
+    pub fn index(&self) -> u32 {
+        match self {
+            WorkItem::Type(idx)
+            | WorkItem::Func(idx)
+            | WorkItem::Table(idx)
+            | WorkItem::Global(idx)
+            | WorkItem::Memory(idx)
+            | WorkItem::Data(idx)
+            | WorkItem::Elem(idx)
+            | WorkItem::Tag(idx) => *idx,
+        }
+    }
+}
+
+/// A named set of roots, so that kept items can be attributed to the groups that need them.
+#[derive(Clone, Debug)]
+pub struct RootGroup {
+    pub name: String,
+    pub roots: Vec<WorkItem>,
+}
+
+impl FromStr for RootGroup {
+    type Err = anyhow::Error;
+
+    /// Parses a group in the form `<name>=<items>`, e.g. `crash=funcs:12,globals:3`. Kinds may be
+    /// singular or plural, as in config files.
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((name, items)) = s.split_once('=') else {
+            anyhow::bail!(
+                "expected a group like \"crash=funcs:12\", but got \"{}\"",
+                s
+            );
+        };
+        // Names end up in file names with --split-out, so they are kept simple.
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!(
+                "group names may only contain letters, digits, '-', and '_', but got \"{}\"",
+                name
+            );
+        }
+        let mut roots: Vec<WorkItem> = vec![];
+        for item in items.split(',') {
+            let item = match item.split_once(':') {
+                Some(("memories", idx)) => format!("memory:{}", idx),
+                Some((kind, idx)) => format!("{}:{}", kind.strip_suffix('s').unwrap_or(kind), idx),
+                None => item.to_string(),
+            };
+            roots.push(item.parse()?);
+        }
+        Ok(RootGroup {
+            name: name.to_string(),
+            roots,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct LivenessOptions {
+    /// Functions whose bodies should not be searched for uses.
+    pub cut_funcs: IndexSet,
+    /// Items that are never kept, even if live code refers to them.
+    pub dropped: Uses,
+    /// Whether to keep the possible targets of `call_indirect`.
+    pub indirect_closure: bool,
+    /// Whether to keep the active element segments of live tables.
+    pub chase_exports: bool,
+    /// Whether to keep passive element segments that refer to a live function.
+    pub keep_passive_elems: bool,
+    /// Whether to leave out declared element segments that refer to a live function, which are
+    /// kept otherwise so that they keep their indices.
+    pub drop_declared_elems: bool,
+    /// Whether to ignore the functions in element segments, to find the ones nothing else needs.
+    pub skip_elem_funcs: bool,
+}
+
+/// Lists every item in a set of uses, one index space at a time.
+pub fn live_items(uses: &Uses) -> Vec<WorkItem> {
+    let mut res: Vec<WorkItem> = vec![];
+    res.extend(uses.live_types.iter().map(WorkItem::Type));
+    res.extend(uses.live_funcs.iter().map(WorkItem::Func));
+    res.extend(uses.live_tables.iter().map(WorkItem::Table));
+    res.extend(uses.live_globals.iter().map(WorkItem::Global));
+    res.extend(uses.live_memories.iter().map(WorkItem::Memory));
+    res.extend(uses.live_datas.iter().map(WorkItem::Data));
+    res.extend(uses.live_elems.iter().map(WorkItem::Elem));
+    res.extend(uses.live_tags.iter().map(WorkItem::Tag));
+    res
+}
+
+/// Iterates over all live objects, starting from the given roots, until we have gathered all the
+/// references.
+pub fn find_live(
+    module: &ParsedModule,
+    roots: Vec<WorkItem>,
+    options: &LivenessOptions,
+) -> Result<Uses> {
+    // Everything in here has been queued at some point, so nothing is ever processed twice.
+    let mut all_uses = Uses::default();
+    let mut work_queue: VecDeque<WorkItem> = VecDeque::new();
+    for item in roots {
+        if options.dropped.contains(item) {
+            anyhow::bail!("{} cannot be both kept and dropped", item);
+        }
+        if all_uses.insert(item) {
+            log::debug!("keeping {} because it was requested", item);
+            work_queue.push_back(item);
+        }
+    }
+
+    // Function bodies are by far the most expensive thing to scan, so we scan them all up front
+    // in parallel rather than one at a time as they are discovered. The module keeps the scans, so
+    // searching it again with other roots skips this.
+    let mut func_body_uses: Vec<Uses> = module
+        .body_scans()?
+        .par_iter()
+        .enumerate()
+        .map(|(i, scan)| {
+            let idx = module.num_imported_functions + i as u32;
+            if options.cut_funcs.contains(idx) {
+                log::debug!("not following the body of func:{} because it is cut", idx);
+                Ok(Uses::default())
+            } else {
+                get_scan_uses(module, scan, options)
+            }
+        })
+        .collect::<Result<_>>()?;
+
+    // Segments that are kept because of the functions in them, by function.
+    let mut segments_of_func: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (i, elem) in module.elems.iter().enumerate() {
+        let follow = match elem.kind {
+            ElementKind::Passive => options.keep_passive_elems,
+            ElementKind::Declared => !options.drop_declared_elems,
+            ElementKind::Active { .. } => false,
+        };
+        if follow {
+            for idx in get_elem_funcs(elem)? {
+                segments_of_func.entry(idx).or_default().push(i as u32);
+            }
+        }
+    }
+
+    while let Some(work) = work_queue.pop_front() {
+        if let WorkItem::Func(idx) = work {
+            for elem_idx in segments_of_func.remove(&idx).unwrap_or_default() {
+                let item = WorkItem::Elem(elem_idx);
+                if !options.dropped.contains(item) && all_uses.insert(item) {
+                    log::debug!("keeping {} because it refers to {}", item, work);
+                    work_queue.push_back(item);
+                }
+            }
+        }
+        let new_uses = match work {
+            WorkItem::Func(idx) if idx >= module.num_imported_functions => {
+                let mut res = Uses::single_type(module.func_types[idx as usize]);
+                res.merge(std::mem::take(
+                    &mut func_body_uses[(idx - module.num_imported_functions) as usize],
+                ));
+                res
+            }
+            _ => get_item_uses(module, work, options)?,
+        };
+
+        for item in live_items(&new_uses) {
+            if options.dropped.contains(item) {
+                log::debug!("not keeping {} for {} because it is dropped", item, work);
+                continue;
+            }
+            if all_uses.insert(item) {
+                log::debug!("keeping {} because {} uses it", item, work);
+                work_queue.push_back(item);
+            }
+        }
+    }
+
+    Ok(all_uses)
+}
+
+/// Finds the kept items each group of roots needs on its own. Items that only other roots need are
+/// in no group.
+pub fn find_group_uses(
+    module: &ParsedModule,
+    groups: &[RootGroup],
+    all_uses: &Uses,
+    options: &LivenessOptions,
+) -> Result<Vec<Uses>> {
+    groups
+        .iter()
+        .map(|group| {
+            let mut res = Uses::default();
+            for item in live_items(&find_live(module, group.roots.clone(), options)?) {
+                if all_uses.contains(item) {
+                    res.insert(item);
+                }
+            }
+            Ok(res)
+        })
+        .collect()
+}
+
+/// Drops the functions that are only kept because they share an active element segment with an
+/// indirect call target of the right signature, so that their entries become `ref.null func`.
+/// Segments that are kept without following indirect calls keep all of their functions.
+pub fn drop_unmatched_indirect_targets(
+    module: &ParsedModule,
+    roots: &[WorkItem],
+    options: &mut LivenessOptions,
+) -> Result<()> {
+    if !options.indirect_closure {
+        return Ok(());
+    }
+
+    options.indirect_closure = false;
+    let direct = find_live(module, roots.to_vec(), options);
+    options.indirect_closure = true;
+    let direct = direct?;
+
+    // The functions of the segments kept either way are followed as if they were requested, and
+    // the rest only if something other than their segment needs them.
+    let mut targets_roots = roots.to_vec();
+    for idx in direct.live_elems.iter() {
+        for func_idx in get_elem_funcs(&module.elems[idx as usize])? {
+            if !options.dropped.contains(WorkItem::Func(func_idx)) {
+                targets_roots.push(WorkItem::Func(func_idx));
+            }
+        }
+    }
+    let skip_elem_funcs = std::mem::replace(&mut options.skip_elem_funcs, true);
+    let kept = find_live(module, targets_roots, options);
+    options.skip_elem_funcs = skip_elem_funcs;
+    let kept = kept?;
+
+    for idx in kept.live_elems.iter() {
+        let elem = &module.elems[idx as usize];
+        if direct.live_elems.contains(idx) || !matches!(elem.kind, ElementKind::Active { .. }) {
+            continue;
+        }
+        for func_idx in get_elem_funcs(elem)? {
+            let item = WorkItem::Func(func_idx);
+            if !kept.live_funcs.contains(func_idx) && options.dropped.insert(item) {
+                log::debug!(
+                    "dropping {} because no kept indirect call can reach it",
+                    item
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Finds everything an item directly refers to, including the item itself.
+pub fn get_item_uses(
+    module: &ParsedModule,
+    item: WorkItem,
+    options: &LivenessOptions,
+) -> Result<Uses> {
+    Ok(match item {
+        WorkItem::Type(idx) => {
+            let mut res = Uses::single_type(idx);
+            res.merge(get_type_uses(&module.types[idx as usize]));
+            res
+        }
+        WorkItem::Func(idx) => {
+            let mut res = Uses::single_func(idx);
+            res.merge(Uses::single_type(module.func_types[idx as usize]));
+            if idx >= module.num_imported_functions && !options.cut_funcs.contains(idx) {
+                let i = (idx - module.num_imported_functions) as usize;
+                res.merge(match module.cached_body_scans() {
+                    Some(scans) => get_scan_uses(module, &scans[i], options)?,
+                    None => {
+                        get_scan_uses(module, &scan_func_body(&module.defined_funcs[i])?, options)?
+                    }
+                });
+            }
+            res
+        }
+        WorkItem::Table(idx) => {
+            let mut res = Uses::single_table(idx);
+            res.merge(get_tabletype_uses(&module.table_types[idx as usize]));
+            if idx >= module.num_imported_tables {
+                let table = &module.defined_tables[(idx - module.num_imported_tables) as usize];
+                if let TableInit::Expr(expr) = &table.init {
+                    res.merge(get_constexpr_uses(expr)?);
+                }
+            }
+            if options.chase_exports {
+                for (i, elem) in module.elems.iter().enumerate() {
+                    if let ElementKind::Active { table_index, .. } = &elem.kind {
+                        if table_index.unwrap_or(0) == idx {
+                            res.merge(Uses::single_elem(i as u32));
+                        }
+                    }
+                }
+            }
+            res
+        }
+        WorkItem::Global(idx) => {
+            let mut res = Uses::single_global(idx);
+            res.merge(get_globaltype_uses(&module.global_types[idx as usize]));
+            if idx >= module.num_imported_globals {
+                let global = &module.defined_globals[(idx - module.num_imported_globals) as usize];
+                res.merge(get_constexpr_uses(&global.init_expr)?)
+            }
+            res
+        }
+        WorkItem::Memory(idx) => Uses::single_memory(idx),
+        WorkItem::Data(idx) => {
+            let mut res = Uses::single_data(idx);
+            let data = &module.datas[idx as usize];
+            match &data.kind {
+                DataKind::Passive => (),
+                DataKind::Active {
+                    memory_index,
+                    offset_expr,
+                } => {
+                    res.merge(Uses::single_memory(*memory_index));
+                    res.merge(get_constexpr_uses(offset_expr)?);
+                }
+            };
+            res
+        }
+        WorkItem::Elem(idx) => {
+            let mut res = Uses::single_elem(idx);
+            let elem = &module.elems[idx as usize];
+            match &elem.kind {
+                ElementKind::Passive | ElementKind::Declared => (),
+                ElementKind::Active {
+                    table_index,
+                    offset_expr,
+                } => {
+                    // It's not clear to me why the table index is optional at this stage, but
+                    // other code in wasm-tools defaults to zero if it's missing.
+                    res.merge(Uses::single_table(table_index.unwrap_or(0)));
+                    res.merge(get_constexpr_uses(offset_expr)?);
+                }
+            };
+            match &elem.items {
+                // A declared segment only lets `ref.func` refer to its functions, so it is cut
+                // down to the kept ones rather than keeping the rest.
+                _ if matches!(elem.kind, ElementKind::Declared) => {}
+                wasmparser::ElementItems::Functions(_) if options.skip_elem_funcs => {}
+                wasmparser::ElementItems::Functions(funcs) => {
+                    for func_idx in funcs.clone() {
+                        res.merge(Uses::single_func(func_idx?));
+                    }
+                }
+                wasmparser::ElementItems::Expressions(ref_type, exprs) => {
+                    res.merge(get_reftype_uses(ref_type));
+                    for expr in exprs.clone() {
+                        let mut uses = get_constexpr_uses(&expr?)?;
+                        if options.skip_elem_funcs {
+                            uses.live_funcs = IndexSet::new();
+                        }
+                        res.merge(uses);
+                    }
+                }
+            };
+            res
+        }
+        WorkItem::Tag(idx) => {
+            let mut res = Uses::single_tag(idx);
+            res.merge(get_tagtype_uses(&module.tag_types[idx as usize]));
+            res
+        }
+    })
+}
+
+/// How one item uses another.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde-impls", derive(serde::Serialize, serde::Deserialize))]
+pub enum UseKind {
+    /// A function calls the other, directly or through a table.
+    Call,
+    /// A function takes a reference to the other with `ref.func`.
+    RefFunc,
+    /// The other is part of the item's type.
+    TypeOf,
+    /// An init expression or offset refers to the other.
+    InitExpr,
+    /// A segment puts the other in a table, or its contents in the other.
+    Segment,
+    /// Any other instruction in a function refers to the other.
+    Instruction,
+}
+
+impl UseKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UseKind::Call => "call",
+            UseKind::RefFunc => "ref.func",
+            UseKind::TypeOf => "type-of",
+            UseKind::InitExpr => "init-expr",
+            UseKind::Segment => "segment",
+            UseKind::Instruction => "instruction",
+        }
+    }
+}
+
+/// Finds everything an item directly refers to, like [`get_item_uses`] but without the item itself,
+/// and with how each one is used. Something used in more than one way is only listed once, under
+/// the most specific kind of use.
+pub fn get_labeled_uses(
+    module: &ParsedModule,
+    item: WorkItem,
+    options: &LivenessOptions,
+) -> Result<Vec<(WorkItem, UseKind)>> {
+    let uses = get_item_uses(module, item, options)?;
+
+    let mut calls = Uses::default();
+    let mut refs = Uses::default();
+    let mut init = Uses::default();
+    match item {
+        WorkItem::Func(idx)
+            if idx >= module.num_imported_functions && !options.cut_funcs.contains(idx) =>
+        {
+            let func = &module.defined_funcs[(idx - module.num_imported_functions) as usize];
+            for instr in func.operators()? {
+                match instr? {
+                    Operator::Call { function_index } | Operator::ReturnCall { function_index } => {
+                        calls.insert(WorkItem::Func(function_index));
+                    }
+                    Operator::CallIndirect {
+                        type_index,
+                        table_index,
+                    }
+                    | Operator::ReturnCallIndirect {
+                        type_index,
+                        table_index,
+                    } if options.indirect_closure => {
+                        calls.merge(get_indirect_call_targets(
+                            type_index,
+                            table_index,
+                            &module.types,
+                            &module.func_types,
+                            &module.elems,
+                        )?);
+                    }
+                    Operator::RefFunc { function_index } => {
+                        refs.insert(WorkItem::Func(function_index));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        WorkItem::Table(idx) if idx >= module.num_imported_tables => {
+            let table = &module.defined_tables[(idx - module.num_imported_tables) as usize];
+            if let TableInit::Expr(expr) = &table.init {
+                init = get_constexpr_uses(expr)?;
+            }
+        }
+        WorkItem::Global(idx) if idx >= module.num_imported_globals => {
+            let global = &module.defined_globals[(idx - module.num_imported_globals) as usize];
+            init = get_constexpr_uses(&global.init_expr)?;
+        }
+        WorkItem::Data(idx) => {
+            if let DataKind::Active { offset_expr, .. } = &module.datas[idx as usize].kind {
+                init = get_constexpr_uses(offset_expr)?;
+            }
+        }
+        WorkItem::Elem(idx) => {
+            if let ElementKind::Active { offset_expr, .. } = &module.elems[idx as usize].kind {
+                init = get_constexpr_uses(offset_expr)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(live_items(&uses)
+        .into_iter()
+        .filter(|used| *used != item)
+        .map(|used| {
+            let kind = if let WorkItem::Type(_) = used {
+                UseKind::TypeOf
+            } else if calls.contains(used) {
+                UseKind::Call
+            } else if refs.contains(used) {
+                UseKind::RefFunc
+            } else if init.contains(used) {
+                UseKind::InitExpr
+            } else {
+                match item {
+                    WorkItem::Func(_) => UseKind::Instruction,
+                    WorkItem::Table(_) | WorkItem::Data(_) | WorkItem::Elem(_) => UseKind::Segment,
+                    _ => UseKind::InitExpr,
+                }
+            };
+            (used, kind)
+        })
+        .collect())
+}
+
+/// Finds the types that make up an item's type, which are what another module linked against this
+/// one would see of it.
+fn get_item_type_uses(module: &ParsedModule, item: WorkItem) -> Uses {
+    match item {
+        WorkItem::Type(idx) => Uses::single_type(idx),
+        WorkItem::Func(idx) => Uses::single_type(module.func_types[idx as usize]),
+        WorkItem::Table(idx) => get_tabletype_uses(&module.table_types[idx as usize]),
+        WorkItem::Global(idx) => get_globaltype_uses(&module.global_types[idx as usize]),
+        WorkItem::Tag(idx) => get_tagtype_uses(&module.tag_types[idx as usize]),
+        WorkItem::Memory(_) | WorkItem::Data(_) | WorkItem::Elem(_) => Uses::default(),
+    }
+}
+
+/// Keeps every type in the rec group of a live type that can be seen from outside the module, and
+/// returns the types that were added. A type's canonical identity depends on its whole rec group,
+/// so removing members of these groups would change which types they match in other modules.
+///
+/// Types can be seen from outside if they are part of the type of a kept import or export, or of
+/// one of the given items, or if such a type refers to them. The rec groups of other types can
+/// still be pruned member by member, since nothing outside the module can tell the difference.
+pub fn preserve_rec_groups(
+    module: &ParsedModule,
+    all_uses: &mut Uses,
+    exported: &[WorkItem],
+) -> Vec<u32> {
+    let mut groups: Vec<std::ops::Range<u32>> = vec![];
+    for rg in &module.rec_groups {
+        let start = groups.last().map_or(0, |group| group.end);
+        let len = rg.types().len() as u32;
+        groups.extend(std::iter::repeat_n(start..start + len, len as usize));
+    }
+
+    let mut queue: Vec<u32> = vec![];
+    for item in live_items(all_uses) {
+        let seen = exported.contains(&item)
+            || module.import_of(item).is_some()
+            || !module.exports_of(item).is_empty();
+        if seen {
+            queue.extend(get_item_type_uses(module, item).live_types.iter());
+        }
+    }
+
+    let mut visible = IndexSet::new();
+    let mut added: Vec<u32> = vec![];
+    while let Some(idx) = queue.pop() {
+        if !visible.insert(idx) {
+            continue;
+        }
+        for member in groups[idx as usize].clone() {
+            if all_uses.live_types.insert(member) {
+                log::debug!(
+                    "keeping type:{} because it is in the same rec group as type:{}",
+                    member,
+                    idx
+                );
+                added.push(member);
+            }
+            queue.push(member);
+        }
+        queue.extend(get_type_uses(&module.types[idx as usize]).live_types.iter());
+    }
+    added.sort();
+    added
+}
+
+/// An operator in a live function that this tool doesn't know how to find uses in.
+pub struct UnknownOperator {
+    pub func_idx: u32,
+    pub name: &'static str,
+}
+
+/// Lists the operators in live functions whose uses are unknown, once per function. Isolation
+/// assumes these refer to nothing, which may drop items they actually need.
+pub fn find_unknown_operators(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    options: &LivenessOptions,
+) -> Result<Vec<UnknownOperator>> {
+    let mut res: Vec<UnknownOperator> = vec![];
+    for idx in all_uses.live_funcs.iter() {
+        if idx < module.num_imported_functions || options.cut_funcs.contains(idx) {
+            continue;
+        }
+        let func = &module.defined_funcs[(idx - module.num_imported_functions) as usize];
+        let mut names: Vec<&'static str> = vec![];
+        for instr in func.operators()? {
+            let instr = instr?;
+            if get_known_instr_uses(&instr).is_none() {
+                names.push(operator_name(&instr));
+            }
+        }
+        names.sort();
+        names.dedup();
+        res.extend(names.into_iter().map(|name| UnknownOperator {
+            func_idx: idx,
+            name,
+        }));
+    }
+    Ok(res)
+}
+
+/// What a defined function's body refers to, which is the same whatever the roots are. Bodies are
+/// only decoded once to find all of this, and everything that needs to know about them later
+/// works from the scan.
+#[derive(Clone, Default)]
+pub struct BodyScan {
+    /// Everything referenced from the function's locals and instructions. The function's own type
+    /// is not included, since it is declared in the function section rather than the body.
+    pub uses: Uses,
+    /// The type and table of each indirect call, whose targets are only followed with
+    /// [`LivenessOptions::indirect_closure`].
+    pub indirect_calls: Vec<(u32, u32)>,
+    /// The functions that `ref.func` takes references to.
+    pub func_refs: Vec<u32>,
+    /// How the body accesses memory, as found by [`AccessScanner`].
+    pub memory_accesses: MemoryAccesses,
+    /// How many instructions the body has, to hold big modules to
+    /// [`Limits::max_operators`](crate::module::Limits::max_operators).
+    pub num_operators: u64,
+}
+
+/// Scans a defined function's locals and instructions.
+pub fn scan_func_body(func: &Func) -> Result<BodyScan> {
+    let mut res = BodyScan::default();
+    let mut accesses = AccessScanner::default();
+    for (_, ty) in &func.locals {
+        res.uses.merge(get_valtype_uses(ty));
+    }
+    for instr in func.operators()? {
+        let instr = instr?;
+        res.num_operators += 1;
+        let uses = get_instr_uses(&instr);
+        accesses.visit(&instr, &uses);
+        res.uses.merge(uses);
+        match instr {
+            Operator::CallIndirect {
+                type_index,
+                table_index,
+            }
+            | Operator::ReturnCallIndirect {
+                type_index,
+                table_index,
+            } => res.indirect_calls.push((type_index, table_index)),
+            Operator::RefFunc { function_index } => res.func_refs.push(function_index),
+            _ => {}
+        }
+    }
+    res.indirect_calls.sort();
+    res.indirect_calls.dedup();
+    res.func_refs.sort();
+    res.func_refs.dedup();
+    res.memory_accesses = accesses.finish();
+    Ok(res)
+}
+
+/// Finds everything a scanned function body refers to under the given options.
+fn get_scan_uses(
+    module: &ParsedModule,
+    scan: &BodyScan,
+    options: &LivenessOptions,
+) -> Result<Uses> {
+    let mut res = scan.uses.clone();
+    if options.indirect_closure {
+        for (type_index, table_index) in &scan.indirect_calls {
+            res.merge(get_indirect_call_targets(
+                *type_index,
+                *table_index,
+                &module.types,
+                &module.func_types,
+                &module.elems,
+            )?);
+        }
+    }
+    Ok(res)
+}
+
+/// Lists the functions an element segment refers to, whether as function indices or `ref.func`
+/// expressions.
+pub fn get_elem_funcs(elem: &Element) -> Result<Vec<u32>> {
+    let mut res: Vec<u32> = vec![];
+    match &elem.items {
+        wasmparser::ElementItems::Functions(funcs) => {
+            for idx in funcs.clone() {
+                res.push(idx?);
+            }
+        }
+        wasmparser::ElementItems::Expressions(_, exprs) => {
+            for expr in exprs.clone() {
+                res.extend(get_constexpr_uses(&expr?)?.live_funcs.iter());
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Finds every function that a `call_indirect` with the given type and table could reach, based on
+/// the active element segments for that table. The segments themselves are kept too, since the
+/// functions would not be reachable through the table otherwise.
+fn get_indirect_call_targets(
+    type_idx: u32,
+    table_idx: u32,
+    types: &[SubType],
+    func_types: &[u32],
+    elems: &[Element],
+) -> Result<Uses> {
+    // Function types are compared structurally, since they are often duplicated across a module.
+    let call_type = &types[type_idx as usize];
+    let matches = |func_idx: u32| {
+        let func_type = func_types[func_idx as usize];
+        func_type == type_idx || types[func_type as usize] == *call_type
+    };
+
+    let mut res = Uses::default();
+    for (i, elem) in elems.iter().enumerate() {
+        let ElementKind::Active { table_index, .. } = &elem.kind else {
+            continue;
+        };
+        if table_index.unwrap_or(0) != table_idx {
+            continue;
+        }
+
+        let mut targets: Vec<u32> = vec![];
+        match &elem.items {
+            wasmparser::ElementItems::Functions(funcs) => {
+                for func_idx in funcs.clone() {
+                    targets.push(func_idx?);
+                }
+            }
+            wasmparser::ElementItems::Expressions(_, exprs) => {
+                for expr in exprs.clone() {
+                    for instr in expr?.get_operators_reader() {
+                        if let Operator::RefFunc { function_index } = instr? {
+                            targets.push(function_index);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut any_match = false;
+        for func_idx in targets {
+            if matches(func_idx) {
+                res.merge(Uses::single_func(func_idx));
+                any_match = true;
+            }
+        }
+        if any_match {
+            res.merge(Uses::single_elem(i as u32));
+        }
+    }
+    Ok(res)
+}
@@ -1,43 +1,149 @@
-mod relocation;
-mod uses;
-
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     fs::{self, File},
-    io::Write,
+    io::{BufWriter, IsTerminal, Read, Write},
+    path::Path,
+    process::ExitCode,
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser as _;
-use wasm_encoder::{
-    reencode::Reencode, CodeSection, ConstExpr, DataSection, DataSegment, DataSegmentMode,
-    ElementMode, ElementSection, ElementSegment, EntityType, ExportSection, Function,
-    FunctionSection, GlobalSection, ImportSection, MemorySection, Module, RawSection, TableSection,
-    TagSection, TypeSection,
-};
-use wasmparser::{
-    Data, DataKind, Element, ElementKind, Export, Global, GlobalType, Import, MemoryType, Operator,
-    Parser, Payload::*, RecGroup, SubType, Table, TableInit, TableType, TagType, ValType,
-};
+use wasmparser::{Parser, Payload::*, WasmFeatures};
 
-use relocation::*;
-use uses::*;
+use wasm_isolate::analyze::*;
+use wasm_isolate::artifacts::*;
+use wasm_isolate::cache;
+use wasm_isolate::compression::*;
+use wasm_isolate::config::Config;
+use wasm_isolate::data::*;
+use wasm_isolate::demangle::{self, for_display};
+use wasm_isolate::diff::*;
+use wasm_isolate::diff_live::*;
+use wasm_isolate::emit::*;
+use wasm_isolate::error::{error_kind, Error, ErrorKind};
+use wasm_isolate::features::{
+    check_features, feature_from_name, find_required_features, validate_exception_handling,
+};
+use wasm_isolate::harness::*;
+use wasm_isolate::index_set::IndexSet;
+use wasm_isolate::link::link_stubs;
+use wasm_isolate::liveness::*;
+use wasm_isolate::module::*;
+use wasm_isolate::patch::find_patch_sites;
+use wasm_isolate::print::print_items;
+use wasm_isolate::profile::load_profile;
+use wasm_isolate::reduce::{reduce, ReduceOptions};
+use wasm_isolate::relocation::*;
+use wasm_isolate::schema;
+use wasm_isolate::stack_trace::parse_stack_trace;
+use wasm_isolate::stats::*;
+use wasm_isolate::uses::*;
 
 #[derive(clap::Parser, Debug)]
 #[command(
     version,
-    about = "wasm-isolate strips a WebAssembly module down to specific features of interest without breaking validation."
+    about = "wasm-isolate strips a WebAssembly module down to specific features of interest without breaking validation.",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    args: Args,
+
+    /// Only print errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Explain what is kept and why. Repeat for even more detail.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Show Rust and C++ symbols demangled in summaries, listings, explanations, and graphs
+    #[arg(long, global = true)]
+    demangle: bool,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Compare the index spaces of two modules, showing which items were added, removed, or
+    /// renumbered
+    Diff(DiffArgs),
+    /// Compare the live closure of the same exports in two versions of a module
+    DiffLive(DiffLiveArgs),
+    /// Answer questions about a module without isolating anything
+    Analyze(AnalyzeArgs),
+    /// Show the chain of uses that keeps an item, with the names and types of everything on it
+    Explain(ExplainArgs),
+    /// Print the text format of just the items that isolation would keep, with their original
+    /// indices and names
+    Print(PrintArgs),
+    /// Write out the initial contents of a memory, as set up by its active data segments
+    MemoryImage(MemoryImageArgs),
+    /// Show the function and instruction at offsets from a trap or stack trace
+    FindOffset(FindOffsetArgs),
+    /// Shrink a module by dropping exports and stubbing out functions for as long as a test
+    /// script still finds it interesting
+    Reduce(ReduceArgs),
+    /// Isolate a function and run it with wasmtime, with stubs for anything it imports
+    #[cfg(feature = "run")]
+    Run(RunArgs),
+    /// Browse the items of a module and their uses, and pick the ones to keep in an isolated module
+    #[cfg(feature = "tui")]
+    Tui(TuiArgs),
+}
+
+#[derive(clap::Args, Clone, Debug)]
 struct Args {
-    /// The file to read from, or "-" to read from stdin
-    filename: String,
+    /// The files to read from, or "-" to read from stdin. Globs like "corpus/*.wasm" are expanded,
+    /// and each file is isolated with the same options.
+    #[arg(required_unless_present = "config")]
+    filename: Vec<String>,
+
+    /// A TOML file describing what to isolate and where to write it. Everything in it adds to the
+    /// options given on the command line.
+    #[arg(long)]
+    config: Option<String>,
 
     /// Type indices to preserve, separated by commas
     #[arg(long, num_args = 1.., value_delimiter = ',')]
     types: Vec<u32>,
 
-    /// Function indices to preserve, separated by commas
-    #[arg(short, long, num_args = 1.., value_delimiter = ',')]
+    /// Exports to preserve, by name, separated by commas
+    #[arg(long, num_args = 1.., value_delimiter = ',', value_name = "NAMES")]
+    exports: Vec<String>,
+
+    /// Functions to preserve, by their names in the name section, separated by commas. Mangled
+    /// Rust and C++ names can be given demangled, e.g. `mycrate::module::function`
+    #[arg(long, num_args = 1.., value_delimiter = ',', value_name = "NAMES")]
+    func_names: Vec<String>,
+
+    /// A named group of items to preserve, like `crash=funcs:12,globals:3`. Kept items are
+    /// attributed to the groups that need them in the summary and --stats, and --split-out writes
+    /// one module per group. Can be given more than once
+    #[arg(long = "group", value_name = "NAME=ITEMS")]
+    groups: Vec<RootGroup>,
+
+    /// A stack trace from V8, SpiderMonkey, JavaScriptCore, or wasmtime, such as from a crash
+    /// report. The function of every wasm frame is preserved
+    #[arg(long, value_name = "FILE")]
+    stack_trace: Option<String>,
+
+    /// A CPU profile whose hottest functions are preserved: a Chrome `.cpuprofile`, a Firefox
+    /// profile like `wasmtime run --profile=guest` writes, or folded stacks from `perf script`
+    #[arg(long, value_name = "FILE")]
+    profile: Option<String>,
+
+    /// With --profile, preserve the fewest functions that together account for this percentage
+    /// of the samples in the module
+    #[arg(long, default_value_t = 95.0, requires = "profile", value_parser = parse_percent)]
+    top_percent: f64,
+
+    /// Function indices to preserve, separated by commas. Frames from browser stack traces like
+    /// "wasm-function[12]" are also accepted
+    #[arg(short, long, num_args = 1.., value_delimiter = ',', value_parser = parse_func_index)]
     funcs: Vec<u32>,
 
     /// Table indices to preserve, separated by commas
@@ -56,806 +162,1739 @@ struct Args {
     #[arg(short, long, num_args = 1.., value_delimiter = ',')]
     datas: Vec<u32>,
 
+    /// Preserve the functions containing these offsets from the start of the module, as reported
+    /// in traps and stack traces, separated by commas. Offsets can be decimal or hex, e.g. 0x1a2b,
+    /// or copied from a browser stack trace, e.g. "module.wasm:0x1a2b"
+    #[arg(long, num_args = 1.., value_delimiter = ',', value_name = "OFFSETS", value_parser = parse_offset)]
+    func_at_offset: Vec<usize>,
+
+    /// Preserve every function with this signature, written like "(i32, i32) -> i64". Can be
+    /// given more than once.
+    #[arg(long, value_name = "SIGNATURE", value_parser = parse_signature)]
+    funcs_with_sig: Vec<Signature>,
+
+    /// Preserve every active data segment that initializes these memories, separated by commas
+    #[arg(long, num_args = 1.., value_delimiter = ',', value_name = "MEMORIES")]
+    datas_for_memory: Vec<u32>,
+
     /// Elem segment indices to preserve, separated by commas
     #[arg(short, long, num_args = 1.., value_delimiter = ',')]
     elems: Vec<u32>,
 
+    /// Preserve every active elem segment that initializes these tables, separated by commas
+    #[arg(long, num_args = 1.., value_delimiter = ',', value_name = "TABLES")]
+    elems_for_table: Vec<u32>,
+
     /// Tag indices to preserve, separated by commas
     #[arg(long, num_args = 1.., value_delimiter = ',')]
     tags: Vec<u32>,
 
+    /// Functions to keep as unreachable stubs (by index or name), without following their bodies
+    #[arg(long, num_args = 1.., value_delimiter = ',')]
+    cut: Vec<String>,
+
+    /// Functions to leave out (by index or name) even if kept code refers to them. Calls to them
+    /// and `ref.func` in code become `unreachable`, and references to them in element segments and
+    /// globals become null.
+    #[arg(long, num_args = 1.., value_delimiter = ',', value_name = "FUNCS")]
+    drop_funcs: Vec<String>,
+
+    /// Items to leave out even if kept code refers to them, like `global:3` or `memory:0`,
+    /// separated by commas. Every kept place that referred to them is patched and listed in a
+    /// warning.
+    #[arg(long, num_args = 1.., value_delimiter = ',', value_name = "ITEMS")]
+    drop: Vec<WorkItem>,
+
+    /// A file listing export names, one per line. Exactly these exports are kept, along with
+    /// everything they need.
+    #[arg(long)]
+    api: Option<String>,
+
+    /// Keep every function that an indirect call in kept code could reach, based on the called
+    /// table's element segments and the call's signature. Other functions in those segments are
+    /// replaced with `ref.null func` unless something else keeps them.
+    #[arg(long)]
+    indirect_closure: bool,
+
+    /// Keep the contents of the active element segments of every kept table, so that the
+    /// functions reachable through the table keep their exports too, even ones that the API file
+    /// doesn't list
+    #[arg(long)]
+    chase_exports: bool,
+
+    /// Keep every passive element segment that refers to a kept function, along with the rest of
+    /// its functions, so that `table.init` in code added to the repro later still works
+    #[arg(long)]
+    keep_passive_elems: bool,
+
+    /// Replace the functions in kept element segments that nothing else keeps with `ref.null
+    /// func`, as if they were given to --drop-funcs. Calling them through a table traps instead.
+    /// This shrinks modules with big tables of function pointers, like vtables.
+    #[arg(long)]
+    null_elem_funcs: bool,
+
+    /// Keep dropped functions that kept element segments refer to as stubs that trap, like the
+    /// ones given to --cut, instead of making their entries null. Every table entry keeps its
+    /// function type, so `call_indirect` behaves as in the original module until a stub is called.
+    #[arg(long)]
+    preserve_table_layout: bool,
+
+    /// Leave out declared element segments unless they are requested. Otherwise, a declared
+    /// segment is kept once any of its functions is kept, cut down to the functions that kept code
+    /// uses `ref.func` on. Either way, those functions are declared in a new segment if they need
+    /// to be.
+    #[arg(long)]
+    drop_declared_elems: bool,
+
+    /// Print the size of each section and the number of items in each index space, before and
+    /// after isolation
+    #[arg(long)]
+    stats: bool,
+
+    /// The maximum number of functions to accept in the input
+    #[arg(long, default_value_t = Limits::default().max_funcs)]
+    max_funcs: u64,
+
+    /// The maximum total number of operators to decode from the input
+    #[arg(long, default_value_t = Limits::default().max_operators)]
+    max_operators: u64,
+
+    /// The maximum size of the output, in bytes
+    #[arg(long, default_value_t = Limits::default().max_output_size)]
+    max_output_size: u64,
+
+    /// Keep what each function body refers to in this directory, so that isolating the same module
+    /// again, with any roots, skips scanning the code
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<String>,
+
+    /// The artifacts to produce, separated by commas. Requires --out-prefix.
+    #[arg(
+        long,
+        num_args = 1..,
+        value_delimiter = ',',
+        requires = "out_prefix",
+        conflicts_with = "out"
+    )]
+    emit: Vec<Artifact>,
+
+    /// The path prefix for the artifacts selected with --emit, e.g. "out/isolated" produces
+    /// "out/isolated.wasm", "out/isolated.wat", and so on
+    #[arg(long)]
+    out_prefix: Option<String>,
+
+    /// Replace imports with definitions so the output can be instantiated on its own. Imported
+    /// functions become stubs that trap.
+    #[arg(long)]
+    standalone: bool,
+
+    /// A module of stubs to link in for the imports the output still needs. Each import is
+    /// replaced with the export of the same name and kind, along with everything it needs, so the
+    /// output no longer imports it.
+    #[arg(long, value_name = "STUBS", conflicts_with = "standalone")]
+    link_stubs: Option<String>,
+
+    /// What to do with the start function in standalone mode
+    #[arg(long, value_enum, default_value_t = StartPolicy::Auto, requires = "standalone")]
+    start_policy: StartPolicy,
+
+    /// Only allow these proposals in the output, separated by commas, e.g. "simd,bulk_memory".
+    /// Names are the ones wasmparser uses, and "mvp", "default", and "all" are also accepted. Fails
+    /// if the kept items need anything else.
+    #[arg(long, num_args = 1.., value_delimiter = ',', value_name = "PROPOSALS", value_parser = parse_feature)]
+    features: Vec<WasmFeatures>,
+
+    /// Check that the output only uses one encoding of exception handling: the legacy `try`
+    /// instructions that older toolchains emit, or `try_table` and `exnref`
+    #[arg(long, value_enum, default_value_t = ExceptionHandling::Any)]
+    exception_handling: ExceptionHandling,
+
+    /// How to remove unused types from rec groups
+    #[arg(long, value_enum, default_value_t = RecGroupPolicy::Prune)]
+    rec_groups: RecGroupPolicy,
+
+    /// What to do with operators whose uses this tool doesn't understand, which usually come from
+    /// proposals newer than the tool
+    #[arg(long, value_enum, default_value_t = UnknownOpcodes::Ignore)]
+    unknown_opcodes: UnknownOpcodes,
+
+    /// Remove the linking and relocation sections of object files, which would no longer match
+    /// the isolated module
+    #[arg(long)]
+    strip_object_metadata: bool,
+
+    /// Only keep the custom sections whose names match one of these globs, separated by commas
+    #[arg(long, num_args = 1.., value_delimiter = ',')]
+    keep_custom: Vec<String>,
+
+    /// Drop the custom sections whose names match one of these globs, separated by commas. This
+    /// takes priority over --keep-custom.
+    #[arg(long, num_args = 1.., value_delimiter = ',')]
+    strip_custom: Vec<String>,
+
+    /// Keep every item in the module, and reencode every section and function body instead of
+    /// copying the ones that don't change. This checks that reencoding a module is faithful, and
+    /// normalizes how it is encoded. Other options like --drop and --keep-names still apply.
+    #[arg(long)]
+    keep_all: bool,
+
+    /// When nothing is requested, write an empty module. Otherwise, every export, the start
+    /// function, and every active segment are kept, which removes dead code from the module.
+    #[arg(long)]
+    empty: bool,
+
+    /// Keep the name section, renumbered to match the isolated module. This includes the names of
+    /// locals and labels in kept functions.
+    #[arg(long)]
+    keep_names: bool,
+
+    /// Leave out the producers section instead of adding wasm-isolate to its list of tools that
+    /// processed the module.
+    #[arg(long)]
+    no_producers: bool,
+
+    /// Record how the module was isolated in a "wasm-isolate" custom section: the command line,
+    /// the SHA-256 hash of the original module, and the roots. The section is JSON.
+    #[arg(long)]
+    annotate: bool,
+
+    /// What to start the names of the exports added for the requested items with. The rest of the
+    /// name is the item's name if it has one, e.g. "isolated_my_func", or else its kind and index,
+    /// e.g. "isolated_func_12".
+    #[arg(long, default_value = "isolated_")]
+    export_prefix: String,
+
+    /// Don't add exports for the requested items
+    #[arg(long, conflicts_with = "export_prefix")]
+    no_synthetic_exports: bool,
+
+    /// Export every kept function, table, memory, global, and tag, not just the requested ones
+    #[arg(long, conflicts_with_all = ["no_synthetic_exports", "api"])]
+    export_all_kept: bool,
+
+    /// Shrink kept data segments to the bytes that kept code loads, stores, or copies at constant
+    /// addresses. The segments of memories that kept code also accesses at addresses computed at
+    /// runtime are kept whole, since any of their bytes could be read.
+    #[arg(long)]
+    trim_data: bool,
+
+    /// Merge kept function types that are identical into one, updating everything that refers to
+    /// them. Only types whose identity can't change are merged: final ones alone in their rec
+    /// group that don't refer to other types.
+    #[arg(long)]
+    dedupe_types: bool,
+
+    /// Lower the initial size of each kept memory to what its kept active data segments need.
+    /// Code that relies on the rest of the memory being there will trap.
+    #[arg(long)]
+    clamp_memory: bool,
+
+    /// Set the initial size of a memory in pages, given as MEMORY=PAGES with the memory's index in
+    /// the original module. Takes priority over --clamp-memory.
+    #[arg(long, value_name = "MEMORY=PAGES", value_parser = parse_memory_minimum)]
+    memory_minimum: Vec<(u32, u64)>,
+
+    /// Give imports a new module or name, e.g. "env::foo=host::bar". The module and name to match
+    /// can be globs, and a "*" on the right keeps that part, so "env::*=host::*" moves every
+    /// import from env to host. Can be given more than once, and the first match applies.
+    #[arg(long, value_name = "FROM=TO", value_parser = parse_import_rename)]
+    rename_import: Vec<ImportRename>,
+
+    /// Rename an export of the isolated module, given as OLD=NEW. This also applies to the exports
+    /// added for the requested items. Can be given more than once.
+    #[arg(long, value_name = "OLD=NEW", value_parser = parse_export_rename, conflicts_with = "api")]
+    rename_export: Vec<(String, String)>,
+
+    /// Drop the exports whose names match one of these globs, separated by commas. Names are
+    /// matched before --rename-export applies. The exported items are still kept if anything else
+    /// needs them.
+    #[arg(long, num_args = 1.., value_delimiter = ',', value_name = "GLOBS", conflicts_with = "api")]
+    strip_exports: Vec<String>,
+
+    /// Drop every data segment, keeping memories and code. Uses of data segments in kept code do
+    /// nothing instead.
+    #[arg(long, conflicts_with_all = ["datas", "datas_for_memory", "trim_data", "passive_init"])]
+    no_data: bool,
+
+    /// Keep every item at its original index, for comparing against logs or traces of the original
+    /// module. Items that nothing needs are kept as placeholders: functions become stubs that
+    /// trap, element and data segments become empty passive ones, and imports and everything else
+    /// stay as they were. Placeholders are not exported, and a placeholder start function is not
+    /// run.
+    #[arg(long, conflicts_with_all = ["dedupe_types", "link_stubs", "no_data"])]
+    no_relocate: bool,
+
+    /// Like --no-relocate, but only for these index spaces, separated by commas: types, funcs,
+    /// tables, memories, globals, tags, elems, or datas. The other spaces are renumbered as usual.
+    #[arg(
+        long,
+        num_args = 1..,
+        value_delimiter = ',',
+        value_name = "SPACES",
+        conflicts_with_all = ["no_relocate", "dedupe_types", "link_stubs", "no_data"]
+    )]
+    stable: Vec<IndexSpace>,
+
+    /// Make kept active data and element segments passive, and export a function named
+    /// "__wasm_isolate_init" that applies them. This lets a test host provide imported memories
+    /// and tables before any data is written to them.
+    #[arg(long)]
+    passive_init: bool,
+
+    /// When to emit a data count section
+    #[arg(long, value_enum, default_value_t = DataCount::Auto)]
+    data_count: DataCount,
+
+    /// Also write a script that instantiates the isolated module with dummy imports and calls each
+    /// requested function with default arguments. It is written next to the isolated module, e.g.
+    /// "out.wasm" gets "out.harness.js" or "out.harness.sh".
+    #[arg(long, value_enum)]
+    emit_harness: Option<Harness>,
+
+    /// Also write a text file that maps the index and the instruction offsets of every kept
+    /// function in the original module to the ones in the isolated module, for translating trace
+    /// and debug info addresses. The format is described in the README.
+    #[arg(long, value_name = "FILE")]
+    emit_offset_map: Option<String>,
+
+    /// Isolate each requested item into its own module in this directory, named after the item,
+    /// e.g. "func_12.wasm". An "index.json" lists what was written.
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["out", "out_prefix", "emit", "emit_harness", "api", "stats"]
+    )]
+    split_out: Option<String>,
+
+    /// How to print the summary of where the requested items ended up, along with any warnings
+    #[arg(long, value_enum, default_value_t = SummaryFormat::Human)]
+    format: SummaryFormat,
+
     #[arg(short, long)]
     out: Option<String>,
+
+    /// Write the isolated modules to this directory, named after their inputs. Without this, when
+    /// there is more than one input, each output is written next to its input as
+    /// "<name>.isolated.wasm".
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["out", "out_prefix", "split_out"])]
+    out_dir: Option<String>,
+
+    /// Replace each input file with its isolated module. The new module is written to a temporary
+    /// file first, so the input is never left half-written. Compressed inputs are compressed the
+    /// same way again, unless --compress says otherwise.
+    #[arg(
+        long,
+        conflicts_with_all = ["out", "out_prefix", "out_dir", "split_out", "emit_harness"]
+    )]
+    in_place: bool,
+
+    /// Write the isolated module to stdout even if it is a terminal
+    #[arg(long)]
+    force: bool,
+
+    /// Compress the isolated module before writing it. Inputs compressed with gzip are always
+    /// accepted, as are brotli inputs ending in ".br" when built with the brotli feature.
+    #[arg(long, value_enum, conflicts_with_all = ["out_prefix", "split_out", "emit_harness"])]
+    compress: Option<Compression>,
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+enum Compression {
+    Gzip,
+    #[cfg(feature = "brotli")]
+    Brotli,
+}
 
-    let filename = args.filename;
-    let mut reader = get_reader(filename);
-    let mut buf = Vec::new();
-    reader.read_to_end(&mut buf)?;
-    let parser = Parser::new(0);
-
-    let mut types: Vec<SubType> = vec![];
-    let mut rec_groups: Vec<RecGroup> = vec![];
-    let mut num_imported_functions: u32 = 0;
-    let mut num_imported_tables: u32 = 0;
-    let mut num_imported_memories: u32 = 0;
-    let mut num_imported_globals: u32 = 0;
-    let mut num_imported_tags: u32 = 0;
-    let mut func_types: Vec<u32> = vec![];
-    let mut table_types: Vec<TableType> = vec![];
-    let mut memory_types: Vec<MemoryType> = vec![];
-    let mut global_types: Vec<GlobalType> = vec![];
-    let mut tag_types: Vec<TagType> = vec![];
-
-    let mut current_func = 0;
-    let mut first_func: bool = true;
-
-    let mut imports: Vec<Import> = vec![];
-    let mut defined_tables: Vec<Table> = vec![];
-    let mut defined_globals: Vec<Global> = vec![];
-    let mut exports: Vec<Export> = vec![];
-    let mut start_idx: Option<u32> = None;
-    let mut elems: Vec<Element> = vec![];
-    let mut defined_funcs: Vec<Func> = vec![];
-    let mut datas: Vec<Data> = vec![];
-
-    let mut sections: Vec<Section> = vec![];
-
-    for payload in parser.parse_all(&buf) {
-        match payload? {
-            // Sections for WebAssembly modules
-            TypeSection(r) => {
-                sections.push(Section::Type);
-
-                for rg in r {
-                    let rg = rg?;
-                    rec_groups.push(rg.clone());
-                    for t in rg.into_types() {
-                        types.push(t);
-                    }
-                }
-            }
-            ImportSection(r) => {
-                sections.push(Section::Import);
-
-                for import in r {
-                    let import = import?;
-                    match import.ty {
-                        wasmparser::TypeRef::Func(type_idx) => {
-                            num_imported_functions += 1;
-                            func_types.push(type_idx);
-                        }
-                        wasmparser::TypeRef::Table(ty) => {
-                            num_imported_tables += 1;
-                            table_types.push(ty);
-                        }
-                        wasmparser::TypeRef::Memory(ty) => {
-                            num_imported_memories += 1;
-                            memory_types.push(ty);
-                        }
-                        wasmparser::TypeRef::Global(ty) => {
-                            num_imported_globals += 1;
-                            global_types.push(ty);
-                        }
-                        wasmparser::TypeRef::Tag(ty) => {
-                            num_imported_tags += 1;
-                            tag_types.push(ty);
-                        }
-                    }
-                    imports.push(import);
-                }
-            }
-            FunctionSection(r) => {
-                sections.push(Section::Function);
-                for f in r {
-                    func_types.push(f?);
-                }
-            }
-            TableSection(r) => {
-                sections.push(Section::Table);
-                for table in r {
-                    let table = table?;
-                    table_types.push(table.ty);
-                    defined_tables.push(table);
-                }
-            }
-            MemorySection(r) => {
-                sections.push(Section::Memory);
-                for mem_type in r {
-                    memory_types.push(mem_type?);
-                }
-            }
-            TagSection(r) => {
-                sections.push(Section::Tag);
-                for tag_type in r {
-                    tag_types.push(tag_type?);
-                }
-            }
-            GlobalSection(r) => {
-                sections.push(Section::Global);
-                for global in r {
-                    let global = global?;
-                    global_types.push(global.ty);
-                    defined_globals.push(global);
-                }
-            }
-            ExportSection(r) => {
-                sections.push(Section::Export);
-                for export in r {
-                    exports.push(export?);
-                }
-            }
-            StartSection { func, range: _ } => {
-                // IDEA: Just because we presere the start function doesn't
-                // necessarily mean we want to preserve the start section.
-                // Should we have a flag for this?
-                sections.push(Section::Start);
-                start_idx = Some(func);
-            }
-            ElementSection(r) => {
-                sections.push(Section::Element);
-                for elem in r {
-                    elems.push(elem?);
-                }
-            }
-            DataCountSection { count: _, range: _ } => {
-                sections.push(Section::DataCount);
-            }
-            DataSection(r) => {
-                sections.push(Section::Data);
-                for data in r {
-                    datas.push(data?);
-                }
-            }
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+enum Harness {
+    /// A Node.js script
+    Js,
+    /// A shell script that runs each function with the wasmtime CLI
+    Wasmtime,
+}
 
-            // Here we know how many functions we'll be receiving as
-            // `CodeSectionEntry`, so we can prepare for that, and
-            // afterwards we can parse and handle each function
-            // individually.
-            CodeSectionStart { .. } => {
-                sections.push(Section::Code);
-                current_func = num_imported_functions;
-            }
-            CodeSectionEntry(body) => {
-                if first_func {
-                    first_func = false
-                } else {
-                    current_func += 1;
-                }
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+enum SummaryFormat {
+    /// Lines of text on stderr
+    Human,
+    /// A single JSON document on stderr
+    Json,
+}
 
-                let mut func = Func {
-                    type_idx: func_types[current_func as usize],
-                    locals: vec![],
-                    instructions: vec![],
-                };
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+enum UnknownOpcodes {
+    /// Assume they refer to nothing
+    Ignore,
+    /// Copy them to the output as usual, with a warning for each function that has them
+    Preserve,
+    /// Refuse to isolate anything that contains them
+    Error,
+}
 
-                for local in body.get_locals_reader()? {
-                    func.locals.push(local?);
-                }
-                for instr in body.get_operators_reader()? {
-                    func.instructions.push(instr?);
-                }
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+enum StartPolicy {
+    /// Export the start function as "_initialize" if it calls any stubbed imports, and keep it
+    /// otherwise
+    Auto,
+    /// Keep the start section, with a warning if it calls any stubbed imports
+    Keep,
+    /// Always export the start function as "_initialize" instead
+    Export,
+}
 
-                defined_funcs.push(func)
-            }
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+enum DataCount {
+    /// Only when kept code uses data segments by index, with `memory.init`, `data.drop`,
+    /// `array.new_data`, or `array.init_data`
+    Auto,
+    /// Always, even if there are no data segments
+    Always,
+    /// Never. The output won't validate if kept code needs one.
+    Never,
+}
 
-            CustomSection(r) => {
-                if r.name() == "name" {
-                    continue;
-                }
-                sections.push(Section::raw(0, &buf[r.range()]));
-            }
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+enum ExceptionHandling {
+    /// Allow either encoding, or both
+    Any,
+    /// Only allow `try`, `catch`, `catch_all`, `delegate`, and `rethrow`
+    Legacy,
+    /// Only allow `try_table`, `throw_ref`, and `exnref`
+    Exnref,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+enum RecGroupPolicy {
+    /// Remove every unused type, even from the middle of a rec group. This changes the canonical
+    /// identity of the other types in the group.
+    Prune,
+    /// Keep whole rec groups for the types of imports and exports and anything they refer to, so
+    /// that those types still match the same types in other modules. Other rec groups are
+    /// pruned.
+    Preserve,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+enum Artifact {
+    /// The isolated module (.wasm)
+    Wasm,
+    /// The isolated module in the text format (.wat)
+    Wat,
+    /// The new index of every kept item, as JSON (.map.json)
+    Map,
+    /// Section sizes and item counts before and after isolation, as JSON (.report.json)
+    Report,
+    /// The dependency graph of the kept items, with their retained sizes, in Graphviz format (.dot)
+    Graph,
+    /// The dependency graph of the kept items, with their retained sizes, as JSON (.graph.json)
+    GraphJson,
+    /// The dependency graph of the kept items, with their retained sizes, in GraphML for tools
+    /// like Gephi (.graphml)
+    Graphml,
+    /// The old and new code offsets of every kept function body, for translating addresses in
+    /// DWARF debug info, as JSON (.offsets.json)
+    Offsets,
+    /// The old and new module offsets of every kept function body, for adjusting the module's
+    /// source map, as JSON (.sourcemap.json)
+    SourceMap,
+}
 
-            _ => {}
+impl Artifact {
+    fn extension(&self) -> &'static str {
+        match self {
+            Artifact::Wasm => "wasm",
+            Artifact::Wat => "wat",
+            Artifact::Map => "map.json",
+            Artifact::Report => "report.json",
+            Artifact::Graph => "dot",
+            Artifact::GraphJson => "graph.json",
+            Artifact::Graphml => "graphml",
+            Artifact::Offsets => "offsets.json",
+            Artifact::SourceMap => "sourcemap.json",
         }
     }
+}
 
-    //
-    // TODO: Ensure that we have an export section for later.
-    //
+#[derive(clap::Args, Debug)]
+struct DiffArgs {
+    /// The first module, such as the original one
+    old: String,
 
-    //
-    // Iterate over all live objects until we have gathered all the references.
-    //
+    /// The second module, such as the isolated one
+    new: String,
 
-    let mut work_queue: Vec<WorkItem> = vec![];
-    for idx in &args.types {
-        if *idx < types.len() as u32 {
-            work_queue.push(WorkItem::Type(*idx));
+    /// Print the differences as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct DiffLiveArgs {
+    /// The old version of the module
+    old: String,
+
+    /// The new version of the module
+    new: String,
+
+    /// Names of exports to use as roots in both modules, separated by commas
+    #[arg(long, required = true, num_args = 1.., value_delimiter = ',')]
+    export: Vec<String>,
+
+    /// Print the differences as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[cfg(feature = "run")]
+#[derive(clap::Args, Debug)]
+struct RunArgs {
+    /// The file to read from, or "-" to read from stdin
+    filename: String,
+
+    /// The function to run, by index or name
+    #[arg(short, long = "funcs", value_name = "FUNC")]
+    func: String,
+
+    /// The arguments to pass to the function, after a "--"
+    #[arg(last = true)]
+    args: Vec<String>,
+}
+
+#[cfg(feature = "tui")]
+#[derive(clap::Args, Debug)]
+struct TuiArgs {
+    /// The file to read from
+    filename: String,
+
+    /// Where to write the isolated module
+    #[arg(short, long, default_value = "isolated.wasm")]
+    out: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct MemoryImageArgs {
+    /// The file to read from, or "-" to read from stdin
+    filename: String,
+
+    /// The memory to build the image of
+    #[arg(short, long, default_value_t = 0)]
+    memory: u32,
+
+    /// The file to write the image to
+    #[arg(short, long)]
+    out: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct ExplainArgs {
+    /// The file to read from, or "-" to read from stdin
+    filename: String,
+
+    /// The item to explain, e.g. "func:5" or "global:2"
+    item: WorkItem,
+
+    /// Exports to start from, separated by commas. Without this, every export and the start
+    /// function are used, which explains why the item is in the module at all.
+    #[arg(long, num_args = 1.., value_delimiter = ',')]
+    exports: Vec<String>,
+
+    /// Follow indirect calls the same way --indirect-closure does when isolating
+    #[arg(long)]
+    indirect_closure: bool,
+
+    /// Follow kept tables to their element segments the same way --chase-exports does when
+    /// isolating
+    #[arg(long)]
+    chase_exports: bool,
+
+    /// Print the answer as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct PrintArgs {
+    /// The file to read from, or "-" to read from stdin
+    filename: String,
+
+    /// Functions to start from, by index or name, separated by commas
+    #[arg(long, num_args = 1.., value_delimiter = ',')]
+    funcs: Vec<String>,
+
+    /// Exports to start from, separated by commas
+    #[arg(long, num_args = 1.., value_delimiter = ',')]
+    exports: Vec<String>,
+
+    /// Other items to start from, like "global:2" or "type:5", separated by commas
+    #[arg(long, num_args = 1.., value_delimiter = ',')]
+    items: Vec<WorkItem>,
+
+    /// Functions whose bodies should not be followed (by index or name). They are still printed.
+    #[arg(long, num_args = 1.., value_delimiter = ',')]
+    cut: Vec<String>,
+
+    /// Follow indirect calls the same way --indirect-closure does when isolating
+    #[arg(long)]
+    indirect_closure: bool,
+
+    /// Follow kept tables to their element segments the same way --chase-exports does when
+    /// isolating
+    #[arg(long)]
+    chase_exports: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ReduceArgs {
+    /// The file to read from, or "-" to read from stdin
+    filename: String,
+
+    /// A program that is run with the path of each candidate module as its only argument, and
+    /// exits with status 0 if the candidate is still interesting
+    #[arg(long)]
+    test: String,
+
+    /// The exports to start from, separated by commas. Defaults to every export.
+    #[arg(long, num_args = 1.., value_delimiter = ',', value_name = "NAMES")]
+    exports: Vec<String>,
+
+    /// The file to write the reduced module to
+    #[arg(short, long)]
+    out: String,
+
+    /// Once removing things one chunk at a time stops working, try this many random candidates
+    /// that remove several unrelated things at once
+    #[arg(long, default_value_t = 0)]
+    random_tries: usize,
+
+    /// The seed for the random candidates
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(clap::Args, Debug)]
+struct FindOffsetArgs {
+    /// The file to read from, or "-" to read from stdin
+    filename: String,
+
+    /// Offsets from the start of the module, in decimal or hex, e.g. 0x1a2b, or as copied from a
+    /// browser stack trace, e.g. "module.wasm:0x1a2b"
+    #[arg(required = true, value_parser = parse_offset)]
+    offsets: Vec<usize>,
+}
+
+#[derive(clap::Args, Debug)]
+#[command(group = clap::ArgGroup::new("from_roots").args(["features", "top", "dropped"]).multiple(true))]
+struct AnalyzeArgs {
+    /// The file to read from, or "-" to read from stdin
+    filename: String,
+
+    /// List everything that refers to the given item, e.g. "func:5" or "global:2"
+    #[arg(long)]
+    users: Option<WorkItem>,
+
+    /// List the contents of the module, with a summary of each function's dependencies
+    #[arg(long)]
+    list: bool,
+
+    /// List the proposals beyond the MVP that the kept items need, which are the engine features
+    /// the isolated module needs. Without --funcs, --exports, or --items, the whole module is
+    /// checked.
+    #[arg(long)]
+    features: bool,
+
+    /// List the N largest functions and data segments that the kept items include, with the chain
+    /// of uses that keeps each one. Without --funcs, --exports, or --items, the whole module is
+    /// ranked, and chains start from the exports and the start function.
+    #[arg(long, value_name = "N")]
+    top: Option<usize>,
+
+    /// List everything that the kept items don't need, and that isolation would therefore drop,
+    /// with names and sizes. Needs --funcs, --exports, or --items.
+    #[arg(long)]
+    dropped: bool,
+
+    /// Functions to start from for --features, --top, and --dropped, by index or name, separated
+    /// by commas
+    #[arg(long, num_args = 1.., value_delimiter = ',', requires = "from_roots")]
+    funcs: Vec<String>,
+
+    /// Exports to start from for --features, --top, and --dropped, separated by commas
+    #[arg(long, num_args = 1.., value_delimiter = ',', requires = "from_roots")]
+    exports: Vec<String>,
+
+    /// Other items to start from for --features, --top, and --dropped, like "global:2", separated
+    /// by commas
+    #[arg(long, num_args = 1.., value_delimiter = ',', requires = "from_roots")]
+    items: Vec<WorkItem>,
+
+    /// Functions whose bodies should not be followed or checked for --features, --top, and
+    /// --dropped (by index or name)
+    #[arg(long, num_args = 1.., value_delimiter = ',', requires = "from_roots")]
+    cut: Vec<String>,
+
+    /// Follow indirect calls the same way --indirect-closure does when isolating
+    #[arg(long, requires = "from_roots")]
+    indirect_closure: bool,
+
+    /// Follow kept tables to their element segments the same way --chase-exports does when
+    /// isolating
+    #[arg(long, requires = "from_roots")]
+    chase_exports: bool,
+
+    /// Print the answers as JSON, one document per line
+    #[arg(long)]
+    json: bool,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    init_logger(cli.quiet, cli.verbose);
+    demangle::set_enabled(cli.demangle);
+    let result = match cli.command {
+        Some(Command::Diff(args)) => run_diff(args),
+        Some(Command::DiffLive(args)) => run_diff_live(args),
+        Some(Command::Analyze(args)) => run_analyze(args),
+        Some(Command::Explain(args)) => run_explain(args),
+        Some(Command::Print(args)) => run_print(args),
+        Some(Command::MemoryImage(args)) => run_memory_image(args),
+        Some(Command::FindOffset(args)) => run_find_offset(args),
+        Some(Command::Reduce(args)) => run_reduce(args),
+        #[cfg(feature = "run")]
+        Some(Command::Run(args)) => run_run(args),
+        #[cfg(feature = "tui")]
+        Some(Command::Tui(args)) => run_tui(args),
+        None => run_inputs(cli.args),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(error_kind(&err).exit_code())
         }
     }
-    for idx in &args.funcs {
-        if *idx < func_types.len() as u32 {
-            work_queue.push(WorkItem::Func(*idx));
+}
+
+/// Everything about a module that has been isolated.
+struct Isolated {
+    all_uses: Uses,
+    relocations: Relocations,
+    out_bytes: Vec<u8>,
+    demote_start: bool,
+    data_trims: Option<DataTrims>,
+    memory_minimums: BTreeMap<u32, u64>,
+    /// The items kept only to hold their index, for --no-relocate.
+    placeholders: Uses,
+}
+
+/// What to isolate, along with the options that apply to every module produced by one run.
+#[derive(Clone, Copy)]
+struct Job<'a> {
+    args: &'a Args,
+    requested: &'a Uses,
+    cut_funcs: &'a IndexSet,
+    api: Option<&'a [String]>,
+    custom_sections: &'a CustomSectionFilter,
+    export_filter: &'a ExportFilter,
+    liveness_options: &'a LivenessOptions,
+    limits: &'a Limits,
+    stubs: Option<&'a ParsedModule<'a>>,
+}
+
+impl<'a> Job<'a> {
+    fn emit_options(
+        &self,
+        demote_start: bool,
+        data_trims: Option<&'a DataTrims>,
+        memory_minimums: &'a BTreeMap<u32, u64>,
+        placeholders: &'a Uses,
+    ) -> EmitOptions<'a> {
+        let args = self.args;
+        EmitOptions {
+            requested: self.requested,
+            cut_funcs: self.cut_funcs,
+            dropped: &self.liveness_options.dropped,
+            api: self.api,
+            chase_exports: args.chase_exports,
+            standalone: args.standalone,
+            demote_start,
+            strip_object_metadata: args.strip_object_metadata,
+            custom_sections: self.custom_sections,
+            synthetic_exports: !args.no_synthetic_exports,
+            export_all_kept: args.export_all_kept,
+            export_prefix: &args.export_prefix,
+            data_trims,
+            memory_minimums,
+            passive_init: args.passive_init,
+            no_data: args.no_data,
+            import_renames: &args.rename_import,
+            export_filter: self.export_filter,
+            keep_names: args.keep_names,
+            producers: !args.no_producers,
+            data_count: match args.data_count {
+                DataCount::Auto => None,
+                DataCount::Always => Some(true),
+                DataCount::Never => Some(false),
+            },
+            reencode: args.keep_all,
+            placeholders,
         }
     }
-    for idx in &args.tables {
-        if *idx < table_types.len() as u32 {
-            work_queue.push(WorkItem::Table(*idx));
+}
+
+/// Finds everything the given roots need and writes out a module containing just that.
+fn isolate(
+    module: &ParsedModule,
+    job: &Job,
+    work_queue: Vec<WorkItem>,
+    warnings: &mut Vec<String>,
+) -> Result<Isolated> {
+    let Job {
+        args,
+        cut_funcs,
+        api,
+        liveness_options,
+        limits,
+        ..
+    } = *job;
+    let mut all_uses = find_live(module, work_queue.clone(), liveness_options)?;
+    if args.rec_groups == RecGroupPolicy::Preserve {
+        let added = preserve_rec_groups(module, &mut all_uses, &work_queue);
+        if args.format == SummaryFormat::Human && !added.is_empty() {
+            log::info!(
+                "Kept {} more type(s) to preserve the identity of rec groups.",
+                added.len()
+            );
         }
     }
-    for idx in &args.globals {
-        if *idx < global_types.len() as u32 {
-            work_queue.push(WorkItem::Global(*idx));
-        }
+    if args.no_data {
+        all_uses.live_datas = IndexSet::new();
     }
-    for idx in &args.memories {
-        if *idx < memory_types.len() as u32 {
-            work_queue.push(WorkItem::Memory(*idx));
+    let merged_types = if args.dedupe_types {
+        let merged = dedupe_types(module, &mut all_uses);
+        if args.format == SummaryFormat::Human {
+            log::info!("Merged {} duplicate function type(s).", merged.len());
         }
-    }
-    for idx in &args.datas {
-        if *idx < datas.len() as u32 {
-            work_queue.push(WorkItem::Data(*idx));
+        for (idx, into) in &merged {
+            log::debug!("merged type {} into type {}", idx, into);
         }
+        merged
+    } else {
+        vec![]
+    };
+
+    for site in find_patch_sites(module, &all_uses, &liveness_options.dropped, cut_funcs)? {
+        warn(
+            args.format,
+            warnings,
+            format!(
+                "{} referred to dropped {}, so it was {}.",
+                site.site,
+                module.describe(site.dropped),
+                site.patch
+            ),
+        );
     }
-    for idx in &args.elems {
-        if *idx < elems.len() as u32 {
-            work_queue.push(WorkItem::Elem(*idx));
+
+    if args.unknown_opcodes != UnknownOpcodes::Ignore {
+        let unknown = find_unknown_operators(module, &all_uses, liveness_options)?;
+        for op in &unknown {
+            warn(
+                args.format,
+                warnings,
+                format!(
+                    "func {} uses {}, which may refer to items that were not kept.",
+                    op.func_idx, op.name
+                ),
+            );
         }
-    }
-    for idx in &args.tags {
-        if *idx < tag_types.len() as u32 {
-            work_queue.push(WorkItem::Tag(*idx));
+        if args.unknown_opcodes == UnknownOpcodes::Error && !unknown.is_empty() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "found {} unknown operator(s) in live functions (use --unknown-opcodes=preserve to continue anyway)",
+                    unknown.len()
+                ),
+            )
+            .into());
         }
     }
 
-    let mut all_uses = Uses::default();
+    let mut relocations = build_relocations(&all_uses);
+    relocations.merge_types(&merged_types);
 
-    while !work_queue.is_empty() {
-        let work = work_queue.first().expect("non-empty queue");
-
-        let new_uses = match work {
-            WorkItem::Type(idx) => {
-                let mut res = Uses::single_type(*idx);
-                res.merge(get_type_uses(&types[*idx as usize]));
-                res
-            }
-            WorkItem::Func(idx) => {
-                let mut res = Uses::single_func(*idx);
-                res.merge(Uses::single_type(func_types[*idx as usize]));
-                if *idx >= num_imported_functions {
-                    let func = &defined_funcs[(idx - num_imported_functions) as usize];
-                    res.merge(Uses::single_type(func.type_idx));
-                    for (_, ty) in &func.locals {
-                        res.merge(get_valtype_uses(ty));
-                    }
-                    for instr in &func.instructions {
-                        res.merge(get_instr_uses(instr));
+    let demote_start = match module.start_idx {
+        Some(start_idx) if args.standalone && all_uses.live_funcs.contains(start_idx) => {
+            // Stubbed imports trap, so a start function that calls one would make the module
+            // impossible to instantiate.
+            let start_uses = find_live(module, vec![WorkItem::Func(start_idx)], liveness_options)?;
+            let calls_imports = start_uses
+                .live_funcs
+                .iter()
+                .any(|idx| idx < module.num_imported_functions);
+            match args.start_policy {
+                StartPolicy::Auto => {
+                    if calls_imports {
+                        let message = "The start function calls stubbed imports, so it has been exported as \"_initialize\" instead.";
+                        match args.format {
+                            SummaryFormat::Human => log::info!("{}", message),
+                            SummaryFormat::Json => warnings.push(message.to_string()),
+                        }
                     }
+                    calls_imports
                 }
-                res
-            }
-            WorkItem::Table(idx) => {
-                let mut res = Uses::single_table(*idx);
-                res.merge(get_tabletype_uses(&table_types[*idx as usize]));
-                if *idx >= num_imported_tables {
-                    let table = &defined_tables[(idx - num_imported_tables) as usize];
-                    if let TableInit::Expr(expr) = &table.init {
-                        res.merge(get_constexpr_uses(expr)?);
+                StartPolicy::Keep => {
+                    if calls_imports {
+                        warn(
+                            args.format,
+                            warnings,
+                            "the start function calls stubbed imports, so instantiation may trap."
+                                .to_string(),
+                        );
                     }
+                    false
                 }
-                res
-            }
-            WorkItem::Global(idx) => {
-                let mut res = Uses::single_global(*idx);
-                res.merge(get_globaltype_uses(&global_types[*idx as usize]));
-                if *idx >= num_imported_globals {
-                    let global = &defined_globals[(idx - num_imported_globals) as usize];
-                    res.merge(get_constexpr_uses(&global.init_expr)?)
-                }
-                res
+                StartPolicy::Export => true,
             }
-            WorkItem::Memory(idx) => Uses::single_memory(*idx),
-            WorkItem::Data(idx) => {
-                let mut res = Uses::single_data(*idx);
-                let data = &datas[*idx as usize];
-                match &data.kind {
-                    DataKind::Passive => (),
-                    DataKind::Active {
-                        memory_index,
-                        offset_expr,
-                    } => {
-                        res.merge(Uses::single_memory(*memory_index));
-                        res.merge(get_constexpr_uses(offset_expr)?);
-                    }
-                };
-                res
+        }
+        _ => false,
+    };
+
+    let data_trims = if args.trim_data {
+        let accesses = find_memory_accesses(module, &all_uses, cut_funcs)?;
+        let trims = plan_data_trims(module, &all_uses, &accesses);
+        if args.format == SummaryFormat::Human {
+            let has_segments = |memory: u32| {
+                all_uses.live_datas.iter().any(|idx| {
+                    matches!(module.datas[idx as usize].kind,
+                        wasmparser::DataKind::Active { memory_index, .. } if memory_index == memory)
+                })
+            };
+            for idx in accesses.computed.iter().filter(|idx| has_segments(**idx)) {
+                log::info!(
+                    "Memory {} is also accessed at addresses computed at runtime, so its data segments are kept whole.",
+                    idx
+                );
             }
-            WorkItem::Elem(idx) => {
-                let mut res = Uses::single_elem(*idx);
-                let elem = &elems[*idx as usize];
-                match &elem.kind {
-                    ElementKind::Passive | ElementKind::Declared => (),
-                    ElementKind::Active {
-                        table_index,
-                        offset_expr,
-                    } => {
-                        // It's not clear to me why the table index is optional at this stage, but
-                        // other code in wasm-tools defaults to zero if it's missing.
-                        res.merge(Uses::single_table(table_index.unwrap_or(0)));
-                        res.merge(get_constexpr_uses(offset_expr)?);
-                    }
-                };
-                match &elem.items {
-                    wasmparser::ElementItems::Functions(funcs) => {
-                        for func_idx in funcs.clone() {
-                            res.merge(Uses::single_func(func_idx?));
-                        }
-                    }
-                    wasmparser::ElementItems::Expressions(ref_type, exprs) => {
-                        res.merge(get_reftype_uses(ref_type));
-                        for expr in exprs.clone() {
-                            res.merge(get_constexpr_uses(&expr?)?);
-                        }
-                    }
-                };
-                res
+        }
+        let removed: usize = trims
+            .iter()
+            .map(|(idx, pieces)| {
+                let kept: usize = pieces.iter().map(|piece| piece.len()).sum();
+                module.datas[*idx as usize].data.len() - kept
+            })
+            .sum();
+        if args.format == SummaryFormat::Human {
+            log::info!(
+                "Trimmed {} bytes from {} data segment(s).",
+                removed,
+                trims.len()
+            );
+        }
+        Some(trims)
+    } else {
+        None
+    };
+
+    if args.passive_init
+        && !demote_start
+        && module
+            .start_idx
+            .is_some_and(|idx| all_uses.live_funcs.contains(idx))
+    {
+        warn(
+            args.format,
+            warnings,
+            format!(
+                "the start function runs on instantiation, before {} can be called, so it will not see any kept segments.",
+                INIT_FUNC_NAME
+            ),
+        );
+    }
+
+    let mut memory_minimums = if args.clamp_memory {
+        clamp_memory_minimums(module, &all_uses, data_trims.as_ref())
+    } else {
+        BTreeMap::new()
+    };
+    for (idx, pages) in &args.memory_minimum {
+        let Some(ty) = module.memory_types.get(*idx as usize) else {
+            anyhow::bail!("memory {} is out of range", idx);
+        };
+        if let Some(max) = ty.maximum.filter(|max| pages > max) {
+            anyhow::bail!(
+                "memory {} can have at most {} pages, so it cannot start with {}",
+                idx,
+                max,
+                pages
+            );
+        }
+        memory_minimums.insert(*idx, *pages);
+    }
+    if args.format == SummaryFormat::Human {
+        let changed = memory_minimums
+            .iter()
+            .filter(|(idx, pages)| module.memory_types[**idx as usize].initial != **pages);
+        for (idx, pages) in changed {
+            log::info!(
+                "Memory {} now starts with {} page(s) instead of {}.",
+                idx,
+                pages,
+                module.memory_types[*idx as usize].initial
+            );
+        }
+    }
+
+    // Everything else is kept too, after every analysis of what the kept code does, so that
+    // nothing moves.
+    let stable: &[IndexSpace] = if args.no_relocate {
+        &IndexSpace::ALL
+    } else {
+        &args.stable
+    };
+    let placeholders = placeholder_items(module, &all_uses, stable)?;
+    let mut padded_uses = all_uses.clone();
+    padded_uses.merge(placeholders.clone());
+    if !stable.is_empty() {
+        relocations = build_relocations(&padded_uses);
+    }
+    let emit_options = job.emit_options(
+        demote_start,
+        data_trims.as_ref(),
+        &memory_minimums,
+        &placeholders,
+    );
+    let out_bytes = emit(module, &padded_uses, &relocations, &emit_options)?;
+    let chased = chased_exports(module, &relocations, &emit_options)?;
+    let (mut out_bytes, relocations) = match job.stubs {
+        Some(stubs) => {
+            let linked = link_stubs(&ParsedModule::parse(&out_bytes)?, stubs)?;
+            if args.format == SummaryFormat::Human {
+                log::info!("Linked {} import(s) to stubs.", linked.linked.len());
             }
-            WorkItem::Tag(idx) => {
-                let mut res = Uses::single_tag(*idx);
-                res.merge(get_tagtype_uses(&tag_types[*idx as usize]));
-                res
+            for name in &linked.linked {
+                log::debug!("linked the import {}", name);
             }
+            (linked.wasm, relocations.then(&linked.relocations))
+        }
+        None => (out_bytes, relocations),
+    };
+    if args.annotate {
+        append_annotation(
+            &mut out_bytes,
+            module,
+            &work_queue,
+            std::env::args().skip(1).collect(),
+        )?;
+    }
+    if out_bytes.len() as u64 > limits.max_output_size {
+        anyhow::bail!(
+            "the output would be {} bytes, which is more than the limit of {} (use --max-output-size to raise it)",
+            out_bytes.len(),
+            limits.max_output_size
+        );
+    }
+    if !args.features.is_empty() {
+        let allowed = args
+            .features
+            .iter()
+            .fold(WasmFeatures::empty(), |acc, features| acc | *features);
+        let required = find_required_features(module, &all_uses, cut_funcs)?;
+        check_features(module, &required, allowed, &out_bytes)?;
+    }
+    match args.exception_handling {
+        ExceptionHandling::Any => {}
+        ExceptionHandling::Legacy => validate_exception_handling(&out_bytes, true)?,
+        ExceptionHandling::Exnref => validate_exception_handling(&out_bytes, false)?,
+    }
+
+    if let Some(api) = api {
+        let mut expected = api.to_vec();
+        expected.extend(chased);
+        if demote_start {
+            expected.push("_initialize".to_string());
+        }
+        if args.passive_init {
+            expected.push(INIT_FUNC_NAME.to_string());
+        }
+        verify_api(&out_bytes, &expected)?;
+    }
+    Ok(Isolated {
+        all_uses,
+        relocations,
+        out_bytes,
+        demote_start,
+        data_trims,
+        memory_minimums,
+        placeholders,
+    })
+}
+
+/// Expands globs in the input file names, then isolates each input with the same options.
+fn run_inputs(args: Args) -> Result<()> {
+    let mut inputs: Vec<String> = vec![];
+    for name in &args.filename {
+        if name == "-" || !name.contains(['*', '?', '[']) {
+            inputs.push(name.clone());
+            continue;
+        }
+        let before = inputs.len();
+        for path in glob::glob(name)? {
+            inputs.push(path?.to_string_lossy().into_owned());
+        }
+        if inputs.len() == before {
+            anyhow::bail!("no files match \"{}\"", name);
+        }
+    }
+
+    if inputs.len() <= 1 && args.out_dir.is_none() {
+        return run_isolate(Args {
+            filename: inputs,
+            ..args
+        });
+    }
+    if inputs.len() > 1 {
+        if inputs.iter().any(|input| input == "-") {
+            anyhow::bail!("stdin cannot be read along with other inputs");
+        }
+        if args.out.is_some() || args.out_prefix.is_some() || args.split_out.is_some() {
+            anyhow::bail!("-o, --out-prefix, and --split-out only work with a single input (use --out-dir instead)");
+        }
+    }
+    if let Some(dir) = &args.out_dir {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut failed = 0;
+    for input in &inputs {
+        let path = Path::new(input);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let name = name
+            .strip_suffix(".gz")
+            .or_else(|| name.strip_suffix(".br"))
+            .unwrap_or(&name);
+        let stem = Path::new(name)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let out = match &args.out_dir {
+            _ if args.in_place => None,
+            Some(dir) => Some(Path::new(dir).join(format!("{}.wasm", stem))),
+            None => Some(path.with_file_name(format!("{}.isolated.wasm", stem))),
         };
-        work_queue.remove(0);
+        log::info!("{}:", input);
+        let result = run_isolate(Args {
+            filename: vec![input.clone()],
+            out: out.map(|out| out.to_string_lossy().into_owned()),
+            ..args.clone()
+        });
+        if let Err(err) = result {
+            log::error!("{:#}", err);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!(
+            "{} of {} inputs could not be isolated",
+            failed,
+            inputs.len()
+        );
+    }
+    Ok(())
+}
 
-        // Push all unused things to the queue
-        for idx in &new_uses.live_types {
-            if !all_uses.live_types.contains(idx) {
-                work_queue.push(WorkItem::Type(*idx));
-            }
+fn run_isolate(mut args: Args) -> Result<()> {
+    let config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let Some(filename) = args.filename.first().cloned().or(config.input.clone()) else {
+        anyhow::bail!("no input file was given, either on the command line or in the config file");
+    };
+    let raw = read_raw_input(&filename)?;
+    let compressed = compression_of(&raw, &filename);
+    let buf = decompress(raw, &filename)?;
+    if args.in_place && args.compress.is_none() {
+        // Replacing a compressed file with a plain module would break whatever serves it.
+        args.compress = match compressed {
+            Some(CompressionFormat::Gzip) => Some(Compression::Gzip),
+            #[cfg(feature = "brotli")]
+            Some(CompressionFormat::Brotli) => Some(Compression::Brotli),
+            // Without the brotli feature, decompressing it has already failed.
+            _ => None,
+        };
+    }
+    let limits = Limits {
+        max_funcs: args.max_funcs,
+        max_operators: args.max_operators,
+        max_output_size: args.max_output_size,
+    };
+    let module = ParsedModule::parse_with_limits(&buf, &limits)?;
+    if let Some(dir) = &args.cache_dir {
+        cache::load_or_save(&module, Path::new(dir))?;
+    }
+    apply_config(&mut args, config, &module)?;
+    for name in &args.func_names {
+        let Some(idx) = module.find_func_by_name(name) else {
+            anyhow::bail!("no function named \"{}\" was found", name);
+        };
+        args.funcs.push(idx);
+    }
+    for name in &args.exports.clone() {
+        let Some(item) = module.find_export(name) else {
+            anyhow::bail!("export \"{}\" was not found in the module", name);
+        };
+        add_root(&mut args, item);
+    }
+    if let Some(path) = &args.profile {
+        let text = String::from_utf8(read_input(path)?)
+            .with_context(|| format!("the profile {} is not text", path))?;
+        let profile = load_profile(&module, &text)?;
+        if profile.funcs.is_empty() {
+            anyhow::bail!("none of the samples in the profile are in functions of this module");
         }
-        for idx in &new_uses.live_funcs {
-            if !all_uses.live_funcs.contains(idx) {
-                work_queue.push(WorkItem::Func(*idx));
-            }
+        let hot = profile.hottest(args.top_percent);
+        let hot_samples: u64 = hot.iter().map(|func| func.samples).sum();
+        if args.format == SummaryFormat::Human {
+            log::info!(
+                "Using the {} hottest function(s) from the profile, which account for {:.1}% of its {} sample(s) in this module.",
+                hot.len(),
+                hot_samples as f64 * 100.0 / profile.total_samples() as f64,
+                profile.total_samples()
+            );
         }
-        for idx in &new_uses.live_tables {
-            if !all_uses.live_tables.contains(idx) {
-                work_queue.push(WorkItem::Table(*idx));
-            }
+        if profile.other_samples > 0 {
+            log::debug!(
+                "{} sample(s) in the profile were outside this module",
+                profile.other_samples
+            );
         }
-        for idx in &new_uses.live_globals {
-            if !all_uses.live_globals.contains(idx) {
-                work_queue.push(WorkItem::Global(*idx));
-            }
+        for func in hot {
+            log::debug!("func:{} has {} sample(s)", func.idx, func.samples);
+            args.funcs.push(func.idx);
         }
-        for idx in &new_uses.live_memories {
-            if !all_uses.live_memories.contains(idx) {
-                work_queue.push(WorkItem::Memory(*idx));
-            }
+    }
+    if let Some(path) = &args.stack_trace {
+        let text = String::from_utf8(read_input(path)?)
+            .with_context(|| format!("the stack trace {} is not text", path))?;
+        let frames = parse_stack_trace(&module, &text);
+        if frames.is_empty() {
+            anyhow::bail!("no wasm frames were found in the stack trace {}", path);
         }
-        for idx in &new_uses.live_datas {
-            if !all_uses.live_datas.contains(idx) {
-                work_queue.push(WorkItem::Data(*idx));
+        let mut funcs: Vec<u32> = vec![];
+        for frame in &frames {
+            match frame.func {
+                Some(idx) => {
+                    log::debug!("line {} of the stack trace is func:{}", frame.line, idx);
+                    if !funcs.contains(&idx) {
+                        funcs.push(idx);
+                    }
+                }
+                None => log::warn!(
+                    "line {} of the stack trace does not refer to a function in this module: {}",
+                    frame.line,
+                    frame.text
+                ),
             }
         }
-        for idx in &new_uses.live_elems {
-            if !all_uses.live_elems.contains(idx) {
-                work_queue.push(WorkItem::Elem(*idx));
-            }
+        if args.format == SummaryFormat::Human {
+            log::info!(
+                "Found {} wasm frame(s) in the stack trace, in {} function(s).",
+                frames.len(),
+                funcs.len()
+            );
         }
-        for idx in &new_uses.live_tags {
-            if !all_uses.live_tags.contains(idx) {
-                work_queue.push(WorkItem::Tag(*idx));
+        args.funcs.extend(funcs);
+    }
+    for (i, group) in args.groups.clone().iter().enumerate() {
+        if args.groups[..i]
+            .iter()
+            .any(|other| other.name == group.name)
+        {
+            anyhow::bail!("the group \"{}\" was given more than once", group.name);
+        }
+        for root in &group.roots {
+            if !module.contains(*root) {
+                anyhow::bail!("{} in group \"{}\" is out of range", root, group.name);
             }
+            add_root(&mut args, *root);
         }
-
-        all_uses.merge(new_uses);
     }
 
-    //
-    // Track relocations
-    //
-
-    let mut relocations = HashMap::<Relocation, u32>::new();
-
-    for type_idx in &all_uses.live_types {
-        // Type canonicalization be damned. Surely no self-respecting compiler would leave
-        // redundant types in its output.
-        let new_idx = get_new_index(&all_uses.live_types, type_idx);
-        relocations.insert(Relocation::Type(*type_idx), new_idx);
+    if args.in_place && filename == "-" {
+        anyhow::bail!("stdin cannot be edited in place");
     }
-    for func_idx in &all_uses.live_funcs {
-        let new_idx = get_new_index(&all_uses.live_funcs, func_idx);
-        relocations.insert(Relocation::Func(*func_idx), new_idx);
+    let to_stdout = args.out.is_none()
+        && args.out_prefix.is_none()
+        && args.split_out.is_none()
+        && !args.in_place;
+    if to_stdout && !args.force && std::io::stdout().is_terminal() {
+        anyhow::bail!("refusing to write a binary module to a terminal (use -o to write it to a file, or --force to write it anyway)");
     }
-    for table_idx in &all_uses.live_tables {
-        let new_idx = get_new_index(&all_uses.live_tables, table_idx);
-        relocations.insert(Relocation::Table(*table_idx), new_idx);
+    if args.emit_harness.is_some() && args.out.is_none() && !args.emit.contains(&Artifact::Wasm) {
+        anyhow::bail!("--emit-harness needs the isolated module to be written to a file (use -o or --emit wasm)");
     }
-    for global_idx in &all_uses.live_globals {
-        let new_idx = get_new_index(&all_uses.live_globals, global_idx);
-        relocations.insert(Relocation::Global(*global_idx), new_idx);
+
+    let api = match &args.api {
+        Some(path) => Some(read_api_file(path)?),
+        None => None,
+    };
+    let stubs_buf = match &args.link_stubs {
+        Some(path) => Some(read_input(path)?),
+        None => None,
+    };
+    let stubs = match &stubs_buf {
+        Some(buf) => Some(ParsedModule::parse(buf)?),
+        None => None,
+    };
+
+    let mut cut_funcs = IndexSet::new();
+    for spec in &args.cut {
+        cut_funcs.insert(module.resolve_func(spec)?);
     }
-    for mem_idx in &all_uses.live_memories {
-        let new_idx = get_new_index(&all_uses.live_memories, mem_idx);
-        relocations.insert(Relocation::Memory(*mem_idx), new_idx);
+    let mut dropped = Uses::default();
+    for spec in &args.drop_funcs {
+        dropped.insert(WorkItem::Func(module.resolve_func(spec)?));
     }
-    for data_idx in &all_uses.live_datas {
-        let new_idx = get_new_index(&all_uses.live_datas, data_idx);
-        relocations.insert(Relocation::Data(*data_idx), new_idx);
+    for item in &args.drop {
+        if let WorkItem::Type(_) = item {
+            anyhow::bail!("types cannot be dropped");
+        }
+        if !module.contains(*item) {
+            anyhow::bail!("{} is out of range", item);
+        }
+        dropped.insert(*item);
     }
-    for elem_idx in &all_uses.live_elems {
-        let new_idx = get_new_index(&all_uses.live_elems, elem_idx);
-        relocations.insert(Relocation::Elem(*elem_idx), new_idx);
+
+    // Functions picked by offset count as requested, just like the ones given by index.
+    for offset in &args.func_at_offset {
+        let Some(idx) = module.func_at_offset(*offset) else {
+            anyhow::bail!("offset {:#x} is not inside any function body", offset);
+        };
+        args.funcs.push(idx);
     }
-    for tag_idx in &all_uses.live_tags {
-        let new_idx = get_new_index(&all_uses.live_tags, tag_idx);
-        relocations.insert(Relocation::Tag(*tag_idx), new_idx);
+
+    if !args.empty && !has_roots(&args) {
+        // The original exports stay, so there is no need for synthetic ones.
+        for export in &module.exports {
+            add_root(&mut args, export_item(export));
+        }
+        if let Some(idx) = module.start_idx {
+            args.funcs.push(idx);
+        }
+        // Active segments write to memories and tables when the module is instantiated, and trap
+        // if they don't fit, so they have effects even if nothing else uses them.
+        for (i, data) in module.datas.iter().enumerate() {
+            if let wasmparser::DataKind::Active { .. } = data.kind {
+                args.datas.push(i as u32);
+            }
+        }
+        for (i, elem) in module.elems.iter().enumerate() {
+            if let wasmparser::ElementKind::Active { .. } = elem.kind {
+                args.elems.push(i as u32);
+            }
+        }
+        args.no_synthetic_exports = true;
+        if args.format == SummaryFormat::Human {
+            log::info!("Nothing was requested, so keeping every export, the start function, and every active segment.");
+        }
     }
 
     //
-    // Output the new wasm module.
+    // Iterate over all live objects until we have gathered all the references.
     //
 
-    let mut out = Module::new();
-    let mut reencoder = RelocatingReencoder {
-        relocations: &relocations,
-    };
-    for section in sections {
-        match section {
-            Section::Passthrough(sec) => {
-                out.section(&sec);
+    let mut work_queue: Vec<WorkItem> = vec![];
+    for idx in &args.types {
+        if *idx < module.types.len() as u32 {
+            work_queue.push(WorkItem::Type(*idx));
+        }
+    }
+    for idx in &args.funcs {
+        if *idx < module.func_types.len() as u32 {
+            work_queue.push(WorkItem::Func(*idx));
+        }
+    }
+    for idx in &args.tables {
+        if *idx < module.table_types.len() as u32 {
+            work_queue.push(WorkItem::Table(*idx));
+        }
+    }
+    for idx in &args.globals {
+        if *idx < module.global_types.len() as u32 {
+            work_queue.push(WorkItem::Global(*idx));
+        }
+    }
+    for idx in &args.memories {
+        if *idx < module.memory_types.len() as u32 {
+            work_queue.push(WorkItem::Memory(*idx));
+        }
+    }
+    for idx in &args.datas {
+        if *idx < module.datas.len() as u32 {
+            work_queue.push(WorkItem::Data(*idx));
+        }
+    }
+    if !args.funcs_with_sig.is_empty() {
+        for (idx, type_idx) in module.func_types.iter().enumerate() {
+            let ty = module.signature(*type_idx)?;
+            if args
+                .funcs_with_sig
+                .iter()
+                .any(|sig| ty.params() == sig.params && ty.results() == sig.results)
+            {
+                work_queue.push(WorkItem::Func(idx as u32));
             }
-
-            Section::Type => {
-                let mut type_section = TypeSection::new();
-                let mut idx: u32 = 0;
-                for rg in &rec_groups {
-                    let mut sub_types: Vec<wasm_encoder::SubType> = vec![];
-                    for ty in rg.types() {
-                        if relocations.get(&Relocation::Type(idx)).is_some() {
-                            sub_types.push(reencoder.sub_type(ty.clone())?);
-                        }
-                        idx += 1;
-                    }
-                    if sub_types.len() == 1 {
-                        type_section.ty().subtype(sub_types.first().unwrap());
-                    } else if sub_types.len() > 1 || rg.is_explicit_rec_group() {
-                        type_section.ty().rec(sub_types)
-                    }
-                }
-                out.section(&type_section);
+        }
+    }
+    for (i, data) in module.datas.iter().enumerate() {
+        if let wasmparser::DataKind::Active { memory_index, .. } = data.kind {
+            if args.datas_for_memory.contains(&memory_index) {
+                work_queue.push(WorkItem::Data(i as u32));
             }
-            Section::Import => {
-                let mut import_section = ImportSection::new();
-
-                let mut num_imported_funcs = 0;
-                let mut num_imported_tables = 0;
-                let mut num_imported_memories = 0;
-                let mut num_imported_globals = 0;
-                let mut num_imported_tags = 0;
-                for import in &imports {
-                    match import.ty {
-                        wasmparser::TypeRef::Func(type_idx) => {
-                            if all_uses.live_funcs.contains(&num_imported_funcs) {
-                                import_section.import(
-                                    import.module,
-                                    import.name,
-                                    EntityType::Function(reencoder.type_index(type_idx)),
-                                );
-                            }
-                            num_imported_funcs += 1;
-                        }
-                        wasmparser::TypeRef::Table(ty) => {
-                            if all_uses.live_tables.contains(&num_imported_tables) {
-                                import_section.import(
-                                    import.module,
-                                    import.name,
-                                    reencoder.table_type(ty)?,
-                                );
-                            }
-                            num_imported_tables += 1;
-                        }
-                        wasmparser::TypeRef::Memory(ty) => {
-                            if all_uses.live_memories.contains(&num_imported_memories) {
-                                import_section.import(
-                                    import.module,
-                                    import.name,
-                                    reencoder.memory_type(ty),
-                                );
-                            }
-                            num_imported_memories += 1;
-                        }
-                        wasmparser::TypeRef::Global(ty) => {
-                            if all_uses.live_globals.contains(&num_imported_globals) {
-                                import_section.import(
-                                    import.module,
-                                    import.name,
-                                    reencoder.global_type(ty)?,
-                                );
-                            }
-                            num_imported_globals += 1;
-                        }
-                        wasmparser::TypeRef::Tag(ty) => {
-                            if all_uses.live_tags.contains(&num_imported_tags) {
-                                import_section.import(
-                                    import.module,
-                                    import.name,
-                                    reencoder.tag_type(ty),
-                                );
-                            }
-                            num_imported_tags += 1;
-                        }
-                    }
-                }
-
-                out.section(&import_section);
+        }
+    }
+    for idx in &args.elems {
+        if *idx < module.elems.len() as u32 {
+            work_queue.push(WorkItem::Elem(*idx));
+        }
+    }
+    for (i, elem) in module.elems.iter().enumerate() {
+        if let wasmparser::ElementKind::Active { table_index, .. } = elem.kind {
+            // Segments without a table index apply to table 0.
+            if args.elems_for_table.contains(&table_index.unwrap_or(0)) {
+                work_queue.push(WorkItem::Elem(i as u32));
             }
-            Section::Function => {
-                let mut function_section = FunctionSection::new();
-                for (i, _) in defined_funcs.iter().enumerate() {
-                    let idx = num_imported_functions + i as u32;
-                    if relocations.get(&Relocation::Func(idx)).is_some() {
-                        function_section.function(reencoder.type_index(func_types[idx as usize]));
-                    }
+        }
+    }
+    for idx in &args.tags {
+        if *idx < module.tag_types.len() as u32 {
+            work_queue.push(WorkItem::Tag(*idx));
+        }
+    }
+    if args.keep_all {
+        work_queue.extend(
+            module
+                .all_items()
+                .into_iter()
+                .filter(|item| !dropped.contains(*item)),
+        );
+    }
+    if let Some(api) = &api {
+        for name in api {
+            let Some(item) = module.find_export(name) else {
+                anyhow::bail!(
+                    "export \"{}\" from the API file was not found in the module",
+                    name
+                );
+            };
+            work_queue.push(item);
+        }
+    }
+
+    let mut liveness_options = LivenessOptions {
+        cut_funcs: cut_funcs.clone(),
+        dropped,
+        indirect_closure: args.indirect_closure,
+        chase_exports: args.chase_exports,
+        keep_passive_elems: args.keep_passive_elems,
+        drop_declared_elems: args.drop_declared_elems,
+        skip_elem_funcs: args.null_elem_funcs,
+    };
+    if !args.null_elem_funcs {
+        drop_unmatched_indirect_targets(&module, &work_queue, &mut liveness_options)?;
+    }
+    if args.null_elem_funcs {
+        // Whatever the kept segments refer to that isn't kept without them is dropped, which
+        // makes their entries null.
+        let kept = find_live(&module, work_queue.clone(), &liveness_options)?;
+        for idx in kept.live_elems.iter() {
+            for func_idx in get_elem_funcs(&module.elems[idx as usize])? {
+                if !kept.live_funcs.contains(func_idx) {
+                    liveness_options.dropped.insert(WorkItem::Func(func_idx));
                 }
-                out.section(&function_section);
             }
-            Section::Table => {
-                let mut table_section = TableSection::new();
-                for (i, table) in defined_tables.iter().enumerate() {
-                    let idx = num_imported_tables + i as u32;
-                    if relocations.get(&Relocation::Table(idx)).is_some() {
-                        match &table.init {
-                            wasmparser::TableInit::RefNull => {
-                                table_section.table(reencoder.table_type(table.ty)?);
-                            }
-                            wasmparser::TableInit::Expr(init_expr) => {
-                                table_section.table_with_init(
-                                    reencoder.table_type(table.ty)?,
-                                    &reencoder.const_expr(init_expr.clone())?,
-                                );
-                            }
-                        }
-                    }
-                }
-                out.section(&table_section);
+        }
+        liveness_options.skip_elem_funcs = false;
+    }
+    if args.preserve_table_layout {
+        let kept = find_live(&module, work_queue.clone(), &liveness_options)?;
+        for idx in kept.live_elems.iter() {
+            let elem = &module.elems[idx as usize];
+            if matches!(elem.kind, wasmparser::ElementKind::Declared) {
+                continue;
             }
-            Section::Memory => {
-                let mut memory_section = MemorySection::new();
-                for idx in num_imported_memories..(memory_types.len() as u32) {
-                    if relocations.get(&Relocation::Memory(idx)).is_some() {
-                        let mem_type = &memory_types[idx as usize];
-                        memory_section.memory(reencoder.memory_type(mem_type.clone()));
-                    }
+            for func_idx in get_elem_funcs(elem)? {
+                if !liveness_options.dropped.contains(WorkItem::Func(func_idx)) {
+                    continue;
                 }
-                out.section(&memory_section);
-            }
-            Section::Global => {
-                let mut global_section = GlobalSection::new();
-                for (i, global) in defined_globals.iter().enumerate() {
-                    let idx = num_imported_globals + i as u32;
-                    if relocations.get(&Relocation::Global(idx)).is_some() {
-                        global_section.global(
-                            reencoder.global_type(global.ty)?,
-                            &reencoder.const_expr(global.init_expr.clone())?,
-                        );
-                    }
+                if func_idx < module.num_imported_functions {
+                    log::warn!(
+                        "func {}{} is imported, so its entries in elem {} are null rather than a stub",
+                        func_idx,
+                        name_suffix(&module, WorkItem::Func(func_idx)),
+                        idx
+                    );
+                    continue;
                 }
-                out.section(&global_section);
+                liveness_options.dropped.live_funcs.remove(func_idx);
+                liveness_options.cut_funcs.insert(func_idx);
+                cut_funcs.insert(func_idx);
             }
-            Section::Export => {
-                let mut export_section = ExportSection::new();
-                for export in &exports {
-                    // We don't use the reencoder here because we need to actually look up from the
-                    // relocation map anyway to figure out if we should export at all. So then we
-                    // might as well just write the value we find there.
-                    let reloc = match export.kind {
-                        wasmparser::ExternalKind::Func => Relocation::Func(export.index),
-                        wasmparser::ExternalKind::Table => Relocation::Table(export.index),
-                        wasmparser::ExternalKind::Memory => Relocation::Memory(export.index),
-                        wasmparser::ExternalKind::Global => Relocation::Global(export.index),
-                        wasmparser::ExternalKind::Tag => Relocation::Tag(export.index),
-                    };
-                    if let Some(new_idx) = relocations.get(&reloc) {
-                        export_section.export(export.name, export.kind.into(), *new_idx);
-                    }
-                }
+        }
+    }
+    let custom_sections = CustomSectionFilter::new(&args.keep_custom, &args.strip_custom)?;
+    let export_filter = ExportFilter::new(&args.strip_exports, &args.rename_export)?;
+    let requested = Uses {
+        live_types: args.types.iter().copied().collect(),
+        live_funcs: args.funcs.iter().copied().collect(),
+        live_tables: args.tables.iter().copied().collect(),
+        live_globals: args.globals.iter().copied().collect(),
+        live_memories: args.memories.iter().copied().collect(),
+        live_datas: args.datas.iter().copied().collect(),
+        live_elems: args.elems.iter().copied().collect(),
+        live_tags: args.tags.iter().copied().collect(),
+    };
+    let job = Job {
+        args: &args,
+        requested: &requested,
+        cut_funcs: &cut_funcs,
+        api: api.as_deref(),
+        custom_sections: &custom_sections,
+        export_filter: &export_filter,
+        liveness_options: &liveness_options,
+        limits: &limits,
+        stubs: stubs.as_ref(),
+    };
+    let mut warnings: Vec<String> = vec![];
+    if let Some(dir) = &args.split_out {
+        return write_split(&module, &job, work_queue, dir, &mut warnings);
+    }
+    let Isolated {
+        all_uses,
+        relocations,
+        out_bytes,
+        demote_start,
+        data_trims,
+        memory_minimums,
+        placeholders,
+    } = isolate(&module, &job, work_queue.clone(), &mut warnings)?;
+    let emit_options = job.emit_options(
+        demote_start,
+        data_trims.as_ref(),
+        &memory_minimums,
+        &placeholders,
+    );
+    let group_uses = find_group_uses(&module, &args.groups, &all_uses, &liveness_options)?;
+    let group_reports = || build_group_reports(&module, &args.groups, &group_uses);
 
-                // Also export the explicitly-requested things so it's easy to test them in isolation.
-                for idx in &args.funcs {
-                    if let Some(new_idx) = relocations.get(&Relocation::Func(*idx)) {
-                        export_section.export(
-                            &format!("isolated_func_{}", *idx),
-                            wasm_encoder::ExportKind::Func,
-                            *new_idx,
-                        );
-                    }
-                }
-                for idx in &args.tables {
-                    if let Some(new_idx) = relocations.get(&Relocation::Table(*idx)) {
-                        export_section.export(
-                            &format!("isolated_table_{}", *idx),
-                            wasm_encoder::ExportKind::Table,
-                            *new_idx,
-                        );
-                    }
-                }
-                for idx in &args.globals {
-                    if let Some(new_idx) = relocations.get(&Relocation::Global(*idx)) {
-                        export_section.export(
-                            &format!("isolated_global_{}", *idx),
-                            wasm_encoder::ExportKind::Global,
-                            *new_idx,
-                        );
-                    }
-                }
-                for idx in &args.memories {
-                    if let Some(new_idx) = relocations.get(&Relocation::Memory(*idx)) {
-                        export_section.export(
-                            &format!("isolated_memory_{}", *idx),
-                            wasm_encoder::ExportKind::Memory,
-                            *new_idx,
-                        );
-                    }
-                }
-                for idx in &args.tags {
-                    if let Some(new_idx) = relocations.get(&Relocation::Tag(*idx)) {
-                        export_section.export(
-                            &format!("isolated_tag_{}", *idx),
-                            wasm_encoder::ExportKind::Tag,
-                            *new_idx,
-                        );
-                    }
-                }
+    if args.stats {
+        let mut report = build_report(&buf, &out_bytes, &module, &all_uses)?;
+        report.groups = group_reports();
+        write_stats(&mut std::io::stderr(), &report)?;
+    }
 
-                out.section(&export_section);
-            }
-            Section::Start => {
-                if let Some(idx) = start_idx {
-                    if let Some(new_idx) = relocations.get(&Relocation::Func(idx)) {
-                        out.section(&wasm_encoder::StartSection {
-                            function_index: *new_idx,
-                        });
-                    }
+    if let Some(prefix) = &args.out_prefix {
+        for artifact in &args.emit {
+            let path = format!("{}.{}", prefix, artifact.extension());
+            let mut file = File::create(&path)?;
+            match artifact {
+                Artifact::Wasm => file.write_all(&out_bytes)?,
+                Artifact::Wat => {
+                    file.write_all(wasmprinter::print_bytes(&out_bytes)?.as_bytes())?
                 }
-            }
-            Section::Element => {
-                let mut element_section = ElementSection::new();
-                for (i, elem) in elems.iter().enumerate() {
-                    let idx = i as u32;
-                    if relocations.get(&Relocation::Elem(idx)).is_some() {
-                        let expr: ConstExpr;
-                        element_section.segment(ElementSegment {
-                            mode: match &elem.kind {
-                                wasmparser::ElementKind::Passive => ElementMode::Passive,
-                                wasmparser::ElementKind::Active {
-                                    table_index,
-                                    offset_expr,
-                                } => {
-                                    expr = reencoder.const_expr(offset_expr.clone())?;
-                                    ElementMode::Active {
-                                        table: *table_index,
-                                        offset: &expr,
-                                    }
-                                }
-                                wasmparser::ElementKind::Declared => ElementMode::Declared,
-                            },
-                            elements: reencoder.element_items(elem.items.clone())?,
-                        });
-                    }
+                Artifact::Map => {
+                    serde_json::to_writer_pretty(&mut file, &build_map(&all_uses, &relocations)?)?
                 }
-                out.section(&element_section);
-            }
-            Section::Code => {
-                let mut code_section = CodeSection::new();
-                for (i, func) in defined_funcs.iter().enumerate() {
-                    let idx = i as u32 + num_imported_functions;
-                    if all_uses.live_funcs.contains(&idx) {
-                        let mut new_locals: Vec<(u32, wasm_encoder::ValType)> = vec![];
-                        for (n, ty) in &func.locals {
-                            new_locals.push((*n, reencoder.val_type(*ty)?));
-                        }
-                        let mut new_func = Function::new(new_locals);
-                        for instr in &func.instructions {
-                            new_func.instruction(&reencoder.instruction(instr.clone())?);
-                        }
-                        code_section.function(&new_func);
-                    }
+                Artifact::Report => {
+                    let mut report = build_report(&buf, &out_bytes, &module, &all_uses)?;
+                    report.groups = group_reports();
+                    serde_json::to_writer_pretty(&mut file, &report)?
                 }
-                out.section(&code_section);
+                Artifact::Graph => write_dot(
+                    &mut file,
+                    &build_graph(&module, &all_uses, &work_queue, &liveness_options)?,
+                )?,
+                Artifact::GraphJson => serde_json::to_writer_pretty(
+                    &mut file,
+                    &build_graph(&module, &all_uses, &work_queue, &liveness_options)?,
+                )?,
+                Artifact::Graphml => write_graphml(
+                    &mut file,
+                    &build_graph(&module, &all_uses, &work_queue, &liveness_options)?,
+                )?,
+                Artifact::Offsets => serde_json::to_writer_pretty(
+                    &mut file,
+                    &build_offset_map(&module, &out_bytes, &all_uses)?,
+                )?,
+                Artifact::SourceMap => serde_json::to_writer_pretty(
+                    &mut file,
+                    &build_source_map_remap(&module, &out_bytes, &all_uses)?,
+                )?,
             }
-            Section::Data => {
-                let mut data_section = DataSection::new();
-                for (i, data) in datas.iter().enumerate() {
-                    let idx = i as u32;
-                    if relocations.get(&Relocation::Data(idx)).is_some() {
-                        let expr: ConstExpr;
-                        data_section.segment(DataSegment {
-                            mode: match &data.kind {
-                                wasmparser::DataKind::Passive => DataSegmentMode::Passive,
-                                wasmparser::DataKind::Active {
-                                    memory_index,
-                                    offset_expr,
-                                } => {
-                                    expr = reencoder.const_expr(offset_expr.clone())?;
-                                    DataSegmentMode::Active {
-                                        memory_index: *memory_index,
-                                        offset: &expr,
-                                    }
-                                }
-                            },
-                            data: data.data.iter().map(|b| *b).collect::<Vec<u8>>(),
-                        });
-                    }
+        }
+    } else {
+        let compressed = match args.compress {
+            None => None,
+            Some(Compression::Gzip) => Some(gzip(&out_bytes)?),
+            #[cfg(feature = "brotli")]
+            Some(Compression::Brotli) => Some(brotli(&out_bytes)?),
+        };
+        let bytes = compressed.as_deref().unwrap_or(&out_bytes);
+        if let Some(path) = &args.out {
+            fs::write(path, bytes).with_context(|| format!("could not write {}", path))?;
+        } else if args.in_place {
+            write_atomically(Path::new(&filename), bytes)?;
+        } else {
+            std::io::stdout()
+                .write_all(bytes)
+                .context("could not write the isolated module to stdout")?;
+        }
+    }
+
+    if let Some(harness) = args.emit_harness {
+        let wasm_path = match &args.out_prefix {
+            Some(prefix) => format!("{}.wasm", prefix),
+            None => match &args.out {
+                Some(out) => out.clone(),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::Internal,
+                        "--emit-harness was allowed without an output file",
+                    )
+                    .into())
                 }
-                out.section(&data_section);
-            }
-            Section::DataCount => {
-                out.section(&wasm_encoder::DataCountSection {
-                    count: all_uses.live_datas.len() as u32,
-                });
-            }
-            Section::Tag => {
-                let mut tag_section = TagSection::new();
-                for idx in num_imported_tags..(tag_types.len() as u32) {
-                    if relocations.get(&Relocation::Tag(idx)).is_some() {
-                        let tag_type = &tag_types[idx as usize];
-                        tag_section.tag(reencoder.tag_type(tag_type.clone()));
-                    }
+            },
+        };
+        let stem = wasm_path.strip_suffix(".wasm").unwrap_or(&wasm_path);
+        // The harness sits next to the module, so it refers to it by its file name alone.
+        let Some(wasm_file) = Path::new(&wasm_path).file_name() else {
+            anyhow::bail!("the output path {} does not name a file", wasm_path);
+        };
+        let wasm_file = wasm_file.to_string_lossy();
+        let entry_points: Vec<String> =
+            synthetic_exports(&module, &all_uses, &relocations, &emit_options)
+                .into_iter()
+                .filter(|(_, kind, _)| *kind == wasm_encoder::ExportKind::Func)
+                .map(|(name, _, _)| name)
+                .collect();
+        let isolated_module = ParsedModule::parse(&out_bytes)?;
+        match harness {
+            Harness::Js => write_js_harness(
+                &mut File::create(format!("{}.harness.js", stem))?,
+                &isolated_module,
+                &wasm_file,
+                &entry_points,
+            )?,
+            Harness::Wasmtime => {
+                let path = format!("{}.harness.sh", stem);
+                let mut file = File::create(&path)?;
+                write_wasmtime_harness(&mut file, &isolated_module, &wasm_file, &entry_points)?;
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    file.set_permissions(fs::Permissions::from_mode(0o755))?;
                 }
-                out.section(&tag_section);
             }
         }
     }
-    let out_bytes = out.finish();
 
-    if let Some(path) = &args.out {
-        fs::write(path, out_bytes).expect("unable to write file");
-    } else {
-        std::io::stdout()
-            .write_all(&out_bytes)
-            .expect("unable to write output");
+    if let Some(path) = &args.emit_offset_map {
+        let mut file = BufWriter::new(
+            File::create(path).with_context(|| format!("could not create {}", path))?,
+        );
+        write_offset_map(
+            &mut file,
+            &module,
+            &out_bytes,
+            &all_uses,
+            &relocations,
+            &emit_options,
+        )?;
+        file.flush()?;
+    }
+
+    if args.format == SummaryFormat::Json {
+        let mut requested_items: Vec<WorkItem> = vec![];
+        requested_items.extend(args.types.iter().map(|idx| WorkItem::Type(*idx)));
+        requested_items.extend(args.funcs.iter().map(|idx| WorkItem::Func(*idx)));
+        requested_items.extend(cut_funcs.iter().map(WorkItem::Func));
+        requested_items.extend(args.tables.iter().map(|idx| WorkItem::Table(*idx)));
+        requested_items.extend(args.globals.iter().map(|idx| WorkItem::Global(*idx)));
+        requested_items.extend(args.memories.iter().map(|idx| WorkItem::Memory(*idx)));
+        requested_items.extend(args.datas.iter().map(|idx| WorkItem::Data(*idx)));
+        requested_items.extend(args.elems.iter().map(|idx| WorkItem::Elem(*idx)));
+        requested_items.extend(args.tags.iter().map(|idx| WorkItem::Tag(*idx)));
+        let mut summary = build_summary(
+            &module,
+            &all_uses,
+            &relocations,
+            &emit_options,
+            &requested_items,
+            warnings,
+            out_bytes.len(),
+        )?;
+        summary.groups = group_reports();
+        eprintln!("{}", serde_json::to_string(&summary)?);
+        return Ok(());
     }
 
     // Tell the user where the new things are
-    eprintln!("Success! The requested items are now located at these indices:");
+    log::info!("Success! The requested items are now located at these indices:");
     for idx in &args.types {
         if let Some(new_idx) = relocations.get(&Relocation::Type(*idx)) {
-            eprintln!("  Type {} -> {}", *idx, new_idx);
+            log::info!("  Type {} -> {}", *idx, new_idx);
         } else {
-            eprintln!("  Type {} was out of range and therefore ignored.", *idx);
+            log::info!("  Type {} was out of range and therefore ignored.", *idx);
         }
     }
     for idx in &args.funcs {
         if let Some(new_idx) = relocations.get(&Relocation::Func(*idx)) {
-            eprintln!("  Func {} -> {}", *idx, new_idx);
+            log::info!(
+                "  Func {}{} -> {}",
+                *idx,
+                name_suffix(&module, WorkItem::Func(*idx)),
+                new_idx
+            );
         } else {
-            eprintln!("  Func {} was out of range and therefore ignored.", *idx);
+            log::info!("  Func {} was out of range and therefore ignored.", *idx);
+        }
+    }
+    for idx in cut_funcs.iter() {
+        if let Some(new_idx) = relocations.get(&Relocation::Func(idx)) {
+            log::info!(
+                "  Func {}{} -> {} (cut)",
+                idx,
+                name_suffix(&module, WorkItem::Func(idx)),
+                new_idx
+            );
         }
     }
     for idx in &args.tables {
         if let Some(new_idx) = relocations.get(&Relocation::Table(*idx)) {
-            eprintln!("  Table {} -> {}", *idx, new_idx);
+            log::info!("  Table {} -> {}", *idx, new_idx);
         } else {
-            eprintln!("  Table {} was out of range and therefore ignored.", *idx);
+            log::info!("  Table {} was out of range and therefore ignored.", *idx);
         }
     }
     for idx in &args.globals {
         if let Some(new_idx) = relocations.get(&Relocation::Global(*idx)) {
-            eprintln!("  Global {} -> {}", *idx, new_idx);
+            log::info!("  Global {} -> {}", *idx, new_idx);
         } else {
-            eprintln!("  Global {} was out of range and therefore ignored.", *idx);
+            log::info!("  Global {} was out of range and therefore ignored.", *idx);
         }
     }
     for idx in &args.memories {
         if let Some(new_idx) = relocations.get(&Relocation::Memory(*idx)) {
-            eprintln!("  Memory {} -> {}", *idx, new_idx);
+            log::info!("  Memory {} -> {}", *idx, new_idx);
         } else {
-            eprintln!("  Memory {} was out of range and therefore ignored.", *idx);
+            log::info!("  Memory {} was out of range and therefore ignored.", *idx);
         }
     }
     for idx in &args.datas {
         if let Some(new_idx) = relocations.get(&Relocation::Data(*idx)) {
-            eprintln!("  Data segment {} -> {}", *idx, new_idx);
+            log::info!("  Data segment {} -> {}", *idx, new_idx);
         } else {
-            eprintln!(
+            log::info!(
                 "  Data segment {} was out of range and therefore ignored.",
                 *idx
             );
@@ -863,9 +1902,9 @@ fn main() -> Result<()> {
     }
     for idx in &args.elems {
         if let Some(new_idx) = relocations.get(&Relocation::Elem(*idx)) {
-            eprintln!("  Elem segment {} -> {}", *idx, new_idx);
+            log::info!("  Elem segment {} -> {}", *idx, new_idx);
         } else {
-            eprintln!(
+            log::info!(
                 "  Elem segment {} was out of range and therefore ignored.",
                 *idx
             );
@@ -873,70 +1912,1090 @@ fn main() -> Result<()> {
     }
     for idx in &args.tags {
         if let Some(new_idx) = relocations.get(&Relocation::Tag(*idx)) {
-            eprintln!("  Tag {} -> {}", *idx, new_idx);
+            log::info!("  Tag {} -> {}", *idx, new_idx);
+        } else {
+            log::info!("  Tag {} was out of range and therefore ignored.", *idx);
+        }
+    }
+    for item in live_items(&liveness_options.dropped) {
+        log::info!("  {} was dropped.", module.describe(item));
+    }
+    for group in group_reports() {
+        log::info!(
+            "  Group {} needs {} item(s) totaling {} bytes, {} of them ({} bytes) for no other group.",
+            group.name,
+            group.items.len(),
+            group.size,
+            group.exclusive_items,
+            group.exclusive_size
+        );
+    }
+
+    Ok(())
+}
+
+fn run_diff(args: DiffArgs) -> Result<()> {
+    let old_buf = read_input(&args.old)?;
+    let new_buf = read_input(&args.new)?;
+    let old = ParsedModule::parse(&old_buf)?;
+    let new = ParsedModule::parse(&new_buf)?;
+
+    let diffs = diff_modules(&old, &new);
+    if args.json {
+        println!("{}", serde_json::to_string(&build_index_diffs(&diffs))?);
+        return Ok(());
+    }
+    if diffs.is_empty() {
+        println!("The index spaces are identical.");
+        return Ok(());
+    }
+    for diff in &diffs {
+        match diff.change {
+            IndexChange::Added { new: item } => println!("+ {}", new.describe(item)),
+            IndexChange::Removed { old: item } => println!("- {}", old.describe(item)),
+            IndexChange::Renumbered {
+                old: old_item,
+                new: new_item,
+            } => println!("~ {} -> {}", old.describe(old_item), new_item.index()),
+        }
+    }
+    Ok(())
+}
+
+fn run_diff_live(args: DiffLiveArgs) -> Result<()> {
+    let old_buf = read_input(&args.old)?;
+    let new_buf = read_input(&args.new)?;
+    let old = ParsedModule::parse(&old_buf)?;
+    let new = ParsedModule::parse(&new_buf)?;
+
+    let diffs = diff_live(&old, &new, &args.export, &LivenessOptions::default())?;
+    if args.json {
+        println!("{}", serde_json::to_string(&build_live_diffs(&diffs))?);
+        return Ok(());
+    }
+    if diffs.is_empty() {
+        println!("The live closures are identical.");
+        return Ok(());
+    }
+    for diff in &diffs {
+        match diff.change {
+            LiveChange::Added { size } => {
+                println!(
+                    "+ {} {}{}",
+                    diff.kind,
+                    for_display(&diff.key),
+                    format_size(size)
+                )
+            }
+            LiveChange::Removed { size } => {
+                println!(
+                    "- {} {}{}",
+                    diff.kind,
+                    for_display(&diff.key),
+                    format_size(size)
+                )
+            }
+            LiveChange::Resized { old, new } => {
+                println!(
+                    "~ {} {} ({} -> {} bytes)",
+                    diff.kind,
+                    for_display(&diff.key),
+                    old,
+                    new
+                )
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "run")]
+fn run_run(args: RunArgs) -> Result<()> {
+    let buf = read_input(&args.filename)?;
+    let module = ParsedModule::parse(&buf)?;
+
+    let func_idx = module.resolve_func(&args.func)?;
+    if !module.contains(WorkItem::Func(func_idx)) {
+        anyhow::bail!("function {} is out of range", func_idx);
+    }
+    let all_uses = find_live(
+        &module,
+        vec![WorkItem::Func(func_idx)],
+        &LivenessOptions::default(),
+    )?;
+    let relocations = build_relocations(&all_uses);
+    let requested = Uses::single_func(func_idx);
+    let emit_options = EmitOptions {
+        requested: &requested,
+        strip_object_metadata: true,
+        ..Default::default()
+    };
+    let out_bytes = emit(&module, &all_uses, &relocations, &emit_options)?;
+
+    // Call the function through the export we added for it.
+    let new_idx = relocations.get(&Relocation::Func(func_idx));
+    let Some((export, _, _)) = synthetic_exports(&module, &all_uses, &relocations, &emit_options)
+        .into_iter()
+        .find(|(_, kind, idx)| *kind == wasm_encoder::ExportKind::Func && Some(idx) == new_idx)
+    else {
+        anyhow::bail!("function {} was not kept", func_idx);
+    };
+    for result in wasm_isolate::run::call_export(&out_bytes, &export, &args.args)? {
+        println!("{}", wasm_isolate::run::format_val(&result));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "tui")]
+fn run_tui(args: TuiArgs) -> Result<()> {
+    let buf = read_input(&args.filename)?;
+    let module = ParsedModule::parse(&buf)?;
+
+    let options = LivenessOptions::default();
+    let Some(marked) = wasm_isolate::tui::explore(&module, &options)? else {
+        return Ok(());
+    };
+    let all_uses = find_live(&module, marked.clone(), &options)?;
+    let relocations = build_relocations(&all_uses);
+    let mut requested = Uses::default();
+    for item in &marked {
+        requested.insert(*item);
+    }
+    let emit_options = EmitOptions {
+        requested: &requested,
+        strip_object_metadata: true,
+        ..Default::default()
+    };
+    let out_bytes = emit(&module, &all_uses, &relocations, &emit_options)?;
+    fs::write(&args.out, &out_bytes)?;
+    log::info!(
+        "Kept {} items for the {} marked, and wrote {} bytes to {}.",
+        live_items(&all_uses).len(),
+        marked.len(),
+        out_bytes.len(),
+        args.out
+    );
+    Ok(())
+}
+
+fn run_memory_image(args: MemoryImageArgs) -> Result<()> {
+    let buf = read_input(&args.filename)?;
+    let module = ParsedModule::parse(&buf)?;
+
+    let image = build_memory_image(&module, args.memory)?;
+    fs::write(&args.out, &image)?;
+    log::info!(
+        "Wrote the {} byte image of memory {} to {}.",
+        image.len(),
+        args.memory,
+        args.out
+    );
+    Ok(())
+}
+
+fn run_reduce(args: ReduceArgs) -> Result<()> {
+    let buf = read_input(&args.filename)?;
+    let module = ParsedModule::parse(&buf)?;
+    let exports = if args.exports.is_empty() {
+        module.exports.iter().map(|e| e.name.to_string()).collect()
+    } else {
+        args.exports
+    };
+
+    // Candidates are written next to the output, so the test sees them wherever it looks for the
+    // final result.
+    let candidate = Path::new(&args.out).with_extension("candidate.wasm");
+    let options = ReduceOptions {
+        random_tries: args.random_tries,
+        seed: args.seed,
+    };
+    let result = reduce(&module, exports, &options, &mut |wasm| {
+        fs::write(&candidate, wasm)
+            .with_context(|| format!("could not write {}", candidate.display()))?;
+        let status = std::process::Command::new(&args.test)
+            .arg(&candidate)
+            .status()
+            .with_context(|| format!("could not run {}", args.test))?;
+        Ok(status.success())
+    });
+    let _ = fs::remove_file(&candidate);
+    let reduction = result?;
+
+    fs::write(&args.out, &reduction.wasm)
+        .with_context(|| format!("could not write {}", args.out))?;
+    log::info!(
+        "Reduced {} bytes to {} after {} test(s). Kept export(s): {}. Stubbed function(s): {}.",
+        buf.len(),
+        reduction.wasm.len(),
+        reduction.tests,
+        if reduction.exports.is_empty() {
+            "none".to_string()
+        } else {
+            reduction.exports.join(", ")
+        },
+        if reduction.cut_funcs.is_empty() {
+            "none".to_string()
+        } else {
+            let mut cut_funcs = reduction.cut_funcs.clone();
+            cut_funcs.sort();
+            cut_funcs
+                .iter()
+                .map(|idx| idx.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        },
+    );
+    Ok(())
+}
+
+/// Isolates each root into its own module in the given directory, and writes an index of which
+/// file holds which root.
+fn write_split(
+    module: &ParsedModule,
+    job: &Job,
+    roots: Vec<WorkItem>,
+    dir: &str,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let groups = &job.args.groups;
+    let mut seen: HashSet<WorkItem> = HashSet::new();
+    let mut modules: Vec<schema::SplitModule> = vec![];
+    for root in roots {
+        let in_group = groups.iter().any(|group| group.roots.contains(&root));
+        if in_group || !seen.insert(root) {
+            continue;
+        }
+        let mut requested = Uses::default();
+        requested.insert(root);
+        let root_job = Job {
+            requested: &requested,
+            ..*job
+        };
+        let isolated = isolate(module, &root_job, vec![root], warnings)?;
+        let file = format!("{}_{}.wasm", root.kind_name(), root.index());
+        fs::write(Path::new(dir).join(&file), &isolated.out_bytes)?;
+        modules.push(schema::SplitModule {
+            item: schema::Item::new(module, root),
+            file,
+            output_size: isolated.out_bytes.len(),
+        });
+    }
+
+    // Each group is isolated together, so its module has everything any of its roots needs.
+    let mut split_groups: Vec<schema::SplitGroup> = vec![];
+    for group in groups {
+        let mut requested = Uses::default();
+        for root in &group.roots {
+            requested.insert(*root);
+        }
+        let group_job = Job {
+            requested: &requested,
+            ..*job
+        };
+        let isolated = isolate(module, &group_job, group.roots.clone(), warnings)?;
+        let file = format!("group_{}.wasm", group.name);
+        fs::write(Path::new(dir).join(&file), &isolated.out_bytes)?;
+        split_groups.push(schema::SplitGroup {
+            name: group.name.clone(),
+            roots: group
+                .roots
+                .iter()
+                .map(|root| schema::Item::new(module, *root))
+                .collect(),
+            file,
+            output_size: isolated.out_bytes.len(),
+        });
+    }
+
+    let index = schema::SplitIndex {
+        schema: schema::SCHEMA_VERSION,
+        modules,
+        warnings: warnings.clone(),
+        groups: split_groups,
+    };
+    serde_json::to_writer_pretty(File::create(Path::new(dir).join("index.json"))?, &index)?;
+    log::info!(
+        "Wrote {} module(s) to {}.",
+        index.modules.len() + index.groups.len(),
+        dir
+    );
+    Ok(())
+}
+
+/// Whether any of the options that pick items to keep were given.
+fn has_roots(args: &Args) -> bool {
+    !args.types.is_empty()
+        || !args.funcs.is_empty()
+        || !args.tables.is_empty()
+        || !args.globals.is_empty()
+        || !args.memories.is_empty()
+        || !args.datas.is_empty()
+        || !args.elems.is_empty()
+        || !args.tags.is_empty()
+        || !args.funcs_with_sig.is_empty()
+        || !args.datas_for_memory.is_empty()
+        || !args.elems_for_table.is_empty()
+        || args.keep_all
+        || args.api.is_some()
+}
+
+/// Adds an item to the ones requested on the command line.
+fn add_root(args: &mut Args, item: WorkItem) {
+    let indices = match item {
+        WorkItem::Type(_) => &mut args.types,
+        WorkItem::Func(_) => &mut args.funcs,
+        WorkItem::Table(_) => &mut args.tables,
+        WorkItem::Global(_) => &mut args.globals,
+        WorkItem::Memory(_) => &mut args.memories,
+        WorkItem::Data(_) => &mut args.datas,
+        WorkItem::Elem(_) => &mut args.elems,
+        WorkItem::Tag(_) => &mut args.tags,
+    };
+    indices.push(item.index());
+}
+
+/// Adds everything from a config file to the options given on the command line.
+fn apply_config(args: &mut Args, config: Config, module: &ParsedModule) -> Result<()> {
+    for item in config
+        .roots
+        .resolve(module)
+        .context("could not find a root from the config file")?
+    {
+        add_root(args, item);
+    }
+    for item in &config.cut {
+        args.cut
+            .push(item.resolve(module, WorkItem::Func)?.to_string());
+    }
+
+    args.keep_custom.extend(config.custom_sections.keep);
+    args.strip_custom.extend(config.custom_sections.strip);
+    args.standalone |= config.standalone;
+
+    // The command line decides where output goes, if it says anything about it.
+    let output = config.output;
+    if args.out.is_none() && args.out_prefix.is_none() {
+        if output.out.is_some() && output.prefix.is_some() {
+            anyhow::bail!("the config file cannot set both an output file and an output prefix");
+        }
+        args.out = output.out;
+        args.out_prefix = output.prefix;
+    }
+    if args.emit.is_empty() {
+        for name in &output.emit {
+            let artifact = <Artifact as clap::ValueEnum>::from_str(name, true)
+                .map_err(|_| anyhow::anyhow!("unknown artifact \"{}\" in the config file", name))?;
+            args.emit.push(artifact);
+        }
+        if !args.emit.is_empty() && args.out_prefix.is_none() {
+            anyhow::bail!("the config file lists artifacts to emit, but there is no output prefix to write them to");
+        }
+    }
+    Ok(())
+}
+
+fn run_find_offset(args: FindOffsetArgs) -> Result<()> {
+    let buf = read_input(&args.filename)?;
+    let module = ParsedModule::parse(&buf)?;
+
+    // The printer knows where each line of text came from, so borrow its rendering of the
+    // instructions.
+    let mut text = String::new();
+    let lines: HashMap<usize, &str> = wasmprinter::Config::new()
+        .offsets_and_lines(&buf, &mut text)?
+        .filter_map(|(offset, line)| Some((offset?, line.trim())))
+        .collect();
+
+    for offset in args.offsets {
+        let Some(idx) = module.func_at_offset(offset) else {
+            println!("{:#x}: not inside any function body", offset);
+            continue;
+        };
+        let func = &module.defined_funcs[(idx - module.num_imported_functions) as usize];
+        let name = match module.display_name(WorkItem::Func(idx)) {
+            Some(name) => format!(" ({})", name),
+            None => String::new(),
+        };
+        match func.instruction_at(offset)? {
+            Some((op, start)) => {
+                // The printer folds the final `end` into the closing parenthesis of the function.
+                let text = match (lines.get(&start), op) {
+                    (Some(line), _) => line.to_string(),
+                    (None, wasmparser::Operator::End) => "end".to_string(),
+                    (None, op) => format!("{:?}", op),
+                };
+                println!(
+                    "{:#x}: func {}{} at {:#x}: {}",
+                    offset, idx, name, start, text
+                );
+            }
+            None => println!(
+                "{:#x}: func {}{}, in its local declarations",
+                offset, idx, name
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn run_analyze(args: AnalyzeArgs) -> Result<()> {
+    let buf = read_input(&args.filename)?;
+    let module = ParsedModule::parse(&buf)?;
+
+    if args.list {
+        if args.json {
+            println!("{}", serde_json::to_string(&build_inventory(&module)?)?);
+        } else {
+            print_inventory(&module)?;
+        }
+    }
+
+    if let Some(target) = args.users {
+        if !module.contains(target) {
+            anyhow::bail!("{} does not exist in the module", module.describe(target));
+        }
+        let users = find_users(&module, target)?;
+        if args.json {
+            println!(
+                "{}",
+                serde_json::to_string(&build_users(&module, target, &users))?
+            );
+        } else if users.is_empty() {
+            println!("{} is not used by anything.", module.describe(target));
+        } else {
+            println!("{} is used by:", module.describe(target));
+            for user in users {
+                match user {
+                    User::Item(item) => println!("  {}", module.describe(item)),
+                    User::Export(name) => println!("  export \"{}\"", name),
+                    User::Start => println!("  the start section"),
+                }
+            }
+        }
+    }
+
+    if !args.features && args.top.is_none() && !args.dropped {
+        return Ok(());
+    }
+    let roots = resolve_roots(&module, &args.funcs, &args.exports, &args.items)?;
+    if args.dropped && roots.is_empty() {
+        anyhow::bail!("--dropped needs items to keep (use --funcs, --exports, or --items)");
+    }
+    let mut cut_funcs = IndexSet::new();
+    for spec in &args.cut {
+        cut_funcs.insert(module.resolve_func(spec)?);
+    }
+    let mut options = LivenessOptions {
+        cut_funcs: cut_funcs.clone(),
+        indirect_closure: args.indirect_closure,
+        chase_exports: args.chase_exports,
+        ..Default::default()
+    };
+    drop_unmatched_indirect_targets(&module, &roots, &mut options)?;
+    let all_uses = if roots.is_empty() {
+        let mut all = Uses::default();
+        for item in module.all_items() {
+            all.insert(item);
+        }
+        all
+    } else {
+        find_live(&module, roots.clone(), &options)?
+    };
+
+    if args.features {
+        let features = find_required_features(&module, &all_uses, &cut_funcs)?;
+        if args.json {
+            println!(
+                "{}",
+                serde_json::to_string(&build_features(&module, &features))?
+            );
+        } else if features.is_empty() {
+            println!("Nothing beyond the MVP is needed.");
+        } else {
+            println!("These proposals are needed:");
+            for feature in &features {
+                println!(
+                    "  {}: {} uses {}",
+                    feature.proposal,
+                    module.describe(feature.item),
+                    feature.reason
+                );
+            }
+        }
+    }
+
+    if let Some(n) = args.top {
+        let top = largest_items(&module, &all_uses, n);
+        let targets: Vec<WorkItem> = top.iter().map(|(item, _)| *item).collect();
+        let origins: Vec<User> = roots.iter().map(|item| User::Item(*item)).collect();
+        let explanations = explain_each(&module, &origins, &targets, &options)?;
+        if args.json {
+            println!(
+                "{}",
+                serde_json::to_string(&build_top(&module, &top, &explanations))?
+            );
+        } else if top.is_empty() {
+            println!("There are no functions or data segments to rank.");
+        } else {
+            println!("The largest functions and data segments:");
+            for ((item, size), explanation) in top.iter().zip(&explanations) {
+                println!("  {:>8} B  {}", size, module.describe(*item));
+                let Some(explanation) = explanation else {
+                    println!("              not kept by anything");
+                    continue;
+                };
+                let mut chain: Vec<String> = match explanation.origin {
+                    User::Item(_) if explanation.path.len() == 1 => {
+                        println!("              requested");
+                        continue;
+                    }
+                    User::Item(_) => vec![],
+                    User::Export(name) => vec![format!("export \"{}\"", name)],
+                    User::Start => vec!["the start section".to_string()],
+                };
+                chain.extend(explanation.path.iter().map(|item| module.describe(*item)));
+                println!("              kept by {}", chain.join(" -> "));
+            }
+        }
+    }
+
+    if args.dropped {
+        let dropped = unreachable_items(&module, &all_uses);
+        if args.json {
+            println!(
+                "{}",
+                serde_json::to_string(&build_dropped(&module, &dropped))?
+            );
+        } else if dropped.is_empty() {
+            println!("Nothing would be dropped.");
         } else {
-            eprintln!("  Tag {} was out of range and therefore ignored.", *idx);
+            println!("These would be dropped:");
+            for space in dropped.chunk_by(|a, b| a.kind_name() == b.kind_name()) {
+                let size: usize = space
+                    .iter()
+                    .filter_map(|item| module.item_size(*item))
+                    .sum();
+                print!("  {} {}(s)", space.len(), space[0].kind_name());
+                if size > 0 {
+                    print!(", {} B", size);
+                }
+                println!(":");
+                for item in space {
+                    match module.item_size(*item) {
+                        Some(size) => println!("    {}  {} B", module.describe(*item), size),
+                        None => println!("    {}", module.describe(*item)),
+                    }
+                }
+            }
         }
     }
 
     Ok(())
 }
 
-fn get_new_index(live_things: &Vec<u32>, idx: &u32) -> u32 {
-    live_things
+/// Resolves the items to start from that were given on the command line.
+fn resolve_roots(
+    module: &ParsedModule,
+    funcs: &[String],
+    exports: &[String],
+    items: &[WorkItem],
+) -> Result<Vec<WorkItem>> {
+    let mut roots: Vec<WorkItem> = vec![];
+    for spec in funcs {
+        roots.push(WorkItem::Func(module.resolve_func(spec)?));
+    }
+    for name in exports {
+        let Some(item) = module.find_export(name) else {
+            anyhow::bail!("export \"{}\" was not found in the module", name);
+        };
+        roots.push(item);
+    }
+    for item in items {
+        if !module.contains(*item) {
+            anyhow::bail!("{} is out of range", item);
+        }
+        roots.push(*item);
+    }
+    Ok(roots)
+}
+
+fn run_explain(args: ExplainArgs) -> Result<()> {
+    let buf = read_input(&args.filename)?;
+    let module = ParsedModule::parse(&buf)?;
+    if !module.contains(args.item) {
+        anyhow::bail!(
+            "{} does not exist in the module",
+            module.describe(args.item)
+        );
+    }
+
+    let origins: Vec<User> = args
+        .exports
         .iter()
-        .position(|&v| v == *idx)
-        .expect("original index should have been in vec") as u32
+        .map(|name| User::Export(name.as_str()))
+        .collect();
+    let options = LivenessOptions {
+        indirect_closure: args.indirect_closure,
+        chase_exports: args.chase_exports,
+        ..Default::default()
+    };
+    let explanation = explain(&module, &origins, args.item, &options)?;
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string(&build_explanation(&module, args.item, explanation.as_ref()))?
+        );
+        return Ok(());
+    }
+
+    let Some(explanation) = explanation else {
+        println!(
+            "{} is not kept by anything.",
+            describe_in_detail(&module, args.item)
+        );
+        return Ok(());
+    };
+    let origin = match explanation.origin {
+        User::Item(item) => module.describe(item),
+        User::Export(name) => format!("export \"{}\"", name),
+        User::Start => "the start section".to_string(),
+    };
+    println!("{} is kept by {}:", module.describe(args.item), origin);
+    for (i, item) in explanation.path.iter().enumerate() {
+        let arrow = if i == 0 { "  " } else { "  -> " };
+        println!("{}{}", arrow, describe_in_detail(&module, *item));
+    }
+    Ok(())
 }
 
-fn get_reader(filename: String) -> Box<dyn std::io::Read> {
-    if filename == "-" {
-        Box::new(std::io::stdin())
+fn run_print(args: PrintArgs) -> Result<()> {
+    let buf = read_input(&args.filename)?;
+    let module = ParsedModule::parse(&buf)?;
+
+    let roots = resolve_roots(&module, &args.funcs, &args.exports, &args.items)?;
+    if roots.is_empty() {
+        anyhow::bail!("nothing to print (use --funcs, --exports, or --items)");
+    }
+    let mut cut_funcs = IndexSet::new();
+    for spec in &args.cut {
+        cut_funcs.insert(module.resolve_func(spec)?);
+    }
+
+    let mut options = LivenessOptions {
+        cut_funcs,
+        indirect_closure: args.indirect_closure,
+        chase_exports: args.chase_exports,
+        ..Default::default()
+    };
+    drop_unmatched_indirect_targets(&module, &roots, &mut options)?;
+    let all_uses = find_live(&module, roots, &options)?;
+    print!("{}", print_items(&module, &buf, &all_uses)?);
+    Ok(())
+}
+
+fn print_inventory(module: &ParsedModule) -> Result<()> {
+    println!("Types:    {}", module.types.len());
+    println!(
+        "Funcs:    {} ({} imported)",
+        module.func_types.len(),
+        module.num_imported_functions
+    );
+    println!(
+        "Tables:   {} ({} imported)",
+        module.table_types.len(),
+        module.num_imported_tables
+    );
+    println!(
+        "Memories: {} ({} imported)",
+        module.memory_types.len(),
+        module.num_imported_memories
+    );
+    println!(
+        "Globals:  {} ({} imported)",
+        module.global_types.len(),
+        module.num_imported_globals
+    );
+    println!(
+        "Tags:     {} ({} imported)",
+        module.tag_types.len(),
+        module.num_imported_tags
+    );
+    println!("Datas:    {}", module.datas.len());
+    println!("Elems:    {}", module.elems.len());
+    println!();
+
+    println!(
+        "{:>8} {:>8} {:>8} {:>8} {:>8}  {:<12} name",
+        "func", "size", "callees", "globals", "memories", "features"
+    );
+    for summary in summarize_funcs(module)? {
+        let mut features: Vec<&str> = vec![];
+        if summary.uses_simd {
+            features.push("simd");
+        }
+        if summary.uses_atomics {
+            features.push("atomics");
+        }
+        if summary.uses_gc {
+            features.push("gc");
+        }
+        println!(
+            "{:>8} {:>8} {:>8} {:>8} {:>8}  {:<12} {}",
+            summary.idx,
+            summary.size,
+            summary.num_callees,
+            summary.num_globals,
+            summary.num_memories,
+            features.join(","),
+            module
+                .display_name(WorkItem::Func(summary.idx))
+                .unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
+
+fn write_stats(w: &mut dyn Write, report: &schema::Report) -> Result<()> {
+    writeln!(w, "{:<24} {:>10} {:>10}", "Section", "Original", "Isolated")?;
+    for section in &report.sections {
+        writeln!(
+            w,
+            "{:<24} {:>10} {:>10}",
+            section.name, section.original_size, section.isolated_size
+        )?;
+    }
+    writeln!(
+        w,
+        "{:<24} {:>10} {:>10}",
+        "Total", report.original_size, report.isolated_size
+    )?;
+    writeln!(w)?;
+
+    writeln!(
+        w,
+        "{:<24} {:>10} {:>10}",
+        "Index space", "Original", "Isolated"
+    )?;
+    for space in &report.index_spaces {
+        writeln!(
+            w,
+            "{:<24} {:>10} {:>10}",
+            space.name, space.original_count, space.isolated_count
+        )?;
+    }
+    writeln!(w)?;
+
+    // Which functions touch which memory or table only matters when there is more than one.
+    for (kind, users) in [("Memory", &report.memories), ("Table", &report.tables)] {
+        if users.len() < 2 {
+            continue;
+        }
+        for space in users {
+            let funcs: Vec<String> = space.funcs.iter().map(|idx| idx.to_string()).collect();
+            writeln!(
+                w,
+                "{} {} is used by funcs: {}",
+                kind,
+                space.index,
+                if funcs.is_empty() {
+                    "(none)".to_string()
+                } else {
+                    funcs.join(", ")
+                }
+            )?;
+        }
+        writeln!(w)?;
+    }
+
+    if !report.groups.is_empty() {
+        writeln!(
+            w,
+            "{:<24} {:>10} {:>10} {:>10} {:>10}",
+            "Group", "Items", "Size", "Exclusive", "Excl. size"
+        )?;
+        for group in &report.groups {
+            writeln!(
+                w,
+                "{:<24} {:>10} {:>10} {:>10} {:>10}",
+                group.name,
+                group.items.len(),
+                group.size,
+                group.exclusive_items,
+                group.exclusive_size
+            )?;
+        }
+        writeln!(w)?;
+    }
+
+    Ok(())
+}
+
+/// The name of an item in parentheses, for following its index in messages, or nothing if the
+/// item has no name.
+fn name_suffix(module: &ParsedModule, item: WorkItem) -> String {
+    match module.display_name(item) {
+        Some(name) => format!(" ({})", name),
+        None => String::new(),
+    }
+}
+
+/// Sends log messages to stderr. The summary is logged at the info level, so it is printed as-is,
+/// while the per-item decisions behind it only show up with --verbose.
+fn init_logger(quiet: bool, verbose: u8) {
+    let level = if quiet {
+        log::LevelFilter::Error
     } else {
-        Box::new(File::open(filename).expect("Failed to open file"))
+        match verbose {
+            0 => log::LevelFilter::Info,
+            1 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        }
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .format(|buf, record| match record.level() {
+            log::Level::Info => writeln!(buf, "{}", record.args()),
+            log::Level::Warn => writeln!(buf, "Warning: {}", record.args()),
+            level => writeln!(buf, "{}: {}", level.as_str().to_lowercase(), record.args()),
+        })
+        .init();
+}
+
+/// Prints a warning right away for humans, or saves it for the JSON summary.
+fn warn(format: SummaryFormat, warnings: &mut Vec<String>, message: String) {
+    match format {
+        SummaryFormat::Human => log::warn!("{}", message),
+        SummaryFormat::Json => warnings.push(message),
     }
 }
 
-struct Func<'a> {
-    type_idx: u32,
-    locals: Vec<(u32, ValType)>,
-    instructions: Vec<Operator<'a>>,
+fn format_size(size: Option<usize>) -> String {
+    match size {
+        Some(size) => format!(" ({} bytes)", size),
+        None => String::new(),
+    }
 }
 
-enum WorkItem {
-    Type(u32),
-    Func(u32),
-    Table(u32),
-    Global(u32),
-    Memory(u32),
-    Data(u32),
-    Elem(u32),
-    Tag(u32),
+fn parse_import_rename(s: &str) -> Result<ImportRename, String> {
+    ImportRename::parse(s).map_err(|err| err.to_string())
 }
 
-enum Section<'a> {
-    Passthrough(RawSection<'a>),
-    Type,
-    Import,
-    Function,
-    Table,
-    Memory,
-    Global,
-    Export,
-    Start,
-    Element,
-    Code,
-    Data,
-    DataCount,
-    Tag,
-}
-
-impl<'a> Section<'a> {
-    fn raw(id: u8, bytes: &'a [u8]) -> Section<'a> {
-        let foo = RawSection {
-            id: id,
-            data: bytes,
+fn parse_export_rename(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((old, new)) if !old.is_empty() && !new.is_empty() => {
+            Ok((old.to_string(), new.to_string()))
+        }
+        _ => Err(format!("expected OLD=NEW, but got \"{}\"", s)),
+    }
+}
+
+fn parse_feature(s: &str) -> Result<WasmFeatures, String> {
+    feature_from_name(s).ok_or_else(|| {
+        format!(
+            "unknown proposal \"{}\" (expected a name like \"simd\" or \"gc\")",
+            s
+        )
+    })
+}
+
+fn parse_memory_minimum(s: &str) -> Result<(u32, u64), String> {
+    let parse = || {
+        let (memory, pages) = s.split_once('=')?;
+        Some((memory.parse().ok()?, pages.parse().ok()?))
+    };
+    parse().ok_or_else(|| format!("expected MEMORY=PAGES, e.g. 0=1, but got \"{}\"", s))
+}
+
+/// Parses a function index, which may be written the way browsers show it in stack traces, like
+/// "wasm-function[12]" or "wasm://wasm/8c1e3f5a:wasm-function[12]:0x1a2b".
+fn parse_func_index(s: &str) -> Result<u32, String> {
+    let index = match s.split_once("wasm-function[") {
+        Some((_, rest)) => rest.split(']').next().unwrap_or_default(),
+        None => s,
+    };
+    index
+        .trim()
+        .parse()
+        .map_err(|_| format!("expected a function index, but got \"{}\"", s))
+}
+
+fn parse_percent(s: &str) -> Result<f64, String> {
+    match s.trim_end_matches('%').parse::<f64>() {
+        Ok(percent) if percent > 0.0 && percent <= 100.0 => Ok(percent),
+        _ => Err(format!(
+            "expected a percentage above 0 and at most 100, but got \"{}\"",
+            s
+        )),
+    }
+}
+
+/// Parses an offset in decimal or hex. Browser stack traces put the offset at the end of the
+/// frame, like "module.wasm:0x1a2b" or "(wasm://wasm/8c1e3f5a:wasm-function[12]:0x1a2b)", so
+/// only the part after the last colon is used.
+fn parse_offset(s: &str) -> Result<usize, String> {
+    let offset = s.trim().trim_end_matches(')');
+    let offset = offset.rsplit(':').next().unwrap_or(offset);
+    let parsed = match offset
+        .strip_prefix("0x")
+        .or_else(|| offset.strip_prefix("0X"))
+    {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => offset.parse(),
+    };
+    parsed.map_err(|_| {
+        format!(
+            "expected a decimal or hex offset, e.g. 0x1a2b, but got \"{}\"",
+            s
+        )
+    })
+}
+
+/// A function signature given on the command line.
+#[derive(Clone, Debug)]
+struct Signature {
+    params: Vec<wasmparser::ValType>,
+    results: Vec<wasmparser::ValType>,
+}
+
+/// Parses signatures like "(i32, i32) -> i64", "() -> (f32, f32)", or "(externref)". Only
+/// numeric types and the abbreviated reference types can be written.
+fn parse_signature(s: &str) -> Result<Signature, String> {
+    fn parse_types(s: &str) -> Result<Vec<wasmparser::ValType>, String> {
+        use wasmparser::{RefType, ValType};
+
+        let s = s.trim();
+        let s = match s.strip_prefix('(') {
+            Some(rest) => rest
+                .strip_suffix(')')
+                .ok_or_else(|| format!("missing \")\" in \"{}\"", s))?,
+            None => s,
         };
-        Self::Passthrough(foo)
+        s.split(',')
+            .map(str::trim)
+            .filter(|ty| !ty.is_empty())
+            .map(|ty| {
+                Ok(match ty {
+                    "i32" => ValType::I32,
+                    "i64" => ValType::I64,
+                    "f32" => ValType::F32,
+                    "f64" => ValType::F64,
+                    "v128" => ValType::V128,
+                    "funcref" => ValType::Ref(RefType::FUNCREF),
+                    "externref" => ValType::Ref(RefType::EXTERNREF),
+                    "anyref" => ValType::Ref(RefType::ANYREF),
+                    "eqref" => ValType::Ref(RefType::EQREF),
+                    "i31ref" => ValType::Ref(RefType::I31REF),
+                    "structref" => ValType::Ref(RefType::STRUCTREF),
+                    "arrayref" => ValType::Ref(RefType::ARRAYREF),
+                    "exnref" => ValType::Ref(RefType::EXNREF),
+                    "nullref" => ValType::Ref(RefType::NULLREF),
+                    "nullfuncref" => ValType::Ref(RefType::NULLFUNCREF),
+                    "nullexternref" => ValType::Ref(RefType::NULLEXTERNREF),
+                    _ => return Err(format!("unknown type \"{}\"", ty)),
+                })
+            })
+            .collect()
+    }
+
+    let (params, results) = match s.split_once("->") {
+        Some((params, results)) => (params, results),
+        None => (s, ""),
+    };
+    if !params.trim().starts_with('(') {
+        return Err(format!(
+            "expected a signature like \"(i32, i32) -> i64\", but got \"{}\"",
+            s
+        ));
+    }
+    Ok(Signature {
+        params: parse_types(params)?,
+        results: parse_types(results)?,
+    })
+}
+
+/// Reads a list of export names from a file, one per line. Blank lines and lines starting with `#`
+/// are ignored.
+fn read_api_file(path: &str) -> Result<Vec<String>> {
+    let contents = fs::read_to_string(path)?;
+    let mut names: Vec<String> = vec![];
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if !names.iter().any(|name| name == line) {
+            names.push(line.to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// Checks that the exports of the output module are exactly the ones listed in the API file.
+fn verify_api(module: &[u8], api: &[String]) -> Result<()> {
+    let mut exported: Vec<String> = vec![];
+    for payload in Parser::new(0).parse_all(module) {
+        if let ExportSection(r) = payload? {
+            for export in r {
+                exported.push(export?.name.to_string());
+            }
+        }
+    }
+    let missing: Vec<&String> = api.iter().filter(|name| !exported.contains(name)).collect();
+    let extra: Vec<&String> = exported.iter().filter(|name| !api.contains(name)).collect();
+    if !missing.is_empty() || !extra.is_empty() {
+        anyhow::bail!(
+            "output exports do not match the API file (missing: {:?}, unexpected: {:?})",
+            missing,
+            extra
+        );
+    }
+    Ok(())
+}
+
+/// Replaces a file by writing a temporary file next to it and renaming that over it, so that
+/// readers see either the old contents or the new ones. The temporary file gets the original's
+/// permissions, and a name of its own so that concurrent runs on the same file don't share one.
+fn write_atomically(path: &Path, bytes: &[u8]) -> Result<()> {
+    let permissions = fs::metadata(path)
+        .with_context(|| format!("could not read {}", path.display()))?
+        .permissions();
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(
+        ".wasm-isolate.{}-{:016x}.tmp",
+        std::process::id(),
+        fastrand::u64(..)
+    ));
+    let tmp_path = path.with_file_name(tmp_name);
+    let written = File::create_new(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(bytes)?;
+            file.set_permissions(permissions)
+        })
+        .and_then(|()| fs::rename(&tmp_path, path))
+        .with_context(|| format!("could not write {}", path.display()));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    written
+}
+
+/// Reads a module from a file, or from stdin if the name is "-", undoing any compression.
+fn read_input(filename: &str) -> Result<Vec<u8>> {
+    decompress(read_raw_input(filename)?, filename)
+}
+
+/// Reads a file, or stdin if the name is "-", as it is.
+fn read_raw_input(filename: &str) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    if filename == "-" {
+        std::io::stdin().read_to_end(&mut buf)?;
+    } else {
+        File::open(filename)
+            .with_context(|| format!("could not open {}", filename))?
+            .read_to_end(&mut buf)?;
     }
+    Ok(buf)
 }
@@ -0,0 +1,784 @@
+use std::{collections::BTreeMap, ops::Range, sync::OnceLock};
+
+use anyhow::Result;
+use rayon::prelude::*;
+use wasmparser::{
+    BinaryReader, BranchHint, CompositeInnerType, Data, Element, Export, FuncType, FunctionBody,
+    Global, GlobalType, Import, IndirectNameMap, KnownCustom, MemoryType, Name, Operator,
+    OperatorsIterator, Parser, Payload::*, ProducersFieldValue, RecGroup, SubType, Table,
+    TableType, TagType, ValType,
+};
+
+use crate::demangle;
+use crate::error::{Error, ErrorKind};
+use crate::liveness::{scan_func_body, BodyScan, WorkItem};
+
+/// Everything we know about the input module, with each index space laid out in order (imports
+/// first, then definitions).
+pub struct ParsedModule<'a> {
+    /// The original bytes of the module.
+    pub buf: &'a [u8],
+
+    pub types: Vec<SubType>,
+    pub rec_groups: Vec<RecGroup>,
+    pub num_imported_functions: u32,
+    pub num_imported_tables: u32,
+    pub num_imported_memories: u32,
+    pub num_imported_globals: u32,
+    pub num_imported_tags: u32,
+    pub func_types: Vec<u32>,
+    pub table_types: Vec<TableType>,
+    pub memory_types: Vec<MemoryType>,
+    pub global_types: Vec<GlobalType>,
+    pub tag_types: Vec<TagType>,
+
+    pub imports: Vec<Import<'a>>,
+    pub defined_tables: Vec<Table<'a>>,
+    pub defined_globals: Vec<Global<'a>>,
+    pub exports: Vec<Export<'a>>,
+    pub start_idx: Option<u32>,
+    pub elems: Vec<Element<'a>>,
+    pub defined_funcs: Vec<Func<'a>>,
+    /// Where the contents of the code section begin, after its size. Code offsets in debug info
+    /// are relative to this.
+    pub code_start: usize,
+    /// The URL of the module's source map, from the `sourceMappingURL` custom section.
+    pub source_mapping_url: Option<&'a str>,
+    /// The hints from the `metadata.code.branch_hint` section, by function index. Offsets are
+    /// relative to the start of the function body, including its locals.
+    pub branch_hints: Vec<(u32, Vec<BranchHint>)>,
+    /// The other `metadata.code.*` sections, in the order they appear.
+    pub code_metadata: Vec<CodeMetadata<'a>>,
+    /// The fields of the `producers` section and their values, in order.
+    pub producers: Vec<(&'a str, Vec<ProducersFieldValue<'a>>)>,
+    pub datas: Vec<Data<'a>>,
+    pub names: Names<'a>,
+
+    pub sections: Vec<Section>,
+    /// The original byte range of each section, including its id and size.
+    pub section_ranges: Vec<Range<usize>>,
+    /// What each defined function's body refers to, once something has needed all of them.
+    body_scans: OnceLock<Vec<BodyScan>>,
+    /// The most instructions the function bodies may have in total, which is checked when they
+    /// are scanned.
+    max_operators: u64,
+}
+
+pub struct Func<'a> {
+    pub type_idx: u32,
+    pub locals: Vec<(u32, ValType)>,
+    pub body: FunctionBody<'a>,
+    /// The range of the function body in the original module, not including its size prefix.
+    pub body_range: Range<usize>,
+}
+
+impl<'a> Func<'a> {
+    /// Decodes the function's instructions. These are decoded again on every call rather than
+    /// stored, since holding every operator of a big module in memory is far more expensive.
+    pub fn operators(&self) -> Result<OperatorsIterator<'a>> {
+        Ok(self.body.get_operators_reader()?.into_iter())
+    }
+
+    /// Finds the instruction containing the given offset and where it starts, or nothing if the
+    /// offset is outside the function's instructions, such as in its local declarations.
+    pub fn instruction_at(&self, offset: usize) -> Result<Option<(Operator<'a>, usize)>> {
+        let mut res = None;
+        for op in self.body.get_operators_reader()?.into_iter_with_offsets() {
+            let (op, start) = op?;
+            if start > offset {
+                break;
+            }
+            res = Some((op, start));
+        }
+        Ok(res)
+    }
+}
+
+/// Names from the name section, by index space. These are ordered by index so that looking up an
+/// index by a name that appears more than once always finds the lowest one.
+#[derive(Default)]
+pub struct Names<'a> {
+    pub module: Option<&'a str>,
+    pub types: BTreeMap<u32, &'a str>,
+    pub funcs: BTreeMap<u32, &'a str>,
+    pub tables: BTreeMap<u32, &'a str>,
+    pub globals: BTreeMap<u32, &'a str>,
+    pub memories: BTreeMap<u32, &'a str>,
+    pub datas: BTreeMap<u32, &'a str>,
+    pub elems: BTreeMap<u32, &'a str>,
+    pub tags: BTreeMap<u32, &'a str>,
+    /// Names of locals, by function and then by local index.
+    pub locals: BTreeMap<u32, BTreeMap<u32, &'a str>>,
+    /// Names of labels, by function and then by the index of the block that introduces them.
+    pub labels: BTreeMap<u32, BTreeMap<u32, &'a str>>,
+    /// Names of struct fields, by type and then by field index.
+    pub fields: BTreeMap<u32, BTreeMap<u32, &'a str>>,
+}
+
+/// Guards against pathological inputs, such as corrupted or malicious modules from a fuzzer,
+/// which could otherwise make us consume all available memory.
+pub struct Limits {
+    pub max_funcs: u64,
+    pub max_operators: u64,
+    pub max_output_size: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_funcs: 2_000_000,
+            max_operators: 100_000_000,
+            max_output_size: 1 << 30,
+        }
+    }
+}
+
+/// An instruction's offset in a code metadata section, and the data attached to it.
+pub type CodeMetadataEntry<'a> = (u32, &'a [u8]);
+
+/// A section following the code metadata convention, which attaches data to instructions by
+/// function index and code offset.
+pub struct CodeMetadata<'a> {
+    pub name: &'a str,
+    /// The entries of each function, as the offset of an instruction from the start of the
+    /// function body, including its locals, and the data attached to it.
+    pub funcs: Vec<(u32, Vec<CodeMetadataEntry<'a>>)>,
+    /// The range of the whole section, including its id and size.
+    pub range: Range<usize>,
+}
+
+impl<'a> CodeMetadata<'a> {
+    fn parse(name: &'a str, data: &'a [u8], offset: usize, range: Range<usize>) -> Result<Self> {
+        let mut reader = BinaryReader::new(data, offset);
+        let mut funcs = vec![];
+        for _ in 0..reader.read_var_u32()? {
+            let func = reader.read_var_u32()?;
+            let mut entries = vec![];
+            for _ in 0..reader.read_var_u32()? {
+                let offset = reader.read_var_u32()?;
+                let len = reader.read_var_u32()?;
+                entries.push((offset, reader.read_bytes(len as usize)?));
+            }
+            funcs.push((func, entries));
+        }
+        if !reader.eof() {
+            anyhow::bail!("the {} section has extra bytes at the end", name);
+        }
+        Ok(CodeMetadata { name, funcs, range })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Section {
+    Custom,
+    /// The `metadata.code.branch_hint` custom section, which refers to function indices and code
+    /// offsets and so has to be rewritten rather than copied.
+    BranchHints,
+    /// Any other `metadata.code.*` custom section, which follows the same format as branch hints
+    /// but with data this tool doesn't understand.
+    CodeMetadata,
+    /// The `producers` custom section, which lists the tools that made the module and is rebuilt
+    /// to list this one too.
+    Producers,
+    /// The `name` custom section, which refers to indices and so is rebuilt with `--keep-names`
+    /// and left out otherwise.
+    Names,
+    /// The `linking` and `reloc.*` custom sections of an object file, which refer to indices and
+    /// code offsets that isolation changes.
+    ObjectMetadata,
+    Type,
+    Import,
+    Function,
+    Table,
+    Memory,
+    Global,
+    Export,
+    Start,
+    Element,
+    Code,
+    Data,
+    DataCount,
+    Tag,
+}
+
+impl Section {
+    /// Whether this is a custom section, which may appear anywhere in the module.
+    pub fn is_custom(self) -> bool {
+        matches!(
+            self,
+            Section::Custom
+                | Section::Producers
+                | Section::BranchHints
+                | Section::CodeMetadata
+                | Section::Names
+                | Section::ObjectMetadata
+        )
+    }
+}
+
+impl<'a> ParsedModule<'a> {
+    pub fn parse(buf: &'a [u8]) -> Result<ParsedModule<'a>> {
+        Self::parse_with_limits(buf, &Limits::default())
+    }
+
+    pub fn parse_with_limits(buf: &'a [u8], limits: &Limits) -> Result<ParsedModule<'a>> {
+        let parser = Parser::new(0);
+
+        let mut m = ParsedModule {
+            buf,
+            types: vec![],
+            rec_groups: vec![],
+            num_imported_functions: 0,
+            num_imported_tables: 0,
+            num_imported_memories: 0,
+            num_imported_globals: 0,
+            num_imported_tags: 0,
+            func_types: vec![],
+            table_types: vec![],
+            memory_types: vec![],
+            global_types: vec![],
+            tag_types: vec![],
+            imports: vec![],
+            defined_tables: vec![],
+            defined_globals: vec![],
+            exports: vec![],
+            start_idx: None,
+            elems: vec![],
+            defined_funcs: vec![],
+            code_start: 0,
+            source_mapping_url: None,
+            branch_hints: vec![],
+            code_metadata: vec![],
+            producers: vec![],
+            datas: vec![],
+            names: Names::default(),
+            sections: vec![],
+            section_ranges: vec![],
+            body_scans: OnceLock::new(),
+            max_operators: limits.max_operators,
+        };
+
+        let mut current_func = 0;
+        let mut first_func: bool = true;
+        let mut section_start: usize = 0;
+
+        for payload in parser.parse_all(buf) {
+            let payload = payload?;
+
+            // Each section's header sits between the end of the previous section and the start
+            // of this section's contents.
+            let section_range = payload.as_section().map(|(_, range)| {
+                let full_range = section_start..range.end;
+                section_start = range.end;
+                full_range
+            });
+            if let Version { range, .. } = &payload {
+                section_start = range.end;
+            }
+
+            match payload {
+                // Sections for WebAssembly modules
+                TypeSection(r) => {
+                    m.sections.push(Section::Type);
+
+                    for rg in r {
+                        let rg = rg?;
+                        m.rec_groups.push(rg.clone());
+                        for t in rg.into_types() {
+                            m.types.push(t);
+                        }
+                    }
+                }
+                ImportSection(r) => {
+                    m.sections.push(Section::Import);
+
+                    for import in r {
+                        let import = import?;
+                        match import.ty {
+                            wasmparser::TypeRef::Func(type_idx) => {
+                                m.num_imported_functions += 1;
+                                m.func_types.push(type_idx);
+                            }
+                            wasmparser::TypeRef::Table(ty) => {
+                                m.num_imported_tables += 1;
+                                m.table_types.push(ty);
+                            }
+                            wasmparser::TypeRef::Memory(ty) => {
+                                m.num_imported_memories += 1;
+                                m.memory_types.push(ty);
+                            }
+                            wasmparser::TypeRef::Global(ty) => {
+                                m.num_imported_globals += 1;
+                                m.global_types.push(ty);
+                            }
+                            wasmparser::TypeRef::Tag(ty) => {
+                                m.num_imported_tags += 1;
+                                m.tag_types.push(ty);
+                            }
+                        }
+                        m.imports.push(import);
+                    }
+                }
+                FunctionSection(r) => {
+                    m.sections.push(Section::Function);
+                    let num_funcs = m.func_types.len() as u64 + r.count() as u64;
+                    if num_funcs > limits.max_funcs {
+                        return Err(Error::new(
+                            ErrorKind::InvalidModule,
+                            format!(
+                                "the module has {} functions, which is more than the limit of {} (use --max-funcs to raise it)",
+                                num_funcs,
+                                limits.max_funcs
+                            ),
+                        )
+                        .into());
+                    }
+                    for f in r {
+                        let type_idx = f?;
+                        if type_idx as usize >= m.types.len() {
+                            return Err(Error::new(
+                                ErrorKind::InvalidModule,
+                                format!(
+                                    "function {} has type {}, but the module only has {} types",
+                                    m.func_types.len(),
+                                    type_idx,
+                                    m.types.len()
+                                ),
+                            )
+                            .into());
+                        }
+                        m.func_types.push(type_idx);
+                    }
+                }
+                TableSection(r) => {
+                    m.sections.push(Section::Table);
+                    for table in r {
+                        let table = table?;
+                        m.table_types.push(table.ty);
+                        m.defined_tables.push(table);
+                    }
+                }
+                MemorySection(r) => {
+                    m.sections.push(Section::Memory);
+                    for mem_type in r {
+                        m.memory_types.push(mem_type?);
+                    }
+                }
+                TagSection(r) => {
+                    m.sections.push(Section::Tag);
+                    for tag_type in r {
+                        m.tag_types.push(tag_type?);
+                    }
+                }
+                GlobalSection(r) => {
+                    m.sections.push(Section::Global);
+                    for global in r {
+                        let global = global?;
+                        m.global_types.push(global.ty);
+                        m.defined_globals.push(global);
+                    }
+                }
+                ExportSection(r) => {
+                    m.sections.push(Section::Export);
+                    for export in r {
+                        m.exports.push(export?);
+                    }
+                }
+                StartSection { func, range: _ } => {
+                    // IDEA: Just because we presere the start function doesn't
+                    // necessarily mean we want to preserve the start section.
+                    // Should we have a flag for this?
+                    m.sections.push(Section::Start);
+                    m.start_idx = Some(func);
+                }
+                ElementSection(r) => {
+                    m.sections.push(Section::Element);
+                    for elem in r {
+                        m.elems.push(elem?);
+                    }
+                }
+                DataCountSection { count: _, range: _ } => {
+                    m.sections.push(Section::DataCount);
+                }
+                DataSection(r) => {
+                    m.sections.push(Section::Data);
+                    for data in r {
+                        m.datas.push(data?);
+                    }
+                }
+
+                // Here we know how many functions we'll be receiving as
+                // `CodeSectionEntry`, so we can prepare for that, and
+                // afterwards we can parse and handle each function
+                // individually.
+                CodeSectionStart { count, range, .. } => {
+                    check_code_count(&m, count)?;
+                    m.sections.push(Section::Code);
+                    m.code_start = range.start;
+                    current_func = m.num_imported_functions;
+                }
+                CodeSectionEntry(body) => {
+                    if first_func {
+                        first_func = false
+                    } else {
+                        current_func += 1;
+                    }
+
+                    let mut func = Func {
+                        type_idx: m.func_types[current_func as usize],
+                        locals: vec![],
+                        body: body.clone(),
+                        body_range: body.range(),
+                    };
+
+                    for local in body.get_locals_reader()? {
+                        func.locals.push(local?);
+                    }
+                    m.defined_funcs.push(func)
+                }
+
+                CustomSection(r) => {
+                    if let KnownCustom::Name(names) = r.as_known() {
+                        for subsection in names {
+                            let (map, target) = match subsection? {
+                                Name::Module { name, .. } => {
+                                    m.names.module = Some(name);
+                                    continue;
+                                }
+                                Name::Local(map) => {
+                                    read_indirect_names(map, &mut m.names.locals)?;
+                                    continue;
+                                }
+                                Name::Label(map) => {
+                                    read_indirect_names(map, &mut m.names.labels)?;
+                                    continue;
+                                }
+                                Name::Field(map) => {
+                                    read_indirect_names(map, &mut m.names.fields)?;
+                                    continue;
+                                }
+                                Name::Type(map) => (map, &mut m.names.types),
+                                Name::Function(map) => (map, &mut m.names.funcs),
+                                Name::Table(map) => (map, &mut m.names.tables),
+                                Name::Global(map) => (map, &mut m.names.globals),
+                                Name::Memory(map) => (map, &mut m.names.memories),
+                                Name::Data(map) => (map, &mut m.names.datas),
+                                Name::Element(map) => (map, &mut m.names.elems),
+                                Name::Tag(map) => (map, &mut m.names.tags),
+                                _ => continue,
+                            };
+                            for naming in map {
+                                let naming = naming?;
+                                target.insert(naming.index, naming.name);
+                            }
+                        }
+                    }
+                    if r.name() == "sourceMappingURL" {
+                        // This is only informational, so a malformed URL is not worth failing
+                        // over.
+                        let mut reader = BinaryReader::new(r.data(), r.data_offset());
+                        m.source_mapping_url = reader.read_unlimited_string().ok();
+                    }
+                    if let KnownCustom::BranchHints(reader) = r.as_known() {
+                        for func in reader {
+                            let func = func?;
+                            let hints = func.hints.into_iter().collect::<Result<Vec<_>, _>>()?;
+                            m.branch_hints.push((func.func, hints));
+                        }
+                        m.sections.push(Section::BranchHints);
+                    } else if r.name().starts_with("metadata.code.") {
+                        m.code_metadata.push(CodeMetadata::parse(
+                            r.name(),
+                            r.data(),
+                            r.data_offset(),
+                            section_range.clone().unwrap_or_default(),
+                        )?);
+                        m.sections.push(Section::CodeMetadata);
+                    } else if let KnownCustom::Producers(reader) = r.as_known() {
+                        for field in reader {
+                            let field = field?;
+                            let values = field.values.into_iter().collect::<Result<Vec<_>, _>>()?;
+                            m.producers.push((field.name, values));
+                        }
+                        m.sections.push(Section::Producers);
+                    } else if let KnownCustom::Name(_) = r.as_known() {
+                        m.sections.push(Section::Names);
+                    } else if r.name() == "linking" || r.name().starts_with("reloc.") {
+                        m.sections.push(Section::ObjectMetadata);
+                    } else {
+                        m.sections.push(Section::Custom);
+                    }
+                }
+
+                _ => {}
+            }
+
+            if m.section_ranges.len() < m.sections.len() {
+                let Some(section_range) = section_range else {
+                    return Err(Error::new(
+                        ErrorKind::Internal,
+                        "a payload without a range was recorded as a section",
+                    )
+                    .into());
+                };
+                m.section_ranges.push(section_range);
+            }
+        }
+
+        check_code_count(&m, m.defined_funcs.len() as u32)?;
+        Ok(m)
+    }
+
+    /// Reads the name of the custom section at the given range.
+    pub fn custom_section_name(&self, range: &Range<usize>) -> Result<&'a str> {
+        let mut reader = BinaryReader::new(&self.buf[range.clone()], range.start);
+        reader.read_u8()?;
+        reader.read_var_u32()?;
+        Ok(reader.read_string()?)
+    }
+
+    /// Resolves a function given on the command line, either by index or by name. Names are
+    /// looked up in the name section first, then in the exports. Indices must be in range.
+    pub fn resolve_func(&self, spec: &str) -> Result<u32> {
+        if let Ok(idx) = spec.parse::<u32>() {
+            if !self.contains(WorkItem::Func(idx)) {
+                anyhow::bail!("{} is out of range", WorkItem::Func(idx));
+            }
+            return Ok(idx);
+        }
+        if let Some(idx) = self.find_func_by_name(spec) {
+            return Ok(idx);
+        }
+        for export in &self.exports {
+            if export.kind == wasmparser::ExternalKind::Func && export.name == spec {
+                return Ok(export.index);
+            }
+        }
+        anyhow::bail!("no function named \"{}\" was found", spec)
+    }
+
+    /// Scans every defined function's body, in parallel, the first time it is called. Later calls,
+    /// like finding what is live again with other roots, reuse the scans.
+    pub fn body_scans(&self) -> Result<&[BodyScan]> {
+        if let Some(scans) = self.body_scans.get() {
+            return Ok(scans);
+        }
+        let scans = self
+            .defined_funcs
+            .par_iter()
+            .map(scan_func_body)
+            .collect::<Result<_>>()?;
+        self.set_body_scans(scans)?;
+        Ok(self.body_scans.get().unwrap())
+    }
+
+    /// Keeps scans of the function bodies that were made elsewhere, such as loaded from a cache,
+    /// as long as they stay within the operator limit.
+    pub(crate) fn set_body_scans(&self, scans: Vec<BodyScan>) -> Result<()> {
+        let num_operators: u64 = scans.iter().map(|scan| scan.num_operators).sum();
+        if num_operators > self.max_operators {
+            return Err(Error::new(
+                ErrorKind::InvalidModule,
+                format!(
+                    "the module has more than {} operators in total (use --max-operators to raise the limit)",
+                    self.max_operators
+                ),
+            )
+            .into());
+        }
+        let _ = self.body_scans.set(scans);
+        Ok(())
+    }
+
+    /// The body scans, if they have been done already.
+    pub fn cached_body_scans(&self) -> Option<&[BodyScan]> {
+        self.body_scans.get().map(Vec::as_slice)
+    }
+
+    /// Lists every item in the module, one index space at a time.
+    pub fn all_items(&self) -> Vec<WorkItem> {
+        let mut res: Vec<WorkItem> = vec![];
+        res.extend((0..self.types.len() as u32).map(WorkItem::Type));
+        res.extend((0..self.func_types.len() as u32).map(WorkItem::Func));
+        res.extend((0..self.table_types.len() as u32).map(WorkItem::Table));
+        res.extend((0..self.global_types.len() as u32).map(WorkItem::Global));
+        res.extend((0..self.memory_types.len() as u32).map(WorkItem::Memory));
+        res.extend((0..self.datas.len() as u32).map(WorkItem::Data));
+        res.extend((0..self.elems.len() as u32).map(WorkItem::Elem));
+        res.extend((0..self.tag_types.len() as u32).map(WorkItem::Tag));
+        res
+    }
+
+    /// Whether an item actually exists in the module.
+    pub fn contains(&self, item: WorkItem) -> bool {
+        match item {
+            WorkItem::Type(idx) => idx < self.types.len() as u32,
+            WorkItem::Func(idx) => idx < self.func_types.len() as u32,
+            WorkItem::Table(idx) => idx < self.table_types.len() as u32,
+            WorkItem::Global(idx) => idx < self.global_types.len() as u32,
+            WorkItem::Memory(idx) => idx < self.memory_types.len() as u32,
+            WorkItem::Data(idx) => idx < self.datas.len() as u32,
+            WorkItem::Elem(idx) => idx < self.elems.len() as u32,
+            WorkItem::Tag(idx) => idx < self.tag_types.len() as u32,
+        }
+    }
+
+    /// Gets the signature of a type, failing if it is not a function type.
+    pub fn signature(&self, type_idx: u32) -> Result<&FuncType> {
+        match self
+            .types
+            .get(type_idx as usize)
+            .map(|ty| &ty.composite_type.inner)
+        {
+            Some(CompositeInnerType::Func(ty)) => Ok(ty),
+            _ => anyhow::bail!("type {} is not a function type", type_idx),
+        }
+    }
+
+    /// Finds the function with the given name in the name section. Mangled names also match their
+    /// demangled forms, so `mycrate::module::function` finds `_ZN8mycrate6module8function17h...E`.
+    pub fn find_func_by_name(&self, spec: &str) -> Option<u32> {
+        self.names
+            .funcs
+            .iter()
+            .filter(|(_, name)| demangle::name_matches(name, spec))
+            .map(|(idx, _)| *idx)
+            .min()
+    }
+
+    /// Finds the item exported under the given name, if any.
+    pub fn find_export(&self, name: &str) -> Option<WorkItem> {
+        let export = self.exports.iter().find(|e| e.name == name)?;
+        Some(export_item(export))
+    }
+
+    /// Finds the function whose body contains the given offset from the start of the module, which
+    /// is how engines report where a trap happened.
+    pub fn func_at_offset(&self, offset: usize) -> Option<u32> {
+        let i = self
+            .defined_funcs
+            .iter()
+            .position(|func| func.body_range.contains(&offset))?;
+        Some(self.num_imported_functions + i as u32)
+    }
+
+    /// Gets a human-readable name for an item, from the name section if possible and otherwise
+    /// from the exports.
+    pub fn item_name(&self, item: WorkItem) -> Option<&'a str> {
+        let (names, idx) = match item {
+            WorkItem::Type(idx) => (&self.names.types, idx),
+            WorkItem::Func(idx) => (&self.names.funcs, idx),
+            WorkItem::Table(idx) => (&self.names.tables, idx),
+            WorkItem::Global(idx) => (&self.names.globals, idx),
+            WorkItem::Memory(idx) => (&self.names.memories, idx),
+            WorkItem::Data(idx) => (&self.names.datas, idx),
+            WorkItem::Elem(idx) => (&self.names.elems, idx),
+            WorkItem::Tag(idx) => (&self.names.tags, idx),
+        };
+        if let Some(name) = names.get(&idx) {
+            return Some(name);
+        }
+        self.exports
+            .iter()
+            .find(|e| export_item(e) == item)
+            .map(|e| e.name)
+    }
+
+    /// Gets the name to show people for an item, which is demangled if that was asked for.
+    pub fn display_name(&self, item: WorkItem) -> Option<String> {
+        Some(demangle::for_display(self.item_name(item)?).into_owned())
+    }
+
+    /// Finds the import that provides an item, if it is imported.
+    pub fn import_of(&self, item: WorkItem) -> Option<&Import<'a>> {
+        let kind_matches = |ty: &wasmparser::TypeRef| match item {
+            WorkItem::Func(_) => matches!(ty, wasmparser::TypeRef::Func(_)),
+            WorkItem::Table(_) => matches!(ty, wasmparser::TypeRef::Table(_)),
+            WorkItem::Memory(_) => matches!(ty, wasmparser::TypeRef::Memory(_)),
+            WorkItem::Global(_) => matches!(ty, wasmparser::TypeRef::Global(_)),
+            WorkItem::Tag(_) => matches!(ty, wasmparser::TypeRef::Tag(_)),
+            _ => false,
+        };
+        self.imports
+            .iter()
+            .filter(|import| kind_matches(&import.ty))
+            .nth(item.index() as usize)
+    }
+
+    /// Lists the names an item is exported under.
+    pub fn exports_of(&self, item: WorkItem) -> Vec<&'a str> {
+        self.exports
+            .iter()
+            .filter(|e| export_item(e) == item)
+            .map(|e| e.name)
+            .collect()
+    }
+
+    /// Describes an item for humans, e.g. `func 12 (my_func)`.
+    pub fn describe(&self, item: WorkItem) -> String {
+        match self.display_name(item) {
+            Some(name) => format!("{} {} ({})", item.kind_name(), item.index(), name),
+            None => format!("{} {}", item.kind_name(), item.index()),
+        }
+    }
+
+    /// Gets the encoded size of an item in bytes, for the kinds of items where that is
+    /// meaningful. Imported functions have a size of zero.
+    pub fn item_size(&self, item: WorkItem) -> Option<usize> {
+        match item {
+            WorkItem::Func(idx) => Some(if idx < self.num_imported_functions {
+                0
+            } else {
+                let func = &self.defined_funcs[(idx - self.num_imported_functions) as usize];
+                func.body_range.len()
+            }),
+            WorkItem::Data(idx) => Some(self.datas[idx as usize].range.len()),
+            WorkItem::Elem(idx) => Some(self.elems[idx as usize].range.len()),
+            _ => None,
+        }
+    }
+}
+
+/// Gets the item referred to by an export.
+pub fn export_item(export: &Export) -> WorkItem {
+    match export.kind {
+        wasmparser::ExternalKind::Func => WorkItem::Func(export.index),
+        wasmparser::ExternalKind::Table => WorkItem::Table(export.index),
+        wasmparser::ExternalKind::Memory => WorkItem::Memory(export.index),
+        wasmparser::ExternalKind::Global => WorkItem::Global(export.index),
+        wasmparser::ExternalKind::Tag => WorkItem::Tag(export.index),
+    }
+}
+
+/// Reads a name subsection that is keyed by two indices, like local names.
+fn read_indirect_names<'a>(
+    map: IndirectNameMap<'a>,
+    target: &mut BTreeMap<u32, BTreeMap<u32, &'a str>>,
+) -> Result<()> {
+    for indirect in map {
+        let indirect = indirect?;
+        let names = target.entry(indirect.index).or_default();
+        for naming in indirect.names {
+            let naming = naming?;
+            names.insert(naming.index, naming.name);
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the code section has a body for every function that the function section declares,
+/// since the two are only matched up by position.
+fn check_code_count(m: &ParsedModule, count: u32) -> Result<()> {
+    let declared = m.func_types.len() - m.num_imported_functions as usize;
+    if count as usize != declared {
+        return Err(Error::new(
+            ErrorKind::InvalidModule,
+            format!(
+                "the code section has {} function bodies, but the function section declares {} functions",
+                count, declared
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
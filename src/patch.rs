@@ -0,0 +1,243 @@
+//! Rewriting kept code that refers to items dropped with `--drop`, so that the output still
+//! validates. Instructions that would use a dropped item become `unreachable`, references to
+//! dropped functions in constant expressions become null, and active segments for dropped tables
+//! and memories become passive.
+
+use std::fmt;
+
+use anyhow::Result;
+use wasmparser::{ConstExpr, DataKind, ElementItems, ElementKind, Operator, TableInit, ValType};
+
+use crate::error::{Error, ErrorKind};
+use crate::index_set::IndexSet;
+use crate::liveness::{live_items, WorkItem};
+use crate::module::ParsedModule;
+use crate::uses::{get_instr_uses, Uses};
+
+/// What an instruction that refers to a dropped item is replaced with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Patch {
+    /// The instruction becomes `unreachable`.
+    Unreachable,
+    /// A reference to a function in a constant expression becomes `ref.null func`.
+    NullFunc,
+    /// Reading a global in a constant expression becomes the zero value of its type.
+    Zero(ValType),
+}
+
+impl fmt::Display for Patch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Patch::Unreachable => write!(f, "replaced with unreachable"),
+            Patch::NullFunc => write!(f, "replaced with ref.null func"),
+            Patch::Zero(ty) => write!(f, "replaced with a default {}", ty),
+        }
+    }
+}
+
+/// How a kept place that refers to a dropped item is rewritten.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SitePatch {
+    /// An instruction is replaced.
+    Instr(Patch),
+    /// An active segment is made passive, since its table or memory is gone.
+    Passive,
+}
+
+impl fmt::Display for SitePatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SitePatch::Instr(patch) => patch.fmt(f),
+            SitePatch::Passive => write!(f, "made passive"),
+        }
+    }
+}
+
+/// A place in the kept parts of the module that referred to a dropped item.
+pub struct PatchSite {
+    /// Where the reference is, for humans, e.g. `func 3 (main) at 0x1f4`.
+    pub site: String,
+    pub dropped: WorkItem,
+    pub patch: SitePatch,
+}
+
+/// Finds the first dropped item an instruction refers to, and how to rewrite the instruction.
+/// Constant expressions can't contain `unreachable`, so `in_const` limits the rewrites to ones
+/// that stay constant.
+pub fn patch_instr(
+    op: &Operator,
+    dropped: &Uses,
+    global_types: &[wasmparser::GlobalType],
+    in_const: bool,
+) -> Result<Option<(WorkItem, Patch)>> {
+    let Some(item) = live_items(&get_instr_uses(op))
+        .into_iter()
+        .find(|item| dropped.contains(*item))
+    else {
+        return Ok(None);
+    };
+    let patch = match op {
+        // Outside of constant expressions, `ref.func` makes a non-nullable reference of the
+        // function's own type, which a null can't stand in for.
+        Operator::RefFunc { .. } if in_const => Patch::NullFunc,
+        Operator::GlobalGet { global_index } if in_const => {
+            let ty = global_types[*global_index as usize].content_type;
+            if matches!(ty, ValType::Ref(ty) if !ty.is_nullable()) {
+                return Err(unpatchable(op, item));
+            }
+            Patch::Zero(ty)
+        }
+        // These start or continue blocks, so there is nothing to put in their place.
+        Operator::TryTable { .. } | Operator::Catch { .. } => return Err(unpatchable(op, item)),
+        _ if in_const => return Err(unpatchable(op, item)),
+        _ => Patch::Unreachable,
+    };
+    Ok(Some((item, patch)))
+}
+
+fn unpatchable(op: &Operator, item: WorkItem) -> anyhow::Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        format!("{:?} refers to dropped {}, and cannot be patched", op, item),
+    )
+    .into()
+}
+
+/// Lists every kept place that refers to a dropped item, along with how it will be patched.
+pub fn find_patch_sites(
+    module: &ParsedModule,
+    all_uses: &Uses,
+    dropped: &Uses,
+    cut_funcs: &IndexSet,
+) -> Result<Vec<PatchSite>> {
+    let mut res: Vec<PatchSite> = vec![];
+    let add_const_expr = |res: &mut Vec<PatchSite>, site: String, expr: &ConstExpr| {
+        for op in expr.get_operators_reader() {
+            if let Some((item, patch)) = patch_instr(&op?, dropped, &module.global_types, true)? {
+                res.push(PatchSite {
+                    site: site.clone(),
+                    dropped: item,
+                    patch: SitePatch::Instr(patch),
+                });
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    };
+
+    let scans = module.body_scans()?;
+    for idx in all_uses.live_funcs.iter() {
+        if idx < module.num_imported_functions || cut_funcs.contains(idx) {
+            continue;
+        }
+        let i = (idx - module.num_imported_functions) as usize;
+        // Only the few bodies that refer to a dropped item are decoded again, to find where.
+        if !live_items(&scans[i].uses)
+            .into_iter()
+            .any(|item| dropped.contains(item))
+        {
+            continue;
+        }
+        let func = &module.defined_funcs[i];
+        for op in func.body.get_operators_reader()?.into_iter_with_offsets() {
+            let (op, offset) = op?;
+            if let Some((item, patch)) = patch_instr(&op, dropped, &module.global_types, false)? {
+                res.push(PatchSite {
+                    site: format!("{} at {:#x}", module.describe(WorkItem::Func(idx)), offset),
+                    dropped: item,
+                    patch: SitePatch::Instr(patch),
+                });
+            }
+        }
+    }
+
+    for (i, table) in module.defined_tables.iter().enumerate() {
+        let idx = module.num_imported_tables + i as u32;
+        if let (true, TableInit::Expr(expr)) = (all_uses.live_tables.contains(idx), &table.init) {
+            add_const_expr(&mut res, module.describe(WorkItem::Table(idx)), expr)?;
+        }
+    }
+    for (i, global) in module.defined_globals.iter().enumerate() {
+        let idx = module.num_imported_globals + i as u32;
+        if all_uses.live_globals.contains(idx) {
+            add_const_expr(
+                &mut res,
+                module.describe(WorkItem::Global(idx)),
+                &global.init_expr,
+            )?;
+        }
+    }
+
+    for idx in all_uses.live_elems.iter() {
+        let elem = &module.elems[idx as usize];
+        let site = module.describe(WorkItem::Elem(idx));
+        if let ElementKind::Active {
+            table_index,
+            offset_expr,
+        } = &elem.kind
+        {
+            let table = WorkItem::Table(table_index.unwrap_or(0));
+            if dropped.contains(table) {
+                res.push(PatchSite {
+                    site: site.clone(),
+                    dropped: table,
+                    patch: SitePatch::Passive,
+                });
+            } else {
+                add_const_expr(&mut res, site.clone(), offset_expr)?;
+            }
+        }
+        match &elem.items {
+            ElementItems::Functions(funcs) => {
+                for (i, func_idx) in funcs.clone().into_iter().enumerate() {
+                    let func = WorkItem::Func(func_idx?);
+                    if dropped.contains(func) {
+                        res.push(PatchSite {
+                            site: format!("{} item {}", site, i),
+                            dropped: func,
+                            patch: SitePatch::Instr(Patch::NullFunc),
+                        });
+                    }
+                }
+            }
+            ElementItems::Expressions(ty, exprs) => {
+                let before = res.len();
+                for (i, expr) in exprs.clone().into_iter().enumerate() {
+                    add_const_expr(&mut res, format!("{} item {}", site, i), &expr?)?;
+                }
+                if let (false, Some(patched)) = (ty.is_nullable(), res.get(before)) {
+                    return Err(Error::new(
+                        ErrorKind::Unsupported,
+                        format!(
+                            "{} holds non-nullable references, so it cannot refer to dropped {}",
+                            site, patched.dropped
+                        ),
+                    )
+                    .into());
+                }
+            }
+        }
+    }
+
+    for idx in all_uses.live_datas.iter() {
+        let DataKind::Active {
+            memory_index,
+            offset_expr,
+        } = &module.datas[idx as usize].kind
+        else {
+            continue;
+        };
+        let site = module.describe(WorkItem::Data(idx));
+        let memory = WorkItem::Memory(*memory_index);
+        if dropped.contains(memory) {
+            res.push(PatchSite {
+                site,
+                dropped: memory,
+                patch: SitePatch::Passive,
+            });
+        } else {
+            add_const_expr(&mut res, site, offset_expr)?;
+        }
+    }
+
+    Ok(res)
+}
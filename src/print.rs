@@ -0,0 +1,89 @@
+//! Printing the text format of just some of the items in a module, with their original indices and
+//! names, for reading the code that isolation would keep without writing out a module.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use wasmparser::TypeRef;
+
+use crate::liveness::WorkItem;
+use crate::module::ParsedModule;
+use crate::uses::Uses;
+
+/// Prints the fields of the module's text format that define the given items, in the order they
+/// appear in the module. Imports are printed for imported items, and a rec group is printed whole
+/// if any of its types is included.
+///
+/// The printer writes every field of a module at the same indentation, in the same order as the
+/// binary format, so each field can be matched up with its item.
+pub fn print_items(module: &ParsedModule, wasm: &[u8], items: &Uses) -> Result<String> {
+    let text = wasmprinter::print_bytes(wasm)?;
+
+    // Split the module into its top-level fields, leaving out the `(module` and closing lines.
+    let mut fields: Vec<Vec<&str>> = vec![];
+    for line in text.lines().skip(1) {
+        let is_field_start = line.starts_with("  (") && !line.starts_with("   ");
+        match fields.last_mut() {
+            _ if line == ")" => {}
+            Some(field) if !is_field_start => field.push(line),
+            _ => fields.push(vec![line]),
+        }
+    }
+
+    // Imports come first in every index space, so counting the fields of each kind in order gives
+    // each item's index.
+    let mut counts: HashMap<&'static str, u32> = HashMap::new();
+    let mut next = |make: fn(u32) -> WorkItem| {
+        let count = counts.entry(make(0).kind_name()).or_insert(0);
+        *count += 1;
+        make(*count - 1)
+    };
+    let mut imports = module.imports.iter();
+    let mut rec_groups = module.rec_groups.iter();
+    let mut res = String::new();
+    for field in fields {
+        let keyword = field[0]
+            .trim_start()
+            .trim_start_matches('(')
+            .split([' ', ')'])
+            .next()
+            .unwrap_or("");
+        let keep = match keyword {
+            "type" => {
+                rec_groups.next();
+                items.contains(next(WorkItem::Type))
+            }
+            "rec" => {
+                let len = rec_groups.next().map_or(0, |rg| rg.types().len());
+                let mut any = false;
+                for _ in 0..len {
+                    any |= items.contains(next(WorkItem::Type));
+                }
+                any
+            }
+            "import" => match imports.next().map(|import| import.ty) {
+                Some(TypeRef::Func(_)) => items.contains(next(WorkItem::Func)),
+                Some(TypeRef::Table(_)) => items.contains(next(WorkItem::Table)),
+                Some(TypeRef::Memory(_)) => items.contains(next(WorkItem::Memory)),
+                Some(TypeRef::Global(_)) => items.contains(next(WorkItem::Global)),
+                Some(TypeRef::Tag(_)) => items.contains(next(WorkItem::Tag)),
+                None => false,
+            },
+            "func" => items.contains(next(WorkItem::Func)),
+            "table" => items.contains(next(WorkItem::Table)),
+            "memory" => items.contains(next(WorkItem::Memory)),
+            "global" => items.contains(next(WorkItem::Global)),
+            "tag" => items.contains(next(WorkItem::Tag)),
+            "elem" => items.contains(next(WorkItem::Elem)),
+            "data" => items.contains(next(WorkItem::Data)),
+            _ => false,
+        };
+        if keep {
+            for line in field {
+                res.push_str(line.strip_prefix("  ").unwrap_or(line));
+                res.push('\n');
+            }
+        }
+    }
+    Ok(res)
+}
@@ -0,0 +1,239 @@
+//! Reading CPU profiles, to find the hot functions of a module and use them as roots. Three formats
+//! are understood:
+//!
+//! - Chrome and Node `.cpuprofile` files, from the DevTools profiler or `node --cpu-prof`.
+//! - Firefox profiler files, which is what `wasmtime run --profile=guest` writes.
+//! - Folded stacks, one `frame;frame;frame count` per line, as written by `stackcollapse-perf.pl`
+//!   or `inferno-collapse-perf` from `perf script` output.
+//!
+//! Only the innermost frame of each sample counts, so a function is hot when a lot of time is spent
+//! in its own body.
+
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::error::{Error, ErrorKind};
+use crate::module::ParsedModule;
+
+/// A function of the module and how many samples were taken in it.
+#[derive(Clone, Copy, Debug)]
+pub struct HotFunc {
+    pub idx: u32,
+    pub samples: u64,
+}
+
+pub struct Profile {
+    /// Every function of the module that was sampled, hottest first.
+    pub funcs: Vec<HotFunc>,
+    /// Samples in frames that are not functions of the module, like JavaScript or the engine.
+    pub other_samples: u64,
+}
+
+impl Profile {
+    pub fn total_samples(&self) -> u64 {
+        self.funcs.iter().map(|func| func.samples).sum()
+    }
+
+    /// The fewest hottest functions that together account for at least `percent` of the samples
+    /// in the module.
+    pub fn hottest(&self, percent: f64) -> &[HotFunc] {
+        let total = self.total_samples() as f64;
+        let mut sum: u64 = 0;
+        for (i, func) in self.funcs.iter().enumerate() {
+            if sum as f64 >= total * percent / 100.0 {
+                return &self.funcs[..i];
+            }
+            sum += func.samples;
+        }
+        &self.funcs
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChromeProfile {
+    nodes: Vec<ChromeNode>,
+    #[serde(default)]
+    samples: Vec<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChromeNode {
+    id: u64,
+    call_frame: ChromeCallFrame,
+    #[serde(default)]
+    hit_count: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChromeCallFrame {
+    function_name: String,
+    #[serde(default)]
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct GeckoProfile {
+    threads: Vec<GeckoThread>,
+    #[serde(default)]
+    shared: GeckoShared,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct GeckoShared {
+    #[serde(default)]
+    string_array: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeckoThread {
+    samples: GeckoSamples,
+    stack_table: GeckoStackTable,
+    frame_table: GeckoFrameTable,
+    func_table: GeckoFuncTable,
+    /// Older versions of the format keep strings per thread rather than in `shared`.
+    #[serde(default)]
+    string_array: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct GeckoSamples {
+    stack: Vec<Option<usize>>,
+    #[serde(default)]
+    weight: Option<Vec<f64>>,
+}
+
+#[derive(Deserialize)]
+struct GeckoStackTable {
+    frame: Vec<usize>,
+}
+
+#[derive(Deserialize)]
+struct GeckoFrameTable {
+    func: Vec<usize>,
+}
+
+#[derive(Deserialize)]
+struct GeckoFuncTable {
+    name: Vec<usize>,
+}
+
+/// Reads a profile in any of the supported formats and counts the samples in each function.
+pub fn load_profile(module: &ParsedModule, text: &str) -> Result<Profile> {
+    let mut samples: BTreeMap<u32, u64> = BTreeMap::new();
+    let mut other_samples: u64 = 0;
+    // Frames that are known not to be wasm have no name to look up.
+    let mut add =
+        |name: Option<&str>, count: u64| match name.and_then(|name| resolve_frame(module, name)) {
+            Some(idx) => *samples.entry(idx).or_default() += count,
+            None => other_samples += count,
+        };
+
+    if text.trim_start().starts_with('{') {
+        let value: serde_json::Value = serde_json::from_str(text).context("invalid profile")?;
+        if value.get("nodes").is_some() {
+            let profile: ChromeProfile =
+                serde_json::from_value(value).context("invalid Chrome CPU profile")?;
+            let mut counts: BTreeMap<u64, u64> = BTreeMap::new();
+            for id in &profile.samples {
+                *counts.entry(*id).or_default() += 1;
+            }
+            for node in &profile.nodes {
+                // Profiles with a sample list may leave hit counts out, and the other way around.
+                let count = counts.get(&node.id).copied().unwrap_or(node.hit_count);
+                let frame = &node.call_frame;
+                let is_wasm = frame.url.is_empty() || frame.url.starts_with("wasm://");
+                add(is_wasm.then_some(frame.function_name.as_str()), count);
+            }
+        } else if value.get("threads").is_some() {
+            let profile: GeckoProfile =
+                serde_json::from_value(value).context("invalid Firefox profile")?;
+            for thread in &profile.threads {
+                let strings = thread
+                    .string_array
+                    .as_ref()
+                    .unwrap_or(&profile.shared.string_array);
+                for (i, stack) in thread.samples.stack.iter().enumerate() {
+                    let Some(stack) = stack else {
+                        continue;
+                    };
+                    let weight = match &thread.samples.weight {
+                        Some(weights) => weights.get(i).copied().unwrap_or(1.0).round() as u64,
+                        None => 1,
+                    };
+                    let name = thread
+                        .stack_table
+                        .frame
+                        .get(*stack)
+                        .and_then(|frame| thread.frame_table.func.get(*frame))
+                        .and_then(|func| thread.func_table.name.get(*func))
+                        .and_then(|name| strings.get(*name));
+                    match name {
+                        Some(name) => add(Some(name), weight),
+                        None => anyhow::bail!("invalid Firefox profile: sample {} has no frame", i),
+                    }
+                }
+            }
+        } else {
+            return Err(Error::new(
+                ErrorKind::InvalidRequest,
+                "the profile is JSON, but neither a Chrome CPU profile nor a Firefox profile",
+            )
+            .into());
+        }
+    } else {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parsed = line
+                .rsplit_once(' ')
+                .and_then(|(stack, count)| Some((stack, count.parse::<u64>().ok()?)));
+            let Some((stack, count)) = parsed else {
+                anyhow::bail!(
+                    "line {} of the profile is not a folded stack like \"main;foo;bar 12\"",
+                    i + 1
+                );
+            };
+            add(stack.rsplit(';').next(), count);
+        }
+    }
+
+    let mut funcs: Vec<HotFunc> = samples
+        .into_iter()
+        .map(|(idx, samples)| HotFunc { idx, samples })
+        .collect();
+    funcs.sort_by(|a, b| b.samples.cmp(&a.samples).then(a.idx.cmp(&b.idx)));
+    Ok(Profile {
+        funcs,
+        other_samples,
+    })
+}
+
+/// Finds the function a profiler frame refers to. Engines name wasm frames after the name section
+/// when they can, sometimes with a `$` in front, and after the function index otherwise, like
+/// `wasm-function[12]` or `$func12`.
+fn resolve_frame(module: &ParsedModule, name: &str) -> Option<u32> {
+    let name = name.trim();
+    if let Some(idx) = name
+        .split_once("wasm-function[")
+        .and_then(|(_, rest)| rest.split(']').next())
+        .and_then(|idx| idx.parse::<u32>().ok())
+    {
+        return Some(idx).filter(|idx| *idx < module.func_types.len() as u32);
+    }
+    let name = name.strip_prefix('$').unwrap_or(name);
+    if let Some(idx) = module.find_func_by_name(name) {
+        return Some(idx);
+    }
+    name.strip_prefix("func")
+        .and_then(|idx| idx.parse::<u32>().ok())
+        .filter(|idx| *idx < module.func_types.len() as u32)
+}
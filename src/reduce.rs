@@ -0,0 +1,229 @@
+//! Shrinking a module for as long as it stays interesting to some test, like wasm-reduce, but in
+//! terms of whole exports and functions instead of individual instructions.
+
+use anyhow::Result;
+
+use crate::emit::{emit, EmitOptions};
+use crate::index_set::IndexSet;
+use crate::liveness::{find_live, LivenessOptions};
+use crate::module::ParsedModule;
+use crate::relocation::build_relocations;
+use crate::uses::Uses;
+
+/// The smallest module found, and how it was made.
+pub struct Reduction {
+    /// The exports that are still kept.
+    pub exports: Vec<String>,
+    /// The functions that were replaced with `unreachable`, by their original indices.
+    pub cut_funcs: Vec<u32>,
+    pub wasm: Vec<u8>,
+    /// How many candidates were tested.
+    pub tests: usize,
+}
+
+#[derive(Default)]
+pub struct ReduceOptions {
+    /// How many random candidates to try once the ordinary passes stop making progress.
+    pub random_tries: usize,
+    /// The seed for picking random candidates, so that a reduction can be repeated.
+    pub seed: u64,
+}
+
+/// Removes exports and stubs out functions for as long as `is_interesting` still accepts the
+/// result, starting from the given exports. Each pass first tries big chunks and then smaller
+/// ones, and passes repeat until nothing more can be removed.
+///
+/// Items that can only be removed together, like two functions that each keep the other's
+/// behavior interesting, get stuck there. With `random_tries`, random subsets are removed all at
+/// once to get past this, and the ordinary passes start again after each one that works.
+pub fn reduce(
+    module: &ParsedModule,
+    exports: Vec<String>,
+    options: &ReduceOptions,
+    is_interesting: &mut dyn FnMut(&[u8]) -> Result<bool>,
+) -> Result<Reduction> {
+    let mut reduction = Reduction {
+        wasm: emit_candidate(module, &exports, &[])?,
+        exports,
+        cut_funcs: vec![],
+        tests: 1,
+    };
+    if !is_interesting(&reduction.wasm)? {
+        anyhow::bail!("the test does not find the unreduced module interesting");
+    }
+    let mut rng = fastrand::Rng::with_seed(options.seed);
+    let mut random_tries = options.random_tries;
+
+    loop {
+        let mut progress = false;
+
+        let exports = reduction.exports.clone();
+        progress |= apply_greedily(&exports, &mut vec![], &mut |removed| {
+            let kept: Vec<String> = exports
+                .iter()
+                .filter(|name| !removed.contains(name))
+                .cloned()
+                .collect();
+            try_candidate(module, &mut reduction, kept, None, is_interesting)
+        })?;
+
+        let live = live_funcs(module, &reduction.exports, &reduction.cut_funcs)?;
+        let mut cut_funcs = reduction.cut_funcs.clone();
+        progress |= apply_greedily(&live, &mut cut_funcs, &mut |cut| {
+            let exports = reduction.exports.clone();
+            try_candidate(module, &mut reduction, exports, Some(cut), is_interesting)
+        })?;
+
+        if !progress
+            && !try_random(
+                module,
+                &mut reduction,
+                &mut rng,
+                &mut random_tries,
+                is_interesting,
+            )?
+        {
+            return Ok(reduction);
+        }
+    }
+}
+
+/// Tries random candidates until one is interesting or the tries run out. Each one drops a random
+/// fraction of the exports and stubs out a random fraction of the live functions.
+fn try_random(
+    module: &ParsedModule,
+    reduction: &mut Reduction,
+    rng: &mut fastrand::Rng,
+    tries_left: &mut usize,
+    is_interesting: &mut dyn FnMut(&[u8]) -> Result<bool>,
+) -> Result<bool> {
+    let live = live_funcs(module, &reduction.exports, &reduction.cut_funcs)?;
+    while *tries_left > 0 {
+        *tries_left -= 1;
+        let rate = rng.f64() * 0.5;
+        let exports: Vec<String> = reduction
+            .exports
+            .iter()
+            .filter(|_| rng.f64() >= rate)
+            .cloned()
+            .collect();
+        let mut cut_funcs = reduction.cut_funcs.clone();
+        cut_funcs.extend(live.iter().filter(|_| rng.f64() < rate));
+        if try_candidate(module, reduction, exports, Some(&cut_funcs), is_interesting)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Tests a candidate and keeps it if it is still interesting.
+fn try_candidate(
+    module: &ParsedModule,
+    reduction: &mut Reduction,
+    exports: Vec<String>,
+    cut_funcs: Option<&[u32]>,
+    is_interesting: &mut dyn FnMut(&[u8]) -> Result<bool>,
+) -> Result<bool> {
+    let cut_funcs = cut_funcs.unwrap_or(&reduction.cut_funcs).to_vec();
+    let wasm = emit_candidate(module, &exports, &cut_funcs)?;
+    reduction.tests += 1;
+    if wasm.len() >= reduction.wasm.len() || !is_interesting(&wasm)? {
+        return Ok(false);
+    }
+    log::info!(
+        "Reduced to {} bytes ({} export(s), {} stubbed function(s)).",
+        wasm.len(),
+        exports.len(),
+        cut_funcs.len()
+    );
+    *reduction = Reduction {
+        exports,
+        cut_funcs,
+        wasm,
+        tests: reduction.tests,
+    };
+    Ok(true)
+}
+
+/// Tries adding chunks of the candidates to `applied`, keeping each chunk that `test` accepts.
+/// Chunks start at half of the candidates and shrink down to single items. Returns whether
+/// anything was applied.
+fn apply_greedily<T: Clone + PartialEq>(
+    candidates: &[T],
+    applied: &mut Vec<T>,
+    test: &mut dyn FnMut(&[T]) -> Result<bool>,
+) -> Result<bool> {
+    let mut remaining = candidates.to_vec();
+    let mut chunk_size = remaining.len().div_ceil(2).max(1);
+    let mut any = false;
+    while !remaining.is_empty() {
+        let mut i = 0;
+        while i < remaining.len() {
+            let end = (i + chunk_size).min(remaining.len());
+            let mut trial = applied.clone();
+            trial.extend_from_slice(&remaining[i..end]);
+            if test(&trial)? {
+                *applied = trial;
+                remaining.drain(i..end);
+                any = true;
+            } else {
+                i = end;
+            }
+        }
+        if chunk_size == 1 {
+            break;
+        }
+        chunk_size = chunk_size.div_ceil(2);
+    }
+    Ok(any)
+}
+
+/// The defined functions that are kept for some exports, and not already stubbed out.
+fn live_funcs(module: &ParsedModule, exports: &[String], cut_funcs: &[u32]) -> Result<Vec<u32>> {
+    let cut_funcs: IndexSet = cut_funcs.iter().copied().collect();
+    let uses = find_uses(module, exports, &cut_funcs)?;
+    Ok(uses
+        .live_funcs
+        .iter()
+        .filter(|idx| *idx >= module.num_imported_functions && !cut_funcs.contains(*idx))
+        .collect())
+}
+
+fn find_uses(module: &ParsedModule, exports: &[String], cut_funcs: &IndexSet) -> Result<Uses> {
+    let mut roots = vec![];
+    for name in exports {
+        let Some(item) = module.find_export(name) else {
+            anyhow::bail!("export \"{}\" was not found in the module", name);
+        };
+        roots.push(item);
+    }
+    find_live(
+        module,
+        roots,
+        &LivenessOptions {
+            cut_funcs: cut_funcs.clone(),
+            dropped: Uses::default(),
+            ..Default::default()
+        },
+    )
+}
+
+/// Isolates the given exports, which become the only exports of the candidate.
+fn emit_candidate(module: &ParsedModule, exports: &[String], cut_funcs: &[u32]) -> Result<Vec<u8>> {
+    let cut_funcs: IndexSet = cut_funcs.iter().copied().collect();
+    let all_uses = find_uses(module, exports, &cut_funcs)?;
+    let relocations = build_relocations(&all_uses);
+    emit(
+        module,
+        &all_uses,
+        &relocations,
+        &EmitOptions {
+            cut_funcs: &cut_funcs,
+            api: Some(exports),
+            strip_object_metadata: true,
+            synthetic_exports: false,
+            export_prefix: "",
+            ..Default::default()
+        },
+    )
+}
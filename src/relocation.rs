@@ -1,7 +1,18 @@
-use std::{collections::HashMap, fmt::Display};
+use std::fmt::Display;
 
 // use anyhow::Error;
-use wasm_encoder::reencode::{utils, Reencode};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use wasm_encoder::reencode::{self, utils, Reencode};
+use wasm_encoder::{AbstractHeapType, ConstExpr, Elements, HeapType, Instruction, RefType};
+
+use wasmparser::{CompositeInnerType, ElementItems, GlobalType, Operator, SubType, ValType};
+
+use crate::index_set::IndexSet;
+use crate::liveness::WorkItem;
+use crate::module::ParsedModule;
+use crate::patch::{patch_instr, Patch};
+use crate::uses::{get_type_uses, Uses};
 
 #[derive(Eq, PartialEq, Hash)]
 pub enum Relocation {
@@ -15,6 +26,174 @@ pub enum Relocation {
     Tag(u32),
 }
 
+impl From<WorkItem> for Relocation {
+    fn from(item: WorkItem) -> Self {
+        match item {
+            WorkItem::Type(idx) => Relocation::Type(idx),
+            WorkItem::Func(idx) => Relocation::Func(idx),
+            WorkItem::Table(idx) => Relocation::Table(idx),
+            WorkItem::Global(idx) => Relocation::Global(idx),
+            WorkItem::Memory(idx) => Relocation::Memory(idx),
+            WorkItem::Data(idx) => Relocation::Data(idx),
+            WorkItem::Elem(idx) => Relocation::Elem(idx),
+            WorkItem::Tag(idx) => Relocation::Tag(idx),
+        }
+    }
+}
+
+/// The new index of every live item in the output module, stored as one array per index space
+/// so that lookups are cheap.
+#[derive(Default)]
+#[cfg_attr(feature = "serde-impls", derive(serde::Serialize, serde::Deserialize))]
+pub struct Relocations {
+    types: Vec<Option<u32>>,
+    funcs: Vec<Option<u32>>,
+    tables: Vec<Option<u32>>,
+    globals: Vec<Option<u32>>,
+    memories: Vec<Option<u32>>,
+    datas: Vec<Option<u32>>,
+    elems: Vec<Option<u32>>,
+    tags: Vec<Option<u32>>,
+}
+
+impl Relocations {
+    fn space(&self, reloc: &Relocation) -> (&Vec<Option<u32>>, u32) {
+        match *reloc {
+            Relocation::Type(idx) => (&self.types, idx),
+            Relocation::Func(idx) => (&self.funcs, idx),
+            Relocation::Table(idx) => (&self.tables, idx),
+            Relocation::Global(idx) => (&self.globals, idx),
+            Relocation::Memory(idx) => (&self.memories, idx),
+            Relocation::Data(idx) => (&self.datas, idx),
+            Relocation::Elem(idx) => (&self.elems, idx),
+            Relocation::Tag(idx) => (&self.tags, idx),
+        }
+    }
+
+    /// Gets the new index of an item, or `None` if the item was removed.
+    pub fn get(&self, reloc: &Relocation) -> Option<&u32> {
+        let (space, idx) = self.space(reloc);
+        space.get(idx as usize).and_then(|new_idx| new_idx.as_ref())
+    }
+
+    pub fn contains_key(&self, reloc: &Relocation) -> bool {
+        self.get(reloc).is_some()
+    }
+
+    /// Gives an item a new index.
+    pub fn insert(&mut self, item: WorkItem, new_idx: u32) {
+        let space = match item {
+            WorkItem::Type(_) => &mut self.types,
+            WorkItem::Func(_) => &mut self.funcs,
+            WorkItem::Table(_) => &mut self.tables,
+            WorkItem::Global(_) => &mut self.globals,
+            WorkItem::Memory(_) => &mut self.memories,
+            WorkItem::Data(_) => &mut self.datas,
+            WorkItem::Elem(_) => &mut self.elems,
+            WorkItem::Tag(_) => &mut self.tags,
+        };
+        let idx = item.index() as usize;
+        if space.len() <= idx {
+            space.resize(idx + 1, None);
+        }
+        space[idx] = Some(new_idx);
+    }
+
+    /// Follows every new index through a second set of relocations, for when the output is
+    /// rewritten again after it is emitted.
+    pub fn then(&self, next: &Relocations) -> Relocations {
+        let compose = |space: &[Option<u32>], next: &[Option<u32>]| -> Vec<Option<u32>> {
+            space
+                .iter()
+                .map(|idx| idx.and_then(|idx| next.get(idx as usize).copied().flatten()))
+                .collect()
+        };
+        Relocations {
+            types: compose(&self.types, &next.types),
+            funcs: compose(&self.funcs, &next.funcs),
+            tables: compose(&self.tables, &next.tables),
+            globals: compose(&self.globals, &next.globals),
+            memories: compose(&self.memories, &next.memories),
+            datas: compose(&self.datas, &next.datas),
+            elems: compose(&self.elems, &next.elems),
+            tags: compose(&self.tags, &next.tags),
+        }
+    }
+
+    /// Gives each merged type the new index of the type it was merged into, as found by
+    /// [`dedupe_types`].
+    pub fn merge_types(&mut self, merged: &[(u32, u32)]) {
+        for (idx, into) in merged {
+            let new_idx = self.get(&Relocation::Type(*into)).copied();
+            if self.types.len() <= *idx as usize {
+                self.types.resize(*idx as usize + 1, None);
+            }
+            self.types[*idx as usize] = new_idx;
+        }
+    }
+}
+
+/// Maps every live item to its new index in the output module.
+pub fn build_relocations(all_uses: &Uses) -> Relocations {
+    Relocations {
+        // Type canonicalization be damned. Surely no self-respecting compiler would leave
+        // redundant types in its output.
+        types: build_space(&all_uses.live_types),
+        funcs: build_space(&all_uses.live_funcs),
+        tables: build_space(&all_uses.live_tables),
+        globals: build_space(&all_uses.live_globals),
+        memories: build_space(&all_uses.live_memories),
+        datas: build_space(&all_uses.live_datas),
+        elems: build_space(&all_uses.live_elems),
+        tags: build_space(&all_uses.live_tags),
+    }
+}
+
+/// Finds live function types that are the same as an earlier live type, and removes them from the
+/// live types so that only the first one is emitted. Returns each removed type along with the type
+/// it was merged into.
+///
+/// Only types that are alone in an implicit rec group, final, and don't refer to other types are
+/// merged. Identical types like these are already the same type after canonicalization, so
+/// merging them changes no type's identity.
+pub fn dedupe_types(module: &ParsedModule, all_uses: &mut Uses) -> Vec<(u32, u32)> {
+    let mut first: HashMap<&SubType, u32> = HashMap::new();
+    let mut merged: Vec<(u32, u32)> = vec![];
+    let mut idx: u32 = 0;
+    for rg in &module.rec_groups {
+        let len = rg.types().len() as u32;
+        let ty = &module.types[idx as usize];
+        let mergeable = len == 1
+            && !rg.is_explicit_rec_group()
+            && ty.is_final
+            && matches!(ty.composite_type.inner, CompositeInnerType::Func(_))
+            && get_type_uses(ty).live_types.is_empty();
+        if mergeable && all_uses.live_types.contains(idx) {
+            match first.entry(ty) {
+                Entry::Occupied(entry) => merged.push((idx, *entry.get())),
+                Entry::Vacant(entry) => {
+                    entry.insert(idx);
+                }
+            }
+        }
+        idx += len;
+    }
+    for (idx, _) in &merged {
+        all_uses.live_types.remove(*idx);
+    }
+    merged
+}
+
+/// Live items keep their relative order, so each one's new index is its position in the set.
+fn build_space(live: &IndexSet) -> Vec<Option<u32>> {
+    let len = live.iter().last().map_or(0, |idx| idx as usize + 1);
+    let mut res = vec![None; len];
+    for (new_idx, idx) in live.iter().enumerate() {
+        res[idx as usize] = Some(new_idx as u32);
+    }
+    res
+}
+
 #[derive(Debug)]
 pub struct Error(anyhow::Error);
 
@@ -37,12 +216,97 @@ impl From<wasm_encoder::reencode::Error<anyhow::Error>> for Error {
 }
 
 pub struct RelocatingReencoder<'a> {
-    pub relocations: &'a HashMap<Relocation, u32>,
+    relocations: &'a Relocations,
+    /// Items that were left out even though kept code refers to them. Instructions that use them
+    /// are patched as described in [`crate::patch`].
+    dropped: &'a Uses,
+    global_types: &'a [GlobalType],
+    /// Whether we are inside a constant expression, where `unreachable` can't be used.
+    in_const: bool,
 }
 
+impl<'a> RelocatingReencoder<'a> {
+    pub fn new(
+        relocations: &'a Relocations,
+        dropped: &'a Uses,
+        global_types: &'a [GlobalType],
+    ) -> Self {
+        Self {
+            relocations,
+            dropped,
+            global_types,
+            in_const: false,
+        }
+    }
+}
+
+const NULL_FUNC: HeapType = HeapType::Abstract {
+    shared: false,
+    ty: AbstractHeapType::Func,
+};
+
 impl<'a> Reencode for RelocatingReencoder<'a> {
     type Error = Error;
 
+    fn instruction<'b>(
+        &mut self,
+        arg: Operator<'b>,
+    ) -> Result<Instruction<'b>, reencode::Error<Self::Error>> {
+        let patch = patch_instr(&arg, self.dropped, self.global_types, self.in_const)
+            .map_err(|err| reencode::Error::UserError(Error(err)))?;
+        Ok(match patch {
+            None => return utils::instruction(self, arg),
+            Some((_, Patch::Unreachable)) => Instruction::Unreachable,
+            Some((_, Patch::NullFunc)) => Instruction::RefNull(NULL_FUNC),
+            Some((_, Patch::Zero(ty))) => match ty {
+                ValType::I32 => Instruction::I32Const(0),
+                ValType::I64 => Instruction::I64Const(0),
+                ValType::F32 => Instruction::F32Const(0.0),
+                ValType::F64 => Instruction::F64Const(0.0),
+                ValType::V128 => Instruction::V128Const(0),
+                ValType::Ref(ty) => Instruction::RefNull(self.heap_type(ty.heap_type())?),
+            },
+        })
+    }
+
+    fn const_expr(
+        &mut self,
+        const_expr: wasmparser::ConstExpr,
+    ) -> Result<ConstExpr, reencode::Error<Self::Error>> {
+        self.in_const = true;
+        let res = utils::const_expr(self, const_expr);
+        self.in_const = false;
+        res
+    }
+
+    fn element_items<'b>(
+        &mut self,
+        items: ElementItems<'b>,
+    ) -> Result<Elements<'b>, reencode::Error<Self::Error>> {
+        // A plain list of functions has no way to say null, so a list with dropped functions in
+        // it is written as expressions instead.
+        if let ElementItems::Functions(funcs) = &items {
+            let funcs = funcs.clone().into_iter().collect::<Result<Vec<u32>, _>>()?;
+            if funcs
+                .iter()
+                .any(|idx| self.dropped.live_funcs.contains(*idx))
+            {
+                let exprs: Vec<ConstExpr> = funcs
+                    .into_iter()
+                    .map(|idx| {
+                        if self.dropped.live_funcs.contains(idx) {
+                            ConstExpr::ref_null(NULL_FUNC)
+                        } else {
+                            ConstExpr::ref_func(self.function_index(idx))
+                        }
+                    })
+                    .collect();
+                return Ok(Elements::Expressions(RefType::FUNCREF, exprs.into()));
+            }
+        }
+        utils::element_items(self, items)
+    }
+
     fn data_index(&mut self, data: u32) -> u32 {
         utils::data_index(
             self,
@@ -0,0 +1,117 @@
+//! Runs functions from an isolated module with wasmtime, for checking a repro without leaving the
+//! command line.
+
+use anyhow::{Context, Result};
+use wasmtime::{
+    Engine, ExternType, Global, Linker, Memory, Module, Ref, Store, Table, Val, ValType, V128,
+};
+
+/// Instantiates a module and calls one of its exported functions with the given arguments, which
+/// are parsed according to the function's parameter types. Imported functions return default
+/// values, and other imports are filled in with fresh definitions.
+pub fn call_export(wasm: &[u8], export: &str, args: &[String]) -> Result<Vec<Val>> {
+    let engine = Engine::default();
+    let module = Module::new(&engine, wasm)?;
+    let mut store = Store::new(&engine, ());
+    let mut linker: Linker<()> = Linker::new(&engine);
+
+    for import in module.imports() {
+        let (module_name, name) = (import.module(), import.name());
+        match import.ty() {
+            ExternType::Func(_) => {}
+            ExternType::Memory(ty) => {
+                if ty.is_shared() {
+                    anyhow::bail!(
+                        "the shared memory {}.{} cannot be stubbed (isolate with --standalone instead)",
+                        module_name,
+                        name
+                    );
+                }
+                let memory = Memory::new(&mut store, ty)?;
+                linker.define(&store, module_name, name, memory)?;
+            }
+            ExternType::Global(ty) => {
+                let Some(init) = Val::default_for_ty(ty.content()) else {
+                    anyhow::bail!("the global {}.{} has no default value", module_name, name);
+                };
+                let global = Global::new(&mut store, ty, init)?;
+                linker.define(&store, module_name, name, global)?;
+            }
+            ExternType::Table(ty) => {
+                if !ty.element().is_nullable() {
+                    anyhow::bail!(
+                        "the table {}.{} cannot be stubbed because its elements are not nullable",
+                        module_name,
+                        name
+                    );
+                }
+                let init = Ref::null(ty.element().heap_type());
+                let table = Table::new(&mut store, ty, init)?;
+                linker.define(&store, module_name, name, table)?;
+            }
+        }
+    }
+    linker.define_unknown_imports_as_default_values(&module)?;
+
+    let instance = linker.instantiate(&mut store, &module)?;
+    let func = instance
+        .get_func(&mut store, export)
+        .with_context(|| format!("the module has no function export named \"{}\"", export))?;
+    let ty = func.ty(&store);
+    if ty.params().len() != args.len() {
+        anyhow::bail!(
+            "the function takes {} argument(s), but {} were given",
+            ty.params().len(),
+            args.len()
+        );
+    }
+    let params = ty
+        .params()
+        .zip(args)
+        .map(|(ty, arg)| parse_val(&ty, arg))
+        .collect::<Result<Vec<_>>>()?;
+    let mut results = ty
+        .results()
+        .map(|ty| Val::default_for_ty(&ty).unwrap_or(Val::I32(0)))
+        .collect::<Vec<_>>();
+    func.call(&mut store, &params, &mut results)?;
+    Ok(results)
+}
+
+/// Parses a command-line argument as a value of the given type.
+fn parse_val(ty: &ValType, arg: &str) -> Result<Val> {
+    let parsed = match ty {
+        ValType::I32 => arg
+            .parse::<i32>()
+            .or_else(|_| arg.parse::<u32>().map(|x| x as i32))
+            .map(Val::I32)
+            .ok(),
+        ValType::I64 => arg
+            .parse::<i64>()
+            .or_else(|_| arg.parse::<u64>().map(|x| x as i64))
+            .map(Val::I64)
+            .ok(),
+        ValType::F32 => arg.parse::<f32>().map(|x| Val::F32(x.to_bits())).ok(),
+        ValType::F64 => arg.parse::<f64>().map(|x| Val::F64(x.to_bits())).ok(),
+        ValType::V128 => arg.parse::<u128>().map(|x| Val::V128(V128::from(x))).ok(),
+        ValType::Ref(ty) => {
+            (arg == "null" && ty.is_nullable()).then(|| Val::null_ref(ty.heap_type()))
+        }
+    };
+    parsed.with_context(|| format!("\"{}\" is not a valid {}", arg, ty))
+}
+
+/// Formats a value the way it would be written as an argument.
+pub fn format_val(val: &Val) -> String {
+    match val {
+        Val::I32(x) => x.to_string(),
+        Val::I64(x) => x.to_string(),
+        Val::F32(bits) => f32::from_bits(*bits).to_string(),
+        Val::F64(bits) => f64::from_bits(*bits).to_string(),
+        Val::V128(x) => x.as_u128().to_string(),
+        Val::FuncRef(None) | Val::ExternRef(None) | Val::AnyRef(None) => "null".to_string(),
+        Val::FuncRef(Some(_)) => "<funcref>".to_string(),
+        Val::ExternRef(Some(_)) => "<externref>".to_string(),
+        Val::AnyRef(Some(_)) => "<anyref>".to_string(),
+    }
+}
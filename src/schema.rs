@@ -0,0 +1,403 @@
+//! The JSON formats of everything wasm-isolate writes for other programs to read. Every document
+//! carries a `schema` version, which is bumped whenever a field is removed or changes meaning.
+//! Adding new fields does not change the version.
+
+use serde::{Deserialize, Serialize};
+
+use crate::liveness::WorkItem;
+use crate::module::ParsedModule;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A reference to an item in a module, by its index in the original module.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Item {
+    pub kind: String,
+    pub index: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl Item {
+    pub fn new(module: &ParsedModule, item: WorkItem) -> Item {
+        Item {
+            kind: item.kind_name().to_string(),
+            index: item.index(),
+            name: module.item_name(item).map(|name| name.to_string()),
+        }
+    }
+}
+
+/// The new index of every item kept in the isolated module.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Map {
+    pub schema: u32,
+    pub items: Vec<MapEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MapEntry {
+    pub kind: String,
+    pub old_index: u32,
+    pub new_index: u32,
+}
+
+/// Section sizes and index space counts before and after isolation.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Report {
+    pub schema: u32,
+    pub sections: Vec<SectionReport>,
+    pub index_spaces: Vec<IndexSpaceReport>,
+    pub original_size: usize,
+    pub isolated_size: usize,
+    /// The functions that refer to each memory, by their indices in the isolated module.
+    #[serde(default)]
+    pub memories: Vec<IndexUsers>,
+    /// The functions that refer to each table, by their indices in the isolated module.
+    #[serde(default)]
+    pub tables: Vec<IndexUsers>,
+    /// What each group of roots given with `--group` keeps.
+    #[serde(default)]
+    pub groups: Vec<GroupReport>,
+}
+
+/// The kept items that one named group of roots needs, and how much of the output they take up.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GroupReport {
+    pub name: String,
+    pub roots: Vec<Item>,
+    /// Every kept item the group needs, by its index in the original module.
+    pub items: Vec<Item>,
+    /// How many of those items no other group needs.
+    pub exclusive_items: usize,
+    /// The original size of the function bodies and segments the group needs.
+    pub size: usize,
+    /// The part of `size` that no other group needs.
+    pub exclusive_size: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IndexUsers {
+    pub index: u32,
+    pub funcs: Vec<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SectionReport {
+    pub name: String,
+    pub original_size: usize,
+    pub isolated_size: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IndexSpaceReport {
+    pub name: String,
+    pub original_count: usize,
+    pub isolated_count: usize,
+}
+
+/// The result of an isolation run, printed by `--format json` in place of the usual summary.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Summary {
+    pub schema: u32,
+    /// The items given on the command line, and where they ended up.
+    pub requested: Vec<RequestedItem>,
+    pub index_spaces: Vec<IndexSpaceReport>,
+    /// The new index of every item kept in the isolated module.
+    pub items: Vec<MapEntry>,
+    /// The exports added to the isolated module that were not in the original.
+    pub synthetic_exports: Vec<SyntheticExport>,
+    pub warnings: Vec<String>,
+    pub output_size: usize,
+    /// What each group of roots given with `--group` keeps.
+    #[serde(default)]
+    pub groups: Vec<GroupReport>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestedItem {
+    pub kind: String,
+    pub old_index: u32,
+    /// The index in the isolated module, or nothing if the item was out of range.
+    pub new_index: Option<u32>,
+    /// Whether the function was kept as an `unreachable` stub.
+    #[serde(default)]
+    pub cut: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SyntheticExport {
+    pub name: String,
+    pub kind: String,
+    pub index: u32,
+}
+
+/// What `--split-out` wrote, one module per requested item, or per group for items in a group.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SplitIndex {
+    pub schema: u32,
+    pub modules: Vec<SplitModule>,
+    pub warnings: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<SplitGroup>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SplitGroup {
+    pub name: String,
+    pub roots: Vec<Item>,
+    /// The file name of the module, relative to the index.
+    pub file: String,
+    pub output_size: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SplitModule {
+    /// The requested item, by its index in the original module.
+    pub item: Item,
+    /// The file name of the module, relative to the index.
+    pub file: String,
+    pub output_size: usize,
+}
+
+/// The dependency graph between the items kept in the isolated module. Edges point from an item
+/// to the items it uses.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Graph {
+    pub schema: u32,
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GraphNode {
+    #[serde(flatten)]
+    pub item: Item,
+    /// The encoded size of the item, for functions and segments, or zero otherwise.
+    #[serde(default)]
+    pub size: usize,
+    /// The size of the item plus everything that is only kept because of it, which is what
+    /// removing it would save.
+    #[serde(default)]
+    pub retained_size: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GraphEdge {
+    pub from: Item,
+    pub to: Item,
+    /// How `from` uses `to`: `call`, `ref.func`, `type-of`, `init-expr`, `segment`, or
+    /// `instruction`.
+    #[serde(default)]
+    pub kind: String,
+}
+
+/// Where the body of every kept function moved to. Offsets are relative to the start of the code
+/// section's contents, which is how DWARF refers to code in WebAssembly.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OffsetMap {
+    pub schema: u32,
+    pub funcs: Vec<FuncOffsets>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FuncOffsets {
+    pub old_index: u32,
+    pub new_index: u32,
+    pub old_start: usize,
+    pub old_end: usize,
+    pub new_start: usize,
+    pub new_end: usize,
+    /// Whether the body was copied byte for byte, in which case every offset within it moved by
+    /// the same amount. Otherwise only the start and end of the body can be mapped.
+    pub verbatim: bool,
+}
+
+/// Where the body of every kept function moved to, as offsets from the start of the module. Source
+/// maps for WebAssembly use these offsets as column numbers, so they can be rewritten for the
+/// isolated module with this.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SourceMapRemap {
+    pub schema: u32,
+    /// The source map the original module refers to, which the isolated module still refers to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_mapping_url: Option<String>,
+    pub funcs: Vec<FuncOffsets>,
+}
+
+/// The answer to `analyze --users`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Users {
+    pub schema: u32,
+    pub item: Item,
+    pub users: Vec<UserEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UserEntry {
+    Item(Item),
+    Export { name: String },
+    Start,
+}
+
+/// The answer to `explain`. Without an origin, nothing keeps the item, and the path is empty.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Explanation {
+    pub schema: u32,
+    pub item: Item,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<UserEntry>,
+    /// Each item uses the next, starting with the one the origin refers to.
+    pub path: Vec<ItemDetails>,
+}
+
+/// An item along with where it comes from and its type.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ItemDetails {
+    #[serde(flatten)]
+    pub item: Item,
+    /// The type in the text format, e.g. `(func (param i32) (result i32))`.
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub ty: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub import: Option<ImportName>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exports: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImportName {
+    pub module: String,
+    pub name: String,
+}
+
+/// The answer to `analyze --dropped`, one index space at a time.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DroppedItems {
+    pub schema: u32,
+    pub items: Vec<DroppedItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DroppedItem {
+    #[serde(flatten)]
+    pub item: Item,
+    /// The encoded size, for functions and segments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<usize>,
+}
+
+/// The answer to `analyze --top`, largest first.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TopItems {
+    pub schema: u32,
+    pub items: Vec<TopItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TopItem {
+    #[serde(flatten)]
+    pub item: Item,
+    pub size: usize,
+    /// What keeps the item, if anything does.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<UserEntry>,
+    /// Each item uses the next, starting with the one the origin refers to and ending with this
+    /// one.
+    #[serde(default)]
+    pub path: Vec<Item>,
+}
+
+/// The answer to `analyze --list`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Inventory {
+    pub schema: u32,
+    pub index_spaces: Vec<IndexSpaceCount>,
+    pub funcs: Vec<FuncEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IndexSpaceCount {
+    pub name: String,
+    pub count: usize,
+    pub imported: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FuncEntry {
+    pub index: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub size: usize,
+    pub num_callees: usize,
+    pub num_globals: usize,
+    pub num_memories: usize,
+    pub features: Vec<String>,
+}
+
+/// The answer to `analyze --features`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Features {
+    pub schema: u32,
+    pub features: Vec<FeatureEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FeatureEntry {
+    /// The proposal, as named by wasmparser, e.g. `simd`.
+    pub proposal: String,
+    /// The first item found that needs the proposal.
+    pub item: Item,
+    pub reason: String,
+}
+
+/// The answer to `diff`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IndexDiffs {
+    pub schema: u32,
+    pub diffs: Vec<IndexDiffEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IndexDiffEntry {
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub change: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_index: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_index: Option<u32>,
+}
+
+/// The answer to `diff-live`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LiveDiffs {
+    pub schema: u32,
+    pub diffs: Vec<LiveDiffEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LiveDiffEntry {
+    pub kind: String,
+    pub key: String,
+    pub change: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_size: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_size: Option<usize>,
+}
+
+/// How a module was isolated, as recorded by `--annotate` in its `wasm-isolate` custom section.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Annotation {
+    pub schema: u32,
+    /// The version of wasm-isolate that isolated the module.
+    pub version: String,
+    /// The command line, not including the program name.
+    pub args: Vec<String>,
+    /// The SHA-256 hash of the original module, in hex.
+    pub input_sha256: String,
+    pub roots: Vec<Item>,
+}
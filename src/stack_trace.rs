@@ -0,0 +1,96 @@
+//! Reading stack traces from crash reports, to use the functions on the stack as roots. Frames are
+//! recognized in the formats of V8, SpiderMonkey, JavaScriptCore, and wasmtime:
+//!
+//! ```text
+//!     at main (wasm://wasm/8c1e3f5a:wasm-function[12]:0x1a2b)
+//! main@https://example.com/app.wasm:wasm-function[12]:0x1a2b
+//! <?>.wasm-function[12]@[wasm code]
+//!     0:   0x1a2b - app!main
+//!     1:   0x1b3c - <unknown>!<wasm function 12>
+//! ```
+//!
+//! A frame's function is found from its index if it has one, then from its code offset, and then
+//! from its name.
+
+use crate::module::ParsedModule;
+
+/// A line of a stack trace that refers to a function in a wasm module.
+pub struct Frame<'a> {
+    /// The line number, starting at 1.
+    pub line: usize,
+    pub text: &'a str,
+    /// The function, or `None` if the frame refers to something that is not in the module.
+    pub func: Option<u32>,
+}
+
+enum FrameRef<'a> {
+    Index(u32),
+    Offset(usize),
+    Name(&'a str),
+}
+
+/// Lists every frame in a stack trace that looks like a wasm frame, in order. Lines that aren't
+/// wasm frames, like JavaScript frames or the error message, are left out.
+pub fn parse_stack_trace<'a>(module: &ParsedModule, text: &'a str) -> Vec<Frame<'a>> {
+    let num_funcs = module.func_types.len() as u32;
+    let mut res: Vec<Frame> = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let Some(frame) = parse_frame(line.trim()) else {
+            continue;
+        };
+        let func = match frame {
+            FrameRef::Index(idx) => Some(idx).filter(|idx| *idx < num_funcs),
+            FrameRef::Offset(offset) => module.func_at_offset(offset),
+            FrameRef::Name(name) => module.find_func_by_name(name),
+        };
+        res.push(Frame {
+            line: i + 1,
+            text: line.trim(),
+            func,
+        });
+    }
+    res
+}
+
+fn parse_frame(line: &str) -> Option<FrameRef<'_>> {
+    let between = |start: &str, end: &str| {
+        let (_, rest) = line.split_once(start)?;
+        rest.split_once(end)?.0.parse::<u32>().ok()
+    };
+    if let Some(idx) = between("wasm-function[", "]").or_else(|| between("<wasm function ", ">")) {
+        return Some(FrameRef::Index(idx));
+    }
+
+    // wasmtime puts the offset first, like `0:   0x1a2b - app!main`.
+    if let Some((head, _)) = line.split_once(" - ") {
+        let offset = head
+            .split_once(':')
+            .and_then(|(_, offset)| offset.trim().strip_prefix("0x"))
+            .and_then(|offset| usize::from_str_radix(offset, 16).ok());
+        if let Some(offset) = offset {
+            return Some(FrameRef::Offset(offset));
+        }
+    }
+
+    // Browsers put the offset last, like `(https://example.com/app.wasm:0x1a2b)`.
+    let location = line.trim_end_matches(')');
+    if let Some((url, offset)) = location.rsplit_once(":0x") {
+        if url.contains("wasm") {
+            if let Ok(offset) = usize::from_str_radix(offset, 16) {
+                return Some(FrameRef::Offset(offset));
+            }
+        }
+    }
+
+    // Without an index or offset, all that's left is the name, like `main@[wasm code]` or
+    // `at main (wasm://wasm/8c1e3f5a)`.
+    let name = if let Some((name, location)) = line.split_once('@') {
+        location.contains("wasm").then_some(name)
+    } else if let Some((name, location)) = line.strip_prefix("at ")?.split_once(" (") {
+        location.contains("wasm").then_some(name)
+    } else {
+        None
+    }?;
+    let name = name.rsplit('!').next().unwrap_or(name);
+    Some(FrameRef::Name(name.strip_prefix('$').unwrap_or(name)))
+}
@@ -0,0 +1,221 @@
+use anyhow::Result;
+use wasmparser::{Parser, Payload};
+
+use crate::liveness::{live_items, RootGroup, WorkItem};
+use crate::module::ParsedModule;
+use crate::schema::{self, SCHEMA_VERSION};
+use crate::uses::{get_instr_uses, Uses};
+
+/// The encoded size of one section, including its header.
+pub struct SectionSize {
+    pub name: String,
+    pub size: usize,
+}
+
+/// Lists the size of every section in a module, in order.
+pub fn section_sizes(buf: &[u8]) -> Result<Vec<SectionSize>> {
+    let mut res: Vec<SectionSize> = vec![];
+    let mut section_start: usize = 0;
+    for payload in Parser::new(0).parse_all(buf) {
+        let payload = payload?;
+        if let Payload::Version { range, .. } = &payload {
+            section_start = range.end;
+        }
+        let Some((id, range)) = payload.as_section() else {
+            continue;
+        };
+        let name = match &payload {
+            Payload::CustomSection(r) => format!("custom \"{}\"", r.name()),
+            _ => section_name(id).to_string(),
+        };
+        res.push(SectionSize {
+            name,
+            size: range.end - section_start,
+        });
+        section_start = range.end;
+    }
+    Ok(res)
+}
+
+fn section_name(id: u8) -> &'static str {
+    match id {
+        1 => "type",
+        2 => "import",
+        3 => "function",
+        4 => "table",
+        5 => "memory",
+        6 => "global",
+        7 => "export",
+        8 => "start",
+        9 => "element",
+        10 => "code",
+        11 => "data",
+        12 => "datacount",
+        13 => "tag",
+        _ => "unknown",
+    }
+}
+
+/// Pairs up the sections of the original and isolated modules by name, keeping the original
+/// order. Sections that only exist in one of the modules have a size of zero in the other.
+pub fn compare_section_sizes(
+    original: &[SectionSize],
+    isolated: &[SectionSize],
+) -> Vec<(String, usize, usize)> {
+    let mut res: Vec<(String, usize, usize)> = vec![];
+    for section in original {
+        res.push((section.name.clone(), section.size, 0));
+    }
+    for section in isolated {
+        // Custom sections can appear more than once, so match each isolated section with the
+        // first original section of the same name that hasn't been matched yet.
+        match res
+            .iter_mut()
+            .find(|(name, _, size)| *name == section.name && *size == 0)
+        {
+            Some(entry) => entry.2 = section.size,
+            None => res.push((section.name.clone(), 0, section.size)),
+        }
+    }
+    res
+}
+
+/// Counts the items in each index space before and after isolation.
+pub fn index_space_counts(module: &ParsedModule, all_uses: &Uses) -> Vec<schema::IndexSpaceReport> {
+    let spaces = [
+        ("types", module.types.len(), all_uses.live_types.len()),
+        ("funcs", module.func_types.len(), all_uses.live_funcs.len()),
+        (
+            "tables",
+            module.table_types.len(),
+            all_uses.live_tables.len(),
+        ),
+        (
+            "globals",
+            module.global_types.len(),
+            all_uses.live_globals.len(),
+        ),
+        (
+            "memories",
+            module.memory_types.len(),
+            all_uses.live_memories.len(),
+        ),
+        ("datas", module.datas.len(), all_uses.live_datas.len()),
+        ("elems", module.elems.len(), all_uses.live_elems.len()),
+        ("tags", module.tag_types.len(), all_uses.live_tags.len()),
+    ];
+    spaces
+        .into_iter()
+        .map(
+            |(name, original_count, isolated_count)| schema::IndexSpaceReport {
+                name: name.to_string(),
+                original_count,
+                isolated_count,
+            },
+        )
+        .collect()
+}
+
+/// Compares the sizes of sections and index spaces before and after isolation.
+pub fn build_report(
+    original: &[u8],
+    isolated: &[u8],
+    module: &ParsedModule,
+    all_uses: &Uses,
+) -> Result<schema::Report> {
+    let sections = compare_section_sizes(&section_sizes(original)?, &section_sizes(isolated)?)
+        .into_iter()
+        .map(
+            |(name, original_size, isolated_size)| schema::SectionReport {
+                name,
+                original_size,
+                isolated_size,
+            },
+        )
+        .collect();
+
+    let index_spaces = index_space_counts(module, all_uses);
+
+    // Engine bugs with multiple memories or tables depend on exactly which functions touch
+    // which index space, so look at the isolated module itself rather than the original.
+    let isolated_module = ParsedModule::parse(isolated)?;
+    let mut memories: Vec<schema::IndexUsers> = (0..isolated_module.memory_types.len() as u32)
+        .map(|index| schema::IndexUsers {
+            index,
+            funcs: vec![],
+        })
+        .collect();
+    let mut tables: Vec<schema::IndexUsers> = (0..isolated_module.table_types.len() as u32)
+        .map(|index| schema::IndexUsers {
+            index,
+            funcs: vec![],
+        })
+        .collect();
+    for (i, func) in isolated_module.defined_funcs.iter().enumerate() {
+        let idx = isolated_module.num_imported_functions + i as u32;
+        let mut uses = Uses::default();
+        for instr in func.operators()? {
+            uses.merge(get_instr_uses(&instr?));
+        }
+        for mem_idx in uses.live_memories.iter() {
+            memories[mem_idx as usize].funcs.push(idx);
+        }
+        for table_idx in uses.live_tables.iter() {
+            tables[table_idx as usize].funcs.push(idx);
+        }
+    }
+
+    Ok(schema::Report {
+        schema: SCHEMA_VERSION,
+        sections,
+        index_spaces,
+        original_size: original.len(),
+        isolated_size: isolated.len(),
+        memories,
+        tables,
+        groups: vec![],
+    })
+}
+
+/// Attributes the kept items to the groups of roots that need them, as found by
+/// [`crate::liveness::find_group_uses`]. Sizes count function bodies and segments, the only items
+/// with a size of their own.
+pub fn build_group_reports(
+    module: &ParsedModule,
+    groups: &[RootGroup],
+    group_uses: &[Uses],
+) -> Vec<schema::GroupReport> {
+    groups
+        .iter()
+        .zip(group_uses)
+        .enumerate()
+        .map(|(i, (group, uses))| {
+            let items = live_items(uses);
+            let exclusive: Vec<_> = items
+                .iter()
+                .filter(|item| {
+                    group_uses
+                        .iter()
+                        .enumerate()
+                        .all(|(j, other)| i == j || !other.contains(**item))
+                })
+                .collect();
+            let size_of = |item: &WorkItem| module.item_size(*item).unwrap_or(0);
+            schema::GroupReport {
+                name: group.name.clone(),
+                roots: group
+                    .roots
+                    .iter()
+                    .map(|root| schema::Item::new(module, *root))
+                    .collect(),
+                exclusive_items: exclusive.len(),
+                size: items.iter().map(size_of).sum(),
+                exclusive_size: exclusive.into_iter().map(size_of).sum(),
+                items: items
+                    .iter()
+                    .map(|item| schema::Item::new(module, *item))
+                    .collect(),
+            }
+        })
+        .collect()
+}
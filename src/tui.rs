@@ -0,0 +1,494 @@
+//! An interactive explorer for picking what to isolate from an unfamiliar module. Every index space
+//! is shown as a tree of items with their names and sizes, the selected item's dependencies can be
+//! followed in both directions, and marking an item shows everything it would keep.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use rayon::prelude::*;
+
+use crate::liveness::{get_item_uses, live_items, LivenessOptions, WorkItem};
+use crate::module::ParsedModule;
+use crate::wat;
+
+/// The index spaces, in the order they are shown, with how to make an item of each.
+const SPACES: [(&str, MakeItem); 8] = [
+    ("Types", WorkItem::Type),
+    ("Functions", WorkItem::Func),
+    ("Tables", WorkItem::Table),
+    ("Memories", WorkItem::Memory),
+    ("Globals", WorkItem::Global),
+    ("Tags", WorkItem::Tag),
+    ("Element segments", WorkItem::Elem),
+    ("Data segments", WorkItem::Data),
+];
+
+type MakeItem = fn(u32) -> WorkItem;
+
+const HELP: &str = "space: mark  enter: expand/links  tab: switch pane  /: search  n: next match  e: emit  q: quit";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Row {
+    Space(usize),
+    Item(WorkItem),
+}
+
+#[derive(PartialEq)]
+enum Focus {
+    Tree,
+    Links,
+    Search,
+}
+
+struct Explorer<'a> {
+    module: &'a ParsedModule<'a>,
+    /// The items of each index space, in the same order as [`SPACES`].
+    spaces: Vec<Vec<WorkItem>>,
+    /// What each item directly refers to.
+    uses: HashMap<WorkItem, Vec<WorkItem>>,
+    /// What directly refers to each item.
+    users: HashMap<WorkItem, Vec<WorkItem>>,
+    marked: Vec<WorkItem>,
+    /// Everything the marked items need, including themselves.
+    kept: HashSet<WorkItem>,
+    expanded: [bool; SPACES.len()],
+    rows: Vec<Row>,
+    cursor: usize,
+    scroll: usize,
+    focus: Focus,
+    link_cursor: usize,
+    search: String,
+    message: Option<String>,
+}
+
+/// Runs the explorer until the user quits, returning the marked items if they asked to emit a
+/// module with them, or `None` if they quit without emitting.
+pub fn explore(module: &ParsedModule, options: &LivenessOptions) -> Result<Option<Vec<WorkItem>>> {
+    let mut explorer = Explorer::new(module, options)?;
+    let mut terminal = ratatui::init();
+    let res = explorer.run(&mut terminal);
+    ratatui::restore();
+    res
+}
+
+impl<'a> Explorer<'a> {
+    fn new(module: &'a ParsedModule<'a>, options: &LivenessOptions) -> Result<Self> {
+        let all_items = module.all_items();
+        let uses: HashMap<WorkItem, Vec<WorkItem>> = all_items
+            .par_iter()
+            .map(|item| {
+                let uses = get_item_uses(module, *item, options)?;
+                let used = live_items(&uses)
+                    .into_iter()
+                    .filter(|used| used != item)
+                    .collect();
+                Ok((*item, used))
+            })
+            .collect::<Result<_>>()?;
+        let mut users: HashMap<WorkItem, Vec<WorkItem>> = HashMap::new();
+        for item in &all_items {
+            for used in &uses[item] {
+                users.entry(*used).or_default().push(*item);
+            }
+        }
+        let spaces = SPACES
+            .iter()
+            .map(|(_, make)| {
+                all_items
+                    .iter()
+                    .copied()
+                    .filter(|item| item.kind_name() == make(0).kind_name())
+                    .collect()
+            })
+            .collect();
+
+        let mut res = Explorer {
+            module,
+            spaces,
+            uses,
+            users,
+            marked: vec![],
+            kept: HashSet::new(),
+            expanded: [false; SPACES.len()],
+            rows: vec![],
+            cursor: 0,
+            scroll: 0,
+            focus: Focus::Tree,
+            link_cursor: 0,
+            search: String::new(),
+            message: None,
+        };
+        res.build_rows();
+        Ok(res)
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<Option<Vec<WorkItem>>> {
+        loop {
+            terminal.draw(|frame| self.render(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            self.message = None;
+            match self.focus {
+                Focus::Search => match key.code {
+                    KeyCode::Enter => {
+                        self.focus = Focus::Tree;
+                        self.find_next();
+                    }
+                    KeyCode::Esc => {
+                        self.focus = Focus::Tree;
+                        self.search.clear();
+                    }
+                    KeyCode::Backspace => {
+                        self.search.pop();
+                    }
+                    KeyCode::Char(c) => self.search.push(c),
+                    _ => {}
+                },
+                Focus::Links => match key.code {
+                    KeyCode::Up | KeyCode::Char('k') => {
+                        self.link_cursor = self.link_cursor.saturating_sub(1)
+                    }
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        let len = self.links().len();
+                        self.link_cursor = (self.link_cursor + 1).min(len.saturating_sub(1));
+                    }
+                    KeyCode::Enter => {
+                        if let Some((_, item)) = self.links().get(self.link_cursor) {
+                            self.go_to(*item);
+                        }
+                        self.focus = Focus::Tree;
+                    }
+                    KeyCode::Tab | KeyCode::Esc | KeyCode::Left | KeyCode::Char('h') => {
+                        self.focus = Focus::Tree
+                    }
+                    KeyCode::Char('q') => return Ok(None),
+                    _ => {}
+                },
+                Focus::Tree => match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+                    KeyCode::Up | KeyCode::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        self.cursor = (self.cursor + 1).min(self.rows.len() - 1)
+                    }
+                    KeyCode::PageUp => self.cursor = self.cursor.saturating_sub(20),
+                    KeyCode::PageDown => self.cursor = (self.cursor + 20).min(self.rows.len() - 1),
+                    KeyCode::Home | KeyCode::Char('g') => self.cursor = 0,
+                    KeyCode::End | KeyCode::Char('G') => self.cursor = self.rows.len() - 1,
+                    KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => {
+                        match self.rows[self.cursor] {
+                            Row::Space(space) => self.set_expanded(space, true),
+                            Row::Item(_) => self.focus_links(),
+                        }
+                    }
+                    KeyCode::Left | KeyCode::Char('h') => {
+                        let space = match self.rows[self.cursor] {
+                            Row::Space(space) => space,
+                            Row::Item(item) => space_of(item),
+                        };
+                        self.set_expanded(space, false);
+                    }
+                    KeyCode::Tab => self.focus_links(),
+                    KeyCode::Char(' ') => {
+                        if let Row::Item(item) = self.rows[self.cursor] {
+                            self.toggle_mark(item);
+                        }
+                    }
+                    KeyCode::Char('/') => {
+                        self.search.clear();
+                        self.focus = Focus::Search;
+                    }
+                    KeyCode::Char('n') => self.find_next(),
+                    KeyCode::Char('e') => {
+                        if self.marked.is_empty() {
+                            self.message =
+                                Some("Mark at least one item with space first.".to_string());
+                        } else {
+                            return Ok(Some(self.marked.clone()));
+                        }
+                    }
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    fn build_rows(&mut self) {
+        let selected = self.rows.get(self.cursor).copied();
+        self.rows.clear();
+        for (space, items) in self.spaces.iter().enumerate() {
+            self.rows.push(Row::Space(space));
+            if self.expanded[space] {
+                self.rows.extend(items.iter().map(|item| Row::Item(*item)));
+            }
+        }
+        if let Some(selected) = selected {
+            self.cursor = self
+                .rows
+                .iter()
+                .position(|row| *row == selected)
+                .unwrap_or(0);
+        }
+    }
+
+    fn set_expanded(&mut self, space: usize, expanded: bool) {
+        if let Some(Row::Item(_)) = self.rows.get(self.cursor) {
+            if !expanded {
+                self.cursor = self
+                    .rows
+                    .iter()
+                    .position(|row| *row == Row::Space(space))
+                    .unwrap_or(0);
+            }
+        }
+        self.expanded[space] = expanded;
+        self.build_rows();
+    }
+
+    /// Moves the cursor to an item, expanding its index space if needed.
+    fn go_to(&mut self, item: WorkItem) {
+        self.expanded[space_of(item)] = true;
+        self.build_rows();
+        if let Some(row) = self.rows.iter().position(|row| *row == Row::Item(item)) {
+            self.cursor = row;
+        }
+    }
+
+    fn focus_links(&mut self) {
+        if matches!(self.rows[self.cursor], Row::Item(_)) && !self.links().is_empty() {
+            self.link_cursor = 0;
+            self.focus = Focus::Links;
+        }
+    }
+
+    fn toggle_mark(&mut self, item: WorkItem) {
+        match self.marked.iter().position(|marked| *marked == item) {
+            Some(i) => {
+                self.marked.remove(i);
+            }
+            None => self.marked.push(item),
+        }
+        // Following the precomputed uses is much faster than finding what is live from scratch.
+        self.kept.clear();
+        let mut queue = self.marked.clone();
+        while let Some(item) = queue.pop() {
+            if self.kept.insert(item) {
+                queue.extend(self.uses[&item].iter().copied());
+            }
+        }
+    }
+
+    /// Finds the next item after the cursor whose description contains the search text.
+    fn find_next(&mut self) {
+        if self.search.is_empty() {
+            return;
+        }
+        let needle = self.search.to_lowercase();
+        let all_items = self.module.all_items();
+        let current = match self.rows.get(self.cursor) {
+            Some(Row::Item(item)) => all_items
+                .iter()
+                .position(|other| other == item)
+                .map_or(0, |i| i + 1),
+            _ => 0,
+        };
+        let found = all_items[current..]
+            .iter()
+            .chain(&all_items[..current])
+            .find(|item| {
+                self.module
+                    .describe(**item)
+                    .to_lowercase()
+                    .contains(&needle)
+            })
+            .copied();
+        match found {
+            Some(item) => self.go_to(item),
+            None => self.message = Some(format!("Nothing matches \"{}\".", self.search)),
+        }
+    }
+
+    /// The items the selected item refers to, then the items that refer to it.
+    fn links(&self) -> Vec<(&'static str, WorkItem)> {
+        let Some(Row::Item(item)) = self.rows.get(self.cursor) else {
+            return vec![];
+        };
+        let uses = self.uses[item].iter().map(|used| ("uses", *used));
+        let users = self
+            .users
+            .get(item)
+            .into_iter()
+            .flatten()
+            .map(|user| ("used by", *user));
+        uses.chain(users).collect()
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+        let [tree, details] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(main);
+        self.render_tree(frame, tree);
+        self.render_details(frame, details);
+
+        let kept_size: usize = self
+            .kept
+            .iter()
+            .filter_map(|item| self.module.item_size(*item))
+            .sum();
+        let text = match (&self.focus, &self.message) {
+            (Focus::Search, _) => format!("/{}", self.search),
+            (_, Some(message)) => message.clone(),
+            _ => format!(
+                "{} marked, {} kept ({} bytes of code and segments)  |  {}",
+                self.marked.len(),
+                self.kept.len(),
+                kept_size,
+                HELP
+            ),
+        };
+        frame.render_widget(Paragraph::new(text).reversed(), status);
+    }
+
+    fn render_tree(&mut self, frame: &mut Frame, area: Rect) {
+        // Only the visible rows are built, since a big module has hundreds of thousands.
+        let height = area.height.saturating_sub(2) as usize;
+        if self.cursor < self.scroll {
+            self.scroll = self.cursor;
+        } else if height > 0 && self.cursor >= self.scroll + height {
+            self.scroll = self.cursor + 1 - height;
+        }
+        let items: Vec<ListItem> = self.rows[self.scroll..]
+            .iter()
+            .take(height)
+            .map(|row| match row {
+                Row::Space(space) => {
+                    let (name, _) = SPACES[*space];
+                    let kept = self.spaces[*space]
+                        .iter()
+                        .filter(|item| self.kept.contains(item))
+                        .count();
+                    ListItem::new(format!(
+                        "{} {} ({}, {} kept)",
+                        if self.expanded[*space] { "▾" } else { "▸" },
+                        name,
+                        self.spaces[*space].len(),
+                        kept
+                    ))
+                    .add_modifier(Modifier::BOLD)
+                }
+                Row::Item(item) => {
+                    let mark = if self.marked.contains(item) {
+                        "[x]"
+                    } else if self.kept.contains(item) {
+                        "[+]"
+                    } else {
+                        "[ ]"
+                    };
+                    let size = match self.module.item_size(*item) {
+                        Some(size) => format!("  {} B", size),
+                        None => String::new(),
+                    };
+                    ListItem::new(format!(
+                        "  {} {}{}",
+                        mark,
+                        self.module.describe(*item),
+                        size
+                    ))
+                }
+            })
+            .collect();
+        let highlight = if self.focus == Focus::Links {
+            Style::new().add_modifier(Modifier::UNDERLINED)
+        } else {
+            Style::new().reversed()
+        };
+        let list = List::new(items)
+            .block(Block::bordered().title(" Items "))
+            .highlight_style(highlight);
+        let mut state = ListState::default().with_selected(Some(self.cursor - self.scroll));
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+
+    fn render_details(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(" Details ");
+        let Some(Row::Item(item)) = self.rows.get(self.cursor).copied() else {
+            let text = "Select an item to see what it uses and what uses it.\n\n".to_string()
+                + "[x] marks an item to keep, and [+] an item a marked item needs.";
+            frame.render_widget(Paragraph::new(text).block(block), area);
+            return;
+        };
+        let module = self.module;
+
+        let mut lines: Vec<Line> = vec![Line::from(module.describe(item)).bold()];
+        if let Some(ty) = wat::item_type(module, item) {
+            lines.push(Line::from(format!("Type: {}", ty)));
+        }
+        if let Some(size) = module.item_size(item) {
+            lines.push(Line::from(format!("Size: {} bytes", size)));
+        }
+        if let Some(import) = module.import_of(item) {
+            lines.push(Line::from(format!(
+                "Imported from {}.{}",
+                import.module, import.name
+            )));
+        }
+        for name in module.exports_of(item) {
+            lines.push(Line::from(format!("Exported as \"{}\"", name)));
+        }
+        lines.push(Line::from(if self.marked.contains(&item) {
+            "Marked to keep"
+        } else if self.kept.contains(&item) {
+            "Kept, because a marked item needs it"
+        } else {
+            "Not kept"
+        }));
+        lines.push(Line::from(""));
+
+        let links = self.links();
+        if links.is_empty() {
+            lines.push(Line::from("Nothing uses this item, and it uses nothing."));
+        }
+        let header = lines.len();
+        for (i, (direction, link)) in links.iter().enumerate() {
+            let mut line = Line::from(vec![
+                Span::raw(format!("{:>8} ", direction)),
+                Span::raw(module.describe(*link)),
+            ]);
+            if self.focus == Focus::Links && i == self.link_cursor {
+                line = line.reversed();
+            }
+            lines.push(line);
+        }
+
+        // Keep the selected link in view.
+        let height = area.height.saturating_sub(2) as usize;
+        let scroll = match self.focus {
+            Focus::Links => (header + self.link_cursor + 1).saturating_sub(height),
+            _ => 0,
+        };
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(block)
+                .scroll((scroll as u16, 0)),
+            area,
+        );
+    }
+}
+
+fn space_of(item: WorkItem) -> usize {
+    SPACES
+        .iter()
+        .position(|(_, make)| make(0).kind_name() == item.kind_name())
+        .unwrap_or(0)
+}
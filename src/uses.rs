@@ -1,77 +1,83 @@
 use anyhow::Result;
 use wasmparser::{
     ArrayType, BlockType, Catch, CompositeInnerType, ConstExpr, FieldType, FuncType, GlobalType,
-    HeapType, MemArg, Operator, RefType, StorageType, StructType, SubType, TableType, TagType,
-    ValType,
+    Handle, HeapType, MemArg, Operator, RefType, ResumeTable, StorageType, StructType, SubType,
+    TableType, TagType, ValType,
 };
 
-#[derive(Default, Debug)]
+use crate::error::{Error, ErrorKind};
+use crate::features::operator_name;
+use crate::index_set::IndexSet;
+use crate::liveness::WorkItem;
+
+#[derive(Clone, Default, Debug)]
+#[cfg_attr(feature = "serde-impls", derive(serde::Serialize, serde::Deserialize))]
 pub struct Uses {
-    pub live_types: Vec<u32>,
-    pub live_funcs: Vec<u32>,
-    pub live_tables: Vec<u32>,
-    pub live_globals: Vec<u32>,
-    pub live_memories: Vec<u32>,
-    pub live_datas: Vec<u32>,
-    pub live_elems: Vec<u32>,
-    pub live_tags: Vec<u32>,
+    pub live_types: IndexSet,
+    pub live_funcs: IndexSet,
+    pub live_tables: IndexSet,
+    pub live_globals: IndexSet,
+    pub live_memories: IndexSet,
+    pub live_datas: IndexSet,
+    pub live_elems: IndexSet,
+    pub live_tags: IndexSet,
 }
 
 impl Uses {
     pub fn single_type(idx: u32) -> Uses {
-        return Self {
-            live_types: vec![idx],
+        Self {
+            live_types: IndexSet::from_iter([idx]),
             ..Default::default()
-        };
+        }
     }
 
     pub fn single_func(idx: u32) -> Uses {
-        return Self {
-            live_funcs: vec![idx],
+        Self {
+            live_funcs: IndexSet::from_iter([idx]),
             ..Default::default()
-        };
+        }
     }
 
     pub fn single_table(idx: u32) -> Uses {
-        return Self {
-            live_tables: vec![idx],
+        Self {
+            live_tables: IndexSet::from_iter([idx]),
             ..Default::default()
-        };
+        }
     }
 
     pub fn single_global(idx: u32) -> Uses {
-        return Self {
-            live_globals: vec![idx],
+        Self {
+            live_globals: IndexSet::from_iter([idx]),
             ..Default::default()
-        };
+        }
     }
 
     pub fn single_memory(idx: u32) -> Uses {
-        return Self {
-            live_memories: vec![idx],
+        Self {
+            live_memories: IndexSet::from_iter([idx]),
             ..Default::default()
-        };
+        }
     }
 
     pub fn single_data(idx: u32) -> Uses {
-        return Self {
-            live_datas: vec![idx],
+        Self {
+            live_datas: IndexSet::from_iter([idx]),
             ..Default::default()
-        };
+        }
     }
 
     pub fn single_elem(idx: u32) -> Uses {
-        return Self {
-            live_elems: vec![idx],
+        Self {
+            live_elems: IndexSet::from_iter([idx]),
             ..Default::default()
-        };
+        }
     }
 
     pub fn single_tag(idx: u32) -> Uses {
-        return Self {
-            live_tags: vec![idx],
+        Self {
+            live_tags: IndexSet::from_iter([idx]),
             ..Default::default()
-        };
+        }
     }
 
     pub fn all<I>(useses: I) -> Uses
@@ -85,37 +91,57 @@ impl Uses {
         res
     }
 
-    pub fn merge(&mut self, mut other: Uses) {
-        Self::append_and_dedup(&mut self.live_types, &mut other.live_types);
-        Self::append_and_dedup(&mut self.live_funcs, &mut other.live_funcs);
-        Self::append_and_dedup(&mut self.live_tables, &mut other.live_tables);
-        Self::append_and_dedup(&mut self.live_globals, &mut other.live_globals);
-        Self::append_and_dedup(&mut self.live_memories, &mut other.live_memories);
-        Self::append_and_dedup(&mut self.live_datas, &mut other.live_datas);
-        Self::append_and_dedup(&mut self.live_elems, &mut other.live_elems);
-        Self::append_and_dedup(&mut self.live_tags, &mut other.live_tags);
+    pub fn contains(&self, item: WorkItem) -> bool {
+        let (live, idx) = match item {
+            WorkItem::Type(idx) => (&self.live_types, idx),
+            WorkItem::Func(idx) => (&self.live_funcs, idx),
+            WorkItem::Table(idx) => (&self.live_tables, idx),
+            WorkItem::Global(idx) => (&self.live_globals, idx),
+            WorkItem::Memory(idx) => (&self.live_memories, idx),
+            WorkItem::Data(idx) => (&self.live_datas, idx),
+            WorkItem::Elem(idx) => (&self.live_elems, idx),
+            WorkItem::Tag(idx) => (&self.live_tags, idx),
+        };
+        live.contains(idx)
     }
 
-    fn append_and_dedup(vec: &mut Vec<u32>, other: &mut Vec<u32>) {
-        vec.append(other);
-        vec.sort();
-        vec.dedup();
+    /// Adds a single item, returning whether it was newly added.
+    pub fn insert(&mut self, item: WorkItem) -> bool {
+        let (live, idx) = match item {
+            WorkItem::Type(idx) => (&mut self.live_types, idx),
+            WorkItem::Func(idx) => (&mut self.live_funcs, idx),
+            WorkItem::Table(idx) => (&mut self.live_tables, idx),
+            WorkItem::Global(idx) => (&mut self.live_globals, idx),
+            WorkItem::Memory(idx) => (&mut self.live_memories, idx),
+            WorkItem::Data(idx) => (&mut self.live_datas, idx),
+            WorkItem::Elem(idx) => (&mut self.live_elems, idx),
+            WorkItem::Tag(idx) => (&mut self.live_tags, idx),
+        };
+        live.insert(idx)
+    }
+
+    pub fn merge(&mut self, other: Uses) {
+        self.live_types.union_with(&other.live_types);
+        self.live_funcs.union_with(&other.live_funcs);
+        self.live_tables.union_with(&other.live_tables);
+        self.live_globals.union_with(&other.live_globals);
+        self.live_memories.union_with(&other.live_memories);
+        self.live_datas.union_with(&other.live_datas);
+        self.live_elems.union_with(&other.live_elems);
+        self.live_tags.union_with(&other.live_tags);
     }
 }
 
 pub fn get_type_uses(ty: &SubType) -> Uses {
     let mut res = Uses::default();
     if let Some(idx) = &ty.supertype_idx {
-        res.merge(match idx.unpack() {
-            wasmparser::UnpackedIndex::Module(idx) => Uses::single_type(idx),
-            _ => todo!(),
-        });
+        res.merge(get_unpacked_index_uses(idx.unpack()));
     }
     res.merge(match &ty.composite_type.inner {
         CompositeInnerType::Func(func_type) => get_functype_uses(func_type),
         CompositeInnerType::Array(array_type) => get_arraytype_uses(array_type),
         CompositeInnerType::Struct(struct_type) => get_structtype_uses(struct_type),
-        CompositeInnerType::Cont(_) => todo!(),
+        CompositeInnerType::Cont(cont_type) => get_unpacked_index_uses(cont_type.0.unpack()),
     });
     res
 }
@@ -174,16 +200,22 @@ pub fn get_valtype_uses(ty: &ValType) -> Uses {
 }
 
 pub fn get_reftype_uses(ty: &RefType) -> Uses {
-    return get_heaptype_uses(&ty.heap_type());
+    get_heaptype_uses(&ty.heap_type())
 }
 
 pub fn get_heaptype_uses(ty: &HeapType) -> Uses {
     match ty {
         wasmparser::HeapType::Abstract { .. } => Uses::default(),
-        wasmparser::HeapType::Concrete(idx) => match idx {
-            wasmparser::UnpackedIndex::Module(idx) => Uses::single_type(*idx),
-            _ => todo!(),
-        },
+        wasmparser::HeapType::Concrete(idx) => get_unpacked_index_uses(*idx),
+    }
+}
+
+/// Type indices relative to a rec group only appear in types that a validator has canonicalized,
+/// never in types read straight from a module, so only module indices count as uses.
+fn get_unpacked_index_uses(idx: wasmparser::UnpackedIndex) -> Uses {
+    match idx {
+        wasmparser::UnpackedIndex::Module(idx) => Uses::single_type(idx),
+        _ => Uses::default(),
     }
 }
 
@@ -196,7 +228,7 @@ pub fn get_blocktype_uses(blockty: &BlockType) -> Uses {
 }
 
 pub fn get_memarg_uses(memarg: &MemArg) -> Uses {
-    return Uses::single_memory(memarg.memory);
+    Uses::single_memory(memarg.memory)
 }
 
 pub fn get_catch_uses(catch: &Catch) -> Uses {
@@ -208,17 +240,51 @@ pub fn get_catch_uses(catch: &Catch) -> Uses {
     }
 }
 
+pub fn get_resume_table_uses(table: &ResumeTable) -> Uses {
+    let mut res = Uses::default();
+    for handle in &table.handlers {
+        res.merge(match handle {
+            Handle::OnLabel { tag, label: _ } => Uses::single_tag(*tag),
+            Handle::OnSwitch { tag } => Uses::single_tag(*tag),
+        });
+    }
+    res
+}
+
+/// Finds the uses of a constant expression, like a global initializer, a table's init expression,
+/// or a segment offset. These can do arithmetic with the extended-const proposal and build GC
+/// objects, which refer to types, functions, and globals like any other instruction does.
+///
+/// Unlike in function bodies, an instruction this tool doesn't know is an error, since there is no
+/// way to preserve it safely.
 pub fn get_constexpr_uses(expr: &ConstExpr) -> Result<Uses> {
     let mut res = Uses::default();
     let r = expr.get_operators_reader();
     for instr in r {
-        res.merge(get_instr_uses(&instr?));
+        let instr = instr?;
+        let Some(uses) = get_known_instr_uses(&instr) else {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "a constant expression uses {}, which may refer to items this tool does not know about",
+                    operator_name(&instr)
+                ),
+            )
+            .into());
+        };
+        res.merge(uses);
     }
     Ok(res)
 }
 
 pub fn get_instr_uses(instr: &Operator<'_>) -> Uses {
-    match instr {
+    get_known_instr_uses(instr).unwrap_or_default()
+}
+
+/// Finds the uses of an instruction, or returns `None` if this tool doesn't know which indices
+/// the instruction refers to. This happens for instructions from proposals newer than this code.
+pub fn get_known_instr_uses(instr: &Operator<'_>) -> Option<Uses> {
+    Some(match instr {
         Operator::Unreachable => Uses::default(),
         Operator::Nop => Uses::default(),
         Operator::Block { blockty } => get_blocktype_uses(blockty),
@@ -235,8 +301,8 @@ pub fn get_instr_uses(instr: &Operator<'_>) -> Uses {
             type_index,
             table_index,
         } => Uses {
-            live_types: vec![*type_index],
-            live_tables: vec![*table_index],
+            live_types: IndexSet::from_iter([*type_index]),
+            live_tables: IndexSet::from_iter([*table_index]),
             ..Default::default()
         },
         Operator::Drop => Uses::default(),
@@ -432,16 +498,16 @@ pub fn get_instr_uses(instr: &Operator<'_>) -> Uses {
             array_type_index,
             array_data_index,
         } => Uses {
-            live_types: vec![*array_type_index],
-            live_datas: vec![*array_data_index],
+            live_types: IndexSet::from_iter([*array_type_index]),
+            live_datas: IndexSet::from_iter([*array_data_index]),
             ..Default::default()
         },
         Operator::ArrayNewElem {
             array_type_index,
             array_elem_index,
         } => Uses {
-            live_types: vec![*array_type_index],
-            live_elems: vec![*array_elem_index],
+            live_types: IndexSet::from_iter([*array_type_index]),
+            live_elems: IndexSet::from_iter([*array_elem_index]),
             ..Default::default()
         },
         Operator::ArrayGet { array_type_index } => Uses::single_type(*array_type_index),
@@ -454,23 +520,23 @@ pub fn get_instr_uses(instr: &Operator<'_>) -> Uses {
             array_type_index_dst,
             array_type_index_src,
         } => Uses {
-            live_types: vec![*array_type_index_dst, *array_type_index_src],
+            live_types: IndexSet::from_iter([*array_type_index_dst, *array_type_index_src]),
             ..Default::default()
         },
         Operator::ArrayInitData {
             array_type_index,
             array_data_index,
         } => Uses {
-            live_types: vec![*array_type_index],
-            live_datas: vec![*array_data_index],
+            live_types: IndexSet::from_iter([*array_type_index]),
+            live_datas: IndexSet::from_iter([*array_data_index]),
             ..Default::default()
         },
         Operator::ArrayInitElem {
             array_type_index,
             array_elem_index,
         } => Uses {
-            live_types: vec![*array_type_index],
-            live_elems: vec![*array_elem_index],
+            live_types: IndexSet::from_iter([*array_type_index]),
+            live_elems: IndexSet::from_iter([*array_elem_index]),
             ..Default::default()
         },
         Operator::RefTestNonNull { hty } => get_heaptype_uses(hty),
@@ -507,19 +573,19 @@ pub fn get_instr_uses(instr: &Operator<'_>) -> Uses {
         Operator::I64TruncSatF64S => Uses::default(),
         Operator::I64TruncSatF64U => Uses::default(),
         Operator::MemoryInit { data_index, mem } => Uses {
-            live_datas: vec![*data_index],
-            live_memories: vec![*mem],
+            live_datas: IndexSet::from_iter([*data_index]),
+            live_memories: IndexSet::from_iter([*mem]),
             ..Default::default()
         },
         Operator::DataDrop { data_index } => Uses::single_data(*data_index),
         Operator::MemoryCopy { dst_mem, src_mem } => Uses {
-            live_memories: vec![*dst_mem, *src_mem],
+            live_memories: IndexSet::from_iter([*dst_mem, *src_mem]),
             ..Default::default()
         },
         Operator::MemoryFill { mem } => Uses::single_memory(*mem),
         Operator::TableInit { elem_index, table } => Uses {
-            live_elems: vec![*elem_index],
-            live_tables: vec![*table],
+            live_elems: IndexSet::from_iter([*elem_index]),
+            live_tables: IndexSet::from_iter([*table]),
             ..Default::default()
         },
         Operator::ElemDrop { elem_index } => Uses::single_elem(*elem_index),
@@ -527,7 +593,7 @@ pub fn get_instr_uses(instr: &Operator<'_>) -> Uses {
             dst_table,
             src_table,
         } => Uses {
-            live_tables: vec![*dst_table, *src_table],
+            live_tables: IndexSet::from_iter([*dst_table, *src_table]),
             ..Default::default()
         },
         Operator::TypedSelect { ty } => get_valtype_uses(ty),
@@ -544,8 +610,8 @@ pub fn get_instr_uses(instr: &Operator<'_>) -> Uses {
             type_index,
             table_index,
         } => Uses {
-            live_types: vec![*type_index],
-            live_tables: vec![*table_index],
+            live_types: IndexSet::from_iter([*type_index]),
+            live_tables: IndexSet::from_iter([*table_index]),
             ..Default::default()
         },
         Operator::MemoryDiscard { mem } => Uses::single_memory(*mem),
@@ -1030,18 +1096,48 @@ pub fn get_instr_uses(instr: &Operator<'_>) -> Uses {
         Operator::BrOnNull { relative_depth: _ } => Uses::default(),
         Operator::BrOnNonNull { relative_depth: _ } => Uses::default(),
 
-        Operator::ContNew { .. } => todo!(),
-        Operator::ContBind { .. } => todo!(),
-        Operator::Suspend { .. } => todo!(),
-        Operator::Resume { .. } => todo!(),
-        Operator::ResumeThrow { .. } => todo!(),
-        Operator::Switch { .. } => todo!(),
+        Operator::ContNew { cont_type_index } => Uses::single_type(*cont_type_index),
+        Operator::ContBind {
+            argument_index,
+            result_index,
+        } => {
+            let mut res = Uses::single_type(*argument_index);
+            res.merge(Uses::single_type(*result_index));
+            res
+        }
+        Operator::Suspend { tag_index } => Uses::single_tag(*tag_index),
+        Operator::Resume {
+            cont_type_index,
+            resume_table,
+        } => {
+            let mut res = Uses::single_type(*cont_type_index);
+            res.merge(get_resume_table_uses(resume_table));
+            res
+        }
+        Operator::ResumeThrow {
+            cont_type_index,
+            tag_index,
+            resume_table,
+        } => {
+            let mut res = Uses::single_type(*cont_type_index);
+            res.merge(Uses::single_tag(*tag_index));
+            res.merge(get_resume_table_uses(resume_table));
+            res
+        }
+        Operator::Switch {
+            cont_type_index,
+            tag_index,
+        } => {
+            let mut res = Uses::single_type(*cont_type_index);
+            res.merge(Uses::single_tag(*tag_index));
+            res
+        }
 
         Operator::I64Add128 => Uses::default(),
         Operator::I64Sub128 => Uses::default(),
         Operator::I64MulWideS => Uses::default(),
         Operator::I64MulWideU => Uses::default(),
 
-        _ => Uses::default(),
-    }
+        _ => return None,
+    })
 }
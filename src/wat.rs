@@ -0,0 +1,172 @@
+//! Types written the way they look in the text format, e.g. `(func (param i32) (result i32))`, for
+//! messages that people read.
+
+use wasmparser::{
+    CompositeInnerType, FieldType, FuncType, GlobalType, HeapType, MemoryType, RefType,
+    StorageType, SubType, TableType, UnpackedIndex, ValType,
+};
+
+use crate::liveness::WorkItem;
+use crate::module::ParsedModule;
+
+pub fn val_type(ty: &ValType) -> String {
+    match ty {
+        ValType::Ref(ty) => ref_type(ty),
+        _ => ty.to_string(),
+    }
+}
+
+pub fn ref_type(ty: &RefType) -> String {
+    match (ty.heap_type(), ty.is_nullable()) {
+        (
+            HeapType::Abstract {
+                shared: false,
+                ty: abstract_ty,
+            },
+            true,
+        ) => format!("{}ref", abstract_name(abstract_ty)),
+        (heap_type, nullable) => format!(
+            "(ref {}{})",
+            if nullable { "null " } else { "" },
+            heap(&heap_type)
+        ),
+    }
+}
+
+fn heap(ty: &HeapType) -> String {
+    match ty {
+        HeapType::Abstract { shared: false, ty } => abstract_name(*ty),
+        HeapType::Abstract { shared: true, ty } => format!("(shared {})", abstract_name(*ty)),
+        HeapType::Concrete(UnpackedIndex::Module(idx)) => idx.to_string(),
+        HeapType::Concrete(idx) => idx.to_string(),
+    }
+}
+
+/// The abstract heap types are named the same in the text format as their variants, just in
+/// lowercase, e.g. `noextern`.
+fn abstract_name(ty: wasmparser::AbstractHeapType) -> String {
+    format!("{:?}", ty).to_lowercase()
+}
+
+fn list(keyword: &str, types: &[ValType]) -> String {
+    if types.is_empty() {
+        return String::new();
+    }
+    let types: Vec<String> = types.iter().map(val_type).collect();
+    format!(" ({} {})", keyword, types.join(" "))
+}
+
+pub fn func_type(ty: &FuncType) -> String {
+    format!(
+        "(func{}{})",
+        list("param", ty.params()),
+        list("result", ty.results())
+    )
+}
+
+fn field_type(ty: &FieldType) -> String {
+    let storage = match ty.element_type {
+        StorageType::I8 => "i8".to_string(),
+        StorageType::I16 => "i16".to_string(),
+        StorageType::Val(ty) => val_type(&ty),
+    };
+    if ty.mutable {
+        format!("(mut {})", storage)
+    } else {
+        storage
+    }
+}
+
+pub fn sub_type(ty: &SubType) -> String {
+    let composite = match &ty.composite_type.inner {
+        CompositeInnerType::Func(ty) => func_type(ty),
+        CompositeInnerType::Array(ty) => format!("(array {})", field_type(&ty.0)),
+        CompositeInnerType::Struct(ty) => {
+            let fields: Vec<String> = ty
+                .fields
+                .iter()
+                .map(|field| format!(" (field {})", field_type(field)))
+                .collect();
+            format!("(struct{})", fields.concat())
+        }
+        CompositeInnerType::Cont(ty) => format!("(cont {})", ty.0),
+    };
+    let composite = if ty.composite_type.shared {
+        format!("(shared {})", composite)
+    } else {
+        composite
+    };
+    match (ty.is_final, ty.supertype_idx) {
+        (true, None) => composite,
+        (is_final, supertype) => format!(
+            "(sub {}{}{})",
+            if is_final { "final " } else { "" },
+            supertype.map_or(String::new(), |idx| format!("{} ", idx)),
+            composite
+        ),
+    }
+}
+
+fn limits(index_type: &str, initial: u64, maximum: Option<u64>, shared: bool) -> String {
+    let mut res = index_type.to_string();
+    res.push_str(&initial.to_string());
+    if let Some(maximum) = maximum {
+        res.push_str(&format!(" {}", maximum));
+    }
+    if shared {
+        res.push_str(" shared");
+    }
+    res
+}
+
+pub fn table_type(ty: &TableType) -> String {
+    format!(
+        "(table {} {})",
+        limits(
+            if ty.table64 { "i64 " } else { "" },
+            ty.initial,
+            ty.maximum,
+            ty.shared
+        ),
+        ref_type(&ty.element_type)
+    )
+}
+
+pub fn memory_type(ty: &MemoryType) -> String {
+    format!(
+        "(memory {})",
+        limits(
+            if ty.memory64 { "i64 " } else { "" },
+            ty.initial,
+            ty.maximum,
+            ty.shared
+        )
+    )
+}
+
+pub fn global_type(ty: &GlobalType) -> String {
+    let content = val_type(&ty.content_type);
+    match (ty.mutable, ty.shared) {
+        (false, false) => format!("(global {})", content),
+        (true, false) => format!("(global (mut {}))", content),
+        (false, true) => format!("(global (shared {}))", content),
+        (true, true) => format!("(global (shared (mut {})))", content),
+    }
+}
+
+/// The type of an item, or `None` for items that don't have one, like data segments.
+pub fn item_type(module: &ParsedModule, item: WorkItem) -> Option<String> {
+    let func_sig = |type_idx: u32| module.signature(type_idx).ok().map(func_type);
+    match item {
+        WorkItem::Type(idx) => module.types.get(idx as usize).map(sub_type),
+        WorkItem::Func(idx) => func_sig(*module.func_types.get(idx as usize)?),
+        WorkItem::Table(idx) => module.table_types.get(idx as usize).map(table_type),
+        WorkItem::Memory(idx) => module.memory_types.get(idx as usize).map(memory_type),
+        WorkItem::Global(idx) => module.global_types.get(idx as usize).map(global_type),
+        WorkItem::Tag(idx) => {
+            let ty = module.tag_types.get(idx as usize)?;
+            func_sig(ty.func_type_idx).map(|sig| sig.replacen("(func", "(tag", 1))
+        }
+        WorkItem::Data(_) | WorkItem::Elem(_) => None,
+    }
+}
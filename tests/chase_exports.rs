@@ -0,0 +1,54 @@
+//! Keeping the exports of functions that kept tables refer to, with --chase-exports.
+
+mod common;
+
+use wasmparser::{Parser, Payload};
+
+use common::isolate;
+
+/// The names of the module's exports, in order.
+fn export_names(wasm: &[u8]) -> Vec<String> {
+    let mut res = vec![];
+    for payload in Parser::new(0).parse_all(wasm) {
+        if let Payload::ExportSection(r) = payload.unwrap() {
+            for export in r {
+                res.push(export.unwrap().name.to_string());
+            }
+        }
+    }
+    res
+}
+
+#[test]
+fn keeps_the_exports_of_functions_in_kept_tables() {
+    let out = isolate(
+        "chase-exports",
+        r#"(module
+          (table $t 2 funcref)
+          (elem (table $t) (i32.const 0) func $a $b)
+          (func $a (export "a") (result i32) i32.const 1)
+          (func $b (export "also_b") (result i32) i32.const 2)
+          (func $c (export "c") (result i32) i32.const 3))"#,
+        &["--tables", "0", "--chase-exports", "--no-synthetic-exports"],
+    );
+    assert_eq!(export_names(&out), ["a", "also_b"]);
+}
+
+#[test]
+fn keeps_the_exports_of_functions_in_kept_tables_outside_the_api() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("chase-exports-api");
+    std::fs::create_dir_all(&dir).unwrap();
+    let api = dir.join("api.txt");
+    std::fs::write(&api, "table\n").unwrap();
+    let out = isolate(
+        "chase-exports-api",
+        r#"(module
+          (table $t (export "table") 2 funcref)
+          (elem (table $t) (i32.const 0) func $a $b)
+          (func $a (export "a") (result i32) i32.const 1)
+          (func $b (export "also_b") (result i32) i32.const 2)
+          (func $c (export "c") (result i32) i32.const 3))"#,
+        &["--api", api.to_str().unwrap(), "--chase-exports"],
+    );
+    assert_eq!(export_names(&out), ["table", "a", "also_b"]);
+}
@@ -0,0 +1,90 @@
+//! Moving branch hints and other code metadata along with the instructions they are attached to.
+
+mod common;
+
+use wasm_encoder::{CustomSection, Encode, Section};
+use wasmparser::Operator;
+
+use wasm_isolate::module::ParsedModule;
+
+/// The kept function reads a global whose index shrinks from two bytes to one, so everything after
+/// it in the body moves back a byte.
+fn module() -> String {
+    format!(
+        r#"(module
+          {}
+          (global $g (mut i32) (i32.const 0))
+          (func $dropped (result i32) i32.const 0)
+          (func $kept (export "kept") (result i32)
+            global.get $g
+            (@metadata.code.branch_hint "\01")
+            if (result i32)
+              i32.const 1
+            else
+              i32.const 2
+            end))"#,
+        "(global i32 (i32.const 0))".repeat(200),
+    )
+}
+
+/// The instruction at the given offset from the start of a function body, as code metadata counts
+/// them.
+fn op_at(wasm: &[u8], func: u32, offset: u32) -> Operator<'_> {
+    let module = ParsedModule::parse(wasm).unwrap();
+    let body = &module.defined_funcs[(func - module.num_imported_functions) as usize].body;
+    let start = body.range().start;
+    body.get_operators_reader()
+        .unwrap()
+        .into_iter_with_offsets()
+        .map(|op| op.unwrap())
+        .find(|(_, op_offset)| *op_offset == start + offset as usize)
+        .unwrap()
+        .0
+}
+
+#[test]
+fn remaps_code_metadata_after_a_dropped_function() {
+    let mut input = wat::parse_str(module()).unwrap();
+    let parsed = ParsedModule::parse(&input).unwrap();
+    let [(1, hints)] = &parsed.branch_hints[..] else {
+        panic!("expected hints for the kept function only");
+    };
+    let old_offset = hints[0].func_offset;
+    assert!(matches!(op_at(&input, 1, old_offset), Operator::If { .. }));
+
+    // Another section following the code metadata convention, with an entry at the same place.
+    let mut data = vec![];
+    for n in [1, 1, 1, old_offset, 1] {
+        n.encode(&mut data);
+    }
+    data.push(0xab);
+    CustomSection {
+        name: "metadata.code.test".into(),
+        data: data.into(),
+    }
+    .append_to(&mut input);
+
+    let (res, out) = common::run("code-metadata", &input, &["-f", "1"]);
+    assert!(
+        res.status.success(),
+        "{}",
+        String::from_utf8_lossy(&res.stderr)
+    );
+    let out = out.unwrap();
+    common::validate(&out);
+
+    let parsed = ParsedModule::parse(&out).unwrap();
+    let [(0, hints)] = &parsed.branch_hints[..] else {
+        panic!("expected hints for the kept function at its new index");
+    };
+    let new_offset = hints[0].func_offset;
+    assert!(hints[0].taken);
+    assert_eq!(new_offset, old_offset - 1);
+    assert!(matches!(op_at(&out, 0, new_offset), Operator::If { .. }));
+
+    let [metadata] = &parsed.code_metadata[..] else {
+        panic!("expected the other code metadata section to be kept");
+    };
+    assert_eq!(metadata.name, "metadata.code.test");
+    assert_eq!(metadata.funcs, [(0, vec![(new_offset, &[0xab][..])])]);
+}
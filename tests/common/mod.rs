@@ -0,0 +1,70 @@
+//! Helpers shared by the tests that run wasm-isolate on small modules.
+
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use wasmparser::{DataKind, Operator, Parser, Payload, Validator, WasmFeatures};
+
+/// Runs wasm-isolate on the given module with the given arguments, writing the isolated module to
+/// a file of its own. Returns how the run went, and the output module if one was written. Each
+/// test needs its own name, since the files are named after it.
+pub fn run(name: &str, wasm: &[u8], args: &[&str]) -> (Output, Option<Vec<u8>>) {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.wasm");
+    let output = dir.join("isolated.wasm");
+    std::fs::write(&input, wasm).unwrap();
+    let _ = std::fs::remove_file(&output);
+
+    let res = Command::new(env!("CARGO_BIN_EXE_wasm-isolate"))
+        .arg(&input)
+        .args(args)
+        .arg("-o")
+        .arg(&output)
+        .output()
+        .unwrap();
+    (res, std::fs::read(&output).ok())
+}
+
+/// Isolates the given module, and returns the output after checking that it validates.
+pub fn isolate(name: &str, wat: &str, args: &[&str]) -> Vec<u8> {
+    let (res, out) = run(name, &wat::parse_str(wat).unwrap(), args);
+    assert!(
+        res.status.success(),
+        "wasm-isolate failed on {}: {}",
+        name,
+        String::from_utf8_lossy(&res.stderr)
+    );
+    let out = out.unwrap();
+    validate(&out);
+    out
+}
+
+pub fn validate(wasm: &[u8]) {
+    Validator::new_with_features(WasmFeatures::all())
+        .validate_all(wasm)
+        .unwrap();
+}
+
+/// The offset expression and bytes of each active data segment.
+pub fn active_datas(wasm: &[u8]) -> Vec<(Vec<Operator<'_>>, &[u8])> {
+    let mut res = vec![];
+    for payload in Parser::new(0).parse_all(wasm) {
+        if let Payload::DataSection(r) = payload.unwrap() {
+            for data in r {
+                let data = data.unwrap();
+                if let DataKind::Active { offset_expr, .. } = data.kind {
+                    let ops = offset_expr
+                        .get_operators_reader()
+                        .into_iter()
+                        .collect::<Result<Vec<_>, _>>()
+                        .unwrap();
+                    res.push((ops, data.data));
+                }
+            }
+        }
+    }
+    res
+}
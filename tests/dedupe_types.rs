@@ -0,0 +1,77 @@
+//! Merging identical function types with --dedupe-types.
+
+mod common;
+
+use wasmparser::{Operator, Parser, Payload};
+
+use wasm_isolate::module::ParsedModule;
+
+use common::isolate;
+
+const MODULE: &str = r#"(module
+  (type $a (func (result i32)))
+  (type $b (func (result i32)))
+  (type $open (sub (func (result i32))))
+  (rec (type $rec (func (result i32))))
+  (table 1 funcref)
+  (elem (i32.const 0) func $f)
+  (func $f (type $a) i32.const 7)
+  (func (export "g") (type $b)
+    i32.const 0
+    call_indirect (type $b))
+  (func (export "h") (type $open)
+    i32.const 0
+    call_indirect (type $open))
+  (func (export "i") (type $rec)
+    i32.const 0
+    call_indirect (type $rec)))"#;
+
+fn dedupe(name: &str, funcs: &str) -> Vec<u8> {
+    isolate(name, MODULE, &["-f", funcs, "-e", "0", "--dedupe-types"])
+}
+
+/// The type index of each call_indirect in the module's code.
+fn indirect_call_types(wasm: &[u8]) -> Vec<u32> {
+    let mut res = vec![];
+    for payload in Parser::new(0).parse_all(wasm) {
+        if let Payload::CodeSectionEntry(body) = payload.unwrap() {
+            for op in body.get_operators_reader().unwrap() {
+                if let Operator::CallIndirect { type_index, .. } = op.unwrap() {
+                    res.push(type_index);
+                }
+            }
+        }
+    }
+    res
+}
+
+#[test]
+fn merges_only_final_types_alone_in_their_rec_group() {
+    let out = dedupe("dedupe-types", "1,2,3");
+    let module = ParsedModule::parse(&out).unwrap();
+    // $b is merged into $a. $open isn't final and $rec is in a rec group of its own making, so
+    // both are left alone even though they look the same.
+    assert_eq!(module.types.len(), 3);
+    assert!(module.types[0].is_final);
+    assert!(!module.types[1].is_final);
+    assert!(module.rec_groups[2].is_explicit_rec_group());
+
+    assert_eq!(module.func_types, [0, 0, 1, 2]);
+    // The call_indirect against $b now names $a, the type of the function in the table.
+    assert_eq!(indirect_call_types(&out), [0, 1, 2]);
+}
+
+#[cfg(feature = "run")]
+#[test]
+fn indirect_calls_against_a_merged_type_still_match() {
+    use wasmtime::{Engine, Instance, Module, Store};
+
+    // Only "g" is kept, since wasmtime isn't built with the GC proposal that $open needs.
+    let out = dedupe("dedupe-types-run", "1");
+    let engine = Engine::default();
+    let module = Module::new(&engine, &out).unwrap();
+    let mut store = Store::new(&engine, ());
+    let instance = Instance::new(&mut store, &module, &[]).unwrap();
+    let g = instance.get_typed_func::<(), i32>(&mut store, "g").unwrap();
+    assert_eq!(g.call(&mut store, ()).unwrap(), 7);
+}
@@ -0,0 +1,83 @@
+//! Isolating the same roots twice gives the same module, summary, and report.
+
+use wasmparser::{Validator, WasmFeatures};
+
+use wasm_isolate::emit::{check_deterministic, EmitOptions};
+use wasm_isolate::liveness::{LivenessOptions, WorkItem};
+use wasm_isolate::uses::Uses;
+
+const MODULE: &str = r#"(module
+  (type $binop (func (param i32 i32) (result i32)))
+  (type $thunk (func (result i32)))
+  (import "env" "log" (func $log (param i32)))
+  (import "env" "base" (global $base i32))
+  (memory $mem 1)
+  (table $table 4 funcref)
+  (global $counter (mut i32) (i32.const 0))
+  (global $offset i32 (global.get $base))
+  (tag $oops (param i32))
+  (elem (table $table) (i32.const 0) func $add $sub $forty_two)
+  (elem $passive func $sub)
+  (data (i32.const 16) "hello")
+  (data $later "world")
+  (func $add (type $binop) local.get 0 local.get 1 i32.add)
+  (func $sub (type $binop) local.get 0 local.get 1 i32.sub)
+  (func $forty_two (type $thunk) i32.const 42)
+  (func $entry (export "entry") (param i32) (result i32)
+    global.get $counter
+    i32.const 1
+    i32.add
+    global.set $counter
+    local.get 0
+    call $log
+    i32.const 0
+    i32.const 0
+    i32.const 5
+    memory.init $later
+    i32.const 0
+    i32.const 0
+    i32.const 1
+    table.init $table $passive
+    local.get 0
+    i32.const 2
+    i32.const 1
+    call_indirect $table (type $binop))
+  (func $fail (export "fail")
+    i32.const 0
+    throw $oops)
+  (func $unused (export "unused") (result i32)
+    global.get $offset
+    i32.load)
+)"#;
+
+#[test]
+fn isolating_twice_gives_the_same_output() {
+    let wasm = wat::parse_str(MODULE).unwrap();
+    let roots = [WorkItem::Func(4), WorkItem::Func(5), WorkItem::Elem(0)];
+    let mut requested = Uses::default();
+    for root in roots {
+        requested.insert(root);
+    }
+    let liveness_options = LivenessOptions {
+        indirect_closure: true,
+        ..Default::default()
+    };
+    for options in [
+        EmitOptions {
+            requested: &requested,
+            ..Default::default()
+        },
+        EmitOptions {
+            requested: &requested,
+            export_all_kept: true,
+            keep_names: true,
+            reencode: true,
+            ..Default::default()
+        },
+    ] {
+        let out = check_deterministic(&wasm, &roots, &liveness_options, &options).unwrap();
+        Validator::new_with_features(WasmFeatures::all())
+            .validate_all(&out)
+            .unwrap();
+    }
+}
@@ -0,0 +1,90 @@
+//! Patching kept code that refers to items left out with --drop-funcs and --drop.
+
+mod common;
+
+use wasmparser::{Operator, Parser, Payload};
+
+use common::isolate;
+
+/// Every instruction in the module's code.
+fn code_ops(wasm: &[u8]) -> Vec<Operator<'_>> {
+    let mut res = vec![];
+    for payload in Parser::new(0).parse_all(wasm) {
+        if let Payload::CodeSectionEntry(body) = payload.unwrap() {
+            for op in body.get_operators_reader().unwrap() {
+                res.push(op.unwrap());
+            }
+        }
+    }
+    res
+}
+
+#[test]
+fn replaces_typed_function_references_with_unreachable() {
+    // `ref.func` makes a `(ref $t)`, which neither `local.set` on a non-nullable local nor
+    // `br_on_cast` would accept a `funcref` in place of.
+    let out = isolate(
+        "drop-typed-ref-func",
+        r#"(module
+          (type $t (func (result i32)))
+          (func $a (type $t) i32.const 1)
+          (func (export "call") (result i32)
+            (local $f (ref $t))
+            ref.func $a
+            local.set $f
+            local.get $f
+            call_ref $t)
+          (func (export "cast") (result i32)
+            (block $b (result (ref $t))
+              ref.func $a
+              br_on_cast $b (ref $t) (ref $t)
+              drop
+              i32.const 0
+              return)
+            call_ref $t))"#,
+        &["-f", "1,2", "--drop-funcs", "0"],
+    );
+    let ops = code_ops(&out);
+    assert!(!ops
+        .iter()
+        .any(|op| matches!(op, Operator::RefFunc { .. } | Operator::RefNull { .. })));
+    assert_eq!(
+        ops.iter()
+            .filter(|op| matches!(op, Operator::Unreachable))
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn replaces_function_references_in_segments_with_null() {
+    let out = isolate(
+        "drop-elem-ref-func",
+        r#"(module
+          (table $t 2 funcref)
+          (elem (table $t) (i32.const 0) func $a $b)
+          (func $a)
+          (func $b)
+          (func (export "get") (result funcref)
+            i32.const 0
+            table.get $t))"#,
+        &["-f", "2", "-e", "0", "--drop-funcs", "0"],
+    );
+    let mut items = vec![];
+    for payload in Parser::new(0).parse_all(&out) {
+        if let Payload::ElementSection(r) = payload.unwrap() {
+            for elem in r {
+                if let wasmparser::ElementItems::Expressions(_, exprs) = elem.unwrap().items {
+                    for expr in exprs {
+                        let mut ops = expr.unwrap().get_operators_reader();
+                        items.push(ops.read().unwrap());
+                    }
+                }
+            }
+        }
+    }
+    assert!(matches!(
+        items[..],
+        [Operator::RefNull { .. }, Operator::RefFunc { .. }]
+    ));
+}
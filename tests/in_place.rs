@@ -0,0 +1,49 @@
+//! Replacing inputs with their isolated modules.
+
+mod common;
+
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+const MODULE: &str = r#"(module
+  (func (export "a") (result i32) i32.const 1)
+  (func (export "b") (result i32) i32.const 2))"#;
+
+#[test]
+fn recompresses_gzip_inputs() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("in-place-gzip");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("input.wasm.gz");
+    let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(&wat::parse_str(MODULE).unwrap()).unwrap();
+    fs::write(&path, encoder.finish().unwrap()).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+    let res = Command::new(env!("CARGO_BIN_EXE_wasm-isolate"))
+        .arg(&path)
+        .args(["-f", "1", "--in-place"])
+        .output()
+        .unwrap();
+    assert!(
+        res.status.success(),
+        "{}",
+        String::from_utf8_lossy(&res.stderr)
+    );
+
+    let mut out = vec![];
+    GzDecoder::new(fs::File::open(&path).unwrap())
+        .read_to_end(&mut out)
+        .unwrap();
+    common::validate(&out);
+    assert_eq!(
+        fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+        0o640
+    );
+    // Nothing is left behind next to the input.
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+}
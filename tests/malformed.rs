@@ -0,0 +1,97 @@
+//! Malformed and oversized modules are reported as invalid instead of crashing wasm-isolate.
+
+mod common;
+
+use wasm_encoder::{CodeSection, Function, FunctionSection, Instruction, Module, TypeSection};
+
+use wasm_isolate::error::{error_kind, ErrorKind};
+use wasm_isolate::module::ParsedModule;
+
+/// Checks that the module is rejected as invalid, both by the library and on the command line.
+fn assert_invalid(name: &str, wasm: &[u8]) {
+    let err = ParsedModule::parse(wasm).err().unwrap();
+    assert_eq!(error_kind(&err), ErrorKind::InvalidModule, "{:#}", err);
+
+    let (res, _) = common::run(name, wasm, &["--funcs", "0"]);
+    let stderr = String::from_utf8_lossy(&res.stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    assert_eq!(
+        res.status.code(),
+        Some(ErrorKind::InvalidModule.exit_code() as i32),
+        "{}",
+        stderr
+    );
+}
+
+fn types() -> TypeSection {
+    let mut types = TypeSection::new();
+    types.ty().function([], []);
+    types
+}
+
+fn code(bodies: usize) -> CodeSection {
+    let mut code = CodeSection::new();
+    for _ in 0..bodies {
+        let mut f = Function::new([]);
+        f.instruction(&Instruction::End);
+        code.function(&f);
+    }
+    code
+}
+
+#[test]
+fn rejects_code_without_a_function_section() {
+    let mut module = Module::new();
+    module.section(&types()).section(&code(1));
+    assert_invalid("malformed-no-funcs", &module.finish());
+}
+
+#[test]
+fn rejects_functions_without_code() {
+    let mut funcs = FunctionSection::new();
+    funcs.function(0);
+    let mut module = Module::new();
+    module.section(&types()).section(&funcs);
+    assert_invalid("malformed-no-code", &module.finish());
+}
+
+#[test]
+fn rejects_functions_with_unknown_types() {
+    let mut funcs = FunctionSection::new();
+    funcs.function(3);
+    let mut module = Module::new();
+    module.section(&types()).section(&funcs).section(&code(1));
+    assert_invalid("malformed-bad-type", &module.finish());
+}
+
+#[test]
+fn rejects_modules_with_too_many_operators() {
+    let wasm = wat::parse_str(
+        r#"(module
+          (func (export "a") (result i32) i32.const 1)
+          (func (export "b") (result i32) i32.const 2))"#,
+    )
+    .unwrap();
+    let (res, _) = common::run(
+        "too-many-operators",
+        &wasm,
+        &["-f", "0", "--max-operators", "3"],
+    );
+    assert_eq!(
+        res.status.code(),
+        Some(ErrorKind::InvalidModule.exit_code() as i32),
+        "{}",
+        String::from_utf8_lossy(&res.stderr)
+    );
+    // Each body has two operators, counting its `end`.
+    let (res, _) = common::run(
+        "few-enough-operators",
+        &wasm,
+        &["-f", "0", "--max-operators", "4"],
+    );
+    assert!(
+        res.status.success(),
+        "{}",
+        String::from_utf8_lossy(&res.stderr)
+    );
+}
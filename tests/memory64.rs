@@ -0,0 +1,181 @@
+//! Isolating modules that use memory64, with addresses and limits that don't fit in 32 bits.
+
+mod common;
+
+use wasmparser::{MemArg, Operator, Parser, Payload};
+
+use common::{active_datas, isolate};
+
+/// Every memory argument in the module's code.
+fn memargs(wasm: &[u8]) -> Vec<MemArg> {
+    let mut res = vec![];
+    for payload in Parser::new(0).parse_all(wasm) {
+        if let Payload::CodeSectionEntry(body) = payload.unwrap() {
+            for op in body.get_operators_reader().unwrap() {
+                match op.unwrap() {
+                    Operator::I32Load { memarg } | Operator::I64Store { memarg } => {
+                        res.push(memarg)
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    res
+}
+
+#[test]
+fn keeps_64_bit_limits() {
+    let out = isolate(
+        "memory64-limits",
+        r#"(module
+          (memory i64 2 70000)
+          (table i64 1 0x1_0000_0000 funcref)
+          (func (export "size") (result i64)
+            memory.size
+            table.size
+            i64.add))"#,
+        &["-f", "0"],
+    );
+    let (mut memory, mut table) = (None, None);
+    for payload in Parser::new(0).parse_all(&out) {
+        match payload.unwrap() {
+            Payload::MemorySection(r) => memory = r.into_iter().next().transpose().unwrap(),
+            Payload::TableSection(r) => table = r.into_iter().next().transpose().unwrap(),
+            _ => {}
+        }
+    }
+    let memory = memory.unwrap();
+    assert!(memory.memory64);
+    assert_eq!((memory.initial, memory.maximum), (2, Some(70000)));
+    let table = table.unwrap().ty;
+    assert!(table.table64);
+    assert_eq!((table.initial, table.maximum), (1, Some(0x1_0000_0000)));
+}
+
+#[test]
+fn keeps_large_memarg_offsets() {
+    let out = isolate(
+        "memory64-offsets",
+        r#"(module
+          (memory i64 1)
+          (func (export "load") (result i32)
+            i64.const 0
+            i32.load offset=0x100000000)
+          (func (export "store")
+            i64.const 0
+            i64.const 1
+            i64.store offset=0xfffffffffffffff0))"#,
+        &["-f", "0,1"],
+    );
+    let offsets: Vec<u64> = memargs(&out).iter().map(|memarg| memarg.offset).collect();
+    assert_eq!(offsets, [0x1_0000_0000, 0xffff_ffff_ffff_fff0]);
+}
+
+#[test]
+fn trims_data_near_the_end_of_the_address_space() {
+    let out = isolate(
+        "memory64-trim",
+        r#"(module
+          (memory i64 1)
+          (data (i64.const 0xffff_ffff_ffff_fff0) "0123456789abcdef")
+          (func (export "load") (result i32)
+            i64.const 0xffff_ffff_ffff_fff4
+            i32.load offset=2))"#,
+        &["-f", "0", "-d", "0", "--trim-data"],
+    );
+    let datas = active_datas(&out);
+    assert_eq!(datas.len(), 1);
+    assert!(matches!(
+        datas[0].0[..],
+        [Operator::I64Const { value: -10 }, Operator::End]
+    ));
+    assert_eq!(datas[0].1, b"6789");
+}
+
+#[test]
+fn trims_data_for_accesses_that_wrap_around() {
+    // The access runs past the end of the address space, so it covers the rest of the segment
+    // rather than wrapping around to the start of memory.
+    let out = isolate(
+        "memory64-trim-wrap",
+        r#"(module
+          (memory i64 1)
+          (data (i64.const 0xffff_ffff_ffff_fff0) "0123456789abcdef")
+          (func (export "store")
+            i64.const 0xffff_ffff_ffff_fff0
+            i64.const 0
+            i64.store offset=12))"#,
+        &["-f", "0", "-d", "0", "--trim-data"],
+    );
+    let datas = active_datas(&out);
+    assert_eq!(datas.len(), 1);
+    assert!(matches!(
+        datas[0].0[..],
+        [Operator::I64Const { value: -4 }, Operator::End]
+    ));
+    assert!(datas[0].1.starts_with(b"cde"));
+}
+
+#[test]
+fn clamps_memory_with_data_near_the_end_of_the_address_space() {
+    let out = isolate(
+        "memory64-clamp",
+        r#"(module
+          (memory $low i64 10)
+          (memory $high i64 10)
+          (data (memory $low) (i64.const 0x1_0000) "a")
+          (data (memory $high) (i64.const 0xffff_ffff_ffff_fff0) "0123456789abcdef")
+          (func (export "sizes") (result i64)
+            memory.size $low
+            memory.size $high
+            i64.add))"#,
+        &["-f", "0", "-d", "0,1", "--clamp-memory"],
+    );
+    let mut initials = vec![];
+    for payload in Parser::new(0).parse_all(&out) {
+        if let Payload::MemorySection(r) = payload.unwrap() {
+            for ty in r {
+                initials.push(ty.unwrap().initial);
+            }
+        }
+    }
+    // The segment that runs to the end of the address space can't fit in any memory, so that
+    // memory keeps its size.
+    assert_eq!(initials, [2, 10]);
+}
+
+#[test]
+fn applies_segments_near_the_end_of_the_address_space_from_the_init_function() {
+    let out = isolate(
+        "memory64-passive-init",
+        r#"(module
+          (memory i64 1)
+          (data (i64.const 0xffff_ffff_ffff_fff0) "0123456789abcdef")
+          (func (export "load") (result i32)
+            i64.const 0xffff_ffff_ffff_fff4
+            i32.load))"#,
+        &["-f", "0", "-d", "0", "--passive-init", "--trim-data"],
+    );
+    assert!(active_datas(&out).is_empty());
+
+    let mut addresses = vec![];
+    for payload in Parser::new(0).parse_all(&out) {
+        if let Payload::CodeSectionEntry(body) = payload.unwrap() {
+            let ops = body
+                .get_operators_reader()
+                .unwrap()
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            for window in ops.windows(4) {
+                if let [Operator::I64Const { value }, Operator::I32Const { .. }, Operator::I32Const { .. }, Operator::MemoryInit { .. }] =
+                    window
+                {
+                    addresses.push(*value as u64);
+                }
+            }
+        }
+    }
+    assert_eq!(addresses, [0xffff_ffff_ffff_fff4]);
+}
@@ -0,0 +1,97 @@
+//! Running modules isolated with --passive-init, whose init function has to put the same things
+//! in memory and tables that instantiating the original module does. This needs the run feature
+//! for wasmtime.
+
+#![cfg(feature = "run")]
+
+mod common;
+
+use wasmtime::{
+    Engine, Extern, Global, GlobalType, Instance, Module, Mutability, Store, Val, ValType,
+};
+
+use common::isolate;
+
+const MODULE: &str = r#"(module
+  (import "env" "data_base" (global $data_base i32))
+  (import "env" "elem_base" (global $elem_base i32))
+  (memory (export "memory") 1)
+  (table (export "table") 6 funcref)
+  (data (global.get $data_base) "hello")
+  (data (i32.const 100) "world")
+  (data (i32.add (global.get $data_base) (i32.const 200)) "again")
+  (data (i32.const 102) "RL")
+  (elem (global.get $elem_base) func $one $two)
+  (elem (i32.const 4) func $three)
+  (func $one (result i32) i32.const 1)
+  (func $two (result i32) i32.const 2)
+  (func $three (result i32) i32.const 3)
+  (func (export "call") (param i32) (result i32)
+    local.get 0
+    call_indirect (result i32)))"#;
+
+/// What a module holds after instantiation and, if it has one, its init function: the first page
+/// of its memory, and the result of calling each slot of its table, or `None` for empty slots.
+fn image(engine: &Engine, wasm: &[u8], init: Option<&str>) -> (Vec<u8>, Vec<Option<i32>>) {
+    let module = Module::new(engine, wasm).unwrap();
+    let mut store = Store::new(engine, ());
+    let mut imports: Vec<Extern> = vec![];
+    for import in module.imports() {
+        let value = match import.name() {
+            "data_base" => 16,
+            "elem_base" => 1,
+            name => panic!("unexpected import {}", name),
+        };
+        let ty = GlobalType::new(ValType::I32, Mutability::Const);
+        imports.push(Global::new(&mut store, ty, Val::I32(value)).unwrap().into());
+    }
+    let instance = Instance::new(&mut store, &module, &imports).unwrap();
+    if let Some(init) = init {
+        instance
+            .get_typed_func::<(), ()>(&mut store, init)
+            .unwrap()
+            .call(&mut store, ())
+            .unwrap();
+    }
+
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+    let bytes = memory.data(&store)[..0x1_0000].to_vec();
+    let call = instance
+        .get_typed_func::<i32, i32>(&mut store, "call")
+        .unwrap();
+    let slots = (0..6).map(|i| call.call(&mut store, i).ok()).collect();
+    (bytes, slots)
+}
+
+#[test]
+fn init_function_reproduces_the_original_image() {
+    let out = isolate(
+        "passive-init-image",
+        MODULE,
+        &[
+            "--exports",
+            "memory,table,call",
+            "-d",
+            "0,1,2,3",
+            "-e",
+            "0,1",
+            "--passive-init",
+            "--no-synthetic-exports",
+        ],
+    );
+    let engine = Engine::default();
+    let original = image(&engine, &wat::parse_str(MODULE).unwrap(), None);
+    assert_eq!(&original.0[16..21], b"hello");
+    assert_eq!(&original.0[100..105], b"woRLd");
+    assert_eq!(&original.0[216..221], b"again");
+    assert_eq!(original.1, [None, Some(1), Some(2), None, Some(3), None]);
+
+    // Nothing is applied on instantiation, and all of it is after the init function.
+    let before = image(&engine, &out, None);
+    assert!(before.0.iter().all(|byte| *byte == 0));
+    assert!(before.1.iter().all(Option::is_none));
+    assert_eq!(
+        image(&engine, &out, Some(wasm_isolate::emit::INIT_FUNC_NAME)),
+        original
+    );
+}
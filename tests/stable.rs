@@ -0,0 +1,123 @@
+//! Keeping items at their original indices with --no-relocate and --stable.
+
+mod common;
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use wasm_isolate::module::ParsedModule;
+use wasm_isolate::schema::Map;
+
+const MODULE: &str = r#"(module
+  (type $unused (func (param f64)))
+  (type $t (func (result i32)))
+  (import "env" "f" (func $imp (type $t)))
+  (import "env" "g" (global $gimp i32))
+  (tag $tag0)
+  (tag $tag1 (param i32))
+  (table $t0 1 funcref)
+  (table $t1 2 funcref)
+  (memory $m0 1)
+  (memory $m1 1)
+  (global $g0 i32 (i32.const 0))
+  (global $g1 (mut i32) (global.get $gimp))
+  (elem $e0 (table $t0) (i32.const 0) func $f0)
+  (elem $e1 (table $t1) (i32.const 0) func $f1 $f2)
+  (data $d0 (memory $m0) (i32.const 0) "a")
+  (data $d1 (memory $m1) (i32.const 0) "b")
+  (func $f0 (type $t) i32.const 0)
+  (func $f1 (type $t) call $imp)
+  (func $f2 (type $t)
+    global.get $g1
+    i32.load $m1
+    i32.eqz
+    if
+      i32.const 1
+      throw $tag1
+    end
+    i32.const 0
+    call_indirect $t1 (type $t))
+  (func $keep (export "keep") (type $t) call $f2))"#;
+
+/// Isolates the last function with the given extra arguments, and returns the output module and
+/// its map of new indices.
+fn isolate_with_map(name: &str, args: &[&str]) -> (Vec<u8>, Map) {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.wasm");
+    std::fs::write(&input, wat::parse_str(MODULE).unwrap()).unwrap();
+    let prefix = dir.join("isolated");
+    let res = Command::new(env!("CARGO_BIN_EXE_wasm-isolate"))
+        .arg(&input)
+        .args(["-f", "4", "-d", "1", "-e", "1"])
+        .args(["--emit", "wasm,map", "--out-prefix"])
+        .arg(&prefix)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        res.status.success(),
+        "{}",
+        String::from_utf8_lossy(&res.stderr)
+    );
+    let wasm = std::fs::read(prefix.with_extension("wasm")).unwrap();
+    common::validate(&wasm);
+    let map =
+        serde_json::from_slice(&std::fs::read(prefix.with_extension("map.json")).unwrap()).unwrap();
+    (wasm, map)
+}
+
+/// How many items each index space has.
+fn counts(module: &ParsedModule) -> [usize; 8] {
+    [
+        module.types.len(),
+        module.func_types.len(),
+        module.table_types.len(),
+        module.memory_types.len(),
+        module.global_types.len(),
+        module.tag_types.len(),
+        module.elems.len(),
+        module.datas.len(),
+    ]
+}
+
+#[test]
+fn no_relocate_keeps_every_index() {
+    let (out, map) = isolate_with_map("no-relocate", &["--no-relocate"]);
+    for entry in &map.items {
+        assert_eq!(entry.old_index, entry.new_index, "{:?}", entry);
+    }
+    // Every index space has something kept past its first item.
+    for kind in [
+        "type", "func", "table", "memory", "global", "tag", "elem", "data",
+    ] {
+        assert!(
+            map.items
+                .iter()
+                .any(|entry| entry.kind == kind && entry.old_index > 0),
+            "nothing past the first {} was kept",
+            kind
+        );
+    }
+
+    let input = wat::parse_str(MODULE).unwrap();
+    assert_eq!(
+        counts(&ParsedModule::parse(&out).unwrap()),
+        counts(&ParsedModule::parse(&input).unwrap())
+    );
+}
+
+#[test]
+fn stable_keeps_the_indices_of_the_chosen_spaces() {
+    let (_, map) = isolate_with_map("stable", &["--stable", "funcs,globals"]);
+    for entry in &map.items {
+        if entry.kind == "func" || entry.kind == "global" {
+            assert_eq!(entry.old_index, entry.new_index, "{:?}", entry);
+        }
+    }
+    // Other index spaces are still compacted.
+    assert!(map
+        .items
+        .iter()
+        .any(|entry| entry.kind == "table" && entry.old_index != entry.new_index));
+}
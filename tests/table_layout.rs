@@ -0,0 +1,115 @@
+//! Keeping every table entry in place with --preserve-table-layout.
+
+mod common;
+
+use wasmparser::{ElementItems, FuncType, Operator, Parser, Payload};
+
+use common::isolate;
+
+/// The parts of a module that a table's layout depends on.
+#[derive(Default)]
+struct Funcs<'a> {
+    types: Vec<FuncType>,
+    /// The type of every function, imported or defined.
+    func_types: Vec<u32>,
+    bodies: Vec<Vec<Operator<'a>>>,
+    elem_funcs: Vec<u32>,
+    /// The index of the function exported as "call".
+    call: u32,
+}
+
+fn funcs(wasm: &[u8]) -> Funcs<'_> {
+    let mut res = Funcs::default();
+    for payload in Parser::new(0).parse_all(wasm) {
+        match payload.unwrap() {
+            Payload::TypeSection(r) => {
+                for ty in r.into_iter_err_on_gc_types() {
+                    res.types.push(ty.unwrap());
+                }
+            }
+            Payload::FunctionSection(r) => {
+                for ty in r {
+                    res.func_types.push(ty.unwrap());
+                }
+            }
+            Payload::ExportSection(r) => {
+                for export in r {
+                    let export = export.unwrap();
+                    if export.name == "call" {
+                        res.call = export.index;
+                    }
+                }
+            }
+            Payload::ElementSection(r) => {
+                for elem in r {
+                    if let ElementItems::Functions(items) = elem.unwrap().items {
+                        for idx in items {
+                            res.elem_funcs.push(idx.unwrap());
+                        }
+                    }
+                }
+            }
+            Payload::CodeSectionEntry(body) => {
+                let ops = body.get_operators_reader().unwrap();
+                res.bodies
+                    .push(ops.into_iter().collect::<Result<_, _>>().unwrap());
+            }
+            _ => {}
+        }
+    }
+    res
+}
+
+#[test]
+fn keeps_dropped_table_entries_as_stubs_of_the_same_type() {
+    let wat = r#"(module
+      (type $v (func))
+      (type $i (func (result i32)))
+      (table 4 funcref)
+      (elem (i32.const 0) func $a $b $c $d)
+      (func $a (type $i) i32.const 1)
+      (func $b (type $v))
+      (func $c (type $i) i32.const 3)
+      (func $d (param i64) (result f32) f32.const 0)
+      (func (export "call") (result i32)
+        i32.const 2
+        call_indirect (type $i)))"#;
+    let out = isolate(
+        "preserve-table-layout",
+        wat,
+        &[
+            "-f",
+            "4",
+            "-e",
+            "0",
+            "--null-elem-funcs",
+            "--preserve-table-layout",
+        ],
+    );
+    let input = wat::parse_str(wat).unwrap();
+    let (before, after) = (funcs(&input), funcs(&out));
+
+    // Every entry is still there, and has the type the original entry had.
+    assert_eq!(after.elem_funcs.len(), before.elem_funcs.len());
+    for (old, new) in before.elem_funcs.iter().zip(&after.elem_funcs) {
+        let old_ty = &before.types[before.func_types[*old as usize] as usize];
+        let new_ty = &after.types[after.func_types[*new as usize] as usize];
+        assert_eq!(old_ty, new_ty);
+        // Nothing keeps the functions but the table, so they all trap.
+        assert!(matches!(
+            after.bodies[*new as usize][..],
+            [Operator::Unreachable, Operator::End]
+        ));
+    }
+
+    // The call still goes through the same slot, with the same signature.
+    let ops = &after.bodies[after.call as usize];
+    let Operator::CallIndirect { type_index, .. } = ops[1] else {
+        panic!("expected a call_indirect, but got {:?}", ops);
+    };
+    assert!(matches!(ops[0], Operator::I32Const { value: 2 }));
+    assert_eq!(
+        after.types[type_index as usize],
+        before.types[before.func_types[2] as usize]
+    );
+}
@@ -0,0 +1,56 @@
+//! Trimming kept data segments to the bytes that kept code uses, with --trim-data.
+
+mod common;
+
+use wasmparser::Operator;
+
+use common::{active_datas, isolate};
+
+#[test]
+fn trims_segments_only_accessed_at_constant_addresses() {
+    let out = isolate(
+        "trim-data-constant",
+        r#"(module
+          (memory 1)
+          (data (i32.const 0x100) "0123456789abcdef")
+          (data (i32.const 0x1000) "unused")
+          (func (export "load") (result i32)
+            i32.const 0x104
+            i32.load8_u))"#,
+        &["-f", "0", "-d", "0,1", "--trim-data"],
+    );
+    let datas = active_datas(&out);
+    assert_eq!(datas.len(), 2);
+    assert!(matches!(
+        datas[0].0[..],
+        [Operator::I32Const { value: 0x104 }, Operator::End]
+    ));
+    assert_eq!(datas[0].1, b"4");
+    assert_eq!(datas[1].1, b"");
+}
+
+#[test]
+fn keeps_segments_whole_when_addresses_are_computed() {
+    // The second load could read any byte of the first memory, but none of the second.
+    let out = isolate(
+        "trim-data-computed",
+        r#"(module
+          (memory $a 1)
+          (memory $b 1)
+          (data (memory $a) (i32.const 0x100) "0123456789abcdef")
+          (data (memory $b) (i32.const 0x100) "0123456789abcdef")
+          (func (export "load") (param $ptr i32) (result i32)
+            i32.const 0x104
+            i32.load8_u $b
+            local.get $ptr
+            i32.const 8
+            i32.add
+            i32.load8_u $a
+            i32.add))"#,
+        &["-f", "0", "-d", "0,1", "--trim-data"],
+    );
+    let datas = active_datas(&out);
+    assert_eq!(datas.len(), 2);
+    assert_eq!(datas[0].1, b"0123456789abcdef");
+    assert_eq!(datas[1].1, b"4");
+}
@@ -0,0 +1,59 @@
+//! Copying sections as-is when isolation leaves them unchanged.
+
+mod common;
+
+use std::collections::BTreeMap;
+
+use wasmparser::{Parser, Payload};
+
+use common::isolate;
+
+/// The bytes of each non-custom section by id.
+fn sections(wasm: &[u8]) -> BTreeMap<u8, &[u8]> {
+    let mut res = BTreeMap::new();
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = payload.unwrap();
+        if let Payload::CustomSection(_) = payload {
+            continue;
+        }
+        if let Some((id, range)) = payload.as_section() {
+            res.insert(id, &wasm[range]);
+        }
+    }
+    res
+}
+
+#[test]
+fn copies_only_the_sections_that_stay_the_same() {
+    // The last function is dropped, which changes the function and code sections but leaves the
+    // sections that follow it with the same bytes.
+    let wat = r#"(module
+      (type (func (result i32)))
+      (memory 1)
+      (global $g i32 (i32.const 0))
+      (export "kept" (func $kept))
+      (data (i32.const 0x80) "hi")
+      (func $kept (type 0) global.get $g)
+      (func $dropped (type 0) i32.const 1))"#;
+    let input = wat::parse_str(wat).unwrap();
+    let out = isolate(
+        "verbatim",
+        wat,
+        &["-f", "0", "-d", "0", "--no-synthetic-exports"],
+    );
+    assert_ne!(out, input);
+
+    let (before, after) = (sections(&input), sections(&out));
+    assert_eq!(
+        before.keys().collect::<Vec<_>>(),
+        after.keys().collect::<Vec<_>>()
+    );
+    for (id, bytes) in &before {
+        // The function and code sections lose the dropped function.
+        if *id == 3 || *id == 10 {
+            assert_ne!(after[id], *bytes, "section {}", id);
+        } else {
+            assert_eq!(after[id], *bytes, "section {}", id);
+        }
+    }
+}